@@ -0,0 +1,10 @@
+use interpreter::error::ErrorType;
+use interpreter::{Interpreter, Value};
+
+#[no_mangle]
+pub fn lox_plugin_register(interpreter: &mut Interpreter) {
+    interpreter.define_native("double", 1, |args| match &args[0] {
+        Value::Number(n) => Ok(Value::Number(n * 2.0)),
+        _ => Err(ErrorType::WrongType),
+    });
+}
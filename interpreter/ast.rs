@@ -0,0 +1,877 @@
+use crate::error::Error;
+use crate::expr::{Expr, ExprId, Visitor as ExprVisitor};
+use crate::lexer::Lexer;
+use crate::parser::Parser;
+use crate::pattern::Pattern;
+use crate::runtime_value::format_number;
+use crate::statement::{Arm, Catch, Stmt, Visitor as StmtVisitor};
+use crate::token::{Literal, Span, Token};
+use crate::types::{Signature, Type};
+use std::rc::Rc;
+
+/// Prints the parsed program as s-expressions, one top-level statement per line.
+/// Unlike `format_source` it shows the tree as the interpreter sees it, e.g. for loops desugared into while loops.
+pub fn print_ast(source_code: &str) -> Result<String, Vec<Error>> {
+    let mut lexer = Lexer::new(source_code);
+    let tokens = lexer.scan_tokens()?;
+    let mut parser = Parser::new(&tokens);
+    let stmts = parser.parse_tokens()?;
+
+    let mut output = String::new();
+    for stmt in &stmts {
+        output.push_str(&stmt.accept(&mut AstPrinter).map_err(|e| vec![e])?);
+        output.push('\n');
+    }
+    Ok(output)
+}
+
+/// Prints the program like `print_ast` even when it has errors, the parts that failed to parse are `(error)`
+pub fn print_partial_ast(source_code: &str) -> (String, Vec<Error>) {
+    let tokens = match Lexer::new(source_code).scan_tokens() {
+        Ok(tokens) => tokens,
+        Err(errors) => return (String::new(), errors),
+    };
+    let (stmts, mut errors) = Parser::new(&tokens).parse_partial();
+    let mut output = String::new();
+    for stmt in &stmts {
+        match stmt.accept(&mut AstPrinter) {
+            Ok(printed) => output.push_str(&printed),
+            Err(error) => errors.push(error),
+        }
+        output.push('\n');
+    }
+    (output, errors)
+}
+
+struct AstPrinter;
+
+type PrintResult = Result<String, Error>;
+
+fn parenthesize(name: &str, parts: &[String]) -> String {
+    let mut output = format!("({}", name);
+    for part in parts {
+        output.push(' ');
+        output.push_str(part);
+    }
+    output.push(')');
+    output
+}
+
+impl AstPrinter {
+    fn stmts(&mut self, stmts: &[Stmt]) -> Result<Vec<String>, Error> {
+        stmts.iter().map(|stmt| stmt.accept(self)).collect()
+    }
+
+    fn function(
+        &mut self,
+        kind: &str,
+        name: &str,
+        params: &[String],
+        body: &[Stmt],
+    ) -> PrintResult {
+        let mut parts = vec![name.to_owned(), format!("({})", params.join(" "))];
+        parts.extend(self.stmts(body)?);
+        Ok(parenthesize(kind, &parts))
+    }
+
+    fn pattern(&mut self, pattern: &Pattern) -> PrintResult {
+        match pattern {
+            Pattern::Literal { value, token } => self.visit_literal(value, &token.span()),
+            Pattern::Binding { name, .. } => Ok(name.clone()),
+            Pattern::List { items, rest, .. } => {
+                let mut parts = items
+                    .iter()
+                    .map(|item| self.pattern(item))
+                    .collect::<Result<Vec<_>, _>>()?;
+                if let Some(rest) = rest {
+                    parts.push(format!("...{}", rest));
+                }
+                Ok(parenthesize("list", &parts))
+            }
+            Pattern::Instance { class, fields, .. } => {
+                let mut parts = Vec::new();
+                if let Some(class) = class {
+                    parts.push(class.accept(self)?);
+                }
+                for (name, field) in fields {
+                    parts.push(parenthesize(name, &[self.pattern(field)?]));
+                }
+                Ok(parenthesize("instance", &parts))
+            }
+        }
+    }
+}
+
+impl ExprVisitor<String> for AstPrinter {
+    fn visit_binary(&mut self, left: &Expr, operator_token: &Token, right: &Expr) -> PrintResult {
+        Ok(parenthesize(
+            operator_token.operator(),
+            &[left.accept(self)?, right.accept(self)?],
+        ))
+    }
+
+    fn visit_literal(&mut self, literal: &Literal, _span: &Span) -> PrintResult {
+        Ok(match literal {
+            Literal::String(s) => format!("{:?}", s),
+            Literal::Number(n) => format_number(*n),
+            Literal::Bool(b) => b.to_string(),
+            Literal::Null => String::from("null"),
+        })
+    }
+
+    fn visit_unary(&mut self, operator_token: &Token, expr: &Expr) -> PrintResult {
+        Ok(parenthesize(
+            operator_token.operator(),
+            &[expr.accept(self)?],
+        ))
+    }
+
+    fn visit_grouping(&mut self, expr: &Expr) -> PrintResult {
+        Ok(parenthesize("group", &[expr.accept(self)?]))
+    }
+
+    fn visit_var(&mut self, name: &String, _token: &Token, _id: ExprId) -> PrintResult {
+        Ok(name.clone())
+    }
+
+    fn visit_assignment(
+        &mut self,
+        name: &String,
+        expr: &Expr,
+        _token: &Token,
+        _id: ExprId,
+    ) -> PrintResult {
+        Ok(parenthesize("=", &[name.clone(), expr.accept(self)?]))
+    }
+
+    fn visit_logical(&mut self, left: &Expr, operator_token: &Token, right: &Expr) -> PrintResult {
+        self.visit_binary(left, operator_token, right)
+    }
+
+    fn visit_call(
+        &mut self,
+        callee: &Expr,
+        _token: &Token,
+        arguments: &Vec<Expr>,
+        named: &[(String, Expr)],
+    ) -> PrintResult {
+        let mut parts = vec![callee.accept(self)?];
+        for argument in arguments {
+            parts.push(argument.accept(self)?);
+        }
+        for (name, value) in named {
+            parts.push(parenthesize(&format!("{}:", name), &[value.accept(self)?]));
+        }
+        Ok(parenthesize("call", &parts))
+    }
+
+    fn visit_closure(
+        &mut self,
+        params: &Rc<Vec<String>>,
+        _signature: &Signature,
+        _defaults: &Rc<Vec<Expr>>,
+        body: &Rc<Vec<Stmt>>,
+        name: &String,
+        _token: &Token,
+    ) -> PrintResult {
+        self.function("closure", name, params, body)
+    }
+
+    fn visit_get(&mut self, name: &String, _token: &Token, expr: &Expr) -> PrintResult {
+        Ok(parenthesize(".", &[expr.accept(self)?, name.clone()]))
+    }
+
+    fn visit_set(
+        &mut self,
+        _token: &Token,
+        name: &String,
+        value: &Expr,
+        obj: &Expr,
+    ) -> PrintResult {
+        Ok(parenthesize(
+            ".=",
+            &[obj.accept(self)?, name.clone(), value.accept(self)?],
+        ))
+    }
+
+    fn visit_this(&mut self, _token: &Token, _id: ExprId) -> PrintResult {
+        Ok(String::from("this"))
+    }
+
+    fn visit_super(&mut self, _token: &Token, method_name: &String, _id: ExprId) -> PrintResult {
+        Ok(format!("(super {})", method_name))
+    }
+
+    fn visit_index(&mut self, object: &Expr, index: &Expr, _token: &Token) -> PrintResult {
+        Ok(parenthesize(
+            "[]",
+            &[object.accept(self)?, index.accept(self)?],
+        ))
+    }
+
+    fn visit_set_index(
+        &mut self,
+        object: &Expr,
+        index: &Expr,
+        value: &Expr,
+        _token: &Token,
+    ) -> PrintResult {
+        Ok(parenthesize(
+            "[]=",
+            &[
+                object.accept(self)?,
+                index.accept(self)?,
+                value.accept(self)?,
+            ],
+        ))
+    }
+
+    fn visit_spread(&mut self, expr: &Expr, _token: &Token) -> PrintResult {
+        Ok(parenthesize("...", &[expr.accept(self)?]))
+    }
+
+    fn visit_error(&mut self, _token: &Token) -> PrintResult {
+        Ok(String::from("(error)"))
+    }
+}
+
+impl StmtVisitor<String> for AstPrinter {
+    fn visit_print_stmt(&mut self, expr: &Expr) -> PrintResult {
+        Ok(parenthesize("print", &[expr.accept(self)?]))
+    }
+
+    fn visit_expr_stmt(&mut self, expr: &Expr) -> PrintResult {
+        Ok(parenthesize("expr", &[expr.accept(self)?]))
+    }
+
+    fn visit_var(
+        &mut self,
+        name: &String,
+        value: &Option<Expr>,
+        _annotation: &Option<Type>,
+        _span: &Span,
+    ) -> PrintResult {
+        let mut parts = vec![name.clone()];
+        if let Some(value) = value {
+            parts.push(value.accept(self)?);
+        }
+        Ok(parenthesize("var", &parts))
+    }
+
+    fn visit_block_stmt(&mut self, stmts: &Vec<Stmt>) -> PrintResult {
+        Ok(parenthesize("block", &self.stmts(stmts)?))
+    }
+
+    fn visit_if_stmt(
+        &mut self,
+        condition: &Expr,
+        then_body: &Stmt,
+        else_body: &Option<Box<Stmt>>,
+    ) -> PrintResult {
+        let mut parts = vec![condition.accept(self)?, then_body.accept(self)?];
+        if let Some(else_body) = else_body {
+            parts.push(else_body.accept(self)?);
+        }
+        Ok(parenthesize("if", &parts))
+    }
+
+    fn visit_while_stmt(
+        &mut self,
+        condition: &Expr,
+        body: &Stmt,
+        increment: &Option<Expr>,
+    ) -> PrintResult {
+        let mut parts = vec![condition.accept(self)?, body.accept(self)?];
+        if let Some(increment) = increment {
+            parts.push(parenthesize("increment", &[increment.accept(self)?]));
+        }
+        Ok(parenthesize("while", &parts))
+    }
+
+    fn visit_do_while_stmt(&mut self, body: &Stmt, condition: &Expr) -> PrintResult {
+        Ok(parenthesize(
+            "do",
+            &[body.accept(self)?, condition.accept(self)?],
+        ))
+    }
+
+    fn visit_for_in_stmt(
+        &mut self,
+        name: &String,
+        iterable: &Expr,
+        body: &Stmt,
+        _token: &Token,
+    ) -> PrintResult {
+        Ok(parenthesize(
+            "for-in",
+            &[name.clone(), iterable.accept(self)?, body.accept(self)?],
+        ))
+    }
+
+    fn visit_break_stmt(&mut self, _token: &Token) -> PrintResult {
+        Ok(String::from("(break)"))
+    }
+
+    fn visit_continue_stmt(&mut self, _token: &Token) -> PrintResult {
+        Ok(String::from("(continue)"))
+    }
+
+    fn visit_function_stmt(
+        &mut self,
+        name: &String,
+        params: &Rc<Vec<String>>,
+        _signature: &Signature,
+        _defaults: &Rc<Vec<Expr>>,
+        body: &Rc<Vec<Stmt>>,
+        _token: &Token,
+    ) -> PrintResult {
+        self.function("fn", name, params, body)
+    }
+
+    fn visit_class_stmt(
+        &mut self,
+        name: &String,
+        _token: &Token,
+        members: &Vec<Stmt>,
+        superclass: &Option<Expr>,
+    ) -> PrintResult {
+        let mut parts = vec![name.clone()];
+        if let Some(superclass) = superclass {
+            parts.push(parenthesize(":", &[superclass.accept(self)?]));
+        }
+        parts.extend(self.stmts(members)?);
+        Ok(parenthesize("class", &parts))
+    }
+
+    fn visit_return_stmt(&mut self, value: &Option<Expr>, _token: &Token) -> PrintResult {
+        let parts = match value {
+            Some(value) => vec![value.accept(self)?],
+            None => vec![],
+        };
+        Ok(parenthesize("return", &parts))
+    }
+
+    fn visit_try_stmt(
+        &mut self,
+        body: &[Stmt],
+        catches: &[Catch],
+        finally: &Option<Vec<Stmt>>,
+        _token: &Token,
+    ) -> PrintResult {
+        let mut parts = vec![parenthesize("block", &self.stmts(body)?)];
+        for Catch { name, class, body } in catches {
+            let mut catch = vec![name.clone()];
+            if let Some(class) = class {
+                catch.push(class.accept(self)?);
+            }
+            catch.push(parenthesize("block", &self.stmts(body)?));
+            parts.push(parenthesize("catch", &catch));
+        }
+        if let Some(finally) = finally {
+            parts.push(parenthesize(
+                "finally",
+                &[parenthesize("block", &self.stmts(finally)?)],
+            ));
+        }
+        Ok(parenthesize("try", &parts))
+    }
+
+    fn visit_match_stmt(&mut self, value: &Expr, arms: &[Arm], _token: &Token) -> PrintResult {
+        let mut parts = vec![value.accept(self)?];
+        for Arm { pattern, body } in arms {
+            parts.push(parenthesize(
+                "arm",
+                &[self.pattern(pattern)?, body.accept(self)?],
+            ));
+        }
+        Ok(parenthesize("match", &parts))
+    }
+
+    fn visit_throw_stmt(&mut self, value: &Expr, _token: &Token) -> PrintResult {
+        Ok(parenthesize("throw", &[value.accept(self)?]))
+    }
+
+    fn visit_error_stmt(&mut self, _token: &Token, _end: &Token) -> PrintResult {
+        Ok(String::from("(error)"))
+    }
+}
+
+/// Prints the parsed program as an indented tree, one node per line followed by its `line:start-end` span.
+/// The format doesn't change between runs, so it suits golden tests.
+pub fn dump_ast(source_code: &str) -> Result<String, Vec<Error>> {
+    let mut lexer = Lexer::new(source_code);
+    let tokens = lexer.scan_tokens()?;
+    let mut parser = Parser::new(&tokens);
+    let stmts = parser.parse_tokens()?;
+
+    let mut dumper = AstDumper::default();
+    for stmt in &stmts {
+        dumper.stmt(stmt).map_err(|e| vec![e])?;
+    }
+    Ok(dumper.output)
+}
+
+#[derive(Default)]
+struct AstDumper {
+    output: String,
+    depth: usize,
+    /// Span of the node being visited, visitors aren't given it
+    span: Span,
+}
+
+type DumpResult = Result<(), Error>;
+
+impl AstDumper {
+    fn line(&mut self, label: &str) {
+        self.output.push_str(&format!(
+            "{}{} {}:{}-{}\n",
+            "  ".repeat(self.depth),
+            label,
+            self.span.line,
+            self.span.start,
+            self.span.end
+        ));
+    }
+
+    /// Line without a span, for parts of a node that aren't nodes themselves
+    fn label(&mut self, label: &str) {
+        self.output
+            .push_str(&format!("{}{}\n", "  ".repeat(self.depth), label));
+    }
+
+    fn nested(&mut self, dump: impl FnOnce(&mut Self) -> DumpResult) -> DumpResult {
+        self.depth += 1;
+        let result = dump(self);
+        self.depth -= 1;
+        result
+    }
+
+    fn expr(&mut self, expr: &Expr) -> DumpResult {
+        self.span = expr.span();
+        expr.accept(self)
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) -> DumpResult {
+        self.span = stmt.span();
+        stmt.accept(self)
+    }
+
+    fn stmts(&mut self, stmts: &[Stmt]) -> DumpResult {
+        stmts.iter().try_for_each(|stmt| self.stmt(stmt))
+    }
+
+    fn labeled_stmts(&mut self, label: &str, stmts: &[Stmt]) -> DumpResult {
+        self.label(label);
+        self.nested(|d| d.stmts(stmts))
+    }
+
+    fn function(&mut self, kind: &str, name: &str, params: &[String], body: &[Stmt]) -> DumpResult {
+        self.line(&format!("{} {}({})", kind, name, params.join(", ")));
+        self.nested(|d| d.stmts(body))
+    }
+}
+
+impl ExprVisitor<()> for AstDumper {
+    fn visit_binary(&mut self, left: &Expr, operator: &Token, right: &Expr) -> DumpResult {
+        self.line(&format!("Binary {}", operator.operator()));
+        self.nested(|d| {
+            d.expr(left)?;
+            d.expr(right)
+        })
+    }
+
+    fn visit_literal(&mut self, literal: &Literal, _span: &Span) -> DumpResult {
+        let value = match literal {
+            Literal::String(s) => format!("{:?}", s),
+            Literal::Number(n) => format_number(*n),
+            Literal::Bool(b) => b.to_string(),
+            Literal::Null => String::from("null"),
+        };
+        self.line(&format!("Literal {}", value));
+        Ok(())
+    }
+
+    fn visit_unary(&mut self, operator: &Token, expr: &Expr) -> DumpResult {
+        self.line(&format!("Unary {}", operator.operator()));
+        self.nested(|d| d.expr(expr))
+    }
+
+    fn visit_grouping(&mut self, expr: &Expr) -> DumpResult {
+        self.line("Grouping");
+        self.nested(|d| d.expr(expr))
+    }
+
+    fn visit_var(&mut self, name: &String, _token: &Token, _id: ExprId) -> DumpResult {
+        self.line(&format!("Var {}", name));
+        Ok(())
+    }
+
+    fn visit_assignment(
+        &mut self,
+        name: &String,
+        expr: &Expr,
+        _token: &Token,
+        _id: ExprId,
+    ) -> DumpResult {
+        self.line(&format!("Assign {}", name));
+        self.nested(|d| d.expr(expr))
+    }
+
+    fn visit_logical(&mut self, left: &Expr, operator: &Token, right: &Expr) -> DumpResult {
+        self.line(&format!("Logical {}", operator.operator()));
+        self.nested(|d| {
+            d.expr(left)?;
+            d.expr(right)
+        })
+    }
+
+    fn visit_call(
+        &mut self,
+        callee: &Expr,
+        _token: &Token,
+        arguments: &Vec<Expr>,
+        named: &[(String, Expr)],
+    ) -> DumpResult {
+        self.line("Call");
+        self.nested(|d| {
+            d.expr(callee)?;
+            arguments.iter().try_for_each(|argument| d.expr(argument))?;
+            for (name, value) in named {
+                d.label(&format!("{}:", name));
+                d.nested(|d| d.expr(value))?;
+            }
+            Ok(())
+        })
+    }
+
+    fn visit_closure(
+        &mut self,
+        params: &Rc<Vec<String>>,
+        _signature: &Signature,
+        _defaults: &Rc<Vec<Expr>>,
+        body: &Rc<Vec<Stmt>>,
+        name: &String,
+        _token: &Token,
+    ) -> DumpResult {
+        self.function("Closure", name, params, body)
+    }
+
+    fn visit_get(&mut self, name: &String, _token: &Token, expr: &Expr) -> DumpResult {
+        self.line(&format!("Get {}", name));
+        self.nested(|d| d.expr(expr))
+    }
+
+    fn visit_set(&mut self, _token: &Token, name: &String, value: &Expr, obj: &Expr) -> DumpResult {
+        self.line(&format!("Set {}", name));
+        self.nested(|d| {
+            d.expr(obj)?;
+            d.expr(value)
+        })
+    }
+
+    fn visit_this(&mut self, _token: &Token, _id: ExprId) -> DumpResult {
+        self.line("This");
+        Ok(())
+    }
+
+    fn visit_super(&mut self, _token: &Token, method_name: &String, _id: ExprId) -> DumpResult {
+        self.line(&format!("Super {}", method_name));
+        Ok(())
+    }
+
+    fn visit_index(&mut self, object: &Expr, index: &Expr, _token: &Token) -> DumpResult {
+        self.line("Index");
+        self.nested(|d| {
+            d.expr(object)?;
+            d.expr(index)
+        })
+    }
+
+    fn visit_set_index(
+        &mut self,
+        object: &Expr,
+        index: &Expr,
+        value: &Expr,
+        _token: &Token,
+    ) -> DumpResult {
+        self.line("SetIndex");
+        self.nested(|d| {
+            d.expr(object)?;
+            d.expr(index)?;
+            d.expr(value)
+        })
+    }
+
+    fn visit_spread(&mut self, expr: &Expr, _token: &Token) -> DumpResult {
+        self.line("Spread");
+        self.nested(|d| d.expr(expr))
+    }
+
+    fn visit_error(&mut self, _token: &Token) -> DumpResult {
+        self.line("Error");
+        Ok(())
+    }
+}
+
+impl StmtVisitor<()> for AstDumper {
+    fn visit_print_stmt(&mut self, expr: &Expr) -> DumpResult {
+        self.line("Print");
+        self.nested(|d| d.expr(expr))
+    }
+
+    fn visit_expr_stmt(&mut self, expr: &Expr) -> DumpResult {
+        self.line("Expression");
+        self.nested(|d| d.expr(expr))
+    }
+
+    fn visit_var(
+        &mut self,
+        name: &String,
+        value: &Option<Expr>,
+        _annotation: &Option<Type>,
+        _span: &Span,
+    ) -> DumpResult {
+        self.line(&format!("VarDecl {}", name));
+        match value {
+            Some(value) => self.nested(|d| d.expr(value)),
+            None => Ok(()),
+        }
+    }
+
+    fn visit_block_stmt(&mut self, stmts: &Vec<Stmt>) -> DumpResult {
+        self.line("Block");
+        self.nested(|d| d.stmts(stmts))
+    }
+
+    fn visit_if_stmt(
+        &mut self,
+        condition: &Expr,
+        then_body: &Stmt,
+        else_body: &Option<Box<Stmt>>,
+    ) -> DumpResult {
+        self.line("If");
+        self.nested(|d| {
+            d.expr(condition)?;
+            d.stmt(then_body)?;
+            match else_body {
+                Some(else_body) => {
+                    d.label("Else");
+                    d.nested(|d| d.stmt(else_body))
+                }
+                None => Ok(()),
+            }
+        })
+    }
+
+    fn visit_while_stmt(
+        &mut self,
+        condition: &Expr,
+        body: &Stmt,
+        increment: &Option<Expr>,
+    ) -> DumpResult {
+        self.line("While");
+        self.nested(|d| {
+            d.expr(condition)?;
+            d.stmt(body)?;
+            match increment {
+                Some(increment) => {
+                    d.label("Increment");
+                    d.nested(|d| d.expr(increment))
+                }
+                None => Ok(()),
+            }
+        })
+    }
+
+    fn visit_do_while_stmt(&mut self, body: &Stmt, condition: &Expr) -> DumpResult {
+        self.line("DoWhile");
+        self.nested(|d| {
+            d.stmt(body)?;
+            d.expr(condition)
+        })
+    }
+
+    fn visit_for_in_stmt(
+        &mut self,
+        name: &String,
+        iterable: &Expr,
+        body: &Stmt,
+        _token: &Token,
+    ) -> DumpResult {
+        self.line(&format!("ForIn {}", name));
+        self.nested(|d| {
+            d.expr(iterable)?;
+            d.stmt(body)
+        })
+    }
+
+    fn visit_break_stmt(&mut self, _token: &Token) -> DumpResult {
+        self.line("Break");
+        Ok(())
+    }
+
+    fn visit_continue_stmt(&mut self, _token: &Token) -> DumpResult {
+        self.line("Continue");
+        Ok(())
+    }
+
+    fn visit_function_stmt(
+        &mut self,
+        name: &String,
+        params: &Rc<Vec<String>>,
+        _signature: &Signature,
+        _defaults: &Rc<Vec<Expr>>,
+        body: &Rc<Vec<Stmt>>,
+        _token: &Token,
+    ) -> DumpResult {
+        self.function("Function", name, params, body)
+    }
+
+    fn visit_class_stmt(
+        &mut self,
+        name: &String,
+        _token: &Token,
+        members: &Vec<Stmt>,
+        superclass: &Option<Expr>,
+    ) -> DumpResult {
+        self.line(&format!("Class {}", name));
+        self.nested(|d| {
+            if let Some(superclass) = superclass {
+                d.label("Superclass");
+                d.nested(|d| d.expr(superclass))?;
+            }
+            d.stmts(members)
+        })
+    }
+
+    fn visit_return_stmt(&mut self, value: &Option<Expr>, _token: &Token) -> DumpResult {
+        self.line("Return");
+        match value {
+            Some(value) => self.nested(|d| d.expr(value)),
+            None => Ok(()),
+        }
+    }
+
+    fn visit_try_stmt(
+        &mut self,
+        body: &[Stmt],
+        catches: &[Catch],
+        finally: &Option<Vec<Stmt>>,
+        _token: &Token,
+    ) -> DumpResult {
+        self.line("Try");
+        self.nested(|d| {
+            d.stmts(body)?;
+            for Catch { name, class, body } in catches {
+                d.label(&format!("Catch {}", name));
+                d.nested(|d| {
+                    if let Some(class) = class {
+                        d.expr(class)?;
+                    }
+                    d.labeled_stmts("Body", body)
+                })?;
+            }
+            match finally {
+                Some(finally) => d.labeled_stmts("Finally", finally),
+                None => Ok(()),
+            }
+        })
+    }
+
+    fn visit_throw_stmt(&mut self, value: &Expr, _token: &Token) -> DumpResult {
+        self.line("Throw");
+        self.nested(|d| d.expr(value))
+    }
+
+    fn visit_match_stmt(&mut self, value: &Expr, arms: &[Arm], _token: &Token) -> DumpResult {
+        self.line("Match");
+        self.nested(|d| {
+            d.expr(value)?;
+            for Arm { pattern, body } in arms {
+                d.label(&format!("Arm {}", AstPrinter.pattern(pattern)?));
+                d.nested(|d| d.stmt(body))?;
+            }
+            Ok(())
+        })
+    }
+
+    fn visit_error_stmt(&mut self, _token: &Token, _end: &Token) -> DumpResult {
+        self.line("Error");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{dump_ast, print_ast, print_partial_ast};
+
+    #[test]
+    fn prints_s_expressions() {
+        let ast = print_ast("print -(1 + 2) * 3 == x and !y;\nvar s = \"a b\";").unwrap();
+        assert_eq!(
+            ast,
+            "(print (and (== (* (- (group (+ 1 2))) 3) x) (! y)))\n(var s \"a b\")\n"
+        );
+        let ast = print_ast(
+            "for (var i = 0; i < 2; i = i + 1) print i;\nclass B : A { var f; m(a) { return super.m(a); } }\ntry { throw E(\"x\", cause: e); } catch (e: E) {}",
+        )
+        .unwrap();
+        assert_eq!(
+            ast,
+            "(block (var i 0) (while (< i 2) (print i) (increment (= i (+ i 1)))))
+(class B (: A) (var f) (fn m (a) (block (return (call (super m) a)))))
+(try (block (throw (call E \"x\" (cause: e)))) (catch e E (block)))
+"
+        );
+    }
+
+    #[test]
+    fn prints_partial_trees() {
+        let (ast, errors) = print_partial_ast(
+            "var a = ;\nprint a +* 2;\nfn f(x) {\n    x = ) 1;\n    return g(x, );\n}\nprint a;",
+        );
+        assert_eq!(
+            ast,
+            "(var a (error))\n(error)\n(fn f (x) (block (error) (return (call g x (error)))))\n(print a)\n"
+        );
+        let codes: Vec<_> = errors.iter().map(|e| e.error_type.code()).collect();
+        assert_eq!(
+            codes,
+            vec![
+                "UnparsableExpression",
+                "UnparsableExpression",
+                "UnparsableExpression",
+                "ExpectedSemicolon",
+                "UnparsableExpression"
+            ]
+        );
+        assert_eq!(errors[1].span.line, 2);
+    }
+
+    #[test]
+    fn dumps_indented_tree_with_spans() {
+        let ast = dump_ast("var s = -1 + x;\nif (s) print s; else { s = f(a, n: 2); }").unwrap();
+        assert_eq!(
+            ast,
+            "VarDecl s 1:1-15
+  Binary + 1:9-14
+    Unary - 1:9-10
+      Literal 1 1:10-10
+    Var x 1:14-14
+If 2:1-40
+  Var s 2:5-5
+  Print 2:8-15
+    Var s 2:14-14
+  Else
+    Block 2:22-40
+      Expression 2:24-38
+        Assign s 2:24-37
+          Call 2:28-37
+            Var f 2:28-28
+            Var a 2:30-30
+            n:
+              Literal 2 2:36-36
+"
+        );
+    }
+}
@@ -0,0 +1,32 @@
+use crate::error::ErrorType;
+use crate::runtime_value::Value;
+
+/// Single call from a script to a native function
+#[derive(Debug, Clone)]
+pub struct AuditEntry {
+    pub native: String,
+    pub arguments: Vec<Value>,
+    pub result: Result<Value, ErrorType>,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interpreter::Interpreter;
+
+    #[test]
+    fn audit_log_records_native_calls() {
+        let mut interpreter = Interpreter::new();
+        interpreter.run("clock();").unwrap();
+        assert!(interpreter.audit_log().is_empty());
+
+        interpreter.enable_audit_log();
+        interpreter
+            .run("fn measure() { return clock(); } measure(); clock();")
+            .unwrap();
+        let log = interpreter.audit_log();
+        assert_eq!(log.len(), 2);
+        assert!(log.iter().all(|entry| entry.native == "clock"));
+        assert!(log[0].arguments.is_empty());
+        assert!(log[0].result.as_ref().unwrap().as_number().is_some());
+    }
+}
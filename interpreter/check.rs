@@ -0,0 +1,134 @@
+use crate::fixes::{diagnose, Diagnostic};
+use std::fmt;
+
+/// What `diagnose` found in one file
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileCheck {
+    pub path: String,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl FileCheck {
+    pub fn errors(&self) -> usize {
+        self.diagnostics.iter().filter(|d| !d.warning).count()
+    }
+
+    pub fn warnings(&self) -> usize {
+        self.diagnostics.iter().filter(|d| d.warning).count()
+    }
+}
+
+/// Diagnostics of every file of a project, printed file by file and summed up in a table
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ProjectCheck {
+    pub files: Vec<FileCheck>,
+}
+
+impl ProjectCheck {
+    pub fn errors(&self) -> usize {
+        self.files.iter().map(FileCheck::errors).sum()
+    }
+
+    pub fn warnings(&self) -> usize {
+        self.files.iter().map(FileCheck::warnings).sum()
+    }
+}
+
+/// Lexes, parses and resolves every file without running any of them.
+/// Files are given as their path and source code.
+pub fn check_project(sources: &[(String, String)]) -> ProjectCheck {
+    let files = sources
+        .iter()
+        .map(|(path, source_code)| {
+            let mut diagnostics = diagnose(source_code);
+            diagnostics.sort_by_key(|d| (d.span.line, d.span.start));
+            FileCheck {
+                path: path.clone(),
+                diagnostics,
+            }
+        })
+        .collect();
+    ProjectCheck { files }
+}
+
+impl fmt::Display for ProjectCheck {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for file in &self.files {
+            for diagnostic in &file.diagnostics {
+                let severity = if diagnostic.warning {
+                    "warning"
+                } else {
+                    "error"
+                };
+                writeln!(
+                    f,
+                    "{}:{}:{}: {}: {} [{}]",
+                    file.path,
+                    diagnostic.span.line,
+                    diagnostic.span.start,
+                    severity,
+                    diagnostic.message,
+                    diagnostic.code
+                )?;
+            }
+        }
+        if self.files.iter().any(|file| !file.diagnostics.is_empty()) {
+            writeln!(f)?;
+        }
+
+        let width = self
+            .files
+            .iter()
+            .map(|file| file.path.chars().count())
+            .chain(Some("Total".len()))
+            .max()
+            .unwrap_or_default();
+        writeln!(f, "{:width$}  {:>6}  {:>8}", "File", "Errors", "Warnings")?;
+        for file in &self.files {
+            writeln!(
+                f,
+                "{:width$}  {:>6}  {:>8}",
+                file.path,
+                file.errors(),
+                file.warnings()
+            )?;
+        }
+        write!(
+            f,
+            "{:width$}  {:>6}  {:>8}",
+            "Total",
+            self.errors(),
+            self.warnings()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::check_project;
+
+    #[test]
+    fn checks_every_file_and_sums_up() {
+        let sources = vec![
+            (String::from("ok.lox"), String::from("print 1;")),
+            (
+                String::from("lib/broken.lox"),
+                String::from("var a = 1\nreturn a;\nif (a = 2) print a;"),
+            ),
+        ];
+        let check = check_project(&sources);
+        assert_eq!(check.errors(), 2);
+        assert_eq!(check.warnings(), 1);
+        assert_eq!(
+            check.to_string(),
+            "lib/broken.lox:2:1: error: Expected semicolon [ExpectedSemicolon]
+lib/broken.lox:2:1: error: Can't return from top-level code [CantReturnOutsideFunction]
+lib/broken.lox:3:5: warning: Assignment used as a condition, comparisons use == [AssignmentInCondition]
+
+File            Errors  Warnings
+ok.lox               0         0
+lib/broken.lox       2         1
+Total                2         1"
+        );
+    }
+}
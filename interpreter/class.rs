@@ -1,60 +1,69 @@
+use crate::environment::{Environment, SlotTable};
 use crate::error::{error, Error, ErrorType};
 use crate::expr::Expr;
 use crate::function::{Callable, Function};
 use crate::interpreter::Interpreter;
 use crate::runtime_value::Value;
 use crate::statement::Stmt;
-use crate::token::Literal;
 use crate::token::Token;
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::rc::Rc;
 
 type Properties = HashMap<String, Value>;
+/// Declared fields and the expressions giving their values, fields without one start as null
+type Fields = Vec<(String, Option<Expr>)>;
 type Methods = HashMap<String, Function>;
-type Superclass = Option<Box<Class>>;
+type Superclass = Option<Rc<Class>>;
 
-#[derive(Debug, Clone)]
+/// Classes are shared through Rc, instances and subclasses point to the same one
+#[derive(Debug)]
 pub struct Class {
     name: String,
-    properties: Properties,
+    fields: Fields,
     methods: Methods,
     superclass: Superclass,
+    /// Scope the class is declared in, field values are evaluated there for every new instance
+    scope: Rc<RefCell<Environment>>,
+    /// Slots of the program declaring the class, for its field values and methods
+    slots: SlotTable,
 }
 
 impl Class {
     pub fn new(
         name: &String,
         members: &Vec<Stmt>,
-        superclass: Option<Box<Class>>,
-        interpreter: &mut Interpreter,
-    ) -> Result<Self, Error> {
-        let mut properties: HashMap<String, Value> = HashMap::new();
+        superclass: Superclass,
+        closure: Rc<RefCell<Environment>>,
+        scope: Rc<RefCell<Environment>>,
+        slots: &SlotTable,
+    ) -> Self {
+        let mut fields = Vec::new();
         let mut methods: HashMap<String, Function> = HashMap::new();
 
         for member in members {
             match member {
-                Stmt::Var { name, value } => {
-                    let val = value.clone().unwrap_or(Expr::Literal {
-                        value: Literal::Null,
-                    });
-                    properties.insert(name.clone(), interpreter.evaluate(&val)?);
-                }
+                Stmt::Var { name, value, .. } => fields.push((name.clone(), value.clone())),
                 Stmt::Function {
                     name,
                     token,
                     body,
                     params,
+                    defaults,
+                    signature,
+                    ..
                 } => {
                     methods.insert(
                         name.clone(),
                         Function::Standard {
-                            params: params.clone(),
-                            body: body.clone(),
+                            params: Rc::clone(params),
+                            defaults: Rc::clone(defaults),
+                            variadic: signature.variadic,
+                            body: Rc::clone(body),
                             name: name.clone(),
                             token: token.clone(),
-                            this: None,
-                            closure: Rc::clone(&interpreter.env),
+                            closure: Rc::clone(&closure),
+                            slots: Rc::clone(slots),
                         },
                     );
                 }
@@ -62,73 +71,178 @@ impl Class {
             }
         }
 
-        Ok(Class {
+        Class {
             name: name.clone(),
-            properties,
+            fields,
             methods,
             superclass,
-        })
+            scope,
+            slots: Rc::clone(slots),
+        }
+    }
+
+    /// Class put back together from its parts, e.g. when restoring a snapshot
+    pub(crate) fn from_parts(
+        name: String,
+        fields: Fields,
+        methods: Methods,
+        superclass: Superclass,
+        scope: Rc<RefCell<Environment>>,
+        slots: SlotTable,
+    ) -> Self {
+        Class {
+            name,
+            fields,
+            methods,
+            superclass,
+            scope,
+            slots,
+        }
     }
 
     pub fn to_string(&self) -> String {
         self.name.clone()
     }
 
+    /// Fields declared by this class, without the inherited ones
+    pub fn fields(&self) -> impl Iterator<Item = (&String, Option<&Expr>)> {
+        self.fields
+            .iter()
+            .map(|(name, value)| (name, value.as_ref()))
+    }
+
+    pub(crate) fn scope(&self) -> &Rc<RefCell<Environment>> {
+        &self.scope
+    }
+
+    pub(crate) fn slots(&self) -> &SlotTable {
+        &self.slots
+    }
+
+    /// Evaluates the values of the fields for a new instance, the ones of superclasses first
+    fn initialize_fields(
+        &self,
+        interpreter: &mut Interpreter,
+        properties: &mut Properties,
+    ) -> Result<(), Error> {
+        if let Some(superclass) = &self.superclass {
+            superclass.initialize_fields(interpreter, properties)?;
+        }
+        for (name, value) in &self.fields {
+            let value = match value {
+                Some(value) => interpreter.with_slots(&self.slots, |interpreter| {
+                    interpreter.evaluate_in(value, Rc::clone(&self.scope))
+                })?,
+                None => Value::Null,
+            };
+            properties.insert(name.clone(), value);
+        }
+        Ok(())
+    }
+
+    pub fn is_same(&self, other: &Class) -> bool {
+        std::ptr::eq(self, other)
+    }
+
+    /// Whether this class is the other one or inherits from it
+    pub fn is_subclass_of(&self, other: &Class) -> bool {
+        self.is_same(other)
+            || self
+                .superclass
+                .as_ref()
+                .is_some_and(|sc| sc.is_subclass_of(other))
+    }
+
+    pub fn superclass(&self) -> Option<&Rc<Class>> {
+        self.superclass.as_ref()
+    }
+
+    pub fn methods(&self) -> impl Iterator<Item = (&String, &Function)> {
+        self.methods.iter()
+    }
+
     pub fn find_method(&self, name: &String) -> Option<&Function> {
         self.methods.get(name).or_else(|| match &self.superclass {
-            Some(sc) => sc.find_method(name).clone(),
+            Some(sc) => sc.find_method(name),
             _ => None,
         })
     }
 }
 
-impl Callable for Class {
+impl Callable for Rc<Class> {
     fn arity(&self) -> usize {
         0
     }
 
-    fn call(&self, interpreter: &mut Interpreter, arguments: &Vec<Value>) -> Result<Value, Error> {
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: &Vec<Value>,
+        token: &Token,
+    ) -> Result<Value, Error> {
+        let mut properties = HashMap::new();
+        self.initialize_fields(interpreter, &mut properties)?;
         let instance = Rc::new(RefCell::new(Instance {
-            class: self.clone(),
-            properties: self.properties.clone(),
+            class: Rc::clone(self),
+            properties,
         }));
 
-        if let Some(constructor) = self.methods.get("constructor") {
+        let constructor_name = interpreter.dialect().constructor_name().to_owned();
+        if let Some(constructor) = self.find_method(&constructor_name) {
             constructor
                 .clone()
                 .bind(Rc::clone(&instance))
-                .call(interpreter, arguments)?;
+                .call(interpreter, arguments, token)?;
         }
 
         Ok(Value::Instance(instance))
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Debug)]
 pub struct Instance {
-    class: Class,
+    class: Rc<Class>,
     properties: Properties,
 }
 
 impl Instance {
+    /// Instance of the class with the given fields, no constructor runs
+    pub(crate) fn with_fields(class: Rc<Class>, properties: Properties) -> Self {
+        Instance { class, properties }
+    }
+
     pub fn to_string(&self) -> String {
         format!("{} instance", self.class.name)
     }
 
-    pub fn get_super(&self) -> Option<Class> {
-        self.class.superclass.as_ref().map(|v| *v.clone())
+    pub fn class(&self) -> &Rc<Class> {
+        &self.class
     }
 
-    pub fn get(&self, name: &String, token: &Token) -> Result<Value, Error> {
-        self.properties.get(name).map_or_else(
+    pub fn fields(&self) -> impl Iterator<Item = (&String, &Value)> {
+        self.properties.iter()
+    }
+
+    pub fn field(&self, name: &str) -> Option<&Value> {
+        self.properties.get(name)
+    }
+
+    pub fn get_super(&self) -> Option<Rc<Class>> {
+        self.class.superclass.clone()
+    }
+
+    /// Methods are bound to the given Rc, so `this` inside them is the very same instance
+    pub fn get(
+        instance: &Rc<RefCell<Instance>>,
+        name: &String,
+        token: &Token,
+    ) -> Result<Value, Error> {
+        let this = instance.borrow();
+        this.properties.get(name).map_or_else(
             || {
-                self.class.find_method(name).map_or_else(
+                this.class.find_method(name).map_or_else(
                     || error(token, ErrorType::PropertyDoesntExist),
-                    |fun| {
-                        Ok(Value::Function(
-                            fun.clone().bind(Rc::new(RefCell::new(self.clone()))),
-                        ))
-                    },
+                    |fun| Ok(Value::Function(fun.clone().bind(Rc::clone(instance)))),
                 )
             },
             |val| Ok(val.clone()),
@@ -138,4 +252,65 @@ impl Instance {
     pub fn set(&mut self, name: &String, _token: &Token, value: Value) {
         self.properties.insert(name.clone(), value);
     }
+
+    pub(crate) fn set_field(&mut self, name: &str, value: Value) {
+        self.properties.insert(name.to_owned(), value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interpreter::tests::{assert_true, evaluate};
+    use crate::interpreter::Interpreter;
+    use std::rc::Rc;
+
+    #[test]
+    fn instances_share_their_class() {
+        let mut interpreter = Interpreter::new();
+        interpreter
+            .run("class A { method() {} } class B : A {} var a = A(); var b = B();")
+            .unwrap();
+        let get = |name| interpreter.env.borrow().get_deep(name).unwrap();
+
+        let class = get("A").into_class().unwrap();
+        let instance = get("a").into_instance().unwrap();
+        assert!(Rc::ptr_eq(&class, instance.borrow().class()));
+
+        let subclass = get("b").into_instance().unwrap();
+        let superclass = subclass.borrow().get_super().unwrap();
+        assert!(Rc::ptr_eq(&class, &superclass));
+    }
+
+    #[test]
+    fn methods_mutate_the_instance() {
+        let code = "
+            class Counter {
+                var count = 0;
+                increment() { this.count = this.count + 1; return this; }
+            }
+            var counter = Counter();
+            counter.increment();
+            var increment = counter.increment;
+            increment();
+            counter.increment().increment();
+            counter.count;
+        ";
+        assert_eq!(evaluate(code).unwrap().as_number(), Some(&4.0));
+        assert_true("class A { self() { return this; } } var a = A(); a.self() == a;");
+    }
+
+    #[test]
+    fn every_instance_gets_its_own_field_values() {
+        assert_true(
+            "class Bag { var items = list(); }\n\
+             var a = Bag(); var b = Bag(); a.items.push(1);\n\
+             a.items.len() == 1 and b.items.len() == 0;",
+        );
+        assert_true(
+            "var made = 0; fn count() { made = made + 1; return made; }\n\
+             class A { var id = count(); } class B : A { var tag = count(); }\n\
+             var first = B(); var second = B();\n\
+             first.id == 1 and first.tag == 2 and second.id == 3 and second.tag == 4;",
+        );
+    }
 }
@@ -0,0 +1,242 @@
+use crate::error::{Error, ErrorType};
+use crate::features::{Features, LanguageVersion};
+use crate::function::Function;
+use crate::interpreter::Interpreter;
+use crate::lexer::Lexer;
+use crate::parser::Parser;
+use crate::resolver::Resolver;
+use crate::runtime_value::Value;
+use crate::token::TokenType;
+
+/// Flavour of Lox the interpreter speaks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Dialect {
+    /// This crate's Lox, with its extensions
+    #[default]
+    LoxRs,
+    /// The canonical Lox of the book's Java interpreter: `fun`, `nil`, `init` and `class B < A`,
+    /// none of the extensions, and jlox's output and error messages
+    Jlox,
+}
+
+impl Dialect {
+    pub fn features(self) -> Features {
+        match self {
+            Dialect::LoxRs => Features::all(),
+            Dialect::Jlox => LanguageVersion::Inheritance.features(),
+        }
+    }
+
+    /// Name of the method that initializes new instances
+    pub fn constructor_name(self) -> &'static str {
+        match self {
+            Dialect::LoxRs => "constructor",
+            Dialect::Jlox => "init",
+        }
+    }
+}
+
+/// Why a program run the way jlox runs it failed, the exit codes are the ones jlox uses
+#[derive(Debug, Clone, PartialEq)]
+pub enum JloxFailure {
+    /// Errors found before running, one `[line N] Error at 'x': message` line each
+    Compile(Vec<String>),
+    /// `message\n[line N]`
+    Runtime(String),
+}
+
+impl JloxFailure {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            JloxFailure::Compile(_) => 65,
+            JloxFailure::Runtime(_) => 70,
+        }
+    }
+}
+
+/// Runs the program in the jlox dialect, reporting errors like jlox does
+pub fn run_jlox(interpreter: &mut Interpreter, source_code: &str) -> Result<(), JloxFailure> {
+    interpreter.set_dialect(Dialect::Jlox);
+    let compile_errors = |errors: Vec<Error>| {
+        JloxFailure::Compile(
+            errors
+                .iter()
+                .map(|e| compile_error(e, source_code))
+                .collect(),
+        )
+    };
+    let tokens = Lexer::with_dialect(source_code, Dialect::Jlox)
+        .scan_tokens()
+        .map_err(compile_errors)?;
+    let stmts = Parser::with_features(&tokens, Dialect::Jlox.features())
+        .parse_tokens()
+        .map_err(compile_errors)?;
+    Resolver::new(interpreter)
+        .resolve(&stmts)
+        .map_err(compile_errors)?;
+    interpreter
+        .run_statements(&stmts)
+        .map(|_| ())
+        .map_err(|e| JloxFailure::Runtime(format!("{}\n[line {}]", message(&e), e.token.line)))
+}
+
+/// jlox shows nil as `nil` and functions as `<fn name>`
+pub fn jlox_string(value: &Value) -> String {
+    match value {
+        Value::Null => String::from("nil"),
+        Value::Function(Function::Native { .. }) => String::from("<native fn>"),
+        Value::Function(Function::Standard { name, .. }) => format!("<fn {}>", name),
+        value => value.to_string(),
+    }
+}
+
+fn compile_error(e: &Error, source_code: &str) -> String {
+    let location = match &e.token.token_type {
+        TokenType::EOF => String::from(" at end"),
+        // the lexer has no token to point at
+        TokenType::Invalid => String::new(),
+        _ => {
+            let line = source_code.lines().nth(e.span.line.wrapping_sub(1));
+            let lexeme: String = line
+                .unwrap_or("")
+                .chars()
+                .skip(e.span.start.saturating_sub(1))
+                .take(e.span.end + 1 - e.span.start.max(1))
+                .collect();
+            format!(" at '{}'", lexeme)
+        }
+    };
+    format!("[line {}] Error{}: {}", e.token.line, location, message(e))
+}
+
+/// jlox's wording of the error, errors jlox doesn't have keep this crate's message
+fn message(e: &Error) -> String {
+    let name = || {
+        e.token
+            .token_type
+            .as_identifier()
+            .cloned()
+            .unwrap_or_default()
+    };
+    match &e.error_type {
+        ErrorType::WrongType => match e.token.token_type {
+            TokenType::Plus => String::from("Operands must be two numbers or two strings."),
+            // unary operators point only at themselves
+            TokenType::Minus if e.span == e.token.span() => {
+                String::from("Operand must be a number.")
+            }
+            _ => String::from("Operands must be numbers."),
+        },
+        ErrorType::UndefinedVariable => format!("Undefined variable '{}'.", name()),
+        ErrorType::PropertyDoesntExist | ErrorType::MethodNotFound => {
+            format!("Undefined property '{}'.", name())
+        }
+        ErrorType::ValueNotCallable => String::from("Can only call functions and classes."),
+        ErrorType::ValueNotInstance => String::from("Only instances have properties."),
+        ErrorType::CanOnlyInheritFromClass => String::from("Superclass must be a class."),
+        ErrorType::CantInheritFromItself => String::from("A class can't inherit from itself."),
+        ErrorType::CantUseVariableInItsInitializer => {
+            String::from("Can't read local variable in its own initializer.")
+        }
+        ErrorType::CantReturnOutsideFunction => String::from("Can't return from top-level code."),
+        ErrorType::CantReturnValueFromConstructor => {
+            String::from("Can't return a value from an initializer.")
+        }
+        ErrorType::CantUseThis => String::from("Can't use 'this' outside of a class."),
+        ErrorType::CantUseSuper => String::from("Can't use 'super' outside of a class."),
+        ErrorType::MaximumArguments => String::from("Can't have more than 255 arguments."),
+        ErrorType::UnexpectedCharacter => String::from("Unexpected character."),
+        ErrorType::ExpectedSemicolon => String::from("Expect ';' after expression."),
+        ErrorType::ExpectedIdentifier => String::from("Expect variable name."),
+        ErrorType::InvalidAssignment => String::from("Invalid assignment target."),
+        ErrorType::StackOverflow => String::from("Stack overflow."),
+        error_type => format!("{}.", error_type),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{run_jlox, JloxFailure};
+    use crate::interpreter::Interpreter;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn run(source: &str) -> (Vec<String>, Result<(), JloxFailure>) {
+        let printed = Rc::new(RefCell::new(Vec::new()));
+        let output = Rc::clone(&printed);
+        let mut interpreter = Interpreter::new();
+        interpreter.set_output(move |line| output.borrow_mut().push(line.to_owned()));
+        let result = run_jlox(&mut interpreter, source);
+        let printed = printed.borrow().clone();
+        (printed, result)
+    }
+
+    #[test]
+    fn runs_jlox_programs() {
+        let (printed, result) = run("class A {\n  init(x) { this.x = x; }\n  get() { return this.x; }\n}\n\
+             class B < A {}\nfun f() { 1; }\nprint f();\nprint B(1).get();\nprint nil;\nprint f;\nprint f == f;\nprint clock;\nprint B(2);");
+        assert_eq!(result, Ok(()));
+        assert_eq!(
+            printed,
+            vec![
+                "nil",
+                "1",
+                "nil",
+                "<fn f>",
+                "true",
+                "<native fn>",
+                "B instance"
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_errors_like_jlox() {
+        let (printed, result) = run("print 1;\nprint -\"a\";");
+        assert_eq!(printed, vec!["1"]);
+        assert_eq!(
+            result,
+            Err(JloxFailure::Runtime(String::from(
+                "Operand must be a number.\n[line 2]"
+            )))
+        );
+        assert_eq!(
+            run("print \"a\" < \"b\";").1,
+            Err(JloxFailure::Runtime(String::from(
+                "Operands must be numbers.\n[line 1]"
+            )))
+        );
+        assert_eq!(run("print undefined;").1.unwrap_err().exit_code(), 70);
+        assert_eq!(
+            run("var a = 1 % 2;").1,
+            Err(JloxFailure::Compile(vec![String::from(
+                "[line 1] Error: Unexpected character."
+            )]))
+        );
+        assert_eq!(
+            run("class A {\n  var x;\n}").1,
+            Err(JloxFailure::Compile(vec![String::from(
+                "[line 2] Error at 'var': class fields are not enabled in this version of the language."
+            )]))
+        );
+        assert_eq!(
+            run("class A { add(b) { return 1; } }\nprint A() + A();").1,
+            Err(JloxFailure::Runtime(String::from(
+                "Operands must be two numbers or two strings.\n[line 2]"
+            )))
+        );
+        assert_eq!(
+            run("fun f(a = 1) {}").1,
+            Err(JloxFailure::Compile(vec![String::from(
+                "[line 1] Error at '=': default parameter values are not enabled in this version of the language."
+            )]))
+        );
+        // jlox has no break, so it's just an undefined variable
+        assert_eq!(
+            run("break;").1,
+            Err(JloxFailure::Runtime(String::from(
+                "Undefined variable 'break'.\n[line 1]"
+            )))
+        );
+    }
+}
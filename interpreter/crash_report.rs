@@ -0,0 +1,94 @@
+use crate::ast::print_ast;
+use crate::error::Error;
+use crate::lexer::Lexer;
+use std::any::Any;
+use std::fs::write;
+use std::io;
+
+/// Everything a maintainer needs to reproduce a crash of the interpreter, rendered as one text file
+pub struct CrashReport<'a> {
+    pub source: &'a str,
+    /// Command line options the interpreter ran with
+    pub options: &'a [String],
+    /// Message of the panic
+    pub failure: String,
+}
+
+impl<'a> CrashReport<'a> {
+    pub fn new(source: &'a str, options: &'a [String], panic: &(dyn Any + Send)) -> Self {
+        let failure = panic
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| panic.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| String::from("panic without a message"));
+        CrashReport {
+            source,
+            options,
+            failure,
+        }
+    }
+
+    pub fn render(&self) -> String {
+        let mut report = format!("lox-rs {} crash report\n", env!("CARGO_PKG_VERSION"));
+        section(&mut report, "failure", &self.failure);
+        section(&mut report, "options", &self.options.join(" "));
+        section(&mut report, "source", self.source);
+        // the dumps run in the reporting process, they may fail the same way the interpreter did
+        section(&mut report, "tokens", &shielded(|| tokens(self.source)));
+        section(
+            &mut report,
+            "ast",
+            &shielded(|| print_ast(self.source).unwrap_or_else(|e| diagnostics(&e))),
+        );
+        report
+    }
+
+    pub fn write(&self, path: &str) -> io::Result<()> {
+        write(path, self.render())
+    }
+}
+
+fn section(report: &mut String, name: &str, content: &str) {
+    report.push_str(&format!("\n== {} ==\n{}", name, content));
+    if !content.ends_with('\n') {
+        report.push('\n');
+    }
+}
+
+fn tokens(source: &str) -> String {
+    match Lexer::new(source).scan_tokens() {
+        Ok(tokens) => tokens.iter().map(|t| format!("{:?}\n", t)).collect(),
+        Err(errors) => diagnostics(&errors),
+    }
+}
+
+fn diagnostics(errors: &[Error]) -> String {
+    errors.iter().map(|e| format!("{}\n", e)).collect()
+}
+
+fn shielded(dump: impl FnOnce() -> String) -> String {
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(dump))
+        .unwrap_or_else(|_| String::from("<panicked>"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CrashReport;
+
+    #[test]
+    fn report_has_every_section() {
+        let options = vec![String::from("--strict")];
+        let panic: Box<dyn std::any::Any + Send> = Box::new("index out of bounds");
+        let report = CrashReport::new("print 1 +;", &options, panic.as_ref()).render();
+        for section in &["failure", "options", "source", "tokens", "ast"] {
+            assert!(
+                report.contains(&format!("== {} ==", section)),
+                "{}",
+                section
+            );
+        }
+        assert!(report.contains("index out of bounds"));
+        assert!(report.contains("--strict"));
+        assert!(report.contains("Number(1.0)"));
+    }
+}
@@ -0,0 +1,618 @@
+use crate::environment::Environment;
+use crate::error::Error;
+use crate::interpreter::Interpreter;
+use crate::runtime_value::Value;
+use crate::token::Span;
+use crate::utils::{print, PrintType};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, Write};
+use std::rc::Rc;
+
+/// Gets called by the interpreter before it runs anything, see `Interpreter::set_debugger`.
+/// Which of the calls it stops at decides how fine grained stepping is, see `Frame::kind`.
+pub trait Debugger {
+    /// Blocks aren't reported, execution pauses at the statements inside of them instead
+    fn before_statement(&mut self, frame: &mut Frame);
+
+    fn before_expression(&mut self, _frame: &mut Frame) {}
+
+    /// Called with the value the expression evaluated to, expressions that fail skip it
+    fn after_expression(&mut self, _frame: &mut Frame, _value: &Value) {}
+
+    /// Called once the callee and its arguments are evaluated
+    fn before_call(&mut self, _frame: &mut Frame) {}
+
+    /// Called at the statement an uncaught runtime error was raised in, before its calls return.
+    /// The program stops with the error once this returns.
+    fn on_error(&mut self, _frame: &mut Frame, _error: &Error) {}
+}
+
+/// How much code runs between two pauses when stepping
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Granularity {
+    Statement,
+    /// Every expression, including the ones desugared constructs hide in a single statement, like for loop increments
+    Expression,
+    Call,
+}
+
+/// Pauses before statements on a line. With a condition, only when the Lox expression is truthy
+/// in the scope of the statement. With a hit count, only once it was reached that many times.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Breakpoint {
+    pub condition: Option<String>,
+    pub hit_count: Option<usize>,
+}
+
+#[derive(Debug, Clone)]
+struct LineBreakpoint {
+    breakpoint: Breakpoint,
+    /// Times it was reached with its condition holding
+    hits: usize,
+}
+
+/// Lines the debugger wants to pause at
+#[derive(Debug, Clone, Default)]
+pub struct Breakpoints {
+    lines: BTreeMap<usize, LineBreakpoint>,
+    /// Removed once hit, used to run to a cursor
+    temporary: Option<Span>,
+}
+
+impl Breakpoints {
+    pub fn add(&mut self, line: usize) {
+        self.set(line, Breakpoint::default());
+    }
+
+    /// Replaces the breakpoint on the line, its hits are counted from zero again
+    pub fn set(&mut self, line: usize, breakpoint: Breakpoint) {
+        self.lines.insert(
+            line,
+            LineBreakpoint {
+                breakpoint,
+                hits: 0,
+            },
+        );
+    }
+
+    /// Whether there was a breakpoint on the line
+    pub fn remove(&mut self, line: usize) -> bool {
+        self.lines.remove(&line).is_some()
+    }
+
+    pub fn contains(&self, line: usize) -> bool {
+        self.lines.contains_key(&line)
+    }
+
+    pub fn get(&self, line: usize) -> Option<&Breakpoint> {
+        self.lines.get(&line).map(|l| &l.breakpoint)
+    }
+
+    /// Lines with a breakpoint, in ascending order
+    pub fn lines(&self) -> impl Iterator<Item = usize> + '_ {
+        self.lines.keys().copied()
+    }
+
+    /// Pauses once at the first statement, expression or call starting inside the span
+    pub fn run_to(&mut self, span: Span) {
+        self.temporary = Some(span);
+    }
+
+    pub(crate) fn hit_temporary(&mut self, span: Span) -> bool {
+        let hit = self
+            .temporary
+            .is_some_and(|t| t.line == span.line && t.start <= span.start && span.start <= t.end);
+        if hit {
+            self.temporary = None;
+        }
+        hit
+    }
+
+    /// Counts a hit of the breakpoint whose condition holds, true once it reached its hit count
+    pub(crate) fn count_hit(&mut self, line: usize) -> bool {
+        match self.lines.get_mut(&line) {
+            Some(line) => {
+                line.hits += 1;
+                line.hits >= line.breakpoint.hit_count.unwrap_or(1)
+            }
+            None => false,
+        }
+    }
+}
+
+/// Function call in progress, scopes of the callers stay reachable through it
+pub(crate) struct ActiveCall {
+    pub function: String,
+    pub line: usize,
+    pub caller_env: Rc<RefCell<Environment>>,
+}
+
+/// Function running on the call stack, and the line it's at
+#[derive(Debug, Clone, PartialEq)]
+pub struct StackFrame {
+    pub function: String,
+    pub line: usize,
+}
+
+/// State of the program at the point where the interpreter is about to continue
+pub struct Frame<'a> {
+    /// Source of the statement, expression or call about to run
+    pub span: Span,
+    /// Which of the debugger's callbacks got the frame
+    pub kind: Granularity,
+    is_breakpoint: bool,
+    interpreter: &'a mut Interpreter,
+}
+
+impl<'a> Frame<'a> {
+    pub(crate) fn new(
+        span: Span,
+        kind: Granularity,
+        is_breakpoint: bool,
+        interpreter: &'a mut Interpreter,
+    ) -> Self {
+        Frame {
+            span,
+            kind,
+            is_breakpoint,
+            interpreter,
+        }
+    }
+
+    /// Value of the variable as seen from the current scope
+    pub fn get(&self, name: &str) -> Option<Value> {
+        self.interpreter.env.borrow().lookup(name)
+    }
+
+    pub fn is_breakpoint(&self) -> bool {
+        self.is_breakpoint
+    }
+
+    /// Breakpoints can be changed while the program is paused
+    pub fn breakpoints(&mut self) -> &mut Breakpoints {
+        self.interpreter.breakpoints()
+    }
+
+    /// The paused function first, then the ones that called it, down to the top-level code
+    pub fn call_stack(&self) -> Vec<StackFrame> {
+        let calls = self.interpreter.active_calls();
+        let mut line = self.span.line;
+        let mut stack = Vec::new();
+        for call in calls.iter().rev() {
+            stack.push(StackFrame {
+                function: call.function.clone(),
+                line,
+            });
+            line = call.line;
+        }
+        stack.push(StackFrame {
+            function: String::from("<script>"),
+            line,
+        });
+        stack
+    }
+
+    /// Evaluates the expression in the scope of a function on the call stack, 0 being the paused one.
+    /// Assignments change the program's variables. None if the call stack isn't that deep.
+    pub fn evaluate(
+        &mut self,
+        depth: usize,
+        expression: &str,
+    ) -> Option<Result<Value, Vec<Error>>> {
+        let scope = self.interpreter.scope_at(depth)?;
+        Some(self.interpreter.evaluate_in_scope(expression, scope))
+    }
+}
+
+/// Debugger driven by commands typed into stdin
+pub struct CliDebugger {
+    source_lines: Vec<String>,
+    stepping: bool,
+    granularity: Granularity,
+    /// Depth on the call stack `print` evaluates at, 0 being the paused function
+    selected_frame: usize,
+}
+
+impl CliDebugger {
+    /// Pauses before the first statement
+    pub fn new(source_code: &str) -> Self {
+        CliDebugger {
+            source_lines: source_code.lines().map(String::from).collect(),
+            stepping: true,
+            granularity: Granularity::Statement,
+            selected_frame: 0,
+        }
+    }
+
+    /// Runs the program until it hits a breakpoint or fails with an uncaught error
+    pub fn post_mortem(source_code: &str) -> Self {
+        CliDebugger {
+            stepping: false,
+            ..CliDebugger::new(source_code)
+        }
+    }
+
+    fn pause(&mut self, frame: &mut Frame) {
+        if frame.is_breakpoint() || (self.stepping && frame.kind == self.granularity) {
+            self.selected_frame = 0;
+            self.show(frame.span);
+            self.prompt(frame);
+        }
+    }
+
+    /// Prints the line, with the part about to run underlined when it isn't the whole statement
+    fn show(&self, span: Span) {
+        let source = self
+            .source_lines
+            .get(span.line.wrapping_sub(1))
+            .map_or("", String::as_str);
+        println!("{:>4} | {}", span.line, source);
+        if self.granularity != Granularity::Statement {
+            let width = (span.end + 1).saturating_sub(span.start).max(1);
+            println!(
+                "     | {}{}",
+                " ".repeat(span.start.saturating_sub(1)),
+                "^".repeat(width)
+            );
+        }
+    }
+
+    /// Reads commands until one of them resumes the program
+    fn prompt(&mut self, frame: &mut Frame) {
+        let stdin = io::stdin();
+        loop {
+            print!("(debug) ");
+            io::stdout().flush().ok();
+            let mut command = String::new();
+            match stdin.lock().read_line(&mut command) {
+                // without input there is nobody to step through the program
+                Ok(0) | Err(_) => {
+                    self.stepping = false;
+                    return;
+                }
+                Ok(_) => (),
+            }
+
+            let mut words = command.split_whitespace();
+            match (words.next(), words.next()) {
+                (Some("step") | Some("s"), granularity) => {
+                    self.granularity = match granularity {
+                        None => self.granularity,
+                        Some("statement") => Granularity::Statement,
+                        Some("expression") => Granularity::Expression,
+                        Some("call") => Granularity::Call,
+                        Some(_) => {
+                            print("Steps are statement, expression or call", PrintType::Error);
+                            continue;
+                        }
+                    };
+                    self.stepping = true;
+                    return;
+                }
+                (Some("until") | Some("u"), Some(cursor)) => match parse_cursor(cursor) {
+                    Some(span) => {
+                        frame.breakpoints().run_to(span);
+                        self.stepping = false;
+                        return;
+                    }
+                    None => print("Expected <line> or <line>:<column>", PrintType::Error),
+                },
+                (Some("continue") | Some("c"), None) => {
+                    self.stepping = false;
+                    return;
+                }
+                (Some("break") | Some("b"), Some(_)) => match parse_breakpoint(&command) {
+                    Some((line, breakpoint)) => frame.breakpoints().set(line, breakpoint),
+                    None => print(
+                        "Expected <line> [after <hits>] [if <condition>]",
+                        PrintType::Error,
+                    ),
+                },
+                (Some("delete") | Some("d"), Some(line)) => {
+                    let removed = line.parse().is_ok_and(|l| frame.breakpoints().remove(l));
+                    if !removed {
+                        print("There is no breakpoint on this line", PrintType::Error);
+                    }
+                }
+                (Some("print") | Some("p"), Some(_)) => {
+                    let expression = command.trim_start().split_once(' ').map_or("", |(_, e)| e);
+                    match frame.evaluate(self.selected_frame, expression) {
+                        Some(Ok(value)) => println!("{}", value),
+                        Some(Err(errors)) => errors
+                            .iter()
+                            .for_each(|e| print(&e.to_string(), PrintType::Error)),
+                        None => print("The selected frame has returned", PrintType::Error),
+                    }
+                }
+                (Some("backtrace") | Some("bt"), None) => {
+                    for (depth, stack_frame) in frame.call_stack().iter().enumerate() {
+                        let marker = if depth == self.selected_frame { '*' } else { ' ' };
+                        println!(
+                            "{} #{} {} at line {}",
+                            marker, depth, stack_frame.function, stack_frame.line
+                        );
+                    }
+                }
+                (Some("frame") | Some("f"), Some(depth)) => match depth.parse() {
+                    Ok(depth) if depth < frame.call_stack().len() => self.selected_frame = depth,
+                    _ => print("There is no frame at this depth", PrintType::Error),
+                },
+                (Some("breakpoints"), None) => {
+                    let lines: Vec<String> = frame.breakpoints().lines().map(|l| l.to_string()).collect();
+                    println!("Breakpoints: {}", lines.join(", "));
+                }
+                (None, _) => (),
+                _ => println!(
+                    "Commands: step (s) [statement|expression|call], continue (c), until (u) <line>[:<column>], break (b) <line> [after <hits>] [if <condition>], delete (d) <line>, print (p) <expression>, backtrace (bt), frame (f) <depth>, breakpoints"
+                ),
+            }
+        }
+    }
+}
+
+/// `break <line> [after <hits>] [if <condition>]`, the condition takes the rest of the command
+fn parse_breakpoint(command: &str) -> Option<(usize, Breakpoint)> {
+    let (command, condition) = match command.split_once(" if ") {
+        Some((command, condition)) => (command, Some(condition.trim().to_owned())),
+        None => (command, None),
+    };
+    let mut words = command.split_whitespace().skip(1);
+    let line = words.next()?.parse().ok()?;
+    let hit_count = match (words.next(), words.next()) {
+        (Some("after"), Some(hits)) => Some(hits.parse().ok()?),
+        (None, _) => None,
+        _ => return None,
+    };
+    Some((
+        line,
+        Breakpoint {
+            condition,
+            hit_count,
+        },
+    ))
+}
+
+/// Without a column the whole line is the target
+fn parse_cursor(cursor: &str) -> Option<Span> {
+    let mut parts = cursor.splitn(2, ':');
+    let line = parts.next()?.parse().ok()?;
+    let (start, end) = match parts.next() {
+        Some(column) => {
+            let column = column.parse().ok()?;
+            (column, column)
+        }
+        None => (0, usize::MAX),
+    };
+    Some(Span { line, start, end })
+}
+
+impl Debugger for CliDebugger {
+    fn before_statement(&mut self, frame: &mut Frame) {
+        self.pause(frame);
+    }
+
+    fn before_expression(&mut self, frame: &mut Frame) {
+        self.pause(frame);
+    }
+
+    fn before_call(&mut self, frame: &mut Frame) {
+        self.pause(frame);
+    }
+
+    fn on_error(&mut self, frame: &mut Frame, error: &Error) {
+        print(&error.to_string(), PrintType::Error);
+        println!("The program stops once it's resumed");
+        self.selected_frame = 0;
+        self.show(frame.span);
+        self.prompt(frame);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Breakpoint, Debugger, Frame, Granularity, StackFrame};
+    use crate::error::Error;
+    use crate::interpreter::Interpreter;
+    use crate::token::Span;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    type Pauses = Rc<RefCell<Vec<(Granularity, usize, Option<f64>)>>>;
+
+    /// Records where breakpoints were hit and the value of `i` there
+    struct RecordingDebugger(Pauses);
+
+    impl RecordingDebugger {
+        fn record(&mut self, frame: &mut Frame) {
+            if frame.is_breakpoint() {
+                let i = frame.get("i").and_then(|i| i.as_number().copied());
+                self.0.borrow_mut().push((frame.kind, frame.span.line, i));
+                frame.breakpoints().remove(1);
+            }
+        }
+    }
+
+    impl Debugger for RecordingDebugger {
+        fn before_statement(&mut self, frame: &mut Frame) {
+            self.record(frame);
+        }
+
+        fn before_expression(&mut self, frame: &mut Frame) {
+            self.record(frame);
+        }
+
+        fn before_call(&mut self, frame: &mut Frame) {
+            self.record(frame);
+        }
+    }
+
+    const LOOP: &str =
+        "var total = 0;\nfor (var i = 0; i < 2; i = i + 1) {\n    total = total + i;\n}";
+
+    fn debugged(pauses: &Pauses) -> Interpreter {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_debugger(Box::new(RecordingDebugger(Rc::clone(pauses))));
+        interpreter
+    }
+
+    #[test]
+    fn debugger_pauses_at_breakpoints() {
+        let pauses = Pauses::default();
+        let mut interpreter = debugged(&pauses);
+        interpreter.breakpoints().add(1);
+        interpreter.breakpoints().add(3);
+        interpreter.run(LOOP).unwrap();
+        assert_eq!(
+            *pauses.borrow(),
+            vec![
+                (Granularity::Statement, 1, None),
+                (Granularity::Statement, 3, Some(0.0)),
+                (Granularity::Statement, 3, Some(1.0))
+            ]
+        );
+        assert_eq!(
+            interpreter.breakpoints().lines().collect::<Vec<_>>(),
+            vec![3]
+        );
+    }
+
+    #[test]
+    fn conditional_and_hit_count_breakpoints() {
+        let pauses = Pauses::default();
+        let mut interpreter = debugged(&pauses);
+        let loop_body = "for (var i = 0; i < 6; i = i + 1) {\n    print i;\n}";
+        interpreter.breakpoints().set(
+            2,
+            Breakpoint {
+                condition: Some(String::from("i % 2 == 0")),
+                hit_count: Some(2),
+            },
+        );
+        interpreter.run(loop_body).unwrap();
+        let lines: Vec<Option<f64>> = pauses.borrow().iter().map(|p| p.2).collect();
+        assert_eq!(lines, vec![Some(2.0), Some(4.0)]);
+
+        // conditions that can't be evaluated pause, so the mistake is noticed
+        pauses.borrow_mut().clear();
+        interpreter.breakpoints().set(
+            2,
+            Breakpoint {
+                condition: Some(String::from("undefined_name > 1")),
+                hit_count: None,
+            },
+        );
+        interpreter.run("var i = 10;\nprint i;").unwrap();
+        assert_eq!(pauses.borrow().len(), 1);
+
+        let value = interpreter.evaluate_expression("i * 2").unwrap();
+        assert_eq!(value.as_number(), Some(&20.0));
+        assert!(interpreter.evaluate_expression("i * 2;").is_err());
+    }
+
+    #[test]
+    fn runs_to_cursor_inside_desugared_loops() {
+        let pauses = Pauses::default();
+        let mut interpreter = debugged(&pauses);
+        // the increment of the for loop
+        interpreter.breakpoints().run_to(Span {
+            line: 2,
+            start: 24,
+            end: 24,
+        });
+        interpreter.run(LOOP).unwrap();
+        assert_eq!(
+            *pauses.borrow(),
+            vec![(Granularity::Expression, 2, Some(0.0))]
+        );
+
+        // it's removed once hit
+        interpreter.run(LOOP).unwrap();
+        assert_eq!(pauses.borrow().len(), 1);
+    }
+
+    struct InspectingDebugger(Rc<RefCell<Vec<StackFrame>>>);
+
+    impl Debugger for InspectingDebugger {
+        fn before_statement(&mut self, frame: &mut Frame) {
+            if frame.is_breakpoint() {
+                *self.0.borrow_mut() = frame.call_stack();
+                let sum = frame.evaluate(0, "a + b").unwrap().unwrap();
+                assert_eq!(sum.as_number(), Some(&3.0));
+                frame.evaluate(1, "x = x * 10").unwrap().unwrap();
+                assert!(frame.evaluate(2, "x").unwrap().is_err());
+                assert!(frame.evaluate(3, "x").is_none());
+            }
+        }
+    }
+
+    #[test]
+    fn evaluates_in_paused_frames() {
+        let stack = Rc::new(RefCell::new(Vec::new()));
+        let mut interpreter = Interpreter::new();
+        interpreter.set_debugger(Box::new(InspectingDebugger(Rc::clone(&stack))));
+        interpreter.breakpoints().add(2);
+        let value = interpreter
+            .run(
+                "fn add(a, b) {\n    return a + b;\n}\nfn outer() {\n    var x = 4;\n    add(1, 2);\n    return x;\n}\nouter();",
+            )
+            .unwrap();
+        assert_eq!(value.as_number(), Some(&40.0));
+        assert_eq!(
+            *stack.borrow(),
+            vec![
+                StackFrame {
+                    function: String::from("add"),
+                    line: 2
+                },
+                StackFrame {
+                    function: String::from("outer"),
+                    line: 6
+                },
+                StackFrame {
+                    function: String::from("<script>"),
+                    line: 9
+                },
+            ]
+        );
+    }
+
+    #[derive(Default)]
+    struct PostMortem {
+        errors: Vec<(usize, Vec<StackFrame>, Option<f64>)>,
+    }
+
+    impl Debugger for Rc<RefCell<PostMortem>> {
+        fn before_statement(&mut self, _frame: &mut Frame) {}
+
+        fn on_error(&mut self, frame: &mut Frame, _error: &Error) {
+            let divisor = frame.evaluate(0, "d").and_then(Result::ok);
+            self.borrow_mut().errors.push((
+                frame.span.line,
+                frame.call_stack(),
+                divisor.and_then(|d| d.as_number().copied()),
+            ));
+        }
+    }
+
+    #[test]
+    fn uncaught_errors_open_the_debugger_where_they_are_raised() {
+        let post_mortem = Rc::new(RefCell::new(PostMortem::default()));
+        let mut interpreter = Interpreter::new();
+        interpreter.set_debugger(Box::new(Rc::clone(&post_mortem)));
+        interpreter
+            .run("fn check(d) {\n    if (d == 0) throw \"zero\";\n    return d;\n}")
+            .unwrap();
+        interpreter.run("try { check(0); } catch (e) {}").unwrap();
+        assert!(post_mortem.borrow().errors.is_empty());
+
+        interpreter.run("check(1);\ncheck(0);").unwrap_err();
+        let errors = &post_mortem.borrow().errors;
+        assert_eq!(errors.len(), 1);
+        let (line, stack, divisor) = &errors[0];
+        assert_eq!(*line, 2);
+        assert_eq!(stack[0].function, "check");
+        assert_eq!(stack[1].line, 2);
+        assert_eq!(*divisor, Some(0.0));
+    }
+}
@@ -0,0 +1,150 @@
+use crate::error::Error;
+
+/// How errors are shown by the command line and the other printing entry points
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DiagnosticOptions {
+    /// Errors shown in total, the rest are only counted
+    pub max_errors: Option<usize>,
+    /// Errors of one kind shown before the rest of them are summed up
+    pub max_per_kind: usize,
+    /// Groups errors by kind, caps every group and drops repeated errors.
+    /// Without it every error is shown, in the order they were found.
+    pub dedup: bool,
+}
+
+impl DiagnosticOptions {
+    const DEFAULT: DiagnosticOptions = DiagnosticOptions {
+        max_errors: None,
+        max_per_kind: 10,
+        dedup: true,
+    };
+}
+
+impl Default for DiagnosticOptions {
+    fn default() -> Self {
+        DiagnosticOptions::DEFAULT
+    }
+}
+
+/// Lines showing the errors. Generated code can fail with hundreds of errors of one kind,
+/// so only the first few of every kind are shown, followed by how many more there are.
+pub fn render_diagnostics(errors: &[Error], options: &DiagnosticOptions) -> Vec<String> {
+    let groups: Vec<Vec<&Error>> = if options.dedup {
+        group_by_kind(errors)
+    } else {
+        vec![errors.iter().collect()]
+    };
+    let max_per_kind = if options.dedup {
+        options.max_per_kind
+    } else {
+        usize::MAX
+    };
+
+    let mut lines = Vec::new();
+    let mut shown = 0;
+    let mut omitted = 0;
+    for group in groups {
+        let capped = group.len().min(max_per_kind);
+        let budget = options
+            .max_errors
+            .map_or(usize::MAX, |max| max.saturating_sub(shown));
+        let taken = capped.min(budget);
+        lines.extend(group[..taken].iter().map(|error| error.to_string()));
+        shown += taken;
+        if taken < capped {
+            omitted += group.len() - taken;
+        } else if group.len() > capped {
+            lines.push(format!(
+                "... and {} more similar errors",
+                group.len() - capped
+            ));
+        }
+    }
+    if omitted > 0 {
+        lines.push(format!("... and {} more errors", omitted));
+    }
+    lines
+}
+
+/// Groups in the order their first error was found, without errors repeating one already in the group
+fn group_by_kind(errors: &[Error]) -> Vec<Vec<&Error>> {
+    let mut groups: Vec<(String, Vec<&Error>)> = Vec::new();
+    for error in errors {
+        let code = error.error_type.code();
+        let index = match groups.iter().position(|(kind, _)| *kind == code) {
+            Some(index) => index,
+            None => {
+                groups.push((code, Vec::new()));
+                groups.len() - 1
+            }
+        };
+        let group = &mut groups[index].1;
+        let repeated = group
+            .iter()
+            .any(|seen| seen.span == error.span && seen.to_string() == error.to_string());
+        if !repeated {
+            group.push(error);
+        }
+    }
+    groups.into_iter().map(|(_, group)| group).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{render_diagnostics, DiagnosticOptions};
+    use crate::error::{Error, ErrorType};
+    use crate::token::{Token, TokenType};
+
+    fn errors() -> Vec<Error> {
+        let mut errors = Vec::new();
+        for line in 1..=30 {
+            let token = Token::new(TokenType::Semicolon, line, 1, 1);
+            errors.push(Error::new(&token, ErrorType::UndefinedVariable));
+            if line % 10 == 0 {
+                errors.push(Error::new(&token, ErrorType::WrongType));
+            }
+        }
+        // reported twice
+        errors.push(errors[0].clone());
+        errors
+    }
+
+    #[test]
+    fn caps_every_kind_of_error() {
+        let lines = render_diagnostics(&errors(), &DiagnosticOptions::default());
+        assert_eq!(lines.len(), 10 + 1 + 3);
+        assert_eq!(lines[0], "1.1-1 : Variable is undefined");
+        assert_eq!(lines[10], "... and 20 more similar errors");
+        assert_eq!(lines[11], "10.1-1 : Unexpected type mismatch");
+        assert_eq!(lines[13], "30.1-1 : Unexpected type mismatch");
+    }
+
+    #[test]
+    fn caps_all_errors() {
+        let lines = render_diagnostics(
+            &errors(),
+            &DiagnosticOptions {
+                max_errors: Some(5),
+                ..DiagnosticOptions::default()
+            },
+        );
+        assert_eq!(lines.len(), 6);
+        assert_eq!(lines[5], "... and 28 more errors");
+
+        let lines = render_diagnostics(
+            &errors(),
+            &DiagnosticOptions {
+                max_errors: Some(5),
+                dedup: false,
+                ..DiagnosticOptions::default()
+            },
+        );
+        assert_eq!(lines[1], "2.1-1 : Variable is undefined");
+        assert_eq!(lines[5], "... and 29 more errors");
+        let all = DiagnosticOptions {
+            dedup: false,
+            ..DiagnosticOptions::default()
+        };
+        assert_eq!(render_diagnostics(&errors(), &all).len(), 34);
+    }
+}
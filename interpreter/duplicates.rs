@@ -0,0 +1,414 @@
+use crate::error::Error;
+use crate::expr::Expr;
+use crate::lexer::Lexer;
+use crate::parser::Parser;
+use crate::statement::Stmt;
+use crate::token::Span;
+use crate::walk::{walk_expr, walk_stmt, walk_stmts, Walk};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::mem;
+
+/// Place one copy of duplicated code was found at
+#[derive(Debug, Clone, PartialEq)]
+pub struct CloneSite {
+    pub file: String,
+    /// Span of the first statement
+    pub span: Span,
+    pub end_line: usize,
+}
+
+impl fmt::Display for CloneSite {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}-{}",
+            self.file, self.span.line, self.span.start, self.end_line
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DuplicateKind {
+    /// Every site is the whole body of a function, or a whole function declaration
+    FunctionBody,
+    Statements,
+}
+
+/// Code that is the same at every site, apart from names and layout
+#[derive(Debug, Clone, PartialEq)]
+pub struct Duplicate {
+    pub kind: DuplicateKind,
+    /// Number of syntax tree nodes of one copy
+    pub size: usize,
+    pub sites: Vec<CloneSite>,
+}
+
+impl fmt::Display for Duplicate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match self.kind {
+            DuplicateKind::FunctionBody => "function body",
+            DuplicateKind::Statements => "statements",
+        };
+        write!(f, "Duplicated {} ({} nodes) at:", kind, self.size)?;
+        for site in &self.sites {
+            write!(f, "\n  {}", site)?;
+        }
+        Ok(())
+    }
+}
+
+/// Finds sequences of statements of at least `min_size` nodes that appear more than once in the sources,
+/// given as pairs of file name and source code. Names and spans are ignored, so renamed copies are found too.
+/// Only the largest duplicates are reported, not the smaller ones inside them.
+pub fn find_duplicates(
+    sources: &[(String, String)],
+    min_size: usize,
+) -> Result<Vec<Duplicate>, (String, Vec<Error>)> {
+    let mut candidates = Vec::new();
+    for (file, source_code) in sources {
+        let stmts = Lexer::new(source_code)
+            .scan_tokens()
+            .and_then(|tokens| Parser::new(&tokens).parse_tokens())
+            .map_err(|errors| (file.clone(), errors))?;
+        Blocks {
+            file,
+            candidates: &mut candidates,
+        }
+        .block(&stmts, false);
+    }
+
+    let mut groups: HashMap<u64, Vec<Candidate>> = HashMap::new();
+    for candidate in candidates.into_iter().filter(|c| c.size >= min_size) {
+        groups.entry(candidate.hash).or_default().push(candidate);
+    }
+    let mut groups: Vec<Vec<Candidate>> = groups
+        .into_values()
+        .map(without_overlaps)
+        .filter(|group| group.len() > 1)
+        .collect();
+    groups.sort_by_key(|group| std::cmp::Reverse(group[0].size));
+
+    let mut duplicates: Vec<Vec<Candidate>> = Vec::new();
+    for group in groups {
+        let inside_reported = duplicates.iter().any(|reported| {
+            group
+                .iter()
+                .all(|site| reported.iter().any(|outer| outer.contains(site)))
+        });
+        if !inside_reported {
+            duplicates.push(group);
+        }
+    }
+    let mut duplicates: Vec<Duplicate> = duplicates
+        .into_iter()
+        .map(|group| Duplicate {
+            kind: if group.iter().all(|site| site.function_body) {
+                DuplicateKind::FunctionBody
+            } else {
+                DuplicateKind::Statements
+            },
+            size: group[0].size,
+            sites: group
+                .into_iter()
+                .map(|site| CloneSite {
+                    file: site.file,
+                    span: site.span,
+                    end_line: site.end.0,
+                })
+                .collect(),
+        })
+        .collect();
+    duplicates.sort_by(|a, b| {
+        let position = |d: &Duplicate| (d.sites[0].file.clone(), d.sites[0].span.line);
+        position(a).cmp(&position(b))
+    });
+    Ok(duplicates)
+}
+
+/// Sequence of statements of one block
+struct Candidate {
+    hash: u64,
+    size: usize,
+    file: String,
+    span: Span,
+    /// Line and column the last statement ends at
+    end: (usize, usize),
+    function_body: bool,
+}
+
+impl Candidate {
+    fn start(&self) -> (usize, usize) {
+        (self.span.line, self.span.start)
+    }
+
+    fn contains(&self, other: &Candidate) -> bool {
+        self.file == other.file && self.start() <= other.start() && other.end <= self.end
+    }
+
+    fn overlaps(&self, other: &Candidate) -> bool {
+        self.file == other.file && self.start() <= other.end && other.start() <= self.end
+    }
+}
+
+/// `a; a; a;` repeats `a; a;`, but the overlapping copies are one piece of code
+fn without_overlaps(group: Vec<Candidate>) -> Vec<Candidate> {
+    let mut kept: Vec<Candidate> = Vec::new();
+    for candidate in group {
+        if !kept.iter().any(|site| site.overlaps(&candidate)) {
+            kept.push(candidate);
+        }
+    }
+    kept
+}
+
+/// Collects every sequence of statements of every block as a candidate
+struct Blocks<'a> {
+    file: &'a str,
+    candidates: &'a mut Vec<Candidate>,
+}
+
+impl Blocks<'_> {
+    fn block(&mut self, stmts: &[Stmt], function_body: bool) {
+        let normalized: Vec<Normalized> = stmts.iter().map(normalize).collect();
+        for start in 0..normalized.len() {
+            let mut hasher = DefaultHasher::new();
+            let mut size = 0;
+            for (end, statement) in normalized.iter().enumerate().skip(start) {
+                statement.text.hash(&mut hasher);
+                size += statement.size;
+                self.candidates.push(Candidate {
+                    hash: hasher.finish(),
+                    size,
+                    file: self.file.to_owned(),
+                    span: stmts[start].span(),
+                    end: statement.end,
+                    function_body: (function_body && start == 0 && end == stmts.len() - 1)
+                        || (start == end && matches!(stmts[start], Stmt::Function { .. })),
+                });
+            }
+        }
+        walk_stmts(self, stmts);
+    }
+
+    fn function_body(&mut self, body: &[Stmt]) {
+        match body {
+            // the parser wraps the body in a block
+            [Stmt::Block { stmts, .. }] => self.block(stmts, true),
+            body => self.block(body, true),
+        }
+    }
+}
+
+impl<'a> Walk<'a> for Blocks<'_> {
+    fn stmt(&mut self, stmt: &'a Stmt) {
+        match stmt {
+            Stmt::Block { stmts, .. } => self.block(stmts, false),
+            Stmt::Function { body, .. } => self.function_body(body),
+            Stmt::Try {
+                body,
+                catches,
+                finally,
+                ..
+            } => {
+                self.block(body, false);
+                catches
+                    .iter()
+                    .for_each(|catch| self.block(&catch.body, false));
+                if let Some(finally) = finally {
+                    self.block(finally, false);
+                }
+            }
+            _ => walk_stmt(self, stmt),
+        }
+    }
+
+    fn expr(&mut self, expr: &'a Expr) {
+        match expr {
+            Expr::Closure { body, .. } => self.function_body(body),
+            _ => walk_expr(self, expr),
+        }
+    }
+}
+
+/// Statement with names and spans left out
+struct Normalized {
+    text: String,
+    size: usize,
+    end: (usize, usize),
+}
+
+fn normalize(stmt: &Stmt) -> Normalized {
+    let mut normalizer = Normalizer {
+        text: String::new(),
+        size: 0,
+        end: (0, 0),
+    };
+    normalizer.stmt(stmt);
+    Normalized {
+        text: normalizer.text,
+        size: normalizer.size,
+        end: normalizer.end,
+    }
+}
+
+/// Writes every node as its kind, and the parts of it that aren't names, e.g. operators and literals
+struct Normalizer {
+    text: String,
+    size: usize,
+    end: (usize, usize),
+}
+
+impl Normalizer {
+    fn node(&mut self, label: &str, span: Span) {
+        self.text.push('(');
+        self.text.push_str(label);
+        self.size += 1;
+        self.end = self.end.max((span.line, span.end));
+    }
+
+    fn close(&mut self) {
+        self.text.push(')');
+    }
+
+    /// Clauses that aren't statements themselves, marked so that their contents aren't mixed up with others'
+    fn clause(&mut self, label: &str, span: Span, walk: impl FnOnce(&mut Self)) {
+        self.node(label, span);
+        walk(self);
+        self.close();
+    }
+}
+
+impl<'a> Walk<'a> for Normalizer {
+    fn stmt(&mut self, stmt: &'a Stmt) {
+        let span = stmt.span();
+        match stmt {
+            Stmt::Function { params, .. } => self.node(&format!("fn {}", params.len()), span),
+            _ => self.node(&format!("{:?}", mem::discriminant(stmt)), span),
+        }
+        match stmt {
+            Stmt::Try {
+                body,
+                catches,
+                finally,
+                ..
+            } => {
+                walk_stmts(self, body);
+                for catch in catches {
+                    self.clause("catch", span, |normalizer| {
+                        if let Some(class) = &catch.class {
+                            normalizer.expr(class);
+                        }
+                        walk_stmts(normalizer, &catch.body);
+                    });
+                }
+                if let Some(finally) = finally {
+                    self.clause("finally", span, |normalizer| {
+                        walk_stmts(normalizer, finally)
+                    });
+                }
+            }
+            Stmt::Match { value, arms, .. } => {
+                self.expr(value);
+                for arm in arms {
+                    self.clause("arm", span, |normalizer| normalizer.stmt(&arm.body));
+                }
+            }
+            _ => walk_stmt(self, stmt),
+        }
+        self.close();
+    }
+
+    fn expr(&mut self, expr: &'a Expr) {
+        let span = expr.span();
+        match expr {
+            Expr::Binary { operator, .. }
+            | Expr::Logical { operator, .. }
+            | Expr::Unary { operator, .. } => {
+                self.node(&format!("{:?}", operator.token_type), span)
+            }
+            Expr::Literal { value, .. } => self.node(&format!("{:?}", value), span),
+            Expr::Closure { params, .. } => self.node(&format!("closure {}", params.len()), span),
+            _ => self.node(&format!("{:?}", mem::discriminant(expr)), span),
+        }
+        match expr {
+            Expr::Call {
+                callee,
+                arguments,
+                named,
+                ..
+            } => {
+                self.expr(callee);
+                arguments.iter().for_each(|arg| self.expr(arg));
+                for (_, arg) in named {
+                    self.clause("named", arg.span(), |normalizer| normalizer.expr(arg));
+                }
+            }
+            _ => walk_expr(self, expr),
+        }
+        self.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find_duplicates, DuplicateKind};
+
+    fn sources(sources: &[(&str, &str)]) -> Vec<(String, String)> {
+        sources
+            .iter()
+            .map(|(file, source)| (file.to_string(), source.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn finds_renamed_function_bodies_across_files() {
+        let duplicates = find_duplicates(
+            &sources(&[
+                (
+                    "a.lox",
+                    "fn total(items) {\n  var sum = 0;\n  for (var i = 0; i < items; i = i + 1) sum = sum + i;\n  return sum;\n}",
+                ),
+                (
+                    "b.lox",
+                    "print 1;\nfn count(n) {\n  var acc = 0;\n  for (var j = 0; j < n; j = j + 1) acc = acc + j;\n  return acc;\n}",
+                ),
+            ]),
+            10,
+        )
+        .unwrap();
+        assert_eq!(duplicates.len(), 1);
+        assert_eq!(duplicates[0].kind, DuplicateKind::FunctionBody);
+        let sites: Vec<String> = duplicates[0].sites.iter().map(|s| s.to_string()).collect();
+        assert_eq!(sites, vec!["a.lox:1:4-4", "b.lox:2:4-5"]);
+    }
+
+    #[test]
+    fn reports_only_the_largest_statement_sequences() {
+        let copy = "var x = a * 2;\nprint x + 1;\nprint x - 1;\n";
+        let source = format!("{}print \"between\";\n{{\n{}}}\n", copy, copy);
+        let duplicates = find_duplicates(&sources(&[("a.lox", &source)]), 5).unwrap();
+        assert_eq!(duplicates.len(), 1);
+        assert_eq!(duplicates[0].kind, DuplicateKind::Statements);
+        let lines: Vec<(usize, usize)> = duplicates[0]
+            .sites
+            .iter()
+            .map(|site| (site.span.line, site.end_line))
+            .collect();
+        assert_eq!(lines, vec![(1, 3), (6, 8)]);
+        // literals are kept, so these differ
+        assert!(
+            find_duplicates(&sources(&[("a.lox", "print 1 + 2; print 1 + 3;")]), 1)
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(
+            find_duplicates(&sources(&[("b.lox", "print (;")]), 5)
+                .unwrap_err()
+                .0,
+            "b.lox"
+        );
+    }
+}
@@ -1,78 +1,256 @@
+use crate::error::ErrorType;
+use crate::expr::ExprId;
 use crate::runtime_value::Value;
+use serde::{Deserialize, Serialize};
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::rc::Rc;
 
+/// Where the resolver found a variable: how many environments up from the expression,
+/// and its position among the locals of that environment
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Slot {
+    pub distance: usize,
+    pub index: usize,
+}
+
+/// Where the resolver found the variables of one program. Functions and classes keep the table
+/// of the program declaring them, so it goes away once nothing the program declared is left.
+pub(crate) type SlotTable = Rc<RefCell<HashMap<ExprId, Slot>>>;
+
+#[derive(Clone, Debug)]
+enum Bindings {
+    /// Globals and top-level variables, code resolved by different runs refers to them, so they're found by name
+    Named(HashMap<String, Value>),
+    /// Locals of a block or a call in the order they're declared, read and assigned by the indices the resolver
+    /// gives out. The names are only kept for debuggers, lookups never compare them.
+    Frame {
+        values: Vec<Value>,
+        names: Vec<String>,
+    },
+}
+
 #[derive(Clone, Debug)]
 pub struct Environment {
-    values: HashMap<String, Value>,
+    bindings: Bindings,
+    read_only: HashSet<String>,
     enclosing: Option<Rc<RefCell<Environment>>>,
 }
 
 impl Environment {
     pub fn new() -> Self {
         Environment {
-            values: HashMap::new(),
+            bindings: Bindings::Named(HashMap::new()),
+            read_only: HashSet::new(),
             enclosing: None,
         }
     }
 
-    pub fn from(env: &Rc<RefCell<Environment>>) -> Self {
+    /// Frame for the locals of a block or a call, with room for as many as the resolver numbered in its scope
+    pub fn frame(env: &Rc<RefCell<Environment>>, locals: usize) -> Self {
         Environment {
-            values: HashMap::new(),
+            bindings: Bindings::Frame {
+                values: Vec::with_capacity(locals),
+                names: Vec::with_capacity(locals),
+            },
+            read_only: HashSet::new(),
             enclosing: Some(Rc::clone(env)),
         }
     }
 
+    /// Environment of top-level variables, looked up by name like the globals
+    pub fn top_level(env: &Rc<RefCell<Environment>>) -> Self {
+        Environment {
+            bindings: Bindings::Named(HashMap::new()),
+            ..Environment::frame(env, 0)
+        }
+    }
+
     pub fn has_enclosing(&self) -> bool {
         self.enclosing.is_some()
     }
 
-    pub fn define_or_update(&mut self, name: &str, value: &Value) -> Option<Value> {
-        self.values.insert(name.to_owned(), value.clone())
+    pub fn enclosing(&self) -> Option<Rc<RefCell<Environment>>> {
+        self.enclosing.clone()
+    }
+
+    pub fn bindings(&self) -> Box<dyn Iterator<Item = (&String, &Value)> + '_> {
+        match &self.bindings {
+            Bindings::Named(values) => Box::new(values.iter()),
+            Bindings::Frame { values, names } => Box::new(names.iter().zip(values)),
+        }
+    }
+
+    /// Declares the name in this scope, declaring it again replaces the value.
+    /// Scopes looked up by name can't declare host constants, that would replace them for the whole script.
+    pub fn define(&mut self, name: &str, value: &Value) -> Result<(), ErrorType> {
+        if matches!(self.bindings, Bindings::Named(_)) && self.is_read_only(name) {
+            return Err(ErrorType::AssignmentToHostConstant);
+        }
+        self.bind(name, value);
+        Ok(())
+    }
+
+    /// Declares the name without checking for host constants, for what the host and the interpreter itself
+    /// declare: globals, parameters, `this` and the like.
+    /// Frames give it the next slot, locals are declared in the order the resolver numbered them.
+    pub fn bind(&mut self, name: &str, value: &Value) {
+        match &mut self.bindings {
+            Bindings::Named(values) => {
+                values.insert(name.to_owned(), value.clone());
+            }
+            Bindings::Frame { values, names } => {
+                values.push(value.clone());
+                names.push(name.to_owned());
+            }
+        }
+    }
+
+    /// Changes the value of a name declared in this scope or an enclosing one, it never declares it
+    pub fn assign(&mut self, name: &str, value: &Value) -> Result<(), ErrorType> {
+        if self.read_only.contains(name) {
+            return Err(ErrorType::AssignmentToHostConstant);
+        }
+        if let Some(old) = self.binding_mut(name) {
+            *old = value.clone();
+            return Ok(());
+        }
+        match &self.enclosing {
+            Some(enclosing) => enclosing.borrow_mut().assign(name, value),
+            None => Err(ErrorType::UndefinedVariable),
+        }
+    }
+
+    /// Locals are always resolved to slots, only the names of globals and top-level variables are looked up
+    fn binding_mut(&mut self, name: &str) -> Option<&mut Value> {
+        match &mut self.bindings {
+            Bindings::Named(values) => values.get_mut(name),
+            Bindings::Frame { .. } => None,
+        }
+    }
+
+    /// Defines a value that scripts can read but never assign to
+    pub fn define_read_only(&mut self, name: &str, value: &Value) {
+        self.read_only.insert(name.to_owned());
+        self.bind(name, value);
+    }
+
+    /// Names defined read-only in this very environment
+    pub fn read_only(&self) -> impl Iterator<Item = &String> {
+        self.read_only.iter()
+    }
+
+    pub fn is_read_only(&self, name: &str) -> bool {
+        self.read_only.contains(name)
+            || self
+                .enclosing
+                .as_ref()
+                .is_some_and(|e| e.borrow().is_read_only(name))
     }
 
-    pub fn assign_at(&mut self, name: &str, value: &Value, distance: usize) -> Option<Value> {
-        if distance > 0 {
-            self.ancestor(distance)
+    /// Assigns the variable the resolver numbered with the slot
+    pub fn assign_at(&mut self, name: &str, value: &Value, slot: Slot) -> Result<(), ErrorType> {
+        if slot.distance > 0 {
+            self.ancestor(slot.distance)?
                 .borrow_mut()
-                .define_or_update(name, value)
+                .assign_slot(name, value, slot.index)
         } else {
-            self.define_or_update(name, value)
+            self.assign_slot(name, value, slot.index)
         }
     }
 
+    /// The top-level scope is numbered by the resolver too, but it's looked up by name like the globals
+    fn assign_slot(&mut self, name: &str, value: &Value, index: usize) -> Result<(), ErrorType> {
+        let old = match &mut self.bindings {
+            Bindings::Frame { values, .. } => values.get_mut(index),
+            Bindings::Named(values) => values.get_mut(name),
+        };
+        match old {
+            Some(old) => {
+                *old = value.clone();
+                Ok(())
+            }
+            None => Err(ErrorType::UndefinedVariable),
+        }
+    }
+
+    /// Value of a global or top-level variable, locals are only found by their slots
     pub fn get(&self, name: &str) -> Option<Value> {
-        self.values.get(name).map(|val| val.clone())
+        match &self.bindings {
+            Bindings::Named(values) => values.get(name).cloned(),
+            Bindings::Frame { .. } => None,
+        }
     }
 
-    pub fn ancestor(&self, distance: usize) -> Rc<RefCell<Environment>> {
-        // this clone here is probably very expensive one
-        let mut env = self
-            .enclosing
-            .clone()
-            .expect("Trying to access environment that doesn't exist");
+    fn get_slot(&self, name: &str, index: usize) -> Option<Value> {
+        match &self.bindings {
+            Bindings::Frame { values, .. } => values.get(index).cloned(),
+            Bindings::Named(values) => values.get(name).cloned(),
+        }
+    }
 
+    /// Environment the given number of scopes up, the resolver never counts more scopes than there are
+    pub fn ancestor(&self, distance: usize) -> Result<Rc<RefCell<Environment>>, ErrorType> {
+        let mut env = self.enclosing.clone();
         for _ in 1..distance {
-            let enclosing = env.borrow().enclosing.clone();
-            if let Some(e) = &enclosing {
-                env = Rc::clone(e)
-            }
+            env = env.and_then(|env| env.borrow().enclosing.clone());
         }
-        env
+        env.ok_or(ErrorType::UndefinedVariable)
     }
 
-    pub fn get_at(&self, name: &str, distance: usize) -> Option<Value> {
-        if distance == 0 {
-            self.get(name)
+    pub fn get_at(&self, name: &str, slot: Slot) -> Option<Value> {
+        if slot.distance == 0 {
+            self.get_slot(name, slot.index)
         } else {
-            self.ancestor(distance).borrow().get(name)
+            self.ancestor(slot.distance)
+                .ok()?
+                .borrow()
+                .get_slot(name, slot.index)
+        }
+    }
+
+    /// Finds the variable by name in this scope or an enclosing one, locals included.
+    /// It's for debuggers, the interpreter reads locals by their slots.
+    pub fn lookup(&self, name: &str) -> Option<Value> {
+        self.local(name)
+            .or_else(|| self.enclosing.as_ref()?.borrow().lookup(name))
+    }
+
+    /// Value of the variable declared in this very scope, found by name like `lookup` does
+    pub fn local(&self, name: &str) -> Option<Value> {
+        match &self.bindings {
+            Bindings::Frame { values, names } => names
+                .iter()
+                .position(|local| local == name)
+                .map(|index| values[index].clone()),
+            Bindings::Named(values) => values.get(name).cloned(),
+        }
+    }
+
+    /// Assigns the variable `local` finds
+    pub fn assign_local(&mut self, name: &str, value: &Value) -> Result<(), ErrorType> {
+        if self.read_only.contains(name) {
+            return Err(ErrorType::AssignmentToHostConstant);
+        }
+        let old = match &mut self.bindings {
+            Bindings::Frame { values, names } => names
+                .iter()
+                .position(|local| local == name)
+                .and_then(move |index| values.get_mut(index)),
+            Bindings::Named(values) => values.get_mut(name),
+        };
+        match old {
+            Some(old) => {
+                *old = value.clone();
+                Ok(())
+            }
+            None => Err(ErrorType::UndefinedVariable),
         }
     }
 
     pub fn get_deep(&self, name: &str) -> Option<Value> {
-        match self.values.get(name) {
-            Some(this) => Some(this.clone()),
+        match self.get(name) {
+            Some(this) => Some(this),
             None => {
                 if let Some(e) = &self.enclosing {
                     e.borrow().get_deep(name)
@@ -83,3 +261,54 @@ impl Environment {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::environment::{Environment, Slot};
+    use crate::error::ErrorType;
+    use crate::runtime_value::Value;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn frames_are_indexed_by_slot() {
+        let globals = Rc::new(RefCell::new(Environment::new()));
+        globals.borrow_mut().bind("g", &Value::Number(0.0));
+        let mut frame = Environment::frame(&globals, 2);
+        frame.bind("a", &Value::Number(1.0));
+        frame.bind("b", &Value::Number(2.0));
+
+        let b = Slot {
+            distance: 0,
+            index: 1,
+        };
+        assert_eq!(frame.get_at("b", b).unwrap().as_number(), Some(&2.0));
+        frame.assign_at("b", &Value::Number(3.0), b).unwrap();
+        assert_eq!(frame.get_at("b", b).unwrap().as_number(), Some(&3.0));
+        let missing = Slot {
+            distance: 0,
+            index: 2,
+        };
+        assert!(frame.get_at("c", missing).is_none());
+        // locals are never found by name, only debuggers look them up that way
+        assert!(frame.get("a").is_none());
+        assert_eq!(frame.lookup("a").unwrap().as_number(), Some(&1.0));
+        assert_eq!(frame.get_deep("g").unwrap().as_number(), Some(&0.0));
+    }
+
+    #[test]
+    fn missing_ancestors_are_errors() {
+        let globals = Rc::new(RefCell::new(Environment::new()));
+        let frame = Environment::frame(&globals, 0);
+        assert!(frame.ancestor(1).is_ok());
+        assert!(matches!(
+            frame.ancestor(2),
+            Err(ErrorType::UndefinedVariable)
+        ));
+        let slot = Slot {
+            distance: 3,
+            index: 0,
+        };
+        assert!(frame.get_at("a", slot).is_none());
+    }
+}
@@ -1,10 +1,14 @@
 use crate::runtime_value::Value;
-use crate::token::{Token, TokenType};
+use crate::token::{Span, Token, TokenType};
+use crate::types::Type;
+use std::fmt;
 
 #[derive(Debug, Clone, Display)]
 pub enum ErrorType {
     #[display(fmt = "String not closed")]
     StringNotClosed,
+    #[display(fmt = "Block comment not closed")]
+    CommentNotClosed,
     #[display(fmt = "Unexpected character")]
     UnexpectedCharacter,
     #[display(fmt = "Unparsable expression")]
@@ -33,6 +37,8 @@ pub enum ErrorType {
     UndefinedVariable,
     #[display(fmt = "Invalid Assignment")]
     InvalidAssignment,
+    #[display(fmt = "Assignment used as a condition, comparisons use ==")]
+    AssignmentInCondition,
     #[display(fmt = "Expected close brace at the end of the block")]
     ExpectedBlockEnd,
     #[display(fmt = "Expected open brace at the start of the block")]
@@ -43,14 +49,64 @@ pub enum ErrorType {
     MaximumArguments,
     #[display(fmt = "This value is not callable")]
     ValueNotCallable,
+    #[display(fmt = "Only lists, strings and instances can be indexed")]
+    ValueNotIndexable,
+    #[display(fmt = "Strings can't be changed, build a new one instead")]
+    StringNotMutable,
+    #[display(fmt = "Only lists and strings can be iterated over")]
+    ValueNotIterable,
+    #[display(fmt = "Not a Unicode code point")]
+    InvalidCodePoint,
     #[display(fmt = "Expected arrow after closure declaration")]
     ExpectedArrow,
+    #[display(fmt = "Instances used with this operator need a {} method", _0)]
+    MissingOperatorMethod(String),
     #[display(fmt = "Invalid number of arguments")]
     InvalidNumberOfArguments,
+    #[display(fmt = "Only lists can be spread into arguments")]
+    SpreadNotList,
+    #[display(fmt = "The rest parameter has to be the last one")]
+    RestParameterNotLast,
+    #[display(fmt = "Parameters after one with a default value need a default value too")]
+    ExpectedDefault,
+    #[display(fmt = "Expected a type after the colon")]
+    ExpectedType,
+    #[display(fmt = "Expected a value of type {} but got {}", _0, _1)]
+    TypeMismatch(Box<Type>, Box<Type>),
     #[display(fmt = "Can't use variable in it's own initializer")]
     CantUseVariableInItsInitializer,
     #[display(fmt = "Return")]
-    Return(Value),
+    Return(Box<Value>),
+    #[display(fmt = "Break")]
+    Break,
+    #[display(fmt = "Continue")]
+    Continue,
+    #[display(fmt = "Uncaught exception: {}", "describe_exception(_0)")]
+    Throw(Box<Value>),
+    #[display(fmt = "Expected catch or finally after try")]
+    ExpectedCatchOrFinally,
+    #[display(fmt = "Class named in the catch clause is not defined")]
+    UndefinedCatchClass,
+    #[display(fmt = "Catch clauses can only filter by classes")]
+    CatchFilterNotClass,
+    #[display(fmt = "Expected a pattern")]
+    ExpectedPattern,
+    #[display(fmt = "Expected arrow after the pattern")]
+    ExpectedArmArrow,
+    #[display(fmt = "Expected close bracket")]
+    ExpectedCloseBracket,
+    #[display(fmt = "Patterns can only name classes")]
+    PatternClassNotClass,
+    #[display(fmt = "This arm is never reached, an earlier one matches everything it does")]
+    UnreachableArm,
+    #[display(fmt = "Expected while after the body of do loop")]
+    ExpectedWhile,
+    #[display(fmt = "Expected the end of the expression")]
+    ExpectedEndOfExpression,
+    #[display(fmt = "Positional arguments can't follow named ones")]
+    PositionalAfterNamedArgument,
+    #[display(fmt = "Named arguments can only be passed to classes")]
+    NamedArgumentsOutsideClass,
     #[display(fmt = "Value is not an instance, therefore you can't access its properties")]
     ValueNotInstance,
     #[display(fmt = "This instance doesn't have this property")]
@@ -67,37 +123,182 @@ pub enum ErrorType {
     CantUseSuper,
     #[display(fmt = "Can't use this outside class")]
     CantUseThis,
+    #[display(fmt = "Can't return from top-level code")]
+    CantReturnOutsideFunction,
+    #[display(fmt = "Can't return a value from a constructor")]
+    CantReturnValueFromConstructor,
+    #[display(
+        fmt = "Field {} has no initializer and isn't assigned in the constructor",
+        _0
+    )]
+    UninitializedField(String),
+    #[display(fmt = "Can't assign to a constant provided by the host")]
+    AssignmentToHostConstant,
+    #[display(fmt = "{}", _0)]
+    Host(String),
+    #[display(fmt = "Invalid format specification")]
+    InvalidFormatSpec,
+    #[display(fmt = "Resource limit exceeded: {}", _0)]
+    ResourceLimit(String),
+    #[display(fmt = "Maximum call depth exceeded")]
+    StackOverflow,
+    #[display(fmt = "Execution took longer than allowed")]
+    ExecutionTimeout,
+    #[display(fmt = "Execution was interrupted by the host")]
+    Interrupted,
+    #[display(fmt = "I/O error: {}", _0)]
+    Io(String),
+    #[display(fmt = "Foreign function call failed: {}", _0)]
+    Ffi(String),
+    #[display(fmt = "Index is out of range")]
+    IndexOutOfRange,
+    #[display(fmt = "Range is empty, its start is above its end")]
+    EmptyRange,
+    #[display(fmt = "{} isn't allowed in this sandbox", _0)]
+    CapabilityDenied(String),
+    #[display(fmt = "{} are not enabled in this version of the language", _0)]
+    FeatureDisabled(String),
+    #[display(fmt = "'{}' is reserved for future use, pick another name", _0)]
+    ReservedWord(String),
+    #[display(fmt = "Code is nested too deeply")]
+    NestedTooDeeply,
+    #[display(fmt = "Variable {} is already declared in this scope", _0)]
+    AlreadyDeclared(String),
+    #[display(fmt = "Variable {} shadows a variable of an outer scope", _0)]
+    ShadowedVariable(String),
+    #[display(fmt = "Source code is longer than {} bytes", _0)]
+    SourceTooLarge(usize),
+    #[display(fmt = "Source code has more than {} tokens", _0)]
+    TooManyTokens(usize),
+    #[display(fmt = "Assertion failed: {}", _0)]
+    AssertionFailed(String),
+}
+
+impl ErrorType {
+    /// Prelude class runtime errors of this type are caught as
+    pub fn exception_class(&self) -> &'static str {
+        match self {
+            ErrorType::ResourceLimit(_) | ErrorType::StackOverflow => "ResourceLimit",
+            ErrorType::ExecutionTimeout | ErrorType::Interrupted => "InterruptError",
+            ErrorType::Io(_) => "IOError",
+            ErrorType::AssertionFailed(_) => "AssertionError",
+            _ => "Error",
+        }
+    }
+
+    /// Name of the variant, without its data, e.g. `UndefinedVariable`
+    pub fn code(&self) -> String {
+        let debug = format!("{:?}", self);
+        match debug.find(|c: char| !c.is_alphanumeric()) {
+            Some(end) => debug[..end].to_owned(),
+            None => debug,
+        }
+    }
+
+    /// Return, break and continue leave statements early through errors, like exceptions do,
+    /// but they are never caught by try
+    pub fn is_signal(&self) -> bool {
+        matches!(
+            self,
+            ErrorType::Return(_) | ErrorType::Break | ErrorType::Continue
+        )
+    }
+
+    /// Errors scripts can catch, timeouts and interruptions only until their cleanup runs out
+    pub fn is_catchable(&self) -> bool {
+        !self.is_signal()
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct Error {
     pub token: Token,
     pub error_type: ErrorType,
+    /// What the error points at, the token's span unless the whole offending node is known
+    pub span: Span,
 }
 
-impl From<Vec<Error>> for Error {
-    // This is used in Resolver, during static analysis and this is not a good way, because I discard errors
-    // Maybe it will be fixed in the future
-    fn from(errors: Vec<Error>) -> Self {
-        errors.get(0).unwrap().clone()
+impl Error {
+    pub fn new(token: &Token, error_type: ErrorType) -> Self {
+        Error {
+            span: token.span(),
+            token: token.clone(),
+            error_type,
+        }
+    }
+
+    pub fn with_span(self, span: Span) -> Self {
+        Error { span, ..self }
+    }
+
+    /// Causes attached to the thrown exception, starting with the direct one
+    pub fn causes(&self) -> Vec<Value> {
+        let mut causes = Vec::new();
+        if let ErrorType::Throw(exception) = &self.error_type {
+            let mut current = cause_of(exception);
+            while let Some(cause) = current {
+                current = cause_of(&cause);
+                causes.push(cause);
+            }
+        }
+        causes
+    }
+}
+
+/// Instances with a message are shown as `Class: message`, followed by their chain of causes
+fn describe_exception(exception: &Value) -> String {
+    let mut description = describe_single_exception(exception);
+    let mut current = cause_of(exception);
+    while let Some(cause) = current {
+        description.push_str(&format!(
+            "\n  caused by: {}",
+            describe_single_exception(&cause)
+        ));
+        current = cause_of(&cause);
+    }
+    description
+}
+
+fn describe_single_exception(exception: &Value) -> String {
+    match exception {
+        Value::Instance(instance) => {
+            let instance = instance.borrow();
+            match instance.field("message") {
+                Some(message) => format!("{}: {}", instance.class().to_string(), message),
+                None => instance.to_string(),
+            }
+        }
+        value => value.to_string(),
+    }
+}
+
+fn cause_of(exception: &Value) -> Option<Value> {
+    match exception {
+        Value::Instance(instance) => match instance.borrow().field("cause") {
+            Some(Value::Null) | None => None,
+            Some(cause) => Some(cause.clone()),
+        },
+        _ => None,
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            fmt,
+            "{}.{}-{} : {}",
+            self.span.line, self.span.start, self.span.end, self.error_type
+        )
     }
 }
 
 pub fn error(token: &Token, error_type: ErrorType) -> Result<Value, Error> {
-    Err(Error {
-        token: token.clone(),
-        error_type,
-    })
+    Err(Error::new(token, error_type))
 }
 
 pub fn return_stmt(val: Value) -> Result<(), Error> {
-    Err(Error {
-        token: Token {
-            token_type: TokenType::Return,
-            line: 0,
-            start: 0,
-            end: 0,
-        },
-        error_type: ErrorType::Return(val),
-    })
+    Err(Error::new(
+        &Token::new(TokenType::Return, 0, 0, 0),
+        ErrorType::Return(Box::new(val)),
+    ))
 }
@@ -0,0 +1,104 @@
+use crate::debugger::{Debugger, Frame};
+use crate::runtime_value::Value;
+use crate::token::Span;
+
+/// Narrates the program as it runs: every statement, and for every expression the parts evaluated
+/// first and what it evaluated to. Meant for small programs, e.g. when learning how evaluation works.
+pub struct Explainer {
+    source_lines: Vec<Vec<char>>,
+    /// Expressions being evaluated, innermost last, with whether "evaluating" was printed for them
+    evaluating: Vec<(Span, bool)>,
+    output: Box<dyn FnMut(&str)>,
+}
+
+impl Explainer {
+    /// Every step is given to `output` as a line of text
+    pub fn new(source_code: &str, output: impl FnMut(&str) + 'static) -> Self {
+        Explainer {
+            source_lines: source_code.lines().map(|l| l.chars().collect()).collect(),
+            evaluating: Vec::new(),
+            output: Box::new(output),
+        }
+    }
+
+    fn text(&self, span: Span) -> String {
+        let line = match self.source_lines.get(span.line.wrapping_sub(1)) {
+            Some(line) => line,
+            None => return String::new(),
+        };
+        let start = span.start.saturating_sub(1).min(line.len());
+        let end = span.end.clamp(start, line.len());
+        line[start..end].iter().collect()
+    }
+
+    fn say(&mut self, depth: usize, step: &str) {
+        (self.output)(&format!("{}{}", "  ".repeat(depth), step));
+    }
+}
+
+impl Debugger for Explainer {
+    fn before_statement(&mut self, frame: &mut Frame) {
+        let line = self.text(Span {
+            line: frame.span.line,
+            start: 1,
+            end: usize::MAX,
+        });
+        let step = format!("line {}: {}", frame.span.line, line.trim());
+        self.say(self.evaluating.len(), &step);
+    }
+
+    fn before_expression(&mut self, frame: &mut Frame) {
+        // an expression with parts is announced once its first part starts
+        let depth = self.evaluating.len();
+        if let Some((span, announced)) = self.evaluating.last_mut() {
+            if !*announced {
+                *announced = true;
+                let span = *span;
+                let step = format!("evaluating {}", self.text(span));
+                self.say(depth - 1, &step);
+            }
+        }
+        self.evaluating.push((frame.span, false));
+    }
+
+    fn after_expression(&mut self, frame: &mut Frame, value: &Value) {
+        let (span, has_parts) = self.evaluating.pop().unwrap_or((frame.span, false));
+        let text = self.text(span);
+        let value = value.repr();
+        // literals evaluate to themselves, there's nothing to explain about them
+        if has_parts || text != value {
+            let step = format!("{} => {}", text, value);
+            self.say(self.evaluating.len(), &step);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Explainer;
+    use crate::interpreter::Interpreter;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn explains_evaluation_steps() {
+        let source = "var x = 2;\nprint x + 3 * 4;";
+        let steps = Rc::new(RefCell::new(Vec::new()));
+        let output = Rc::clone(&steps);
+        let mut interpreter = Interpreter::new();
+        interpreter.set_debugger(Box::new(Explainer::new(source, move |step| {
+            output.borrow_mut().push(step.to_owned())
+        })));
+        interpreter.run(source).unwrap();
+        assert_eq!(
+            steps.borrow().join("\n"),
+            "line 1: var x = 2;
+line 2: print x + 3 * 4;
+evaluating x + 3 * 4
+  x => 2
+  evaluating 3 * 4
+  3 * 4 => 12
+x + 3 * 4 => 14"
+        );
+    }
+}
@@ -1,25 +1,78 @@
 use crate::error::Error;
 use crate::statement::Stmt;
-use crate::token::{Literal, Token};
+use crate::token::{Literal, Span, Token};
+use crate::types::Signature;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Identifies a single expression, so that the results of static analysis can be attached to it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ExprId(usize);
+
+impl ExprId {
+    /// Ids are unique across all parsed sources, because one interpreter can run many of them
+    pub fn new() -> Self {
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+        ExprId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// Ids deserialized inside `f` are replaced with new ones, the same old id always getting the same new one.
+    /// Ids saved by another process would otherwise collide with the ones this process hands out.
+    pub fn renumbering<T>(f: impl FnOnce() -> T) -> T {
+        RENUMBERED.with(|ids| ids.borrow_mut().clear());
+        let result = f();
+        RENUMBERED.with(|ids| ids.borrow_mut().clear());
+        result
+    }
+}
+
+thread_local! {
+    static RENUMBERED: RefCell<HashMap<usize, ExprId>> = RefCell::new(HashMap::new());
+}
+
+impl Serialize for ExprId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(self.0 as u64)
+    }
+}
+
+impl<'de> Deserialize<'de> for ExprId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let saved = u64::deserialize(deserializer)? as usize;
+        Ok(RENUMBERED.with(|ids| *ids.borrow_mut().entry(saved).or_insert_with(ExprId::new)))
+    }
+}
 
 pub trait Visitor<R> {
     fn visit_binary(&mut self, left: &Expr, operator: &Token, right: &Expr) -> Result<R, Error>;
-    fn visit_literal(&mut self, literal: &Literal) -> Result<R, Error>;
+    fn visit_literal(&mut self, literal: &Literal, span: &Span) -> Result<R, Error>;
     fn visit_unary(&mut self, operator: &Token, expr: &Expr) -> Result<R, Error>;
     fn visit_grouping(&mut self, expr: &Expr) -> Result<R, Error>;
-    fn visit_var(&mut self, name: &String, token: &Token) -> Result<R, Error>;
-    fn visit_assignment(&mut self, name: &String, expr: &Expr, token: &Token) -> Result<R, Error>;
+    fn visit_var(&mut self, name: &String, token: &Token, id: ExprId) -> Result<R, Error>;
+    fn visit_assignment(
+        &mut self,
+        name: &String,
+        expr: &Expr,
+        token: &Token,
+        id: ExprId,
+    ) -> Result<R, Error>;
     fn visit_logical(&mut self, left: &Expr, operator: &Token, right: &Expr) -> Result<R, Error>;
     fn visit_call(
         &mut self,
         callee: &Expr,
         token: &Token,
         arguments: &Vec<Expr>,
+        named: &[(String, Expr)],
     ) -> Result<R, Error>;
     fn visit_closure(
         &mut self,
-        params: &Vec<String>,
-        body: &Vec<Stmt>,
+        params: &Rc<Vec<String>>,
+        signature: &Signature,
+        defaults: &Rc<Vec<Expr>>,
+        body: &Rc<Vec<Stmt>>,
         name: &String,
         token: &Token,
     ) -> Result<R, Error>;
@@ -31,11 +84,21 @@ pub trait Visitor<R> {
         value: &Expr,
         obj: &Expr,
     ) -> Result<R, Error>;
-    fn visit_this(&mut self, token: &Token) -> Result<R, Error>;
-    fn visit_super(&mut self, token: &Token, method_name: &String) -> Result<R, Error>;
+    fn visit_this(&mut self, token: &Token, id: ExprId) -> Result<R, Error>;
+    fn visit_super(&mut self, token: &Token, method_name: &String, id: ExprId) -> Result<R, Error>;
+    fn visit_index(&mut self, object: &Expr, index: &Expr, token: &Token) -> Result<R, Error>;
+    fn visit_set_index(
+        &mut self,
+        object: &Expr,
+        index: &Expr,
+        value: &Expr,
+        token: &Token,
+    ) -> Result<R, Error>;
+    fn visit_spread(&mut self, expr: &Expr, token: &Token) -> Result<R, Error>;
+    fn visit_error(&mut self, token: &Token) -> Result<R, Error>;
 }
 
-#[derive(Debug, Clone, EnumAsInner)]
+#[derive(Debug, Clone, EnumAsInner, Serialize, Deserialize)]
 pub enum Expr {
     Binary {
         left: Box<Expr>,
@@ -44,6 +107,7 @@ pub enum Expr {
     },
     Literal {
         value: Literal,
+        span: Span,
     },
     Unary {
         operator: Token,
@@ -51,15 +115,19 @@ pub enum Expr {
     },
     Grouping {
         expr: Box<Expr>,
+        /// Includes the parentheses
+        span: Span,
     },
     Var {
         name: String,
         token: Token,
+        id: ExprId,
     },
     Assign {
         name: String,
         expr: Box<Expr>,
         token: Token,
+        id: ExprId,
     },
     Logical {
         left: Box<Expr>,
@@ -70,10 +138,14 @@ pub enum Expr {
         callee: Box<Expr>,
         token: Token,
         arguments: Vec<Expr>,
+        /// `name: value` arguments, they come after positional ones and set fields of the constructed instance
+        named: Vec<(String, Expr)>,
     },
     Closure {
-        params: Vec<String>,
-        body: Vec<Stmt>,
+        params: Rc<Vec<String>>,
+        signature: Signature,
+        defaults: Rc<Vec<Expr>>,
+        body: Rc<Vec<Stmt>>,
         name: String,
         token: Token,
     },
@@ -90,14 +162,61 @@ pub enum Expr {
     },
     This {
         token: Token,
+        id: ExprId,
     },
     Super {
         token: Token,
         method_name: String,
+        id: ExprId,
+    },
+    /// `object[index]`, the token is the closing bracket
+    Index {
+        object: Box<Expr>,
+        index: Box<Expr>,
+        token: Token,
+    },
+    /// `object[index] = value`
+    SetIndex {
+        object: Box<Expr>,
+        index: Box<Expr>,
+        value: Box<Expr>,
+        token: Token,
+    },
+    /// `...list` among call arguments, passes the list's items as separate arguments
+    Spread {
+        expr: Box<Expr>,
+        token: Token,
+    },
+    /// Missing expression kept by `Parser::parse_partial`, the token is the one found in its place
+    Error {
+        token: Token,
     },
 }
 
 impl Expr {
+    /// Source code the whole expression was parsed from
+    pub fn span(&self) -> Span {
+        match self {
+            Expr::Binary { left, right, .. } | Expr::Logical { left, right, .. } => {
+                left.span().to(right.span())
+            }
+            Expr::Literal { span, .. } | Expr::Grouping { span, .. } => *span,
+            Expr::Unary { operator, expr } => operator.span().to(expr.span()),
+            Expr::Spread { token, expr } => token.span().to(expr.span()),
+            Expr::Assign { token, expr, .. } => token.span().to(expr.span()),
+            Expr::Call { callee, token, .. } => callee.span().to(token.span()),
+            Expr::Get { expr, token, .. } => expr.span().to(token.span()),
+            Expr::Set { obj, value, .. } => obj.span().to(value.span()),
+            Expr::Index { object, token, .. } => object.span().to(token.span()),
+            Expr::SetIndex { object, value, .. } => object.span().to(value.span()),
+            Expr::Var { token, .. }
+            | Expr::Closure { token, .. }
+            | Expr::This { token, .. }
+            | Expr::Super { token, .. }
+            | Expr::Error { token } => token.span(),
+        }
+    }
+
     pub fn accept<R>(&self, visitor: &mut dyn Visitor<R>) -> Result<R, Error> {
         match self {
             Expr::Binary {
@@ -105,11 +224,16 @@ impl Expr {
                 operator,
                 right,
             } => visitor.visit_binary(left, operator, right),
-            Expr::Literal { value } => visitor.visit_literal(value),
-            Expr::Grouping { expr } => visitor.visit_grouping(expr),
+            Expr::Literal { value, span } => visitor.visit_literal(value, span),
+            Expr::Grouping { expr, .. } => visitor.visit_grouping(expr),
             Expr::Unary { operator, expr } => visitor.visit_unary(operator, expr),
-            Expr::Var { name, token } => visitor.visit_var(name, token),
-            Expr::Assign { name, expr, token } => visitor.visit_assignment(name, expr, token),
+            Expr::Var { name, token, id } => visitor.visit_var(name, token, *id),
+            Expr::Assign {
+                name,
+                expr,
+                token,
+                id,
+            } => visitor.visit_assignment(name, expr, token, *id),
             Expr::Logical {
                 left,
                 operator,
@@ -120,13 +244,16 @@ impl Expr {
                 callee,
                 token,
                 arguments,
-            } => visitor.visit_call(callee, token, arguments),
+                named,
+            } => visitor.visit_call(callee, token, arguments, named),
             Expr::Closure {
                 params,
+                signature,
+                defaults,
                 body,
                 token,
                 name,
-            } => visitor.visit_closure(params, body, name, token),
+            } => visitor.visit_closure(params, signature, defaults, body, name, token),
             Expr::Get { name, token, expr } => visitor.visit_get(name, token, expr),
             Expr::Set {
                 token,
@@ -134,8 +261,25 @@ impl Expr {
                 value,
                 obj,
             } => visitor.visit_set(token, name, value, obj),
-            Expr::This { token } => visitor.visit_this(token),
-            Expr::Super { token, method_name } => visitor.visit_super(token, method_name),
+            Expr::This { token, id } => visitor.visit_this(token, *id),
+            Expr::Super {
+                token,
+                method_name,
+                id,
+            } => visitor.visit_super(token, method_name, *id),
+            Expr::Index {
+                object,
+                index,
+                token,
+            } => visitor.visit_index(object, index, token),
+            Expr::SetIndex {
+                object,
+                index,
+                value,
+                token,
+            } => visitor.visit_set_index(object, index, value, token),
+            Expr::Spread { expr, token } => visitor.visit_spread(expr, token),
+            Expr::Error { token } => visitor.visit_error(token),
         }
     }
 }
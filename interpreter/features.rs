@@ -0,0 +1,246 @@
+/// Parts of the language that can be turned off, e.g. to keep students to what they've learned so far.
+/// Programs using a disabled feature are rejected by the parser.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Features {
+    /// `if`, `while`, `do` and `for`
+    pub control_flow: bool,
+    /// `for (var item in iterable)`
+    pub for_in: bool,
+    pub break_continue: bool,
+    /// `fn` declarations and `return`
+    pub functions: bool,
+    /// `name = value` parameters, whose arguments can be left out
+    pub default_params: bool,
+    /// `...name` rest parameters and `...list` spread arguments
+    pub rest_params: bool,
+    /// `value[index]`, read or assigned
+    pub index_expressions: bool,
+    /// `|params| => body` and `fn (params) { body }` expressions
+    pub closures: bool,
+    pub classes: bool,
+    /// `var` declarations in class bodies
+    pub class_fields: bool,
+    /// `class B : A`
+    pub inheritance: bool,
+    /// `try` and `throw`
+    pub exceptions: bool,
+    /// `match` and its patterns
+    pub pattern_matching: bool,
+    /// `name: type` after variables and parameters, `: type` after parameter lists
+    pub type_annotations: bool,
+    /// Rejects the names in `FUTURE_KEYWORDS` with a diagnostic saying they're reserved, so programs
+    /// don't break once they become keywords. It's a restriction rather than a feature, so it's off by default.
+    pub reserve_future_keywords: bool,
+}
+
+impl Features {
+    pub fn all() -> Self {
+        LanguageVersion::Full.features()
+    }
+}
+
+impl Default for Features {
+    fn default() -> Self {
+        Features::all()
+    }
+}
+
+/// Runtime behaviours programs can opt into, they change what valid programs do rather than which are valid.
+/// All of them are off by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Semantics {
+    /// `+` with a string on either side converts the other side to a string, e.g. `"count: " + 3`
+    pub string_conversion: bool,
+}
+
+/// The language as it grows through the chapters of part one of Crafting Interpreters
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LanguageVersion {
+    /// Chapter 8, variables, blocks and print
+    Statements,
+    /// Chapter 9
+    ControlFlow,
+    /// Chapter 10
+    Functions,
+    /// Chapter 12
+    Classes,
+    /// Chapter 13
+    Inheritance,
+    /// Everything, including what the book doesn't have: closures, break, continue, class fields and exceptions
+    Full,
+}
+
+impl LanguageVersion {
+    /// Version the reader has reached by the end of the chapter, chapters before 8 have no statements
+    pub fn chapter(chapter: u8) -> Option<Self> {
+        match chapter {
+            8 => Some(LanguageVersion::Statements),
+            9 => Some(LanguageVersion::ControlFlow),
+            10 | 11 => Some(LanguageVersion::Functions),
+            12 => Some(LanguageVersion::Classes),
+            13 => Some(LanguageVersion::Inheritance),
+            _ => None,
+        }
+    }
+
+    pub fn features(self) -> Features {
+        let full = self == LanguageVersion::Full;
+        Features {
+            control_flow: self >= LanguageVersion::ControlFlow,
+            for_in: full,
+            break_continue: full,
+            functions: self >= LanguageVersion::Functions,
+            default_params: full,
+            rest_params: full,
+            index_expressions: full,
+            closures: full,
+            classes: self >= LanguageVersion::Classes,
+            class_fields: full,
+            inheritance: self >= LanguageVersion::Inheritance,
+            exceptions: full,
+            pattern_matching: full,
+            type_annotations: full,
+            reserve_future_keywords: false,
+        }
+    }
+}
+
+impl From<LanguageVersion> for Features {
+    fn from(version: LanguageVersion) -> Self {
+        version.features()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Features, LanguageVersion, Semantics};
+    use crate::error::ErrorType;
+    use crate::interpreter::tests::{assert_error, assert_true_in, evaluate};
+    use crate::interpreter::Interpreter;
+
+    #[test]
+    fn language_versions_gate_features() {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_features(LanguageVersion::chapter(9).unwrap());
+        assert_true_in(
+            &mut interpreter,
+            "var a = 0; while (a < 3) a = a + 1; a == 3;",
+        );
+        let errors = interpreter
+            .run("for (var c in \"ab\") print c;")
+            .unwrap_err();
+        assert!(
+            matches!(&errors[0].error_type, ErrorType::FeatureDisabled(f) if f == "for-in loops")
+        );
+        let errors = interpreter.run("fn f() {}\nclass A {}").unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].to_string().contains("functions are not enabled"));
+        assert!(matches!(&errors[1].error_type, ErrorType::FeatureDisabled(f) if f == "classes"));
+
+        interpreter.set_features(LanguageVersion::chapter(10).unwrap());
+        let errors = interpreter.run("fn f(a = 1) {}").unwrap_err();
+        assert!(
+            matches!(&errors[0].error_type, ErrorType::FeatureDisabled(f) if f == "default parameter values")
+        );
+        for (code, feature) in [
+            ("fn f(...rest) {}", "rest parameters"),
+            ("clock(...list());", "spread arguments"),
+            ("var l; l[0];", "index expressions"),
+            ("var l; l[0] = 1;", "index expressions"),
+        ] {
+            let errors = interpreter.run(code).unwrap_err();
+            assert!(
+                matches!(&errors[0].error_type, ErrorType::FeatureDisabled(f) if f == feature),
+                "{}",
+                code
+            );
+        }
+
+        interpreter.set_features(LanguageVersion::Classes);
+        assert_true_in(
+            &mut interpreter,
+            "class A { m() { return true; } } A().m();",
+        );
+        let errors = interpreter.run("class B : A {}").unwrap_err();
+        assert!(
+            matches!(&errors[0].error_type, ErrorType::FeatureDisabled(f) if f == "inheritance")
+        );
+
+        interpreter.set_features(Features {
+            closures: false,
+            ..Features::all()
+        });
+        let errors = interpreter.run("var f = |x| => x;").unwrap_err();
+        assert!(matches!(&errors[0].error_type, ErrorType::FeatureDisabled(f) if f == "closures"));
+        let errors = interpreter
+            .run("var g = fn (x) { return x; };")
+            .unwrap_err();
+        assert!(matches!(&errors[0].error_type, ErrorType::FeatureDisabled(f) if f == "closures"));
+        assert_true_in(&mut interpreter, "try { throw 1; } catch (e) {} true;");
+    }
+
+    #[test]
+    fn future_keywords_can_be_reserved() {
+        let mut interpreter = Interpreter::new();
+        assert_true_in(&mut interpreter, "var let = true; let;");
+        interpreter.set_features(Features {
+            reserve_future_keywords: true,
+            ..Features::all()
+        });
+        let errors = interpreter
+            .run("var const = 1;\nfn f(async) {}\nprint let;")
+            .unwrap_err();
+        assert_eq!(errors.len(), 3);
+        assert_eq!(
+            errors[0].error_type.to_string(),
+            "'const' is reserved for future use, pick another name"
+        );
+        assert!(matches!(&errors[1].error_type, ErrorType::ReservedWord(w) if w == "async"));
+        assert!(matches!(&errors[2].error_type, ErrorType::ReservedWord(w) if w == "let"));
+        assert_true_in(&mut interpreter, "var matcher = true; matcher;");
+    }
+
+    #[test]
+    fn strict_mode_requires_initialized_fields() {
+        let code = "class Point {\n  var x;\n  var y;\n  var z = 0;\n  constructor(x) { this.x = x; }\n}\nclass Empty { var e; }";
+        assert!(evaluate(code).is_ok());
+
+        let mut interpreter = Interpreter::new();
+        interpreter.set_strict(true);
+        let errors: Vec<String> = interpreter
+            .run(code)
+            .unwrap_err()
+            .iter()
+            .map(|e| e.to_string())
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                "3.3-8 : Field y has no initializer and isn't assigned in the constructor",
+                "7.15-20 : Field e has no initializer and isn't assigned in the constructor"
+            ]
+        );
+        assert!(interpreter
+            .run("class A { var a; constructor() { if (true) this.a = 1; } }")
+            .is_ok());
+        assert!(interpreter
+            .run("class B { var b; constructor() { fn later() { this.b = 1; } } }")
+            .is_err());
+    }
+
+    #[test]
+    fn strings_convert_operands_of_plus_on_request() {
+        assert_error("\"count: \" + 3;", ErrorType::WrongType);
+        let mut interpreter = Interpreter::new();
+        interpreter.set_semantics(Semantics {
+            string_conversion: true,
+        });
+        assert_true_in(&mut interpreter, "\"count: \" + 3 == \"count: 3\";");
+        assert_true_in(
+            &mut interpreter,
+            "1.5 + \"x\" + true + null == \"1.5xtruenull\";",
+        );
+        assert_true_in(&mut interpreter, "1 + 2 + \"\" == \"3\";");
+        assert!(interpreter.run("1 + true;").is_err());
+    }
+}
@@ -0,0 +1,201 @@
+use crate::class::{Class, Instance};
+use crate::environment::{Environment, SlotTable};
+use crate::error::ErrorType;
+use crate::natives::{define_host_native, NativeResult};
+use crate::runtime_value::Value;
+use libloading::Library;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::mem::transmute;
+use std::rc::Rc;
+
+/// C functions `ffiCall` can call take up to this many doubles
+const MAX_FFI_ARGUMENTS: usize = 4;
+
+/// Defines `ffiLoad(path)` and `ffiCall(library, symbol, args, returns)`, hosts opt into them with `Interpreter::allow_ffi`.
+/// Only numbers cross the boundary: the C function takes doubles and returns a double, or nothing when `returns` is "void".
+pub(crate) fn define_ffi_natives(env: &mut Environment) {
+    let libraries: Rc<RefCell<HashMap<String, Library>>> = Rc::default();
+    let loaded = Rc::clone(&libraries);
+    define_host_native(
+        env,
+        "ffiLoad",
+        1,
+        |allowed| allowed.ffi,
+        move |args| {
+            let path = args[0].as_string().ok_or(ErrorType::WrongType)?;
+            if !loaded.borrow().contains_key(path) {
+                // loading runs the library's initializers, scripts are trusted with that once allowed to load it
+                let library = unsafe { Library::new(path) }.map_err(ffi_error)?;
+                loaded.borrow_mut().insert(path.clone(), library);
+            }
+            Ok(library_object(path))
+        },
+    );
+    define_host_native(
+        env,
+        "ffiCall",
+        4,
+        |allowed| allowed.ffi,
+        move |args| {
+            let path = library_path(&args[0]).ok_or(ErrorType::WrongType)?;
+            let symbol = args[1].as_string().ok_or(ErrorType::WrongType)?;
+            let numbers = match &args[2] {
+                Value::List(items) => items
+                    .borrow()
+                    .iter()
+                    .map(|item| item.as_number().copied().ok_or(ErrorType::WrongType))
+                    .collect::<Result<Vec<f64>, ErrorType>>()?,
+                _ => return Err(ErrorType::WrongType),
+            };
+            let returns = args[3].as_string().ok_or(ErrorType::WrongType)?;
+            let libraries = libraries.borrow();
+            let library = libraries.get(&path).ok_or(ErrorType::WrongType)?;
+            call(library, symbol, &numbers, returns)
+        },
+    );
+}
+
+/// What `ffiLoad` returns, a `Library` instance holding the path it was loaded from
+fn library_object(path: &str) -> Value {
+    let class = Class::from_parts(
+        String::from("Library"),
+        Vec::new(),
+        HashMap::new(),
+        None,
+        Rc::new(RefCell::new(Environment::new())),
+        SlotTable::default(),
+    );
+    let mut fields = HashMap::new();
+    fields.insert(String::from("path"), Value::String(path.to_owned()));
+    Value::Instance(Rc::new(RefCell::new(Instance::with_fields(
+        Rc::new(class),
+        fields,
+    ))))
+}
+
+fn library_path(value: &Value) -> Option<String> {
+    let instance = value.as_instance()?.borrow();
+    if instance.class().to_string() != "Library" {
+        return None;
+    }
+    let path = instance.fields().find(|(name, _)| *name == "path")?.1;
+    path.as_string().cloned()
+}
+
+fn call(library: &Library, symbol: &str, args: &[f64], returns: &str) -> NativeResult {
+    if args.len() > MAX_FFI_ARGUMENTS {
+        return Err(ErrorType::Ffi(format!(
+            "{} takes more than {} arguments",
+            symbol, MAX_FFI_ARGUMENTS
+        )));
+    }
+    let returns_value = match returns {
+        "f64" => true,
+        "void" => false,
+        _ => {
+            return Err(ErrorType::Ffi(format!(
+                "{} isn't a return type, use f64 or void",
+                returns
+            )))
+        }
+    };
+    // the signature can't be checked, calling with the wrong one is the script's mistake like it is in C
+    let address = unsafe { library.get::<*const ()>(symbol.as_bytes()) }.map_err(ffi_error)?;
+    let address = *address;
+    let result = unsafe {
+        match (returns_value, args) {
+            (true, []) => transmute::<*const (), extern "C" fn() -> f64>(address)(),
+            (true, [a]) => transmute::<*const (), extern "C" fn(f64) -> f64>(address)(*a),
+            (true, [a, b]) => {
+                transmute::<*const (), extern "C" fn(f64, f64) -> f64>(address)(*a, *b)
+            }
+            (true, [a, b, c]) => {
+                transmute::<*const (), extern "C" fn(f64, f64, f64) -> f64>(address)(*a, *b, *c)
+            }
+            (true, [a, b, c, d]) => {
+                transmute::<*const (), extern "C" fn(f64, f64, f64, f64) -> f64>(address)(
+                    *a, *b, *c, *d,
+                )
+            }
+            (false, []) => {
+                transmute::<*const (), extern "C" fn()>(address)();
+                return Ok(Value::Null);
+            }
+            (false, [a]) => {
+                transmute::<*const (), extern "C" fn(f64)>(address)(*a);
+                return Ok(Value::Null);
+            }
+            (false, [a, b]) => {
+                transmute::<*const (), extern "C" fn(f64, f64)>(address)(*a, *b);
+                return Ok(Value::Null);
+            }
+            (false, [a, b, c]) => {
+                transmute::<*const (), extern "C" fn(f64, f64, f64)>(address)(*a, *b, *c);
+                return Ok(Value::Null);
+            }
+            (false, [a, b, c, d]) => {
+                transmute::<*const (), extern "C" fn(f64, f64, f64, f64)>(address)(*a, *b, *c, *d);
+                return Ok(Value::Null);
+            }
+            _ => unreachable!("argument count is checked above"),
+        }
+    };
+    Ok(Value::Number(result))
+}
+
+fn ffi_error(e: libloading::Error) -> ErrorType {
+    ErrorType::Ffi(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::ErrorType;
+    use crate::interpreter::Interpreter;
+    use crate::limits::Capabilities;
+
+    const LIBM: &str = "var m = ffiLoad(\"libm.so.6\");\n\
+        fn numbers(...items) { return items; }";
+
+    #[test]
+    fn calls_number_functions_in_c_libraries() {
+        let mut interpreter = Interpreter::new();
+        interpreter.allow_ffi();
+        interpreter.run(LIBM).unwrap();
+        let value = interpreter
+            .run("ffiCall(m, \"pow\", numbers(2, 10), \"f64\") + ffiCall(m, \"cos\", numbers(0), \"f64\");")
+            .unwrap();
+        assert_eq!(value.as_number(), Some(&1025.0));
+
+        for (code, expected) in [
+            ("ffiCall(m, \"cos\", numbers(\"0\"), \"f64\");", "WrongType"),
+            ("ffiCall(1, \"cos\", numbers(0), \"f64\");", "WrongType"),
+            ("ffiCall(m, \"cos\", numbers(0), \"str\");", "Ffi"),
+            (
+                "ffiCall(m, \"cos\", numbers(1, 2, 3, 4, 5), \"f64\");",
+                "Ffi",
+            ),
+            ("ffiCall(m, \"noSuchFunction\", numbers(), \"f64\");", "Ffi"),
+            ("ffiLoad(\"libnothing.so\");", "Ffi"),
+        ] {
+            let errors = interpreter.run(code).unwrap_err();
+            assert_eq!(errors[0].error_type.code(), expected, "{}", code);
+        }
+    }
+
+    #[test]
+    fn sandboxes_deny_foreign_calls() {
+        let mut interpreter = Interpreter::sandboxed();
+        interpreter.allow_ffi();
+        let errors = interpreter.run(LIBM).unwrap_err();
+        assert!(matches!(
+            errors[0].error_type,
+            ErrorType::CapabilityDenied(_)
+        ));
+        interpreter.set_capabilities(Capabilities {
+            ffi: true,
+            ..Capabilities::none()
+        });
+        assert!(interpreter.run(LIBM).is_ok());
+    }
+}
@@ -0,0 +1,369 @@
+use crate::error::{Error, ErrorType};
+use crate::expr::Expr;
+use crate::interpreter::Interpreter;
+use crate::lexer::Lexer;
+use crate::parser::Parser;
+use crate::resolver::Resolver;
+use crate::scopes::{Reference, ScopeReport};
+use crate::statement::Stmt;
+use crate::token::{Span, Token, TokenType};
+use crate::types::check_types;
+use crate::walk::{walk_stmt, walk_stmts, Walk};
+use serde::Serialize;
+
+/// Fixing a file goes over it again after every round, as errors hide the ones behind them
+const MAX_FIX_ROUNDS: usize = 10;
+
+/// Replaces the columns from `start` up to, but without, `end` on the line.
+/// Lines and columns count from 1 like spans do, an empty range inserts the text.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TextEdit {
+    pub line: usize,
+    pub start: usize,
+    pub end: usize,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Fix {
+    pub title: String,
+    pub edits: Vec<TextEdit>,
+}
+
+/// Error or warning found without running the code, with the fix for it when it's known
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Diagnostic {
+    /// Name of the error type, see `ErrorType::code`
+    pub code: String,
+    pub message: String,
+    pub span: Span,
+    /// Warnings are about code that runs, but likely doesn't do what was meant
+    pub warning: bool,
+    pub fix: Option<Fix>,
+}
+
+impl Diagnostic {
+    pub(crate) fn new(error: &Error, warning: bool, fix: Option<Fix>) -> Self {
+        Diagnostic {
+            code: error.error_type.code(),
+            message: error.error_type.to_string(),
+            span: error.span,
+            warning,
+            fix,
+        }
+    }
+}
+
+/// Errors of the lexer, parser and resolver, then the warnings, the ones that can be fixed come with fixes:
+/// missing semicolons, assignments used as conditions and misspelled variables
+pub fn diagnose(source_code: &str) -> Vec<Diagnostic> {
+    let tokens = match Lexer::new(source_code).scan_tokens() {
+        Ok(tokens) => tokens,
+        Err(errors) => {
+            return errors
+                .iter()
+                .map(|e| Diagnostic::new(e, false, None))
+                .collect()
+        }
+    };
+    let (stmts, errors) = Parser::new(&tokens).parse_partial();
+    let mut diagnostics: Vec<Diagnostic> = errors
+        .iter()
+        .map(|error| Diagnostic::new(error, false, missing_semicolon(&tokens, error)))
+        .collect();
+
+    // what did parse is checked too
+    let mut interpreter = Interpreter::new();
+    let globals = interpreter.global_names();
+    let mut resolver = Resolver::new(&mut interpreter);
+    resolver.record_scopes();
+    if let Err(errors) = resolver.resolve(&stmts) {
+        diagnostics.extend(errors.iter().map(|e| Diagnostic::new(e, false, None)));
+    }
+    let warnings = resolver.take_warnings();
+    diagnostics.extend(warnings.iter().map(|e| Diagnostic::new(e, true, None)));
+    let report = resolver.take_report().unwrap_or_default();
+    let mismatches = check_types(&stmts);
+    diagnostics.extend(mismatches.iter().map(|e| Diagnostic::new(e, false, None)));
+
+    let mut conditions = AssignmentConditions::default();
+    walk_stmts(&mut conditions, &stmts);
+    for (token, value) in conditions.0 {
+        let error = Error {
+            token: token.clone(),
+            error_type: ErrorType::AssignmentInCondition,
+            span: token.span().to(value.span()),
+        };
+        diagnostics.push(Diagnostic::new(&error, true, equals_fix(&tokens, token)));
+    }
+    for reference in &report.references {
+        if let Some(fix) = misspelling_fix(&report, &globals, reference) {
+            let token = Token::new(
+                TokenType::Identifier(reference.name.clone()),
+                reference.span.line,
+                reference.span.start,
+                reference.span.end,
+            );
+            let error = Error::new(&token, ErrorType::UndefinedVariable);
+            diagnostics.push(Diagnostic::new(&error, false, Some(fix)));
+        }
+    }
+    diagnostics
+}
+
+/// Source code with every fix applied, and how many fixes were applied
+pub fn fix_source(source_code: &str) -> (String, usize) {
+    let mut source_code = source_code.to_owned();
+    let mut applied = 0;
+    for _ in 0..MAX_FIX_ROUNDS {
+        let diagnostics = diagnose(&source_code);
+        let fixes: Vec<&Fix> = diagnostics.iter().filter_map(|d| d.fix.as_ref()).collect();
+        if fixes.is_empty() {
+            break;
+        }
+        let (fixed, count) = apply_fixes(&source_code, &fixes);
+        if count == 0 {
+            break;
+        }
+        source_code = fixed;
+        applied += count;
+    }
+    (source_code, applied)
+}
+
+/// Applies the fixes whose edits don't overlap an earlier fix, returns the new source and how many were applied
+pub fn apply_fixes(source_code: &str, fixes: &[&Fix]) -> (String, usize) {
+    let mut ranges: Vec<(usize, usize, &str)> = Vec::new();
+    let mut applied = 0;
+    for fix in fixes {
+        let edits: Option<Vec<_>> = fix
+            .edits
+            .iter()
+            .map(|edit| {
+                let start = offset(source_code, edit.line, edit.start)?;
+                let end = offset(source_code, edit.line, edit.end)?;
+                Some((start, end, edit.text.as_str()))
+            })
+            .collect();
+        let edits = match edits {
+            Some(edits) => edits,
+            None => continue,
+        };
+        let overlaps = edits.iter().any(|(start, end, _)| {
+            ranges
+                .iter()
+                .any(|(s, e, _)| start < e && s < end || start == s)
+        });
+        if !overlaps {
+            ranges.extend(edits);
+            applied += 1;
+        }
+    }
+    ranges.sort_by_key(|(start, _, _)| *start);
+    let mut fixed = source_code.to_owned();
+    for (start, end, text) in ranges.into_iter().rev() {
+        fixed.replace_range(start..end, text);
+    }
+    (fixed, applied)
+}
+
+/// Byte offset of the column, the column right after the end of the line is allowed
+fn offset(source_code: &str, line: usize, column: usize) -> Option<usize> {
+    let mut line_start = 0;
+    for (number, text) in source_code.split('\n').enumerate() {
+        if number + 1 == line {
+            let text = text.strip_suffix('\r').unwrap_or(text);
+            return text
+                .char_indices()
+                .map(|(index, _)| index)
+                .chain(std::iter::once(text.len()))
+                .nth(column.checked_sub(1)?)
+                .map(|index| line_start + index);
+        }
+        line_start += text.len() + 1;
+    }
+    None
+}
+
+fn missing_semicolon(tokens: &[Token], error: &Error) -> Option<Fix> {
+    if !matches!(error.error_type, ErrorType::ExpectedSemicolon) {
+        return None;
+    }
+    let index = tokens.iter().position(|token| *token == error.token)?;
+    let previous = &tokens[index.checked_sub(1)?];
+    Some(Fix {
+        title: String::from("Insert missing semicolon"),
+        edits: vec![TextEdit {
+            line: previous.line,
+            start: previous.end + 1,
+            end: previous.end + 1,
+            text: String::from(";"),
+        }],
+    })
+}
+
+/// Conditions of ifs and loops that are a bare assignment, by the assigned variable's token
+#[derive(Default)]
+struct AssignmentConditions<'a>(Vec<(&'a Token, &'a Expr)>);
+
+impl<'a> Walk<'a> for AssignmentConditions<'a> {
+    fn stmt(&mut self, stmt: &'a Stmt) {
+        if let Stmt::If { condition, .. }
+        | Stmt::While { condition, .. }
+        | Stmt::DoWhile { condition, .. } = stmt
+        {
+            if let Expr::Assign { token, expr, .. } = condition {
+                self.0.push((token, expr));
+            }
+        }
+        walk_stmt(self, stmt);
+    }
+}
+
+fn equals_fix(tokens: &[Token], variable: &Token) -> Option<Fix> {
+    let index = tokens.iter().position(|token| token == variable)?;
+    let assign = tokens.get(index + 1)?;
+    if assign.token_type != TokenType::Assign {
+        return None;
+    }
+    Some(Fix {
+        title: String::from("Compare with == instead"),
+        edits: vec![TextEdit {
+            line: assign.line,
+            start: assign.start,
+            end: assign.end + 1,
+            text: String::from("=="),
+        }],
+    })
+}
+
+/// Replacement for a global that is never defined, when a visible name is spelled almost the same
+fn misspelling_fix(report: &ScopeReport, globals: &[String], reference: &Reference) -> Option<Fix> {
+    if reference.resolution.is_some() || globals.contains(&reference.name) {
+        return None;
+    }
+    let mut visible: Vec<&String> = globals.iter().collect();
+    let mut scope = report.scopes.get(reference.in_scope);
+    while let Some(current) = scope {
+        visible.extend(&current.names);
+        scope = current.parent.and_then(|parent| report.scopes.get(parent));
+    }
+    if visible.contains(&&reference.name) {
+        return None;
+    }
+    // one typo in every three letters, short names are too close to everything
+    let allowed = reference.name.chars().count() / 3;
+    if allowed == 0 {
+        return None;
+    }
+    let (distance, closest) = visible
+        .into_iter()
+        .map(|name| (edit_distance(&reference.name, name), name))
+        .min()?;
+    if distance > allowed {
+        return None;
+    }
+    let span = reference.span;
+    Some(Fix {
+        title: format!("Change to {}", closest),
+        edits: vec![TextEdit {
+            line: span.line,
+            start: span.start,
+            end: span.end + 1,
+            text: closest.clone(),
+        }],
+    })
+}
+
+/// Levenshtein distance between the names
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{diagnose, edit_distance, fix_source};
+
+    #[test]
+    fn inserts_missing_semicolons() {
+        let diagnostics = diagnose("var a = 1\nprint a");
+        assert_eq!(diagnostics[0].code, "ExpectedSemicolon");
+        let edit = &diagnostics[0].fix.as_ref().unwrap().edits[0];
+        assert_eq!((edit.line, edit.start, edit.end), (1, 10, 10));
+        assert_eq!(
+            fix_source("var a = 1\nprint a"),
+            (String::from("var a = 1;\nprint a;"), 2)
+        );
+    }
+
+    #[test]
+    fn compares_instead_of_assigning_in_conditions() {
+        let source = "var a = 1;\nif (a = 2) print a;\nwhile ((a = 3)) a = a - 1;";
+        let diagnostics = diagnose(source);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].code, "AssignmentInCondition");
+        assert!(diagnostics[0].warning);
+        assert_eq!(
+            fix_source(source).0,
+            "var a = 1;\nif (a == 2) print a;\nwhile ((a = 3)) a = a - 1;"
+        );
+    }
+
+    #[test]
+    fn suggests_close_variable_names() {
+        let source =
+            "var counter = 1;\nfn f(total) {\n    return totl + countr + clok() + unknown;\n}";
+        let diagnostics = diagnose(source);
+        let titles: Vec<_> = diagnostics
+            .iter()
+            .map(|d| d.fix.as_ref().unwrap().title.as_str())
+            .collect();
+        assert_eq!(
+            titles,
+            vec!["Change to total", "Change to counter", "Change to clock"]
+        );
+        assert_eq!(
+            fix_source(source).0,
+            "var counter = 1;\nfn f(total) {\n    return total + counter + clock() + unknown;\n}"
+        );
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+
+    #[test]
+    fn warns_about_unreachable_arms() {
+        let diagnostics =
+            diagnose("match (1) {\n    1 => {}\n    n => {}\n    1 => {}\n    [n] => {}\n}");
+        let lines: Vec<usize> = diagnostics.iter().map(|d| d.span.line).collect();
+        assert_eq!(lines, vec![4, 5]);
+        assert!(diagnostics
+            .iter()
+            .all(|d| d.warning && d.code == "UnreachableArm"));
+    }
+
+    #[test]
+    fn warns_about_shadowed_variables() {
+        let diagnostics = diagnose("fn f(a) {\n    { var a = 2; }\n}");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].code, "ShadowedVariable");
+        assert!(diagnostics[0].warning);
+        assert_eq!(diagnostics[0].span.line, 2);
+    }
+
+    #[test]
+    fn reports_errors_without_fixes() {
+        let diagnostics = diagnose("return 1;");
+        assert_eq!(diagnostics[0].code, "CantReturnOutsideFunction");
+        assert_eq!(diagnostics[0].fix, None);
+        assert_eq!(fix_source("return 1;").1, 0);
+    }
+}
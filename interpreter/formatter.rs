@@ -0,0 +1,851 @@
+use crate::error::Error;
+use crate::expr::{Expr, ExprId, Visitor as ExprVisitor};
+use crate::lexer::{Comment, Lexer};
+use crate::parser::Parser;
+use crate::pattern::Pattern;
+use crate::runtime_value::format_number;
+use crate::statement::{Arm, Catch, Stmt, Visitor as StmtVisitor};
+use crate::token::{Literal, Span, Token, TokenType};
+use crate::types::{Signature, Type};
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+const INDENT: &str = "    ";
+
+/// Prints the program back as canonical source: four spaces of indentation, one statement per line
+/// and a blank line around functions and classes. Comments go on lines of their own before the statement
+/// they precede, comments after a statement stay at the end of its line when it's printed on one.
+pub fn format_source(source_code: &str) -> Result<String, Vec<Error>> {
+    let mut lexer = Lexer::new(source_code);
+    let tokens = lexer.scan_tokens()?;
+    let mut parser = Parser::new(&tokens);
+    let stmts = parser.parse_tokens()?;
+
+    let mut formatter = Formatter::new(source_code, tokens, lexer.comments);
+    formatter.program(&stmts).map_err(|e| vec![e])
+}
+
+/// Formats the parts of the program that parse and keeps the statements that don't as they were written,
+/// so editors can format a file while it's being typed. The errors are the ones of `Parser::parse_partial`.
+pub fn format_partial(source_code: &str) -> (String, Vec<Error>) {
+    let mut lexer = Lexer::new(source_code);
+    let tokens = match lexer.scan_tokens() {
+        Ok(tokens) => tokens,
+        Err(errors) => return (source_code.to_owned(), errors),
+    };
+    let (stmts, errors) = Parser::new(&tokens).parse_partial();
+    let mut formatter = Formatter::new(source_code, tokens, lexer.comments);
+    match formatter.program(&stmts) {
+        Ok(formatted) => (formatted, errors),
+        Err(error) => (source_code.to_owned(), vec![error]),
+    }
+}
+
+fn is_declaration(stmt: &Stmt) -> bool {
+    matches!(stmt, Stmt::Function { .. } | Stmt::Class { .. })
+}
+
+/// `: type`, or nothing when there's no annotation
+fn annotation(annotation: &Option<Type>) -> String {
+    match annotation {
+        Some(annotation) => format!(": {}", annotation),
+        None => String::new(),
+    }
+}
+
+/// Statements are printed without the indentation of their first line, the enclosing block adds it
+struct Formatter {
+    depth: usize,
+    /// Source the statements that failed to parse and number literals are copied from
+    lines: Vec<String>,
+    /// Tell where the blocks end, so the comments at their ends stay inside of them
+    tokens: Vec<Token>,
+    /// Comments not printed yet, in the order they're in the source
+    comments: VecDeque<Comment>,
+    /// Start of the statement being printed, or the end of the last empty block printed after it
+    cursor: Span,
+}
+
+type FormatResult = Result<String, Error>;
+
+impl Formatter {
+    fn new(source_code: &str, tokens: Vec<Token>, comments: Vec<Comment>) -> Self {
+        Formatter {
+            depth: 0,
+            lines: source_code.lines().map(String::from).collect(),
+            tokens,
+            comments: comments.into(),
+            cursor: Span {
+                line: 0,
+                start: 0,
+                end: 0,
+            },
+        }
+    }
+
+    fn program(&mut self, stmts: &[Stmt]) -> FormatResult {
+        let mut output = String::new();
+        for (i, stmt) in stmts.iter().enumerate() {
+            if i > 0 && (is_declaration(stmt) || is_declaration(&stmts[i - 1])) {
+                output.push('\n');
+            }
+            let span = stmt.span();
+            self.cursor = span;
+            for comment in self.comments_before(span) {
+                output.push_str(&comment);
+                output.push('\n');
+            }
+            let formatted = stmt.accept(self)?;
+            output.push_str(&formatted);
+            output.push_str(&self.trailing_comment(span.line, &formatted));
+            output.push('\n');
+        }
+        // comments the statements didn't take, e.g. ones at the end of the file
+        for comment in self.comments.drain(..) {
+            output.push_str(&comment.text);
+            output.push('\n');
+        }
+        Ok(output)
+    }
+
+    /// Comments starting before the position
+    fn comments_before(&mut self, span: Span) -> Vec<String> {
+        let mut comments = Vec::new();
+        while let Some(comment) = self.comments.front() {
+            if (comment.line, comment.start) >= (span.line, span.start) {
+                break;
+            }
+            comments.extend(self.comments.pop_front().map(|comment| comment.text));
+        }
+        comments
+    }
+
+    /// Comment after the code on the statement's line, if the statement is printed on a single line
+    fn trailing_comment(&mut self, line: usize, formatted: &str) -> String {
+        match self.comments.front() {
+            Some(comment)
+                if comment.line == line && comment.trailing && !formatted.contains('\n') =>
+            {
+                self.comments
+                    .pop_front()
+                    .map_or(String::new(), |comment| format!(" {}", comment.text))
+            }
+            _ => String::new(),
+        }
+    }
+
+    /// Index of the first token at the position or after it
+    fn token_at(&self, span: Span) -> Option<usize> {
+        self.tokens
+            .iter()
+            .position(|token| (token.line, token.start) >= (span.line, span.start))
+    }
+
+    /// Brace closing the block, class or match the statement is the last one of
+    fn closing_brace(&self, last: Span) -> Option<Span> {
+        let mut depth = 0;
+        for token in &self.tokens[self.token_at(last)?..] {
+            match token.token_type {
+                TokenType::OpenBrace => depth += 1,
+                TokenType::CloseBrace if depth == 0 => return Some(token.span()),
+                TokenType::CloseBrace => depth -= 1,
+                _ => (),
+            }
+        }
+        None
+    }
+
+    /// Closing brace of the next `{}` in the statement being printed. Blocks are printed in the order
+    /// they're written in, so it's the one of the empty block being printed.
+    fn next_empty_block(&mut self) -> Option<Span> {
+        let first = self.token_at(self.cursor)?;
+        let close = self.tokens[first..].windows(2).find_map(|pair| {
+            match (&pair[0].token_type, &pair[1].token_type) {
+                (TokenType::OpenBrace, TokenType::CloseBrace) => Some(pair[1].span()),
+                _ => None,
+            }
+        })?;
+        self.cursor = close;
+        Some(close)
+    }
+
+    /// Indented lines of the items of a block, class or match with the comments among them.
+    /// `span` tells where an item starts, `format` prints it.
+    fn members<T>(
+        &mut self,
+        items: &[T],
+        span: impl Fn(&T) -> Span,
+        format: impl Fn(&mut Self, &T) -> FormatResult,
+    ) -> Result<Vec<String>, Error> {
+        let indent = INDENT.repeat(self.depth);
+        let mut lines = Vec::new();
+        for item in items {
+            let start = span(item);
+            self.cursor = start;
+            for comment in self.comments_before(start) {
+                lines.push(format!("{}{}", indent, comment));
+            }
+            let formatted = format(self, item)?;
+            let trailing = self.trailing_comment(start.line, &formatted);
+            lines.push(format!("{}{}{}", indent, formatted, trailing));
+        }
+        if let Some(closing) = items.last().and_then(|last| self.closing_brace(span(last))) {
+            for comment in self.comments_before(closing) {
+                lines.push(format!("{}{}", indent, comment));
+            }
+            self.cursor = closing;
+        }
+        Ok(lines)
+    }
+
+    /// Source from the start of the token to the end of the other one
+    fn written(&self, token: &Token, end: &Token) -> String {
+        let mut text = Vec::new();
+        for line in token.line..=end.line.max(token.line) {
+            let chars: Vec<char> = line
+                .checked_sub(1)
+                .and_then(|index| self.lines.get(index))
+                .map_or(Vec::new(), |line| line.chars().collect());
+            let from = match line == token.line {
+                true => token.start.saturating_sub(1),
+                false => 0,
+            };
+            let to = if line == end.line {
+                end.end
+            } else {
+                chars.len()
+            };
+            let to = to.min(chars.len());
+            text.push(chars[from.min(to)..to].iter().collect::<String>());
+        }
+        text.join("\n")
+    }
+
+    /// Numbers are written the way they're in the source, like `0x1F` or `1_000`. Literals the parser
+    /// made up point at other tokens, so the source is used only when it scans to the same number.
+    fn number_lexeme(&self, number: f64, span: &Span) -> Option<String> {
+        let line = self.lines.get(span.line.checked_sub(1)?)?;
+        let lexeme: String = line
+            .chars()
+            .skip(span.start.saturating_sub(1))
+            .take((span.end + 1).checked_sub(span.start)?)
+            .collect();
+        match Lexer::new(&lexeme).scan_tokens().ok()?.as_slice() {
+            [Token {
+                token_type: TokenType::Literal(Literal::Number(n)),
+                ..
+            }, _]
+                if *n == number =>
+            {
+                Some(lexeme)
+            }
+            _ => None,
+        }
+    }
+
+    fn block(&mut self, stmts: &[Stmt]) -> FormatResult {
+        if stmts.is_empty() {
+            let comments = match self.next_empty_block() {
+                Some(closing) => self.comments_before(closing),
+                None => Vec::new(),
+            };
+            if comments.is_empty() {
+                return Ok(String::from("{}"));
+            }
+            let indent = INDENT.repeat(self.depth + 1);
+            return Ok(format!(
+                "{{\n{}{}\n{}}}",
+                indent,
+                comments.join(&format!("\n{}", indent)),
+                INDENT.repeat(self.depth)
+            ));
+        }
+        self.depth += 1;
+        let lines = self.members(stmts, Stmt::span, |formatter, stmt| stmt.accept(formatter));
+        self.depth -= 1;
+        Ok(format!(
+            "{{\n{}\n{}}}",
+            lines?.join("\n"),
+            INDENT.repeat(self.depth)
+        ))
+    }
+
+    /// Function bodies are parsed into a single block
+    fn body(&mut self, body: &[Stmt]) -> FormatResult {
+        match body {
+            [Stmt::Block { stmts, .. }] => self.block(stmts),
+            stmts => self.block(stmts),
+        }
+    }
+
+    fn function(
+        &mut self,
+        name: &str,
+        params: &[String],
+        signature: &Signature,
+        defaults: &[Expr],
+        body: &[Stmt],
+    ) -> FormatResult {
+        Ok(format!(
+            "{}({}){} {}",
+            name,
+            self.params(params, signature, defaults)?,
+            annotation(&signature.returns),
+            self.body(body)?
+        ))
+    }
+
+    /// Parameters with their annotations, the defaulted ones and the rest one last
+    fn params(
+        &mut self,
+        params: &[String],
+        signature: &Signature,
+        defaults: &[Expr],
+    ) -> FormatResult {
+        let positional = params.len() - signature.variadic as usize;
+        let required = positional - defaults.len();
+        let mut list = Vec::new();
+        for (i, (param, type_)) in params.iter().zip(&signature.params).enumerate() {
+            let mut param = format!("{}{}", param, annotation(type_));
+            if (required..positional).contains(&i) {
+                param = format!("{} = {}", param, defaults[i - required].accept(self)?);
+            }
+            list.push(param);
+        }
+        if let (true, Some(rest)) = (signature.variadic, list.last_mut()) {
+            rest.insert_str(0, "...");
+        }
+        Ok(list.join(", "))
+    }
+
+    /// Fields bound to their own names are written without their pattern
+    fn pattern(&mut self, pattern: &Pattern) -> FormatResult {
+        match pattern {
+            Pattern::Literal { value, token } => self.visit_literal(value, &token.span()),
+            Pattern::Binding { name, .. } => Ok(name.clone()),
+            Pattern::List { items, rest, .. } => {
+                let mut parts = items
+                    .iter()
+                    .map(|item| self.pattern(item))
+                    .collect::<Result<Vec<_>, _>>()?;
+                if let Some(rest) = rest {
+                    parts.push(format!("...{}", rest));
+                }
+                Ok(format!("[{}]", parts.join(", ")))
+            }
+            Pattern::Instance { class, fields, .. } => {
+                let mut parts = Vec::new();
+                for (name, field) in fields {
+                    parts.push(match field {
+                        Pattern::Binding { name: bound, .. } if bound == name => name.clone(),
+                        field => format!("{}: {}", name, self.pattern(field)?),
+                    });
+                }
+                let fields = match parts.is_empty() {
+                    true => String::from("{}"),
+                    false => format!("{{ {} }}", parts.join(", ")),
+                };
+                Ok(match class {
+                    Some(class) => format!("{} {}", class.accept(self)?, fields),
+                    None => fields,
+                })
+            }
+        }
+    }
+
+    fn exprs(&mut self, exprs: &[Expr]) -> Result<Vec<String>, Error> {
+        exprs.iter().map(|expr| expr.accept(self)).collect()
+    }
+}
+
+impl ExprVisitor<String> for Formatter {
+    fn visit_binary(&mut self, left: &Expr, operator_token: &Token, right: &Expr) -> FormatResult {
+        Ok(format!(
+            "{} {} {}",
+            left.accept(self)?,
+            operator_token.operator(),
+            right.accept(self)?
+        ))
+    }
+
+    fn visit_literal(&mut self, literal: &Literal, span: &Span) -> FormatResult {
+        Ok(match literal {
+            Literal::String(s) => format!("\"{}\"", s),
+            Literal::Number(n) => self
+                .number_lexeme(*n, span)
+                .unwrap_or_else(|| format_number(*n)),
+            Literal::Bool(b) => b.to_string(),
+            Literal::Null => String::from("null"),
+        })
+    }
+
+    fn visit_unary(&mut self, operator_token: &Token, expr: &Expr) -> FormatResult {
+        Ok(format!(
+            "{}{}",
+            operator_token.operator(),
+            expr.accept(self)?
+        ))
+    }
+
+    fn visit_grouping(&mut self, expr: &Expr) -> FormatResult {
+        Ok(format!("({})", expr.accept(self)?))
+    }
+
+    fn visit_var(&mut self, name: &String, _token: &Token, _id: ExprId) -> FormatResult {
+        Ok(name.clone())
+    }
+
+    fn visit_assignment(
+        &mut self,
+        name: &String,
+        expr: &Expr,
+        _token: &Token,
+        _id: ExprId,
+    ) -> FormatResult {
+        Ok(format!("{} = {}", name, expr.accept(self)?))
+    }
+
+    fn visit_logical(&mut self, left: &Expr, operator_token: &Token, right: &Expr) -> FormatResult {
+        self.visit_binary(left, operator_token, right)
+    }
+
+    fn visit_call(
+        &mut self,
+        callee: &Expr,
+        _token: &Token,
+        arguments: &Vec<Expr>,
+        named: &[(String, Expr)],
+    ) -> FormatResult {
+        let mut arguments = self.exprs(arguments)?;
+        for (name, value) in named {
+            arguments.push(format!("{}: {}", name, value.accept(self)?));
+        }
+        Ok(format!(
+            "{}({})",
+            callee.accept(self)?,
+            arguments.join(", ")
+        ))
+    }
+
+    fn visit_closure(
+        &mut self,
+        params: &Rc<Vec<String>>,
+        signature: &Signature,
+        defaults: &Rc<Vec<Expr>>,
+        body: &Rc<Vec<Stmt>>,
+        _name: &String,
+        token: &Token,
+    ) -> FormatResult {
+        if token.token_type == TokenType::Function {
+            return Ok(format!(
+                "fn {}",
+                self.function("", params, signature, defaults, body)?
+            ));
+        }
+        // `|x| => expr` is parsed into a lone return
+        let body = match &body[..] {
+            [Stmt::Return {
+                value: Some(value), ..
+            }] => value.accept(self)?,
+            body => self.body(body)?,
+        };
+        Ok(format!(
+            "|{}|{} => {}",
+            self.params(params, signature, defaults)?,
+            annotation(&signature.returns),
+            body
+        ))
+    }
+
+    fn visit_get(&mut self, name: &String, _token: &Token, expr: &Expr) -> FormatResult {
+        Ok(format!("{}.{}", expr.accept(self)?, name))
+    }
+
+    fn visit_set(
+        &mut self,
+        _token: &Token,
+        name: &String,
+        value: &Expr,
+        obj: &Expr,
+    ) -> FormatResult {
+        Ok(format!(
+            "{}.{} = {}",
+            obj.accept(self)?,
+            name,
+            value.accept(self)?
+        ))
+    }
+
+    fn visit_this(&mut self, _token: &Token, _id: ExprId) -> FormatResult {
+        Ok(String::from("this"))
+    }
+
+    fn visit_super(&mut self, _token: &Token, method_name: &String, _id: ExprId) -> FormatResult {
+        Ok(format!("super.{}", method_name))
+    }
+
+    fn visit_index(&mut self, object: &Expr, index: &Expr, _token: &Token) -> FormatResult {
+        Ok(format!("{}[{}]", object.accept(self)?, index.accept(self)?))
+    }
+
+    fn visit_set_index(
+        &mut self,
+        object: &Expr,
+        index: &Expr,
+        value: &Expr,
+        _token: &Token,
+    ) -> FormatResult {
+        Ok(format!(
+            "{}[{}] = {}",
+            object.accept(self)?,
+            index.accept(self)?,
+            value.accept(self)?
+        ))
+    }
+
+    fn visit_spread(&mut self, expr: &Expr, _token: &Token) -> FormatResult {
+        Ok(format!("...{}", expr.accept(self)?))
+    }
+
+    /// The token found in place of the expression belongs to the enclosing node
+    fn visit_error(&mut self, _token: &Token) -> FormatResult {
+        Ok(String::new())
+    }
+}
+
+impl StmtVisitor<String> for Formatter {
+    fn visit_print_stmt(&mut self, expr: &Expr) -> FormatResult {
+        Ok(format!("print {};", expr.accept(self)?))
+    }
+
+    fn visit_expr_stmt(&mut self, expr: &Expr) -> FormatResult {
+        Ok(format!("{};", expr.accept(self)?))
+    }
+
+    fn visit_var(
+        &mut self,
+        name: &String,
+        value: &Option<Expr>,
+        annotation: &Option<Type>,
+        _span: &Span,
+    ) -> FormatResult {
+        let annotation = self::annotation(annotation);
+        Ok(match value {
+            Some(value) => format!("var {}{} = {};", name, annotation, value.accept(self)?),
+            None => format!("var {}{};", name, annotation),
+        })
+    }
+
+    fn visit_block_stmt(&mut self, stmts: &Vec<Stmt>) -> FormatResult {
+        // for loops are desugared into their initializer followed by a while loop with an increment
+        if let [initializer, Stmt::While {
+            condition,
+            body,
+            increment: Some(increment),
+            ..
+        }] = &stmts[..]
+        {
+            return Ok(format!(
+                "for ({} {}; {}) {}",
+                initializer.accept(self)?,
+                condition.accept(self)?,
+                increment.accept(self)?,
+                body.accept(self)?
+            ));
+        }
+        self.block(stmts)
+    }
+
+    fn visit_if_stmt(
+        &mut self,
+        condition: &Expr,
+        then_body: &Stmt,
+        else_body: &Option<Box<Stmt>>,
+    ) -> FormatResult {
+        let mut output = format!(
+            "if ({}) {}",
+            condition.accept(self)?,
+            then_body.accept(self)?
+        );
+        if let Some(else_body) = else_body {
+            output.push_str(&format!(" else {}", else_body.accept(self)?));
+        }
+        Ok(output)
+    }
+
+    fn visit_while_stmt(
+        &mut self,
+        condition: &Expr,
+        body: &Stmt,
+        _increment: &Option<Expr>,
+    ) -> FormatResult {
+        Ok(format!(
+            "while ({}) {}",
+            condition.accept(self)?,
+            body.accept(self)?
+        ))
+    }
+
+    fn visit_do_while_stmt(&mut self, body: &Stmt, condition: &Expr) -> FormatResult {
+        Ok(format!(
+            "do {} while ({});",
+            body.accept(self)?,
+            condition.accept(self)?
+        ))
+    }
+
+    fn visit_for_in_stmt(
+        &mut self,
+        name: &String,
+        iterable: &Expr,
+        body: &Stmt,
+        _token: &Token,
+    ) -> FormatResult {
+        Ok(format!(
+            "for (var {} in {}) {}",
+            name,
+            iterable.accept(self)?,
+            body.accept(self)?
+        ))
+    }
+
+    fn visit_break_stmt(&mut self, _token: &Token) -> FormatResult {
+        Ok(String::from("break;"))
+    }
+
+    fn visit_continue_stmt(&mut self, _token: &Token) -> FormatResult {
+        Ok(String::from("continue;"))
+    }
+
+    fn visit_function_stmt(
+        &mut self,
+        name: &String,
+        params: &Rc<Vec<String>>,
+        signature: &Signature,
+        defaults: &Rc<Vec<Expr>>,
+        body: &Rc<Vec<Stmt>>,
+        _token: &Token,
+    ) -> FormatResult {
+        Ok(format!(
+            "fn {}",
+            self.function(name, params, signature, defaults, body)?
+        ))
+    }
+
+    fn visit_class_stmt(
+        &mut self,
+        name: &String,
+        _token: &Token,
+        members: &Vec<Stmt>,
+        superclass: &Option<Expr>,
+    ) -> FormatResult {
+        let header = match superclass {
+            Some(superclass) => format!("class {} : {}", name, superclass.accept(self)?),
+            None => format!("class {}", name),
+        };
+        if members.is_empty() {
+            return Ok(format!("{} {{}}", header));
+        }
+
+        self.depth += 1;
+        let lines = self.members(members, Stmt::span, |formatter, member| match member {
+            // methods are declared without `fn`
+            Stmt::Function {
+                name,
+                params,
+                signature,
+                defaults,
+                body,
+                ..
+            } => formatter.function(name, params, signature, defaults, body),
+            field => field.accept(formatter),
+        });
+        self.depth -= 1;
+        Ok(format!(
+            "{} {{\n{}\n{}}}",
+            header,
+            lines?.join("\n"),
+            INDENT.repeat(self.depth)
+        ))
+    }
+
+    fn visit_return_stmt(&mut self, value: &Option<Expr>, _token: &Token) -> FormatResult {
+        Ok(match value {
+            Some(value) => format!("return {};", value.accept(self)?),
+            None => String::from("return;"),
+        })
+    }
+
+    fn visit_try_stmt(
+        &mut self,
+        body: &[Stmt],
+        catches: &[Catch],
+        finally: &Option<Vec<Stmt>>,
+        _token: &Token,
+    ) -> FormatResult {
+        let mut output = format!("try {}", self.block(body)?);
+        for Catch { name, class, body } in catches {
+            let filter = match class {
+                Some(class) => format!("{}: {}", name, class.accept(self)?),
+                None => name.clone(),
+            };
+            output.push_str(&format!(" catch ({}) {}", filter, self.block(body)?));
+        }
+        if let Some(finally) = finally {
+            output.push_str(&format!(" finally {}", self.block(finally)?));
+        }
+        Ok(output)
+    }
+
+    fn visit_throw_stmt(&mut self, value: &Expr, _token: &Token) -> FormatResult {
+        Ok(format!("throw {};", value.accept(self)?))
+    }
+
+    fn visit_match_stmt(&mut self, value: &Expr, arms: &[Arm], _token: &Token) -> FormatResult {
+        let header = format!("match ({})", value.accept(self)?);
+        if arms.is_empty() {
+            return Ok(format!("{} {{}}", header));
+        }
+        self.depth += 1;
+        let lines = self.members(
+            arms,
+            |arm| arm.pattern.token().span(),
+            |formatter, Arm { pattern, body }| {
+                Ok(format!(
+                    "{} => {}",
+                    formatter.pattern(pattern)?,
+                    body.accept(formatter)?
+                ))
+            },
+        );
+        self.depth -= 1;
+        Ok(format!(
+            "{} {{\n{}\n{}}}",
+            header,
+            lines?.join("\n"),
+            INDENT.repeat(self.depth)
+        ))
+    }
+
+    fn visit_error_stmt(&mut self, token: &Token, end: &Token) -> FormatResult {
+        // the comments inside are copied with the rest of the statement
+        while self
+            .comments
+            .front()
+            .is_some_and(|comment| comment.line <= end.line)
+        {
+            self.comments.pop_front();
+        }
+        Ok(self.written(token, end))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_partial, format_source};
+
+    #[test]
+    fn formats_canonically() {
+        let source = "var  x=1;fn add(a,b){return a+b;}\nclass B:A{var f=2; init( ){ this.f=-(1+2)*3; }}\nif(x>1)print x;else{print \"no\";}\nfor(var i=0;i<3;i=i+1){continue;}";
+        let expected = "var x = 1;
+
+fn add(a, b) {
+    return a + b;
+}
+
+class B : A {
+    var f = 2;
+    init() {
+        this.f = -(1 + 2) * 3;
+    }
+}
+
+if (x > 1) print x; else {
+    print \"no\";
+}
+for (var i = 0; i < 3; i = i + 1) {
+    continue;
+}
+";
+        assert_eq!(format_source(source).unwrap(), expected);
+    }
+
+    #[test]
+    fn keeps_comments() {
+        let source = "// header\nvar  a=1; // trailing\nfn f(x){ // opens\n  return x; /* after */\n  // before close\n}\nfn g() {\n  // todo\n}\nclass A{\n  var y=2;\n  // end of class\n}\nprint a;\n// end of file";
+        let expected = "// header
+var a = 1; // trailing
+
+fn f(x) {
+    // opens
+    return x; /* after */
+    // before close
+}
+
+fn g() {
+    // todo
+}
+
+class A {
+    var y = 2;
+    // end of class
+}
+
+print a;
+// end of file
+";
+        let formatted = format_source(source).unwrap();
+        assert_eq!(formatted, expected);
+        assert_eq!(format_source(&formatted).unwrap(), formatted);
+        let (partial, _) = format_partial("var a = ; // broken\nprint  1; // fine");
+        assert_eq!(partial, "var a = ; // broken\nprint 1; // fine\n");
+    }
+
+    #[test]
+    fn keeps_number_literals_as_written() {
+        assert_eq!(
+            format_source("var  x=0x1F+1_000;print  2.50;").unwrap(),
+            "var x = 0x1F + 1_000;\nprint 2.50;\n"
+        );
+    }
+
+    #[test]
+    fn keeps_broken_statements_as_written() {
+        let (formatted, errors) =
+            format_partial("var  x=;\nfn f(a){print a+* 1;\n  return a+1;}\nprint   x;");
+        assert_eq!(errors.len(), 2);
+        assert_eq!(
+            formatted,
+            "var x = ;\n\nfn f(a) {\n    print a+* 1;\n    return a + 1;\n}\n\nprint x;\n"
+        );
+    }
+
+    #[test]
+    fn formatting_is_stable() {
+        let source = "var f = |a| => a * 2; var g = |a| => { return a; }; var h = fn(a,b){return a;};\ntry { throw Error(\"x\", cause: null); } catch (e: Error) { } catch (e) { print e; } finally { do { break; } while (true); }\nwhile (false) {}";
+        let formatted = format_source(source).unwrap();
+        assert_eq!(format_source(&formatted).unwrap(), formatted);
+        assert!(formatted.contains("catch (e: Error) {} catch (e) {\n    print e;\n}"));
+        assert!(formatted.contains("var h = fn (a, b) {\n    return a;\n};"));
+        assert!(format_source("var = 1;").is_err());
+    }
+
+    #[test]
+    fn formats_match_arms() {
+        let source = "match(p){Point{x,y:-1}=>print x;[a,...rest]=>{print rest;}{z:\"s\"}=>{}_=>{}}match(1){}";
+        assert_eq!(
+            format_source(source).unwrap(),
+            "match (p) {\n    Point { x, y: -1 } => print x;\n    [a, ...rest] => {\n        print rest;\n    }\n    { z: \"s\" } => {}\n    _ => {}\n}\nmatch (1) {}\n"
+        );
+    }
+
+    #[test]
+    fn keeps_type_annotations() {
+        let source =
+            "var x:number=1;fn add(a:number,b):number{return a+b;}\nvar f=|s:string|:string=>s;fn g(a,b=1){}\nfn h(...xs){h(...xs);}\nxs[0]=xs[i+1];\nfor(var c in \"ab\")print c;";
+        assert_eq!(
+            format_source(source).unwrap(),
+            "var x: number = 1;\n\nfn add(a: number, b): number {\n    return a + b;\n}\n\nvar f = |s: string|: string => s;\n\nfn g(a, b = 1) {}\n\nfn h(...xs) {\n    h(...xs);\n}\n\nxs[0] = xs[i + 1];\nfor (var c in \"ab\") print c;\n"
+        );
+    }
+}
@@ -1,31 +1,53 @@
 use crate::class::Instance;
-use crate::environment::Environment;
+use crate::compat::Dialect;
+use crate::environment::{Environment, SlotTable};
 use crate::error::{error, Error, ErrorType};
+use crate::expr::Expr;
 use crate::interpreter::Interpreter;
-use crate::runtime_value::Value;
-use crate::statement::Stmt;
+use crate::natives::NativeBody;
+use crate::resolver::locals;
+use crate::runtime_value::{Value, ValueKey};
+use crate::statement::{implicit_return, Stmt};
 use crate::token::Token;
 use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
 use std::rc::Rc;
 
 pub trait Callable {
     fn arity(&self) -> usize;
-    fn call(&self, interpreter: &mut Interpreter, arguments: &Vec<Value>) -> Result<Value, Error>;
+    /// Token is the one closing the call expression, it's used to report errors raised by the call itself
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: &Vec<Value>,
+        token: &Token,
+    ) -> Result<Value, Error>;
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 pub enum Function {
     Native {
+        name: String,
         arity: usize,
-        body: fn() -> Value,
+        /// Trailing parameters that can be left out
+        optional: usize,
+        /// Takes any number of arguments after the `arity` ones
+        variadic: bool,
+        body: NativeBody,
     },
     Standard {
-        params: Vec<String>,
+        params: Rc<Vec<String>>,
+        /// Values of the trailing parameters that can be left out, evaluated in the closure on every call
+        defaults: Rc<Vec<Expr>>,
+        /// The last parameter gets the arguments after the others as a list
+        variadic: bool,
         name: String,
-        body: Vec<Stmt>,
+        body: Rc<Vec<Stmt>>,
         token: Token,
-        this: Option<Rc<RefCell<Instance>>>,
         closure: Rc<RefCell<Environment>>,
+        /// Slots of the program declaring the function, its body was resolved together with it
+        slots: SlotTable,
     },
 }
 
@@ -37,38 +59,90 @@ impl Callable for Function {
         }
     }
 
-    fn call(&self, interpreter: &mut Interpreter, args: &Vec<Value>) -> Result<Value, Error> {
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        args: &Vec<Value>,
+        call_token: &Token,
+    ) -> Result<Value, Error> {
         let val = match self {
             Function::Standard {
                 params,
-                name: _,
+                defaults,
+                variadic,
+                name,
                 body,
                 token,
-                this,
                 closure,
+                slots,
             } => {
-                let mut env = Environment::from(closure);
-                if self.arity() != args.len() {
-                    return error(token, ErrorType::InvalidNumberOfArguments);
+                let mut env = Environment::frame(closure, params.len() + locals(body));
+                let positional = params.len() - *variadic as usize;
+                let required = positional - defaults.len();
+                if args.len() < required || (args.len() > positional && !variadic) {
+                    return error(call_token, ErrorType::InvalidNumberOfArguments);
                 }
 
-                if let Some(instance) = this {
-                    let inst = Value::Instance(instance.clone());
-                    env.define_or_update("this", &inst);
-
-                    if let Some(super_instance) = instance.borrow().get_super() {
-                        let super_obj = Value::Class(super_instance);
-                        env.define_or_update("super", &super_obj);
+                for (arg, name) in args.iter().zip(&params[..positional]) {
+                    env.bind(name, arg);
+                }
+                let result = interpreter.with_slots(slots, |interpreter| {
+                    for (i, name) in params[..positional].iter().enumerate().skip(args.len()) {
+                        let value =
+                            interpreter.evaluate_in(&defaults[i - required], Rc::clone(closure))?;
+                        env.bind(name, &value);
                     }
+                    if *variadic {
+                        let rest = args.get(positional..).unwrap_or(&[]).to_vec();
+                        env.bind(
+                            &params[positional],
+                            &Value::List(Rc::new(RefCell::new(rest))),
+                        );
+                    }
+                    interpreter.enter_call(name, call_token)?;
+                    interpreter.profile_call(name, token.line);
+                    let result = interpreter.execute_block(body, Rc::new(RefCell::new(env)));
+                    interpreter.profile_return();
+                    interpreter.exit_call();
+                    result
+                });
+                match result {
+                    Err(Error {
+                        error_type: ErrorType::Return(val),
+                        ..
+                    }) => *val,
+                    // the block evaluates to its last statement, jlox functions give null instead
+                    result => match (interpreter.dialect(), implicit_return(body)) {
+                        (Dialect::LoxRs, Some(_)) => result?,
+                        _ => {
+                            result?;
+                            Value::Null
+                        }
+                    },
                 }
-
-                for (arg, name) in args.into_iter().zip(params.into_iter()) {
-                    env.define_or_update(name, arg);
+            }
+            Function::Native {
+                name,
+                body,
+                arity,
+                optional,
+                variadic,
+            } => {
+                if (args.len() > *arity && !variadic) || args.len() + optional < *arity {
+                    return error(call_token, ErrorType::InvalidNumberOfArguments);
+                }
+                interpreter.profile_call(name, 0);
+                let val = body(interpreter, args);
+                interpreter.profile_return();
+                interpreter.record_native_call(name, args, &val);
+                match val {
+                    Ok(val) => {
+                        interpreter.allocated(&val, call_token)?;
+                        val
+                    }
+                    Err(error_type) => return error(call_token, error_type),
                 }
-                let val = interpreter.execute_block(body, Rc::new(RefCell::new(env)))?;
-                val
             }
-            Function::Native { body, .. } => body(),
         };
 
         Ok(val)
@@ -83,24 +157,247 @@ impl Function {
         }
     }
 
+    /// Functions are the same when they're the same native, or were created by the same declaration
+    /// in the same scope. Methods are bound anew on every lookup, so `a.method == a.method` is false.
+    pub fn is_same(&self, other: &Function) -> bool {
+        match (self, other) {
+            (Function::Native { body: a, .. }, Function::Native { body: b, .. }) => {
+                Rc::ptr_eq(a, b)
+            }
+            (
+                Function::Standard {
+                    closure: a_closure,
+                    token: a_token,
+                    ..
+                },
+                Function::Standard {
+                    closure: b_closure,
+                    token: b_token,
+                    ..
+                },
+            ) => Rc::ptr_eq(a_closure, b_closure) && a_token == b_token,
+            _ => false,
+        }
+    }
+
+    /// Address shared by all the functions the same as this one, see `is_same`
+    pub(crate) fn identity(&self) -> usize {
+        match self {
+            Function::Native { body, .. } => Rc::as_ptr(body) as *const () as usize,
+            Function::Standard { closure, .. } => Rc::as_ptr(closure) as usize,
+        }
+    }
+
+    /// Wraps the function in one remembering its results by the arguments it got, for functions without side effects.
+    /// Arguments are told apart like `==` does, so instances and lists are remembered by identity, not by contents.
+    pub fn memoize(self) -> Self {
+        let name = match &self {
+            Function::Native { name, .. } | Function::Standard { name, .. } => name.clone(),
+        };
+        // the wrapped function gets the arguments as they were passed, so its defaults still apply
+        let (arity, optional, variadic) = match &self {
+            Function::Native {
+                arity,
+                optional,
+                variadic,
+                ..
+            } => (*arity, *optional, *variadic),
+            Function::Standard {
+                defaults, variadic, ..
+            } => (self.arity() - *variadic as usize, defaults.len(), *variadic),
+        };
+        let function = Value::Function(self);
+        let cache: RefCell<HashMap<Vec<ValueKey>, Value>> = RefCell::default();
+        Function::Native {
+            name,
+            arity,
+            optional,
+            variadic,
+            body: Rc::new(move |interpreter, args| {
+                // NaN isn't equal to itself, calls with it are never remembered
+                let key: Option<Vec<ValueKey>> = args.iter().map(ValueKey::new).collect();
+                if let Some(value) = key
+                    .as_ref()
+                    .and_then(|key| cache.borrow().get(key).cloned())
+                {
+                    return Ok(value);
+                }
+                let value = interpreter
+                    .call(&function, args)
+                    .map_err(|e| e.error_type)?;
+                if let Some(key) = key {
+                    cache.borrow_mut().insert(key, value.clone());
+                }
+                Ok(value)
+            }),
+        }
+    }
+
+    /// Methods see `this` in an environment between their closure and their parameters
     pub fn bind(self, instance: Rc<RefCell<Instance>>) -> Self {
         match self {
             Function::Standard {
                 params,
+                defaults,
+                variadic,
                 name,
                 body,
                 token,
                 closure,
-                ..
-            } => Function::Standard {
-                params,
-                name,
-                body,
-                token,
-                this: Some(instance),
-                closure,
-            },
+                slots,
+            } => {
+                let mut env = Environment::frame(&closure, 1);
+                env.bind("this", &Value::Instance(instance));
+                Function::Standard {
+                    params,
+                    defaults,
+                    variadic,
+                    name,
+                    body,
+                    token,
+                    closure: Rc::new(RefCell::new(env)),
+                    slots,
+                }
+            }
             _ => self,
         }
     }
 }
+
+impl fmt::Debug for Function {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str(&self.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::ErrorType;
+    use crate::interpreter::tests::{assert_error, assert_true, evaluate};
+    use crate::interpreter::Interpreter;
+    use crate::runtime_value::Value;
+    use std::rc::Rc;
+
+    #[test]
+    fn memoize_caches_results_by_arguments() {
+        assert_true(
+            "var calls = 0; fn square(x) { calls = calls + 1; return x * x; }
+             var fast = memoize(square);
+             fast(3) == 9 and fast(3) == 9 and fast(-0) == 0 and fast(0) == 0 and calls == 2;",
+        );
+        assert_true(
+            "var calls = 0; fn id(x) { calls = calls + 1; return x; }
+             id = memoize(id); var l = list();
+             id(l); id(l); id(list()); id(\"a\"); id(\"a\"); calls == 3;",
+        );
+        assert_true(
+            "var calls = 0;
+             fn fib(n) { calls = calls + 1; if (n < 2) return n; return fib(n - 1) + fib(n - 2); }
+             fib = memoize(fib); fib(30) == 832040 and calls == 31;",
+        );
+        assert_error("memoize(1);", ErrorType::WrongType);
+        assert_error("memoize(|x| => x)();", ErrorType::InvalidNumberOfArguments);
+        // defaults and rest parameters work through the cache too
+        assert_true(
+            "fn add(a, b = 10) { return a + b; } var fast = memoize(add);
+             fast(1) == 11 and fast(1, 2) == 3;",
+        );
+        assert_true(
+            "fn count(first, ...rest) { return len(rest); } var fast = memoize(count);
+             fast(1) == 0 and fast(1, 2, 3, 4) == 3;",
+        );
+        assert_error(
+            "fn add(a, b = 10) { return a + b; } memoize(add)(1, 2, 3);",
+            ErrorType::InvalidNumberOfArguments,
+        );
+    }
+
+    #[test]
+    fn functions_are_equal_only_to_themselves() {
+        assert_true("fn f() {} var g = f; f == g and clock == clock and f != (|x| => x);");
+        assert_true("fn make() { return |x| => x; } make() != make();");
+        assert_true("class A { m() {} } var a = A(); a.m != a.m;");
+    }
+
+    #[test]
+    fn functions_share_their_declaration() {
+        let mut interpreter = Interpreter::new();
+        interpreter
+            .run("fn make() { return |x| => x; } var a = make(); var b = make();")
+            .unwrap();
+        let body = |name| match interpreter.env.borrow().get(name) {
+            Some(Value::Function(crate::function::Function::Standard { body, .. })) => body,
+            _ => panic!("{} isn't a function", name),
+        };
+        assert!(Rc::ptr_eq(&body("a"), &body("b")));
+    }
+
+    #[test]
+    fn fills_in_default_parameters() {
+        assert_true(
+            "fn greet(name, greeting = \"Hello\") { return greeting + \", \" + name; }\n\
+             greet(\"Ann\") == \"Hello, Ann\" and greet(\"Bob\", \"Hi\") == \"Hi, Bob\";",
+        );
+        // defaults are evaluated on every call, in the scope the function was declared in
+        assert_true(
+            "var n = 1; fn f(a, b = n) { return a + b; } n = 2; f(1) == 3 and f(1, 5) == 6;",
+        );
+        assert_true("var add = |a, b = 10| => a + b; add(1) == 11;");
+        assert_true("class C { var x = 3; get(d = this.x) { return d; } } C().get() == 3;");
+        // wrong argument counts are reported at the call, like they are for natives
+        let errors = evaluate("fn f(a, b = 1) {}\nf();").unwrap_err();
+        assert!(matches!(
+            errors[0].error_type,
+            ErrorType::InvalidNumberOfArguments
+        ));
+        assert_eq!(errors[0].token.line, 2);
+        assert_error("fn f(a = 1, b) {}", ErrorType::ExpectedDefault);
+    }
+
+    #[test]
+    fn collects_rest_arguments_and_spreads_lists() {
+        assert_true(
+            "fn count(first, ...rest) { return len(rest); }\n\
+             count(1) == 0 and count(1, 2, 3) == 2;",
+        );
+        assert_true(
+            "fn pack(...items) { return items; } fn add(a, b, c = 0) { return a + b + c; }\n\
+             var pair = pack(1, 2); add(...pair) == 3 and add(...pair, 4) == 7 and len(pack(0, ...pair)) == 3;",
+        );
+        assert_true("var sum = |...xs| => reduce(xs, |a, b| => a + b, 0); sum(1, 2, 3) == 6;");
+        assert_error("fn f(a, b) {}\nf(...\"ab\");", ErrorType::SpreadNotList);
+        assert_error("fn f(...a, b) {}", ErrorType::RestParameterNotLast);
+    }
+
+    #[test]
+    fn bodies_return_their_last_expression() {
+        assert_true("fn double(x) { var y = x * 2; y; } double(2) == 4;");
+        assert_true(
+            "var f = |a, b| => { a + b; }; var g = fn () { 1; }; f(1, 2) == 3 and g() == 1;",
+        );
+        assert_true("fn f() { if (true) { 1; } } fn g() { var x = 1; } fn h() { return; 1; } f() == null and g() == null and h() == null;");
+        assert_true("class A { var x = 1; value() { this.x; } } A().value() == 1;");
+    }
+
+    #[test]
+    fn functions_can_be_expressions() {
+        assert_true("var add = fn (a, b = 10) { return a + b; }; add(1, 2) == 3 and add(1) == 11;");
+        assert_true(
+            "fn counter() { var n = 0; return fn () { n = n + 1; return n; }; }\n\
+             var next = counter(); next(); next() == 2;",
+        );
+        assert_true(
+            "var l = list(); l.push(1); l.push(2);\n\
+             reduce(map(l, fn (x) { return x * 10; }), fn (...xs) { return xs[0] + xs[1]; }, 0) == 30;",
+        );
+        assert_true("fn (x) { print x; }; (fn () { return true; })();");
+        assert_true("var g; g = fn (x) { return x; }; g(1) == 1;");
+        assert_true(
+            "class A {} var obj = A(); obj.handler = fn () { return 2; }; var h; h = |x| => x + 1; obj.handler() + h(1) == 4;",
+        );
+        assert_error(
+            "var f = fn { return 1; };",
+            ErrorType::ExpectedOpenParenthesis,
+        );
+    }
+}
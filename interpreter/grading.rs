@@ -0,0 +1,93 @@
+use crate::error::Error;
+use crate::interpreter::Interpreter;
+use crate::limits::ResourceLimits;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// Input fed to the script and the output it has to print
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestCase {
+    pub name: String,
+    /// Lines `readLine()` returns, in order
+    pub input: Vec<String>,
+    /// Lines the script has to print, in order
+    pub expected_output: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TestResult {
+    pub name: String,
+    pub passed: bool,
+    /// Lines the script printed
+    pub output: Vec<String>,
+    /// Why the script didn't run to the end, a case with diagnostics never passes
+    pub diagnostics: Vec<Error>,
+}
+
+/// Runs the script once per case in a fresh interpreter without file access.
+/// Scripts are stopped after a million statements or a hundred nested calls, so a stuck one fails its case.
+pub fn grade(source_code: &str, cases: &[TestCase]) -> Vec<TestResult> {
+    cases
+        .iter()
+        .map(|case| grade_case(source_code, case))
+        .collect()
+}
+
+fn grade_case(source_code: &str, case: &TestCase) -> TestResult {
+    let mut interpreter = Interpreter::new();
+    interpreter.set_limits(ResourceLimits {
+        max_call_depth: Some(100),
+        max_statements: Some(1_000_000),
+        ..ResourceLimits::default()
+    });
+    let mut input = case.input.clone().into_iter();
+    interpreter.set_line_reader(move || input.next());
+    let output = Rc::new(RefCell::new(Vec::new()));
+    let printed = Rc::clone(&output);
+    interpreter.set_output(move |line| printed.borrow_mut().push(line.to_owned()));
+
+    let diagnostics = interpreter.run(source_code).err().unwrap_or_default();
+    let output = output.borrow().clone();
+    TestResult {
+        name: case.name.clone(),
+        passed: diagnostics.is_empty() && output == case.expected_output,
+        output,
+        diagnostics,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{grade, TestCase};
+    use crate::error::ErrorType;
+
+    fn case(name: &str, input: &[&str], expected_output: &[&str]) -> TestCase {
+        TestCase {
+            name: name.to_owned(),
+            input: input.iter().map(|l| l.to_string()).collect(),
+            expected_output: expected_output.iter().map(|l| l.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn grades_cases_by_printed_output() {
+        let doubles = "var line = readLine(); while (line != null) { print format(line, \"\") + line; line = readLine(); }";
+        let results = grade(
+            doubles,
+            &[
+                case("two lines", &["a", "b"], &["aa", "bb"]),
+                case("wrong", &["a"], &["a"]),
+            ],
+        );
+        assert!(results[0].passed);
+        assert!(!results[1].passed);
+        assert_eq!(results[1].output, vec!["aa"]);
+
+        let results = grade("while (true) {}", &[case("stuck", &[], &[])]);
+        assert!(!results[0].passed);
+        assert!(matches!(
+            results[0].diagnostics[0].error_type,
+            ErrorType::ExecutionTimeout
+        ));
+    }
+}
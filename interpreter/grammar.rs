@@ -0,0 +1,682 @@
+/// Right-hand side of a grammar rule
+#[derive(Debug, Clone, PartialEq)]
+pub enum Production {
+    /// Exact text of a token, e.g. `"class"`
+    Terminal(&'static str),
+    /// Token with a value, e.g. `IDENTIFIER`
+    Token(&'static str),
+    /// Another rule of the grammar
+    Rule(&'static str),
+    Sequence(Vec<Production>),
+    Choice(Vec<Production>),
+    Optional(Box<Production>),
+    /// Zero or more times
+    Repeat(Box<Production>),
+}
+
+/// Rule of the grammar the parser implements
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    pub name: &'static str,
+    pub production: Production,
+    /// Program using the rule, the tests check the parser accepts it
+    pub example: &'static str,
+}
+
+fn t(text: &'static str) -> Production {
+    Production::Terminal(text)
+}
+
+fn token(name: &'static str) -> Production {
+    Production::Token(name)
+}
+
+fn r(name: &'static str) -> Production {
+    Production::Rule(name)
+}
+
+fn seq(items: Vec<Production>) -> Production {
+    Production::Sequence(items)
+}
+
+fn alt(items: Vec<Production>) -> Production {
+    Production::Choice(items)
+}
+
+fn opt(item: Production) -> Production {
+    Production::Optional(Box::new(item))
+}
+
+fn many(item: Production) -> Production {
+    Production::Repeat(Box::new(item))
+}
+
+fn rule(name: &'static str, production: Production, example: &'static str) -> Rule {
+    Rule {
+        name,
+        production,
+        example,
+    }
+}
+
+/// Grammar of the language, rule by rule from the whole program down to single values.
+/// It has to be kept in sync with the parser, the tests catch the parts that drift apart.
+pub fn grammar() -> Vec<Rule> {
+    let binary = |operand: &'static str, operators: Vec<Production>| {
+        seq(vec![
+            r(operand),
+            many(seq(vec![alt(operators), r(operand)])),
+        ])
+    };
+    vec![
+        rule("program", seq(vec![many(r("declaration")), token("EOF")]), "var a = 1; print a;"),
+        rule(
+            "declaration",
+            alt(vec![r("classDecl"), r("funDecl"), r("varDecl"), r("statement")]),
+            "var a; fn f() {} class A {} print a;",
+        ),
+        rule(
+            "classDecl",
+            seq(vec![
+                t("class"),
+                token("IDENTIFIER"),
+                opt(seq(vec![t(":"), token("IDENTIFIER")])),
+                t("{"),
+                many(alt(vec![r("varDecl"), r("function")])),
+                t("}"),
+            ]),
+            "class A {} class B : A { var x = 1; m() {} }",
+        ),
+        rule("funDecl", seq(vec![t("fn"), r("function")]), "fn f(a) { return a; }"),
+        rule(
+            "function",
+            seq(vec![
+                token("IDENTIFIER"),
+                t("("),
+                opt(r("parameters")),
+                t(")"),
+                opt(r("typeAnnotation")),
+                r("block"),
+            ]),
+            "fn f() {} class A { m(a, b): number {} }",
+        ),
+        rule(
+            "parameters",
+            seq(vec![r("parameter"), many(seq(vec![t(","), r("parameter")]))]),
+            "fn f(a, b, c) {}",
+        ),
+        rule(
+            "parameter",
+            alt(vec![
+                seq(vec![
+                    token("IDENTIFIER"),
+                    opt(r("typeAnnotation")),
+                    opt(seq(vec![t("="), r("expression")])),
+                ]),
+                seq(vec![t("..."), token("IDENTIFIER"), opt(r("typeAnnotation"))]),
+            ]),
+            "fn f(a: string, b = 1, ...rest) {}",
+        ),
+        rule(
+            "typeAnnotation",
+            seq(vec![t(":"), alt(vec![token("IDENTIFIER"), t("null")])]),
+            "var a: number; var b: null;",
+        ),
+        rule(
+            "varDecl",
+            seq(vec![
+                t("var"),
+                token("IDENTIFIER"),
+                opt(r("typeAnnotation")),
+                opt(seq(vec![t("="), r("expression")])),
+                t(";"),
+            ]),
+            "var a; var b = 1;",
+        ),
+        rule(
+            "statement",
+            alt(vec![
+                r("exprStmt"),
+                r("forStmt"),
+                r("ifStmt"),
+                r("printStmt"),
+                r("returnStmt"),
+                r("whileStmt"),
+                r("doWhileStmt"),
+                r("breakStmt"),
+                r("continueStmt"),
+                r("tryStmt"),
+                r("throwStmt"),
+                r("matchStmt"),
+                r("block"),
+            ]),
+            "1; print 2; { 3; }",
+        ),
+        rule("exprStmt", seq(vec![r("expression"), t(";")]), "1 + 2;"),
+        rule(
+            "forStmt",
+            seq(vec![
+                t("for"),
+                t("("),
+                alt(vec![
+                    seq(vec![r("declaration"), r("expression"), t(";"), r("expression")]),
+                    seq(vec![t("var"), token("IDENTIFIER"), t("in"), r("expression")]),
+                ]),
+                t(")"),
+                r("statement"),
+            ]),
+            "for (var i = 0; i < 3; i = i + 1) print i; for (var c in \"abc\") print c;",
+        ),
+        rule(
+            "ifStmt",
+            seq(vec![
+                t("if"),
+                t("("),
+                r("expression"),
+                t(")"),
+                r("statement"),
+                opt(seq(vec![t("else"), r("statement")])),
+            ]),
+            "if (true) print 1; else print 2;",
+        ),
+        rule("printStmt", seq(vec![t("print"), r("expression"), t(";")]), "print 1;"),
+        rule(
+            "returnStmt",
+            seq(vec![t("return"), opt(r("expression")), t(";")]),
+            "fn f() { return; } fn g() { return 1; }",
+        ),
+        rule(
+            "whileStmt",
+            seq(vec![t("while"), t("("), r("expression"), t(")"), r("statement")]),
+            "while (false) print 1;",
+        ),
+        rule(
+            "doWhileStmt",
+            seq(vec![
+                t("do"),
+                r("statement"),
+                t("while"),
+                t("("),
+                r("expression"),
+                t(")"),
+                t(";"),
+            ]),
+            "do print 1; while (false);",
+        ),
+        rule("breakStmt", seq(vec![t("break"), t(";")]), "while (true) break;"),
+        rule("continueStmt", seq(vec![t("continue"), t(";")]), "while (false) continue;"),
+        rule(
+            "tryStmt",
+            seq(vec![
+                t("try"),
+                r("block"),
+                many(r("catchClause")),
+                opt(seq(vec![t("finally"), r("block")])),
+            ]),
+            "try {} catch (e) {} try {} finally {}",
+        ),
+        rule(
+            "catchClause",
+            seq(vec![
+                t("catch"),
+                t("("),
+                token("IDENTIFIER"),
+                opt(seq(vec![t(":"), token("IDENTIFIER")])),
+                t(")"),
+                r("block"),
+            ]),
+            "try {} catch (e: Error) {} catch (e) {}",
+        ),
+        rule("throwStmt", seq(vec![t("throw"), r("expression"), t(";")]), "throw Error(\"x\");"),
+        rule(
+            "matchStmt",
+            seq(vec![
+                t("match"),
+                t("("),
+                r("expression"),
+                t(")"),
+                t("{"),
+                many(seq(vec![r("pattern"), t("=>"), r("statement")])),
+                t("}"),
+            ]),
+            "match (1) { 1 => print 1; n => { print n; } }",
+        ),
+        rule(
+            "pattern",
+            alt(vec![
+                seq(vec![opt(t("-")), token("NUMBER")]),
+                token("STRING"),
+                t("true"),
+                t("false"),
+                t("null"),
+                token("IDENTIFIER"),
+                r("listPattern"),
+                r("instancePattern"),
+            ]),
+            "match (x) { -1 => {} \"a\" => {} true => {} null => {} _ => {} }",
+        ),
+        rule(
+            "listPattern",
+            seq(vec![
+                t("["),
+                opt(alt(vec![
+                    seq(vec![
+                        r("pattern"),
+                        many(seq(vec![t(","), r("pattern")])),
+                        opt(seq(vec![t(","), t("..."), token("IDENTIFIER")])),
+                    ]),
+                    seq(vec![t("..."), token("IDENTIFIER")]),
+                ])),
+                t("]"),
+            ]),
+            "match (x) { [] => {} [a, [b], ...rest] => {} [...all] => {} }",
+        ),
+        rule(
+            "instancePattern",
+            seq(vec![
+                opt(token("IDENTIFIER")),
+                t("{"),
+                opt(seq(vec![
+                    r("fieldPattern"),
+                    many(seq(vec![t(","), r("fieldPattern")])),
+                ])),
+                t("}"),
+            ]),
+            "match (x) { Point { x, y: 0 } => {} {} => {} }",
+        ),
+        rule(
+            "fieldPattern",
+            seq(vec![token("IDENTIFIER"), opt(seq(vec![t(":"), r("pattern")]))]),
+            "match (x) { { x: [first], y } => {} }",
+        ),
+        rule("block", seq(vec![t("{"), many(r("declaration")), t("}")]), "{ var a = 1; { print a; } }"),
+        rule(
+            "expression",
+            alt(vec![r("closure"), r("functionExpr"), r("assignment")]),
+            "var f = |x| => x; f(1);",
+        ),
+        rule(
+            "closure",
+            seq(vec![
+                t("|"),
+                opt(r("parameters")),
+                t("|"),
+                opt(r("typeAnnotation")),
+                t("=>"),
+                alt(vec![r("block"), r("expression")]),
+            ]),
+            "var f = |a, b| => a + b; var g = || => { return 1; };",
+        ),
+        rule(
+            "functionExpr",
+            seq(vec![
+                t("fn"),
+                t("("),
+                opt(r("parameters")),
+                t(")"),
+                opt(r("typeAnnotation")),
+                r("block"),
+            ]),
+            "var f = fn (a, b) { return a + b; }; map(list(), fn (x) { return x; });",
+        ),
+        rule(
+            "assignment",
+            alt(vec![
+                seq(vec![
+                    opt(seq(vec![r("call"), t(".")])),
+                    token("IDENTIFIER"),
+                    t("="),
+                    r("assignment"),
+                ]),
+                seq(vec![
+                    r("call"),
+                    t("["),
+                    r("expression"),
+                    t("]"),
+                    t("="),
+                    r("assignment"),
+                ]),
+                r("logicOr"),
+            ]),
+            "a = b = 1; a.b.c = 2; a[0] = 3;",
+        ),
+        rule("logicOr", binary("logicAnd", vec![t("or")]), "a or b or c;"),
+        rule("logicAnd", binary("equality", vec![t("and")]), "a and b;"),
+        rule("equality", binary("comparison", vec![t("!="), t("==")]), "a == b != c;"),
+        rule(
+            "comparison",
+            binary("term", vec![t(">"), t(">="), t("<"), t("<=")]),
+            "a < b; a <= b; a > b; a >= b;",
+        ),
+        rule("term", binary("factor", vec![t("-"), t("+")]), "1 + 2 - 3;"),
+        rule("factor", binary("unary", vec![t("/"), t("*"), t("%")]), "1 * 2 / 3 % 4;"),
+        rule(
+            "unary",
+            alt(vec![seq(vec![alt(vec![t("!"), t("-")]), r("unary")]), r("call")]),
+            "!!true; -1;",
+        ),
+        rule(
+            "call",
+            seq(vec![
+                r("primary"),
+                many(alt(vec![
+                    seq(vec![t("("), opt(r("arguments")), t(")")]),
+                    seq(vec![t("."), token("IDENTIFIER")]),
+                    seq(vec![t("["), r("expression"), t("]")]),
+                ])),
+            ]),
+            "f()(1).a.b(2, 3)[0];",
+        ),
+        rule(
+            "arguments",
+            seq(vec![r("argument"), many(seq(vec![t(","), r("argument")]))]),
+            "f(1, 2, name: 3);",
+        ),
+        rule(
+            "argument",
+            seq(vec![
+                opt(alt(vec![seq(vec![token("IDENTIFIER"), t(":")]), t("...")])),
+                r("expression"),
+            ]),
+            "A(x: 1); f(...xs);",
+        ),
+        rule(
+            "primary",
+            alt(vec![
+                t("true"),
+                t("false"),
+                t("null"),
+                token("NUMBER"),
+                token("STRING"),
+                token("IDENTIFIER"),
+                t("this"),
+                seq(vec![t("super"), t("."), token("IDENTIFIER")]),
+                seq(vec![t("("), r("expression"), t(")")]),
+            ]),
+            "true; false; null; 1; \"a\"; a; (1); class A : B { m() { return this; } n() { super.m(); } }",
+        ),
+    ]
+}
+
+/// The grammar in EBNF, one `name ::= production` line per rule
+pub fn to_ebnf(rules: &[Rule]) -> String {
+    rules
+        .iter()
+        .map(|rule| format!("{} ::= {}\n", rule.name, ebnf(&rule.production, false)))
+        .collect()
+}
+
+/// Choices inside a sequence and anything but a single item under `?` or `*` need parentheses
+fn ebnf(production: &Production, in_sequence: bool) -> String {
+    match production {
+        Production::Terminal(text) => format!("\"{}\"", text),
+        Production::Token(name) | Production::Rule(name) => name.to_string(),
+        Production::Sequence(items) => items
+            .iter()
+            .map(|item| ebnf(item, true))
+            .collect::<Vec<_>>()
+            .join(" "),
+        Production::Choice(items) => {
+            let choice = items
+                .iter()
+                .map(|item| ebnf(item, false))
+                .collect::<Vec<_>>()
+                .join(" | ");
+            if in_sequence {
+                format!("( {} )", choice)
+            } else {
+                choice
+            }
+        }
+        Production::Optional(item) => format!("{}?", postfix_operand(item)),
+        Production::Repeat(item) => format!("{}*", postfix_operand(item)),
+    }
+}
+
+fn postfix_operand(item: &Production) -> String {
+    match item {
+        Production::Sequence(_) | Production::Choice(_) => format!("( {} )", ebnf(item, false)),
+        item => ebnf(item, true),
+    }
+}
+
+/// Laid out piece of a railroad diagram, the track enters on the left and leaves on the right at `track`
+struct Diagram {
+    width: u32,
+    height: u32,
+    track: u32,
+    svg: String,
+}
+
+const BOX_HEIGHT: u32 = 24;
+const GAP: u32 = 16;
+const RAIL: u32 = 20;
+
+/// Railroad diagrams of every rule, stacked in one SVG image
+pub fn to_railroad_svg(rules: &[Rule]) -> String {
+    let mut body = String::new();
+    let mut y = 10;
+    let mut width = 0;
+    for rule in rules {
+        let diagram = layout(&rule.production);
+        body.push_str(&format!(
+            "<text x=\"10\" y=\"{}\" class=\"rule\">{}</text>\n",
+            y + 14,
+            escape(rule.name)
+        ));
+        y += 24;
+        let track = y + diagram.track;
+        body.push_str(&format!(
+            "<circle cx=\"15\" cy=\"{track}\" r=\"4\"/><path d=\"M19 {track}H30\"/>\n<g transform=\"translate(30 {y})\">{svg}</g>\n<path d=\"M{x} {track}h11\"/><circle cx=\"{end}\" cy=\"{track}\" r=\"4\"/>\n",
+            track = track,
+            y = y,
+            svg = diagram.svg,
+            x = 30 + diagram.width,
+            end = 45 + diagram.width,
+        ));
+        width = width.max(60 + diagram.width);
+        y += diagram.height + 20;
+    }
+    format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" font-family=\"monospace\" font-size=\"13\">\n\
+         <style>path {{ fill: none; stroke: black; }} rect {{ fill: #f4f4f4; stroke: black; }} .rule {{ font-weight: bold; }}</style>\n{}</svg>\n",
+        width, y, body
+    )
+}
+
+fn layout(production: &Production) -> Diagram {
+    match production {
+        Production::Terminal(text) => boxed(&format!("\"{}\"", text), 10),
+        Production::Token(name) => boxed(name, 0),
+        Production::Rule(name) => boxed(name, 0),
+        Production::Sequence(items) => sequence(items.iter().map(layout).collect()),
+        Production::Choice(items) => choice(items.iter().map(layout).collect()),
+        Production::Optional(item) => choice(vec![skip(), layout(item)]),
+        Production::Repeat(item) => choice(vec![skip(), one_or_more(layout(item))]),
+    }
+}
+
+fn skip() -> Diagram {
+    Diagram {
+        width: 0,
+        height: 0,
+        track: 0,
+        svg: String::new(),
+    }
+}
+
+/// Terminals have rounded corners, rules and tokens square ones
+fn boxed(text: &str, radius: u32) -> Diagram {
+    let width = text.chars().count() as u32 * 8 + 20;
+    Diagram {
+        width,
+        height: BOX_HEIGHT,
+        track: BOX_HEIGHT / 2,
+        svg: format!(
+            "<rect width=\"{}\" height=\"{}\" rx=\"{}\"/><text x=\"10\" y=\"16\">{}</text>",
+            width,
+            BOX_HEIGHT,
+            radius,
+            escape(text)
+        ),
+    }
+}
+
+fn sequence(items: Vec<Diagram>) -> Diagram {
+    let track = items.iter().map(|d| d.track).max().unwrap_or(0);
+    let below = items.iter().map(|d| d.height - d.track).max().unwrap_or(0);
+    let mut svg = String::new();
+    let mut x = 0;
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            svg.push_str(&format!("<path d=\"M{} {}h{}\"/>", x, track, GAP));
+            x += GAP;
+        }
+        svg.push_str(&format!(
+            "<g transform=\"translate({} {})\">{}</g>",
+            x,
+            track - item.track,
+            item.svg
+        ));
+        x += item.width;
+    }
+    Diagram {
+        width: x,
+        height: track + below,
+        track,
+        svg,
+    }
+}
+
+/// Branches are stacked under the first one, which stays on the main track
+fn choice(items: Vec<Diagram>) -> Diagram {
+    let inner = items.iter().map(|d| d.width).max().unwrap_or(0);
+    let width = inner + 2 * RAIL;
+    let track = items.first().map_or(0, |d| d.track);
+    let mut svg = String::new();
+    let mut y = 0;
+    for item in &items {
+        let branch = y + item.track;
+        svg.push_str(&format!(
+            "<path d=\"M0 {track}h10V{branch}h10\"/><g transform=\"translate({rail} {y})\">{svg}</g><path d=\"M{end} {branch}H{right}V{track}h10\"/>",
+            track = track,
+            branch = branch,
+            rail = RAIL,
+            y = y,
+            svg = item.svg,
+            end = RAIL + item.width,
+            right = width - 10,
+        ));
+        y += item.height.max(4) + 10;
+    }
+    Diagram {
+        width,
+        height: y - 10,
+        track,
+        svg,
+    }
+}
+
+/// The track goes through the item and loops back under it
+fn one_or_more(item: Diagram) -> Diagram {
+    let width = item.width + 2 * RAIL;
+    let bottom = item.height + 8;
+    Diagram {
+        width,
+        height: bottom + 2,
+        track: item.track,
+        svg: format!(
+            "<path d=\"M0 {track}h{rail}\"/><g transform=\"translate({rail} 0)\">{svg}</g><path d=\"M{end} {track}h{rail}\"/><path d=\"M{back} {track}V{bottom}H10V{track}\"/>",
+            track = item.track,
+            rail = RAIL,
+            svg = item.svg,
+            end = RAIL + item.width,
+            back = width - 10,
+            bottom = bottom,
+        ),
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{grammar, to_ebnf, to_railroad_svg, Production};
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::token::KEYWORDS;
+    use std::collections::HashSet;
+
+    fn walk<'a>(production: &'a Production, found: &mut Vec<&'a Production>) {
+        found.push(production);
+        match production {
+            Production::Sequence(items) | Production::Choice(items) => {
+                items.iter().for_each(|item| walk(item, found))
+            }
+            Production::Optional(item) | Production::Repeat(item) => walk(item, found),
+            _ => (),
+        }
+    }
+
+    #[test]
+    fn parser_accepts_every_example() {
+        for rule in grammar() {
+            let tokens = Lexer::new(rule.example).scan_tokens().unwrap();
+            let parsed = Parser::new(&tokens).parse_tokens();
+            assert!(parsed.is_ok(), "{}: {:?}", rule.name, parsed.err());
+        }
+    }
+
+    #[test]
+    fn rules_reference_each_other_and_every_keyword() {
+        let rules = grammar();
+        let names: HashSet<&str> = rules.iter().map(|rule| rule.name).collect();
+        let mut productions = Vec::new();
+        rules
+            .iter()
+            .for_each(|rule| walk(&rule.production, &mut productions));
+        let mut referenced = HashSet::new();
+        let mut terminals = HashSet::new();
+        for production in productions {
+            match production {
+                Production::Rule(name) => {
+                    assert!(names.contains(name), "{} isn't defined", name);
+                    referenced.insert(*name);
+                }
+                Production::Terminal(text) => {
+                    terminals.insert(*text);
+                }
+                _ => (),
+            }
+        }
+        for name in &names {
+            assert!(
+                *name == "program" || referenced.contains(name),
+                "{} is never used",
+                name
+            );
+        }
+        for keyword in KEYWORDS.keys() {
+            assert!(terminals.contains(keyword), "{} is missing", keyword);
+        }
+    }
+
+    #[test]
+    fn exports_ebnf_and_railroad_diagrams() {
+        let rules = grammar();
+        let ebnf = to_ebnf(&rules);
+        assert!(ebnf.contains(
+            "varDecl ::= \"var\" IDENTIFIER typeAnnotation? ( \"=\" expression )? \";\"\n"
+        ));
+        assert!(ebnf.contains("equality ::= comparison ( ( \"!=\" | \"==\" ) comparison )*\n"));
+        assert_eq!(ebnf.lines().count(), rules.len());
+        let svg = to_railroad_svg(&rules);
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.contains("&lt;="));
+        assert_eq!(svg.matches("class=\"rule\"").count(), rules.len());
+    }
+}
@@ -0,0 +1,195 @@
+use crate::error::Error;
+use crate::interpreter::Interpreter;
+use crate::lexer::Lexer;
+use crate::token::{Token, TokenType};
+
+/// What has to happen after the watched source changed
+#[derive(Debug, PartialEq)]
+pub enum Change {
+    /// Only these top-level functions differ, they can be swapped in the running interpreter
+    Functions(Vec<String>),
+    /// Anything else changed, the program has to run again from scratch
+    Program,
+}
+
+/// Top-level function declarations are cut out of the token stream, leaving only their names behind.
+/// Two sources with the same skeleton differ only inside their functions.
+struct Outline {
+    skeleton: Vec<TokenType>,
+    functions: Vec<(String, Vec<TokenType>)>,
+}
+
+impl Outline {
+    fn new(tokens: Vec<Token>) -> Self {
+        let mut skeleton = vec![];
+        let mut functions = vec![];
+        let mut tokens = tokens.into_iter().map(|t| t.token_type).peekable();
+        let mut depth = 0;
+
+        while let Some(token_type) = tokens.next() {
+            let name = match (&token_type, tokens.peek()) {
+                (TokenType::Function, Some(TokenType::Identifier(name))) if depth == 0 => {
+                    name.clone()
+                }
+                _ => {
+                    match token_type {
+                        TokenType::OpenBrace => depth += 1,
+                        TokenType::CloseBrace => depth -= 1,
+                        _ => (),
+                    }
+                    skeleton.push(token_type);
+                    continue;
+                }
+            };
+
+            skeleton.push(token_type);
+            skeleton.push(tokens.next().unwrap());
+            // everything up to the brace closing the body, including parameters
+            let mut function = vec![];
+            let mut body_depth = 0;
+            for token_type in tokens.by_ref() {
+                match token_type {
+                    TokenType::OpenBrace => body_depth += 1,
+                    TokenType::CloseBrace => body_depth -= 1,
+                    _ => (),
+                }
+                let closed = body_depth == 0 && token_type == TokenType::CloseBrace;
+                function.push(token_type);
+                if closed {
+                    break;
+                }
+            }
+            functions.push((name, function));
+        }
+
+        Outline {
+            skeleton,
+            functions,
+        }
+    }
+}
+
+/// Compares two versions of a program, token by token, so formatting and comments don't matter
+pub fn compare(old_source: &str, new_source: &str) -> Change {
+    let (old, new) = match (
+        Lexer::new(old_source).scan_tokens(),
+        Lexer::new(new_source).scan_tokens(),
+    ) {
+        (Ok(old), Ok(new)) => (Outline::new(old), Outline::new(new)),
+        _ => return Change::Program,
+    };
+
+    if old.skeleton != new.skeleton {
+        return Change::Program;
+    }
+
+    let changed = old
+        .functions
+        .iter()
+        .zip(new.functions.iter())
+        .filter(|(old, new)| old.1 != new.1)
+        .map(|(_, (name, _))| name.clone())
+        .collect();
+    Change::Functions(changed)
+}
+
+/// Swaps the changed functions into the interpreter. The source it runs becomes the new one only once
+/// that worked, so a reload that failed is compared against the last working version on the next save.
+pub(crate) fn reload(
+    interpreter: &mut Interpreter,
+    source_code: &mut String,
+    new_source_code: &str,
+    names: &[String],
+) -> Result<(), Vec<Error>> {
+    interpreter.reload_functions(new_source_code, names)?;
+    *source_code = new_source_code.to_owned();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compare, reload, Change};
+    use crate::interpreter::Interpreter;
+
+    const PROGRAM: &str = "
+        var frame = 0;
+        fn update(dt) { frame = frame + dt; }
+        fn draw() { print frame; }
+        update(1);
+    ";
+
+    #[test]
+    fn function_bodies_can_be_swapped() {
+        let changed = PROGRAM.replace("frame + dt", "frame + dt * 2");
+        assert_eq!(
+            compare(PROGRAM, &changed),
+            Change::Functions(vec![String::from("update")])
+        );
+
+        let reformatted = PROGRAM.replace("{ print frame; }", "{\n    print frame;\n}");
+        assert_eq!(compare(PROGRAM, &reformatted), Change::Functions(vec![]));
+
+        let nested = PROGRAM.replace("{ print frame; }", "{ if (frame) { print frame; } }");
+        assert_eq!(
+            compare(PROGRAM, &nested),
+            Change::Functions(vec![String::from("draw")])
+        );
+    }
+
+    #[test]
+    fn other_changes_restart_the_program() {
+        let changed = PROGRAM.replace("var frame = 0;", "var frame = 1;");
+        assert_eq!(compare(PROGRAM, &changed), Change::Program);
+
+        let renamed = PROGRAM.replace("fn draw()", "fn render()");
+        assert_eq!(compare(PROGRAM, &renamed), Change::Program);
+
+        assert_eq!(compare(PROGRAM, "\"unclosed"), Change::Program);
+    }
+
+    #[test]
+    fn failed_reloads_keep_the_working_source() {
+        let mut interpreter = Interpreter::new();
+        interpreter.run(PROGRAM).unwrap();
+        let mut source_code = PROGRAM.to_owned();
+        let update = vec![String::from("update")];
+
+        let broken = PROGRAM.replace("frame + dt", "frame + dt +");
+        assert_eq!(
+            compare(&source_code, &broken),
+            Change::Functions(update.clone())
+        );
+        assert!(reload(&mut interpreter, &mut source_code, &broken, &update).is_err());
+        assert_eq!(source_code, PROGRAM);
+        let frame = interpreter.run("update(1); frame;").unwrap();
+        assert_eq!(frame.as_number(), Some(&2.0));
+
+        let fixed = PROGRAM.replace("frame + dt", "frame + dt * 2");
+        assert_eq!(
+            compare(&source_code, &fixed),
+            Change::Functions(update.clone())
+        );
+        reload(&mut interpreter, &mut source_code, &fixed, &update).unwrap();
+        assert_eq!(source_code, fixed);
+        let frame = interpreter.run("update(1); frame;").unwrap();
+        assert_eq!(frame.as_number(), Some(&4.0));
+    }
+
+    #[test]
+    fn reloaded_functions_keep_program_state() {
+        let mut interpreter = Interpreter::new();
+        interpreter
+            .run("var total = 0; fn step() { total = total + 1; } fn onReload() {} step(); step();")
+            .unwrap();
+        let reloaded = "var total = 0; fn step() { total = total + 10; } fn onReload() { step(); } step(); step();";
+        let names = [String::from("step"), String::from("onReload")];
+        interpreter.reload_functions(reloaded, &names).unwrap();
+        assert_eq!(evaluate_in(&mut interpreter, "total;"), 12.0);
+        interpreter.run("step();").unwrap();
+        assert_eq!(evaluate_in(&mut interpreter, "total;"), 22.0);
+    }
+
+    fn evaluate_in(interpreter: &mut Interpreter, code: &str) -> f64 {
+        *interpreter.run(code).unwrap().as_number().unwrap()
+    }
+}
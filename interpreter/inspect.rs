@@ -0,0 +1,157 @@
+use crate::runtime_value::Value;
+use serde::Serialize;
+
+/// How tooling (debugger, REPL, the playground's variable pane) sees a runtime value.
+/// Values with members get a reference, which expands into their children on request.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ValueInspection {
+    #[serde(rename = "type")]
+    pub type_name: String,
+    pub preview: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reference: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct InspectedChild {
+    pub name: String,
+    #[serde(flatten)]
+    pub value: ValueInspection,
+}
+
+const MAX_PREVIEW_LENGTH: usize = 80;
+
+/// References handed out so far, a reference is an index into it plus one
+#[derive(Default)]
+pub struct Inspections {
+    values: Vec<Value>,
+}
+
+impl Inspections {
+    pub fn inspect(&mut self, value: &Value) -> ValueInspection {
+        let reference = match value {
+            Value::Instance(_) | Value::Class(_) | Value::List(_) => {
+                self.values.push(value.clone());
+                Some(self.values.len())
+            }
+            _ => None,
+        };
+
+        ValueInspection {
+            type_name: value.type_name().to_owned(),
+            preview: preview(value),
+            reference,
+        }
+    }
+
+    /// Fields of an instance, superclass and methods of a class, sorted by name, items of a list in order
+    pub fn children(&mut self, reference: usize) -> Option<Vec<InspectedChild>> {
+        let value = self.values.get(reference.checked_sub(1)?)?.clone();
+        if let Value::List(list) = &value {
+            let items = list
+                .borrow()
+                .iter()
+                .enumerate()
+                .map(|(i, item)| (i.to_string(), item.clone()))
+                .collect();
+            return Some(self.inspect_all(items));
+        }
+        let mut children: Vec<(String, Value)> = match &value {
+            Value::Instance(instance) => instance
+                .borrow()
+                .fields()
+                .map(|(name, value)| (name.clone(), value.clone()))
+                .collect(),
+            Value::Class(class) => class
+                .methods()
+                .map(|(name, method)| (name.clone(), Value::Function(method.clone())))
+                .collect(),
+            _ => vec![],
+        };
+        children.sort_by(|a, b| a.0.cmp(&b.0));
+        if let Value::Class(class) = &value {
+            if let Some(superclass) = class.superclass() {
+                children.insert(
+                    0,
+                    (String::from("superclass"), Value::Class(superclass.clone())),
+                );
+            }
+        }
+
+        Some(self.inspect_all(children))
+    }
+
+    pub fn inspect_all(&mut self, values: Vec<(String, Value)>) -> Vec<InspectedChild> {
+        values
+            .into_iter()
+            .map(|(name, value)| InspectedChild {
+                name,
+                value: self.inspect(&value),
+            })
+            .collect()
+    }
+
+    /// References are only valid until the next clear, hosts usually clear them whenever the program moves on
+    pub fn clear(&mut self) {
+        self.values.clear();
+    }
+}
+
+fn preview(value: &Value) -> String {
+    let preview = match value {
+        Value::String(s) => format!("\"{}\"", s),
+        _ => value.to_string(),
+    };
+    if preview.chars().count() > MAX_PREVIEW_LENGTH {
+        let mut shortened: String = preview.chars().take(MAX_PREVIEW_LENGTH - 3).collect();
+        shortened.push_str("...");
+        shortened
+    } else {
+        preview
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interpreter::Interpreter;
+
+    #[test]
+    fn values_are_inspected_lazily() {
+        let mut interpreter = Interpreter::new();
+        interpreter
+            .run("class A { a() {} } class B : A { b() {} } var b = B(); b.x = 1; b.y = \"two\"; var n = null;")
+            .unwrap();
+
+        let variables = interpreter.inspect_variables();
+        let names: Vec<&str> = variables.iter().map(|v| v.name.as_str()).collect();
+        assert_eq!(names, vec!["A", "B", "b", "n"]);
+        assert_eq!(
+            serde_json::to_string(&variables[3]).unwrap(),
+            r#"{"name":"n","type":"null","preview":"null"}"#
+        );
+
+        let instance = variables[2].value.reference.unwrap();
+        let fields = interpreter.inspect_children(instance).unwrap();
+        assert_eq!(
+            serde_json::to_string(&fields).unwrap(),
+            r#"[{"name":"x","type":"number","preview":"1"},{"name":"y","type":"string","preview":"\"two\""}]"#
+        );
+
+        let class = variables[1].value.reference.unwrap();
+        let members = interpreter.inspect_children(class).unwrap();
+        let names: Vec<&str> = members.iter().map(|v| v.name.as_str()).collect();
+        assert_eq!(names, vec!["superclass", "b"]);
+        assert!(members[0].value.reference.is_some());
+
+        let list = interpreter.run("var l = list(); l.push(b); l;").unwrap();
+        let list = interpreter.inspect(&list);
+        let items = interpreter
+            .inspect_children(list.reference.unwrap())
+            .unwrap();
+        assert_eq!(items[0].name, "0");
+        assert!(items[0].value.reference.is_some());
+
+        interpreter.clear_inspections();
+        assert!(interpreter.inspect_children(instance).is_none());
+    }
+}
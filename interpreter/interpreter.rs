@@ -1,142 +1,1196 @@
-use crate::class::Class;
-use crate::environment::Environment;
+use crate::audit::AuditEntry;
+use crate::class::{Class, Instance};
+use crate::compat::{jlox_string, Dialect};
+use crate::debugger::{ActiveCall, Breakpoints, Debugger, Frame, Granularity};
+use crate::diagnostics::DiagnosticOptions;
+use crate::environment::{Environment, Slot, SlotTable};
 use crate::error::{error, Error, ErrorType};
-use crate::expr::{Expr, Visitor as ExprVisitor};
+use crate::expr::{Expr, ExprId, Visitor as ExprVisitor};
+use crate::features::{Features, Semantics};
+#[cfg(feature = "ffi")]
+use crate::ffi::define_ffi_natives;
 use crate::function::{Callable, Function};
-use crate::resolver::VarRef;
+use crate::inspect::{InspectedChild, Inspections, ValueInspection};
+use crate::interrupt::InterruptHandle;
+use crate::lexer::Lexer;
+use crate::limits::{Capabilities, ResourceLimits, ResourceUsage};
+use crate::natives::{
+    define_args, define_higher_order, define_line_reader, define_native, define_natives,
+    define_random, NativeResult,
+};
+#[cfg(not(all(target_arch = "wasm32", not(target_os = "wasi"))))]
+use crate::natives::{define_file_natives, define_fs_capability};
+use crate::parser::Parser;
+use crate::pattern::Pattern;
+use crate::profile::{Profile, Profiler};
+use crate::prototypes::{char_at, get_method, has_prototype, index_arg};
+use crate::resolver::{locals, Resolver};
 use crate::runtime_value::Value;
-use crate::statement::{Stmt, Visitor as StmtVisitor};
-use crate::token::{Literal, Token, TokenType};
+use crate::snapshot::{self, SnapshotError};
+use crate::statement::{Arm, Catch, Stmt, Visitor as StmtVisitor};
+use crate::stats::Stats;
+use crate::stdlib::{self, StdlibConfig};
+use crate::testing::{TestRegistry, TestReport};
+use crate::token::{Literal, Span, Token, TokenType};
+use crate::types::check_types;
+use crate::types::{Signature, Type};
 use crate::utils::{print, PrintType};
+use crate::watch::{WatchCallback, Watcher, Watchers};
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::rc::Rc;
 
-// this kind of control flow can be done with exceptions, but I'm not a big fan of that idea
-struct State {
-    should_continue: bool,
-    should_break: bool,
-    should_return: bool,
-    inside_call: bool,
+/// Classes every interpreter starts with, written in Lox itself
+const PRELUDE: &str = include_str!("prelude.lox");
+
+/// Called with the name of a variable that couldn't be found anywhere, before `UndefinedVariable` is raised.
+/// Returned value is stored in globals, so the host is asked about every name at most once.
+pub type UnknownIdentifierResolver = Rc<dyn Fn(&str) -> Option<Value>>;
+type Output = Box<dyn FnMut(&str)>;
+
+pub struct Interpreter {
+    pub env: Rc<RefCell<Environment>>,
+    /// Slots of the program running now
+    slots: SlotTable,
+    globals: Rc<RefCell<Environment>>,
+    unknown_identifier_resolver: Option<UnknownIdentifierResolver>,
+    audit_log: Option<Vec<AuditEntry>>,
+    watchers: Watchers,
+    tests: TestRegistry,
+    /// Names whose watchers are running, changes they make don't notify them again
+    notifying: Vec<String>,
+    inspections: Inspections,
+    strict: bool,
+    diagnostics: DiagnosticOptions,
+    features: Features,
+    semantics: Semantics,
+    dialect: Dialect,
+    resources: ResourceUsage,
+    debugger: Option<Box<dyn Debugger>>,
+    breakpoints: Breakpoints,
+    call_stack: Vec<ActiveCall>,
+    interrupt: InterruptHandle,
+    /// Try statements whose body is running, errors raised inside of them may still be caught
+    try_depth: usize,
+    /// The debugger already saw the error the current run failed with
+    post_mortem_done: bool,
+    stats: Stats,
+    profiler: Option<Profiler>,
+    /// Receives what print statements print, instead of stdout
+    output: Option<Output>,
+    stdlib: StdlibConfig,
+    capabilities: Capabilities,
+    /// Set while code the debugger evaluates runs, it isn't resolved, so it finds locals by their names
+    by_name: bool,
+    /// Capability objects the host granted, by the name scripts `require` them with
+    granted: HashMap<String, Value>,
 }
 
-impl State {
+impl Interpreter {
     pub fn new() -> Self {
-        State {
-            should_break: false,
-            should_continue: false,
-            should_return: false,
-            inside_call: false,
+        Interpreter::with_stdlib(StdlibConfig::default())
+    }
+
+    /// Interpreter for untrusted scripts, they can't touch the host at all until `set_capabilities` allows it.
+    /// Their call depth and statements are limited too, `set_limits` can also bound their memory and time.
+    pub fn sandboxed() -> Self {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_capabilities(Capabilities::none());
+        interpreter.set_limits(ResourceLimits::sandboxed());
+        interpreter
+    }
+
+    /// Interpreter whose natives are in the globals and namespaces the config picks
+    pub fn with_stdlib(stdlib: StdlibConfig) -> Self {
+        let mut interpreter = Interpreter::with_natives(stdlib);
+        interpreter.define_prelude();
+        interpreter
+    }
+
+    /// Interpreter with nothing but the native functions defined
+    pub(crate) fn without_prelude() -> Self {
+        Interpreter::with_natives(StdlibConfig::default())
+    }
+
+    fn with_natives(stdlib: StdlibConfig) -> Self {
+        let watchers = Watchers::default();
+        let tests = TestRegistry::default();
+        let globals = {
+            let e = Rc::new(RefCell::new(Environment::new()));
+            watchers.define_native(&mut e.borrow_mut());
+            tests.define_native(&mut e.borrow_mut());
+            e
+        };
+
+        let mut interpreter = Interpreter {
+            env: Rc::new(RefCell::new(Environment::top_level(&globals))),
+            slots: SlotTable::default(),
+            globals,
+            unknown_identifier_resolver: None,
+            audit_log: None,
+            watchers,
+            tests,
+            notifying: Vec::new(),
+            inspections: Inspections::default(),
+            strict: false,
+            diagnostics: DiagnosticOptions::default(),
+            features: Features::all(),
+            semantics: Semantics::default(),
+            dialect: Dialect::LoxRs,
+            resources: ResourceUsage::default(),
+            debugger: None,
+            breakpoints: Breakpoints::default(),
+            call_stack: Vec::new(),
+            interrupt: InterruptHandle::default(),
+            try_depth: 0,
+            post_mortem_done: false,
+            stats: Stats::default(),
+            profiler: None,
+            output: None,
+            stdlib,
+            capabilities: Capabilities::default(),
+            by_name: false,
+            granted: HashMap::new(),
+        };
+        interpreter.define_builtins(define_natives);
+        interpreter
+    }
+
+    /// Saves the globals and top-level bindings, together with everything the resolver learned about them.
+    /// Restoring the bytes is much cheaper than lexing, parsing and running the same code again,
+    /// so hosts can snapshot a warmed-up interpreter at build time and restore it for every request.
+    /// Native functions aren't saved, instances and functions closing over local scopes can't be.
+    pub fn snapshot(&self) -> Result<Vec<u8>, SnapshotError> {
+        snapshot::save(&self.globals, &self.env)
+    }
+
+    /// Interpreter in the state saved by `snapshot`, the host has to define its native functions again
+    pub fn restore(bytes: &[u8]) -> Result<Self, SnapshotError> {
+        let interpreter = Interpreter::without_prelude();
+        snapshot::restore(bytes, &interpreter.globals, &interpreter.env)?;
+        Ok(interpreter)
+    }
+
+    /// Puts the natives `define` defines where the stdlib config wants them
+    fn define_builtins(&mut self, define: impl FnOnce(&mut Environment)) {
+        let mut natives = Environment::new();
+        define(&mut natives);
+        stdlib::export(&natives, &mut self.globals.borrow_mut(), &self.stdlib);
+    }
+
+    /// Runs the prelude straight in globals, so its classes look like natives to scripts
+    fn define_prelude(&mut self) {
+        let env = std::mem::replace(&mut self.env, Rc::clone(&self.globals));
+        self.run(PRELUDE).expect("prelude should always run");
+        self.env = env;
+        self.stats = Stats::default();
+    }
+
+    /// Lexes, parses, resolves and runs the source code against this interpreter,
+    /// so everything defined by the host beforehand is visible to the script
+    pub fn run(&mut self, source_code: &str) -> Result<Value, Vec<Error>> {
+        self.stats.scripts_run += 1;
+        let result = self.run_source(source_code);
+        if let Err(errors) = &result {
+            self.stats.count_errors(errors);
         }
+        result
     }
 
-    fn will_return(&mut self) -> bool {
-        if self.should_return && self.inside_call {
-            self.should_return = false;
-            return true;
+    /// Every run gets a table of its own, so the slots of a finished run go away with what it declared
+    fn run_source(&mut self, source_code: &str) -> Result<Value, Vec<Error>> {
+        let slots = self.use_slots(SlotTable::default());
+        let result = self.resolve_and_run(source_code);
+        self.use_slots(slots);
+        result
+    }
+
+    fn resolve_and_run(&mut self, source_code: &str) -> Result<Value, Vec<Error>> {
+        let mut lexer = Lexer::with_dialect(source_code, self.dialect);
+        let tokens = lexer.scan_tokens()?;
+        let mut parser = Parser::with_features(&tokens, self.features);
+        let stmts = parser.parse_tokens()?;
+        let mut resolver = Resolver::new(self);
+        resolver.resolve(&stmts)?;
+        // versions without annotations keep reporting wrong arities at runtime, like the book
+        if self.features.type_annotations {
+            let errors = check_types(&stmts);
+            if !errors.is_empty() {
+                return Err(errors);
+            }
         }
-        false
+        self.run_statements(&stmts).map_err(|e| vec![e])
     }
 
-    fn enter_call(&mut self) {
-        self.inside_call = true;
+    /// Runs a resolved program
+    pub(crate) fn run_statements(&mut self, stmts: &[Stmt]) -> Result<Value, Error> {
+        self.resources.start_run();
+        self.post_mortem_done = false;
+        self.interpret(stmts)
     }
 
-    fn exit_call(&mut self) {
-        self.should_return = false;
-        self.inside_call = false;
+    /// Redefines the named top-level functions with their declarations from the new version of the program,
+    /// leaving the rest of the program's state as it is. Calls `onReload()` afterwards if the program defines it.
+    pub fn reload_functions(
+        &mut self,
+        source_code: &str,
+        names: &[String],
+    ) -> Result<(), Vec<Error>> {
+        let slots = self.use_slots(SlotTable::default());
+        let result = self.redefine_functions(source_code, names);
+        self.use_slots(slots);
+        result?;
+        let on_reload = self.env.borrow().get("onReload");
+        if let Some(Value::Function(_)) = on_reload {
+            self.call_global("onReload", &vec![]).map_err(|e| vec![e])?;
+        }
+        Ok(())
     }
 
-    fn will_break(&mut self) -> bool {
-        let should_break = self.should_break;
-        self.should_break = false;
-        should_break
+    fn redefine_functions(
+        &mut self,
+        source_code: &str,
+        names: &[String],
+    ) -> Result<(), Vec<Error>> {
+        let mut lexer = Lexer::with_dialect(source_code, self.dialect);
+        let tokens = lexer.scan_tokens()?;
+        let mut parser = Parser::with_features(&tokens, self.features);
+        let stmts = parser.parse_tokens()?;
+        let mut resolver = Resolver::new(self);
+        resolver.resolve(&stmts)?;
+
+        for stmt in &stmts {
+            if let Stmt::Function { name, .. } = stmt {
+                if names.contains(name) {
+                    stmt.accept(self).map_err(|e| vec![e])?;
+                }
+            }
+        }
+        Ok(())
     }
 
-    fn will_continue(&mut self) -> bool {
-        let should_continue = self.should_continue;
-        self.should_continue = false;
-        should_continue
+    /// Creates an interpreter that sees the globals and everything this one has defined so far,
+    /// but has its own top-level scope. Assignments in the fork never leak back into this interpreter,
+    /// so one warmed-up interpreter can serve many independent runs.
+    pub fn fork(&self) -> Self {
+        let globals = Rc::new(RefCell::new(Environment::top_level(&self.env)));
+        // the fork gets its own `watch`, otherwise scripts would register with the base interpreter
+        let watchers = Watchers::default();
+        watchers.define_native(&mut globals.borrow_mut());
+        let tests = TestRegistry::default();
+        tests.define_native(&mut globals.borrow_mut());
+        Interpreter {
+            env: Rc::new(RefCell::new(Environment::top_level(&globals))),
+            slots: SlotTable::default(),
+            globals,
+            unknown_identifier_resolver: self.unknown_identifier_resolver.clone(),
+            audit_log: self.audit_log.as_ref().map(|_| Vec::new()),
+            watchers,
+            tests,
+            notifying: Vec::new(),
+            inspections: Inspections::default(),
+            strict: self.strict,
+            diagnostics: self.diagnostics,
+            features: self.features,
+            semantics: self.semantics,
+            dialect: self.dialect,
+            resources: ResourceUsage::new(self.resources.limits.clone()),
+            debugger: None,
+            breakpoints: Breakpoints::default(),
+            call_stack: Vec::new(),
+            interrupt: InterruptHandle::default(),
+            try_depth: 0,
+            post_mortem_done: false,
+            stats: Stats::default(),
+            profiler: None,
+            output: None,
+            stdlib: self.stdlib.clone(),
+            capabilities: self.capabilities,
+            by_name: false,
+            granted: self.granted.clone(),
+        }
     }
-}
 
-pub struct Interpreter {
-    pub env: Rc<RefCell<Environment>>,
-    pub distances: HashMap<String, usize>,
-    state: State,
-}
+    /// Enables static checks that reject valid but suspicious code, like fields nobody initializes
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
 
-impl Interpreter {
-    pub fn new() -> Self {
-        let globals = {
-            let e = Rc::new(RefCell::new(Environment::new()));
-            let clock = Value::Function(Function::Native {
-                arity: 0,
-                body: || Value::Number(100.0),
+    pub(crate) fn is_strict(&self) -> bool {
+        self.strict
+    }
+
+    /// How `run_file` and the other entry points printing this interpreter's errors show them
+    pub fn set_diagnostic_options(&mut self, options: DiagnosticOptions) {
+        self.diagnostics = options;
+    }
+
+    pub fn diagnostic_options(&self) -> DiagnosticOptions {
+        self.diagnostics
+    }
+
+    /// Switches to another flavour of Lox, its features replace the ones set before
+    pub fn set_dialect(&mut self, dialect: Dialect) {
+        self.dialect = dialect;
+        self.features = dialect.features();
+    }
+
+    pub(crate) fn dialect(&self) -> Dialect {
+        self.dialect
+    }
+
+    /// Restricts the programs this interpreter accepts, e.g. to `LanguageVersion::chapter(9)`
+    pub fn set_features(&mut self, features: impl Into<Features>) {
+        self.features = features.into();
+    }
+
+    pub fn features(&self) -> Features {
+        self.features
+    }
+
+    pub fn set_semantics(&mut self, semantics: Semantics) {
+        self.semantics = semantics;
+    }
+
+    pub fn semantics(&self) -> Semantics {
+        self.semantics
+    }
+
+    /// Limits scripts exceeding raise a catchable `ResourceLimit` error
+    pub fn set_limits(&mut self, limits: ResourceLimits) {
+        self.resources.limits = limits;
+    }
+
+    pub fn set_capabilities(&mut self, capabilities: Capabilities) {
+        self.capabilities = capabilities;
+    }
+
+    pub fn capabilities(&self) -> Capabilities {
+        self.capabilities
+    }
+
+    /// Defines `readFile`, `writeFile`, `appendFile` and `fileExists`, scripts can't touch files without them.
+    /// Failures raise a catchable `IOError`.
+    #[cfg(not(all(target_arch = "wasm32", not(target_os = "wasi"))))]
+    pub fn allow_file_access(&mut self) {
+        self.define_builtins(define_file_natives);
+    }
+
+    /// Grants scripts the capability `name`, an object holding the natives `define` defines.
+    /// Scripts get it with `require(name)` and can pass it on to the code they trust with it,
+    /// its natives work whatever `set_capabilities` allows.
+    pub fn grant(&mut self, name: &str, define: impl FnOnce(&mut Environment)) {
+        let mut natives = Environment::new();
+        define(&mut natives);
+        let object = stdlib::capability_object(name, &natives);
+        self.granted.insert(name.to_owned(), object);
+    }
+
+    /// Grants the `fs` capability, an object with `readFile`, `writeFile`, `appendFile` and `fileExists`
+    #[cfg(not(all(target_arch = "wasm32", not(target_os = "wasi"))))]
+    pub fn grant_fs(&mut self) {
+        self.grant("fs", define_fs_capability);
+    }
+
+    /// The capability object `require(name)` returns, requiring one the host didn't grant raises `CapabilityDenied`
+    pub(crate) fn require(&self, name: &str) -> NativeResult {
+        self.granted
+            .get(name)
+            .cloned()
+            .ok_or_else(|| ErrorType::CapabilityDenied(format!("Capability '{}'", name)))
+    }
+
+    /// Defines `ffiLoad` and `ffiCall`, scripts can't call into C libraries without them.
+    /// Only numbers are passed and returned, see the README.
+    #[cfg(feature = "ffi")]
+    pub fn allow_ffi(&mut self) {
+        self.define_builtins(define_ffi_natives);
+    }
+
+    /// Where `readLine()` gets its lines from instead of stdin, e.g. a prompt in the browser.
+    /// None means there's no more input.
+    pub fn set_line_reader(&mut self, reader: impl FnMut() -> Option<String> + 'static) {
+        self.define_builtins(|env| define_line_reader(env, reader));
+    }
+
+    /// Sends every line print statements print to the callback, e.g. to capture it
+    pub fn set_output(&mut self, output: impl FnMut(&str) + 'static) {
+        self.output = Some(Box::new(output));
+    }
+
+    /// Arguments scripts read with `argCount()` and `arg(index)`, e.g. the ones given after the script's path
+    pub fn set_args(&mut self, args: Vec<String>) {
+        self.define_builtins(|env| define_args(env, args));
+    }
+
+    /// Seed of `random()` and `randomInt(a, b)`, scripts get the same numbers on every run with the same one
+    pub fn set_random_seed(&mut self, seed: u64) {
+        self.define_builtins(|env| define_random(env, seed));
+    }
+
+    /// Handle the host can stop running scripts with, e.g. from another thread
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.interrupt.clone()
+    }
+
+    /// Called with the token closing the call, before the function's body runs
+    pub(crate) fn enter_call(&mut self, function: &str, token: &Token) -> Result<(), Error> {
+        self.resources
+            .enter_call()
+            .or_else(|e| error(token, e).map(|_| ()))?;
+        self.call_stack.push(ActiveCall {
+            function: function.to_owned(),
+            line: token.line,
+            caller_env: Rc::clone(&self.env),
+        });
+        Ok(())
+    }
+
+    pub(crate) fn exit_call(&mut self) {
+        self.resources.exit_call();
+        self.call_stack.pop();
+    }
+
+    pub(crate) fn active_calls(&self) -> &[ActiveCall] {
+        &self.call_stack
+    }
+
+    /// Scope the function this many calls down the call stack is in, 0 being the current one
+    pub(crate) fn scope_at(&self, depth: usize) -> Option<Rc<RefCell<Environment>>> {
+        match depth {
+            0 => Some(Rc::clone(&self.env)),
+            depth => self
+                .call_stack
+                .len()
+                .checked_sub(depth)
+                .map(|i| Rc::clone(&self.call_stack[i].caller_env)),
+        }
+    }
+
+    /// Accounts for a value built by a native function against the resource limits
+    pub(crate) fn allocated(&mut self, value: &Value, token: &Token) -> Result<(), Error> {
+        self.resources
+            .allocate(value)
+            .or_else(|e| error(token, e).map(|_| ()))
+    }
+
+    /// Checked by natives before they build a string that may be too long, the string is accounted for once returned
+    pub(crate) fn check_string(&self, bytes: usize) -> Result<(), ErrorType> {
+        self.resources.check_string(bytes)
+    }
+
+    /// Checked by methods before they add items to a list
+    pub(crate) fn grow_list(&self, len: usize) -> Result<(), ErrorType> {
+        self.resources.grow_list(len)
+    }
+
+    /// Declares a variable, function or class of the script in the current scope
+    fn declare(&mut self, name: &str, value: &Value, token: &Token) -> Result<(), Error> {
+        let declared = self.env.borrow_mut().define(name, value);
+        declared.or_else(|e| error(token, e).map(|_| ()))
+    }
+
+    /// Joins the operands of `+`, accounting for the new string against the resource limits
+    fn concat(&mut self, operator: &Token, a: String, b: String) -> Result<Value, Error> {
+        if let Err(e) = self.resources.allocate_string(a.len() + b.len()) {
+            return error(operator, e);
+        }
+        Ok(Value::String([a, b].concat()))
+    }
+
+    /// The debugger is called before every statement and expression from now on
+    pub fn set_debugger(&mut self, debugger: Box<dyn Debugger>) {
+        self.debugger = Some(debugger);
+    }
+
+    pub fn breakpoints(&mut self) -> &mut Breakpoints {
+        &mut self.breakpoints
+    }
+
+    pub fn set_unknown_identifier_resolver(&mut self, resolver: UnknownIdentifierResolver) {
+        self.unknown_identifier_resolver = Some(resolver);
+    }
+
+    /// Starts recording every native function call made by scripts
+    pub fn enable_audit_log(&mut self) {
+        self.audit_log.get_or_insert_with(Vec::new);
+    }
+
+    /// Snapshot of the usage counters, for hosts exporting metrics
+    pub fn stats(&self) -> Stats {
+        self.stats.clone()
+    }
+
+    /// Starts counting executed statements and timing function calls, or stops and drops the profile so far
+    pub fn set_profiling(&mut self, profiling: bool) {
+        self.profiler = match profiling {
+            true => Some(Profiler::default()),
+            false => None,
+        };
+    }
+
+    /// Profile since profiling was turned on, empty when it's off
+    pub fn profile_report(&self) -> Profile {
+        self.profiler
+            .as_ref()
+            .map(|profiler| profiler.profile().clone())
+            .unwrap_or_default()
+    }
+
+    /// Called around every call of a Lox or native function, natives are declared on line 0
+    pub(crate) fn profile_call(&mut self, function: &str, line: usize) {
+        if let Some(profiler) = &mut self.profiler {
+            profiler.enter_call(function, line);
+        }
+    }
+
+    pub(crate) fn profile_return(&mut self) {
+        if let Some(profiler) = &mut self.profiler {
+            profiler.exit_call();
+        }
+    }
+
+    /// Native function calls recorded since the audit log was enabled, in the order they happened
+    pub fn audit_log(&self) -> &[AuditEntry] {
+        self.audit_log.as_deref().unwrap_or(&[])
+    }
+
+    pub(crate) fn record_native_call(
+        &mut self,
+        native: &str,
+        arguments: &[Value],
+        result: &Result<Value, ErrorType>,
+    ) {
+        self.stats.count_native_call(native);
+        if let Some(log) = &mut self.audit_log {
+            log.push(AuditEntry {
+                native: native.to_owned(),
+                arguments: arguments.to_vec(),
+                result: result.clone(),
             });
+        }
+    }
 
-            e.borrow_mut().define_or_update("clock", &clock);
-            e
+    /// Makes the value available to scripts as a global variable
+    pub fn define_global(&mut self, name: &str, value: Value) {
+        self.globals.borrow_mut().bind(name, &value);
+    }
+
+    /// Makes the value available to scripts as a global variable that can't be reassigned
+    pub fn define_host_constant(&mut self, name: &str, value: Value) {
+        self.globals.borrow_mut().define_read_only(name, &value);
+    }
+
+    /// Calls the callback whenever a top-level variable with this name is declared or assigned
+    pub fn watch(&mut self, name: &str, callback: WatchCallback) {
+        self.watchers.add(name, Watcher::Host(callback));
+    }
+
+    fn notify_watchers(&mut self, name: &str, value: &Value, token: &Token) -> Result<(), Error> {
+        if self.notifying.iter().any(|n| n == name) {
+            return Ok(());
+        }
+
+        self.notifying.push(name.to_owned());
+        let mut result = Ok(());
+        for watcher in self.watchers.get(name) {
+            result = match watcher {
+                Watcher::Host(callback) => {
+                    callback(name, value);
+                    Ok(())
+                }
+                Watcher::Script(callback) => {
+                    let arguments = vec![Value::String(name.to_owned()), value.clone()];
+                    self.call_value(callback, &arguments, token).map(|_| ())
+                }
+            };
+            if result.is_err() {
+                break;
+            }
+        }
+        self.notifying.pop();
+        result
+    }
+
+    /// Whether the environment holds top-level variables, either of this interpreter or of the one it was forked from
+    fn is_top_level(&self, env: &Rc<RefCell<Environment>>) -> bool {
+        Rc::ptr_eq(env, &self.globals)
+            || env
+                .borrow()
+                .enclosing()
+                .is_some_and(|enclosing| Rc::ptr_eq(&enclosing, &self.globals))
+    }
+
+    /// Describes the value for tooling, serializable to JSON
+    pub fn inspect(&mut self, value: &Value) -> ValueInspection {
+        self.inspections.inspect(value)
+    }
+
+    /// Expands a reference returned by an earlier inspection, None if it isn't valid anymore
+    pub fn inspect_children(&mut self, reference: usize) -> Option<Vec<InspectedChild>> {
+        self.inspections.children(reference)
+    }
+
+    /// Variables of the current scope, sorted by name
+    pub fn inspect_variables(&mut self) -> Vec<InspectedChild> {
+        let mut variables: Vec<(String, Value)> = self
+            .env
+            .borrow()
+            .bindings()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        variables.sort_by(|a, b| a.0.cmp(&b.0));
+        self.inspections.inspect_all(variables)
+    }
+
+    /// Names defined before any script runs: natives, the prelude and whatever the host defined
+    pub(crate) fn global_names(&self) -> Vec<String> {
+        self.globals
+            .borrow()
+            .bindings()
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    pub(crate) fn global_value(&self, name: &str) -> Option<Value> {
+        self.globals.borrow().get(name)
+    }
+
+    /// Invalidates all references handed out by inspections
+    pub fn clear_inspections(&mut self) {
+        self.inspections.clear();
+    }
+
+    /// Registers a host function callable from scripts, errors it returns are reported at the call site
+    pub fn define_native(
+        &mut self,
+        name: &str,
+        arity: usize,
+        body: impl Fn(&[Value]) -> NativeResult + 'static,
+    ) {
+        define_native(&mut self.globals.borrow_mut(), name, arity, body);
+    }
+
+    /// Registers a host function that gets the interpreter, so it can call the functions it's given with `call`
+    pub fn define_higher_order_native(
+        &mut self,
+        name: &str,
+        arity: usize,
+        body: impl Fn(&mut Interpreter, &[Value]) -> NativeResult + 'static,
+    ) {
+        define_higher_order(&mut self.globals.borrow_mut(), name, arity, body);
+    }
+
+    /// Runs the tests scripts have registered with `test(name, fn)` since the last call,
+    /// a test fails when it raises an error, e.g. with `assert`
+    pub fn run_tests(&mut self) -> TestReport {
+        let mut report = TestReport::default();
+        for (name, test) in self.tests.take() {
+            self.resources.start_run();
+            match self.call(&test, &[]) {
+                Ok(_) => report.passed.push(name),
+                Err(e) => report.failed.push((name, e)),
+            }
+        }
+        report
+    }
+
+    /// Calls a function or a class, e.g. one a native was given as an argument
+    pub fn call(&mut self, callee: &Value, arguments: &[Value]) -> Result<Value, Error> {
+        let token = host_token(&callee.to_string());
+        self.call_value(callee.clone(), &arguments.to_vec(), &token)
+    }
+
+    /// Calls a function or a class visible at the top level, e.g. one defined by an earlier `run`
+    pub fn call_global(&mut self, name: &str, arguments: &Vec<Value>) -> Result<Value, Error> {
+        let token = host_token(name);
+        let callee = match self.env.borrow().get_deep(name) {
+            Some(callee) => callee,
+            None => return error(&token, ErrorType::UndefinedVariable),
         };
+        self.resources.start_run();
+        let result = self.call_value(callee, arguments, &token);
+        if let Err(e) = &result {
+            self.stats.count_errors(std::slice::from_ref(e));
+        }
+        result
+    }
 
-        Interpreter {
-            env: Rc::new(RefCell::new(Environment::from(&globals))),
-            state: State::new(),
-            distances: HashMap::new(),
+    fn call_value(
+        &mut self,
+        callee: Value,
+        arguments: &Vec<Value>,
+        token: &Token,
+    ) -> Result<Value, Error> {
+        match callee {
+            Value::Function(func) => func.call(self, arguments, token),
+            Value::Class(class) => class.call(self, arguments, token),
+            _ => error(token, ErrorType::ValueNotCallable),
+        }
+    }
+
+    pub fn evaluate(&mut self, expr: &Expr) -> Result<Value, Error> {
+        if self.debugger.is_none() {
+            return expr.accept(self);
+        }
+        self.pause(expr.span(), Granularity::Expression);
+        let value = expr.accept(self)?;
+        if let Some(mut debugger) = self.debugger.take() {
+            let mut frame = Frame::new(expr.span(), Granularity::Expression, false, self);
+            debugger.after_expression(&mut frame, &value);
+            self.debugger = Some(debugger);
+        }
+        Ok(value)
+    }
+
+    fn execute(&mut self, stmt: &Stmt) -> Result<Value, Error> {
+        if self.interrupt.take() {
+            let reason = self.resources.stop(ErrorType::Interrupted);
+            return Err(Error::new(&span_token(stmt.span()), reason));
+        }
+        if let Err(e) = self.resources.execute_statement() {
+            return Err(Error::new(&span_token(stmt.span()), e));
+        }
+        if let (Some(profiler), false) = (&mut self.profiler, matches!(stmt, Stmt::Block { .. })) {
+            profiler.count_statement(stmt.span().line);
+        }
+        if self.debugger.is_some() && !matches!(stmt, Stmt::Block { .. }) {
+            self.pause(stmt.span(), Granularity::Statement);
+        }
+        let result = stmt.accept(self);
+        if let Err(e) = &result {
+            // the innermost statement reports it, while the scopes and calls it failed in still exist
+            if self.debugger.is_some()
+                && !self.post_mortem_done
+                && self.try_depth == 0
+                && e.error_type.is_catchable()
+            {
+                self.post_mortem_done = true;
+                self.post_mortem(stmt.span(), e);
+            }
+        }
+        result
+    }
+
+    /// Lets the debugger inspect the program an uncaught error is about to stop
+    fn post_mortem(&mut self, span: Span, error: &Error) {
+        if let Some(mut debugger) = self.debugger.take() {
+            let mut frame = Frame::new(span, Granularity::Statement, false, self);
+            debugger.on_error(&mut frame, error);
+            self.debugger = Some(debugger);
+        }
+    }
+
+    /// Hands the debugger the code with this span, which is about to run
+    fn pause(&mut self, span: Span, kind: Granularity) {
+        let is_breakpoint = self.hit_breakpoint(span, kind);
+        // taken out for the time of the callback, so that evaluating code in the frame doesn't call it again
+        if let Some(mut debugger) = self.debugger.take() {
+            let mut frame = Frame::new(span, kind, is_breakpoint, self);
+            match kind {
+                Granularity::Statement => debugger.before_statement(&mut frame),
+                Granularity::Expression => debugger.before_expression(&mut frame),
+                Granularity::Call => debugger.before_call(&mut frame),
+            }
+            self.debugger = Some(debugger);
+        }
+    }
+
+    fn lookup_variable(&mut self, name: &str, token: &Token, id: ExprId) -> Result<Value, Error> {
+        let slot = self.slots.borrow().get(&id).copied();
+        // the resolver doesn't know about globals and top-level variables defined by previous runs,
+        // so these have to be looked up through the whole chain
+        let var = match slot {
+            Some(slot) => self.env.borrow().get_at(name, slot),
+            None if self.by_name => self.env.borrow().lookup(name),
+            None => self
+                .env
+                .borrow()
+                .get_deep(name)
+                .or_else(|| self.resolve_unknown_identifier(name)),
+        };
+
+        match var {
+            Some(val) => Ok(val.clone()),
+            None => error(token, ErrorType::UndefinedVariable),
+        }
+    }
+
+    /// Whether the name is visible from the top level or could be provided by the host
+    pub(crate) fn is_defined(&self, name: &str) -> bool {
+        self.env.borrow().get_deep(name).is_some() || self.unknown_identifier_resolver.is_some()
+    }
+
+    fn resolve_unknown_identifier(&self, name: &str) -> Option<Value> {
+        let resolver = self.unknown_identifier_resolver.as_ref()?;
+        let value = resolver(name)?;
+        self.globals.borrow_mut().bind(name, &value);
+        Some(value)
+    }
+
+    fn assign_unresolved(
+        &mut self,
+        name: &str,
+        value: Value,
+        token: &Token,
+    ) -> Result<Value, Error> {
+        let mut env = Rc::clone(&self.env);
+        while !Rc::ptr_eq(&env, &self.globals) {
+            let declared = match self.by_name {
+                true => env.borrow().local(name),
+                false => env.borrow().get(name),
+            };
+            if declared.is_some() {
+                env.borrow_mut()
+                    .assign_local(name, &value)
+                    .map_err(|e| Error::new(token, e))?;
+                if self.is_top_level(&env) {
+                    self.notify_watchers(name, &value, token)?;
+                }
+                return Ok(value);
+            }
+            let enclosing = env.borrow().enclosing();
+            match enclosing {
+                Some(enclosing) => env = enclosing,
+                None => break,
+            }
+        }
+
+        // everything past the globals belongs to the interpreter this one was forked from,
+        // so the new value is kept in our own globals instead
+        {
+            let mut globals = self.globals.borrow_mut();
+            if globals.is_read_only(name) {
+                return error(token, ErrorType::AssignmentToHostConstant);
+            }
+            if globals.get_deep(name).is_none() {
+                return error(token, ErrorType::UndefinedVariable);
+            }
+            globals.bind(name, &value);
+        }
+        self.notify_watchers(name, &value, token)?;
+        Ok(value)
+    }
+
+    /// Line breakpoints pause only statements, otherwise every expression on the line would pause too
+    fn hit_breakpoint(&mut self, span: Span, kind: Granularity) -> bool {
+        if self.breakpoints.hit_temporary(span) {
+            return true;
+        }
+        let condition = match self.breakpoints.get(span.line) {
+            Some(breakpoint) if kind == Granularity::Statement => breakpoint.condition.clone(),
+            _ => return false,
+        };
+        // a condition that fails to evaluate pauses too, so the mistake doesn't go unnoticed
+        let holds = condition.is_none_or(|condition| {
+            self.evaluate_in_scope(&condition, Rc::clone(&self.env))
+                .map_or(true, |value| value.to_bool())
+        });
+        holds && self.breakpoints.count_hit(span.line)
+    }
+
+    /// Evaluates the expression at the top level, where the host's and the scripts' variables are
+    pub fn evaluate_expression(&mut self, source_code: &str) -> Result<Value, Vec<Error>> {
+        self.evaluate_in_scope(source_code, Rc::clone(&self.env))
+    }
+
+    /// The expression isn't resolved, its variables are looked up by name from the scope outwards.
+    /// Debugger callbacks aren't made while it runs.
+    pub(crate) fn evaluate_in_scope(
+        &mut self,
+        source_code: &str,
+        env: Rc<RefCell<Environment>>,
+    ) -> Result<Value, Vec<Error>> {
+        let mut lexer = Lexer::with_dialect(source_code, self.dialect);
+        let tokens = lexer.scan_tokens()?;
+        let mut parser = Parser::with_features(&tokens, self.features);
+        let expr = parser.parse_expression()?;
+
+        let debugger = self.debugger.take();
+        let previous_env = std::mem::replace(&mut self.env, env);
+        let by_name = std::mem::replace(&mut self.by_name, true);
+        let value = self.evaluate(&expr);
+        self.by_name = by_name;
+        self.env = previous_env;
+        self.debugger = debugger;
+        value.map_err(|e| vec![e])
+    }
+
+    pub(crate) fn resolve_slot(&mut self, id: ExprId, slot: Slot) {
+        self.slots.borrow_mut().insert(id, slot);
+    }
+
+    /// Makes the slots of another program the ones lookups use, returns the previous ones
+    pub(crate) fn use_slots(&mut self, slots: SlotTable) -> SlotTable {
+        std::mem::replace(&mut self.slots, slots)
+    }
+
+    /// Runs `f` with the slots of the program that declared the code it runs
+    pub(crate) fn with_slots<T>(&mut self, slots: &SlotTable, f: impl FnOnce(&mut Self) -> T) -> T {
+        let previous = self.use_slots(Rc::clone(slots));
+        let result = f(self);
+        self.use_slots(previous);
+        result
+    }
+
+    pub fn interpret(&mut self, stmts: &[Stmt]) -> Result<Value, Error> {
+        let mut last_val: Option<Value> = None;
+        for stmt in stmts {
+            last_val = Some(self.execute(stmt)?);
+        }
+        Ok(last_val.map_or_else(|| Value::Null, |v| v))
+    }
+
+    /// Thrown value of the error, runtime errors are turned into instances of the prelude's `Error`
+    fn exception_value(&mut self, e: &Error) -> Result<Value, Error> {
+        match &e.error_type {
+            ErrorType::Throw(value) => Ok(Value::clone(value)),
+            error_type => {
+                let class = self
+                    .globals
+                    .borrow()
+                    .get(error_type.exception_class())
+                    .unwrap_or(Value::Null);
+                self.call_value(
+                    class,
+                    &vec![Value::String(error_type.to_string())],
+                    &e.token,
+                )
+            }
+        }
+    }
+
+    fn catches(
+        &mut self,
+        class: &Option<Expr>,
+        exception: &Value,
+        token: &Token,
+    ) -> Result<bool, Error> {
+        let class = match class {
+            Some(class) => class,
+            None => return Ok(true),
+        };
+        match (self.evaluate(class)?, exception) {
+            (Value::Class(class), Value::Instance(instance)) => {
+                Ok(instance.borrow().class().is_subclass_of(&class))
+            }
+            (Value::Class(_), _) => Ok(false),
+            _ => error(token, ErrorType::CatchFilterNotClass).map(|_| false),
+        }
+    }
+
+    /// Whether the value matches the pattern, the parts it binds are added to the bindings
+    fn matches_pattern<'p>(
+        &mut self,
+        pattern: &'p Pattern,
+        value: &Value,
+        bindings: &mut Vec<(&'p String, Value)>,
+    ) -> Result<bool, Error> {
+        match pattern {
+            Pattern::Literal { value: literal, .. } => Ok(Value::new(literal) == *value),
+            Pattern::Binding { name, .. } => {
+                if name != "_" {
+                    bindings.push((name, value.clone()));
+                }
+                Ok(true)
+            }
+            Pattern::List { items, rest, .. } => {
+                let list = match value {
+                    Value::List(list) => list.borrow().clone(),
+                    _ => return Ok(false),
+                };
+                let fits = match rest {
+                    Some(_) => list.len() >= items.len(),
+                    None => list.len() == items.len(),
+                };
+                if !fits {
+                    return Ok(false);
+                }
+                for (item, value) in items.iter().zip(&list) {
+                    if !self.matches_pattern(item, value, bindings)? {
+                        return Ok(false);
+                    }
+                }
+                if let Some(rest) = rest.as_ref().filter(|rest| *rest != "_") {
+                    let remaining = list[items.len()..].to_vec();
+                    bindings.push((rest, Value::List(Rc::new(RefCell::new(remaining)))));
+                }
+                Ok(true)
+            }
+            Pattern::Instance {
+                class,
+                fields,
+                token,
+            } => {
+                // the class is looked up even for values that aren't instances, so a wrong one is always reported
+                let class = match class {
+                    Some(class) => match self.evaluate(class)? {
+                        Value::Class(class) => Some(class),
+                        _ => return error(token, ErrorType::PatternClassNotClass).map(|_| false),
+                    },
+                    None => None,
+                };
+                let instance = match value {
+                    Value::Instance(instance) => Rc::clone(instance),
+                    _ => return Ok(false),
+                };
+                if let Some(class) = class {
+                    if !instance.borrow().class().is_subclass_of(&class) {
+                        return Ok(false);
+                    }
+                }
+                for (name, field) in fields {
+                    let value = match instance.borrow().field(name) {
+                        Some(value) => value.clone(),
+                        None => return Ok(false),
+                    };
+                    if !self.matches_pattern(field, &value, bindings)? {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            }
         }
     }
 
-    pub fn evaluate(&mut self, expr: &Expr) -> Result<Value, Error> {
-        expr.accept(self)
+    /// Method of an instance that stands in for an operator or for its text, bound to the instance
+    fn magic_method(&self, value: &Value, name: &str) -> Option<Value> {
+        match value {
+            Value::Instance(instance) if self.dialect == Dialect::LoxRs => instance
+                .borrow()
+                .class()
+                .find_method(&name.to_owned())
+                .map(|method| Value::Function(method.clone().bind(Rc::clone(instance)))),
+            _ => None,
+        }
     }
 
-    fn lookup_variable(&mut self, var: VarRef, token: &Token) -> Result<Value, Error> {
-        let distance = self.get_distance(&var);
-        let name = &var.name;
-        let var = self.env.borrow().get_at(name, distance.unwrap_or(0));
+    /// Calls the receiver's method for the operator with the other operand
+    fn operator_method(
+        &mut self,
+        receiver: &Value,
+        name: &str,
+        other: Value,
+        operator: &Token,
+    ) -> Result<Value, Error> {
+        match (self.magic_method(receiver, name), receiver) {
+            (Some(method), _) => self.call_value(method, &vec![other], operator),
+            (None, Value::Instance(_)) => {
+                error(operator, ErrorType::MissingOperatorMethod(name.to_owned()))
+            }
+            (None, _) => error(operator, ErrorType::WrongType),
+        }
+    }
 
-        match var {
-            Some(val) => Ok(val.clone()),
-            None => error(token, ErrorType::UndefinedVariable),
+    /// Calls the instance's `getIndex` or `setIndex` method
+    fn index_method(
+        &mut self,
+        instance: &Value,
+        name: &str,
+        arguments: Vec<Value>,
+        token: &Token,
+    ) -> Result<Value, Error> {
+        match self.magic_method(instance, name) {
+            Some(method) => self.call_value(method, &arguments, token),
+            None => error(token, ErrorType::MissingOperatorMethod(name.to_owned())),
         }
     }
 
-    fn lookup_deep(&mut self, name: &str, token: &Token) -> Result<Value, Error> {
-        self.env
-            .borrow()
-            .get_deep(name)
-            .map_or_else(|| error(token, ErrorType::UndefinedVariable), |v| Ok(v))
+    /// `==`, an instance with an `eq` method on either side decides, others are compared the usual way
+    fn equals(&mut self, a: Value, b: Value, operator: &Token) -> Result<bool, Error> {
+        if let Some(eq) = self.magic_method(&a, "eq") {
+            return Ok(self.call_value(eq, &vec![b], operator)?.to_bool());
+        }
+        if let Some(eq) = self.magic_method(&b, "eq") {
+            return Ok(self.call_value(eq, &vec![a], operator)?.to_bool());
+        }
+        Ok(a == b)
     }
 
-    fn get_distance(&self, var: &VarRef) -> Option<usize> {
-        self.distances.get(&var.to_string()).map(|v| *v)
+    /// Comparisons of instances go through `lt`: `a > b` is `b < a`, `a <= b` is `!(b < a)` and `a >= b` is `!(a < b)`
+    fn compare_instances(&mut self, a: Value, operator: &Token, b: Value) -> Result<Value, Error> {
+        let (receiver, other, negated) = match operator.token_type {
+            TokenType::Less => (a, b, false),
+            TokenType::Greater => (b, a, false),
+            TokenType::LessEquals => (b, a, true),
+            _ => (a, b, true),
+        };
+        let less = self.operator_method(&receiver, "lt", other, operator)?;
+        Ok(Value::Boolean(less.to_bool() != negated))
     }
 
-    pub fn resolve_distance(&mut self, var: VarRef, depth: usize) {
-        self.distances.insert(var.to_string(), depth);
+    /// Text `print` shows for the value, instances with a `toString` method are shown as what it returns
+    pub(crate) fn stringify(&mut self, value: &Value) -> Result<String, Error> {
+        match self.magic_method(value, "toString") {
+            Some(method) => Ok(self.call(&method, &[])?.to_string()),
+            None => Ok(value.to_string()),
+        }
     }
 
-    pub fn interpret(&mut self, stmts: &Vec<Stmt>) -> Result<Value, Error> {
-        let mut last_val: Option<Value> = None;
-        for stmt in stmts {
-            if self.state.will_continue() || self.state.will_return() || self.state.should_break {
-                break;
-            }
-            last_val = Some(stmt.accept(self)?);
+    /// Runs a single pass of the loop body, returns false once the loop got broken out of
+    fn loop_iteration(&mut self, body: &Stmt) -> Result<bool, Error> {
+        match self.execute(body) {
+            Err(Error {
+                error_type: ErrorType::Break,
+                ..
+            }) => Ok(false),
+            Err(Error {
+                error_type: ErrorType::Continue,
+                ..
+            })
+            | Ok(_) => Ok(true),
+            Err(e) => Err(e),
         }
-        Ok(last_val.map_or_else(|| Value::Null, |v| v))
     }
 
     pub fn execute_block(
         &mut self,
-        statements: &Vec<Stmt>,
+        statements: &[Stmt],
         env: Rc<RefCell<Environment>>,
     ) -> Result<Value, Error> {
         let prev_env = self.env.clone();
         self.env = env;
-        let val = self.interpret(statements)?;
+        let val = self.interpret(statements);
         self.env = prev_env;
-        Ok(val)
+        val
+    }
+
+    /// Evaluates the expression as if it was written where the environment is
+    pub(crate) fn evaluate_in(
+        &mut self,
+        expr: &Expr,
+        env: Rc<RefCell<Environment>>,
+    ) -> Result<Value, Error> {
+        let prev_env = std::mem::replace(&mut self.env, env);
+        let val = self.evaluate(expr);
+        self.env = prev_env;
+        val
+    }
+}
+
+/// Stands in for a source location when there is none, e.g. when the host calls into the script
+fn host_token(name: &str) -> Token {
+    Token {
+        token_type: TokenType::Identifier(name.to_owned()),
+        line: 0,
+        start: 0,
+        end: 0,
+    }
+}
+
+/// Stands in for the token of nodes that don't have one, so errors about them can still be reported
+pub(crate) fn span_token(span: Span) -> Token {
+    Token::new(TokenType::Invalid, span.line, span.start, span.end)
+}
+
+/// Operands of a wrong type are pointed at together with their operator
+fn operand_error(operator: &Token, left: &Expr, right: &Expr) -> Result<Value, Error> {
+    Err(Error::new(operator, ErrorType::WrongType).with_span(left.span().to(right.span())))
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Interpreter::new()
     }
 }
 
@@ -153,64 +1207,86 @@ impl ExprVisitor<Value> for Interpreter {
         match operator.token_type {
             TokenType::Plus => match (a, b) {
                 (Value::Number(a), Value::Number(b)) => Ok(Value::Number(a + b)),
-                (Value::String(a), Value::String(b)) => Ok(Value::String([a, b].concat())),
-                _ => error(operator, ErrorType::WrongType),
+                (Value::String(a), Value::String(b)) => self.concat(operator, a, b),
+                // jlox has no operator overloading
+                (a @ Value::Instance(_), b) if self.dialect == Dialect::LoxRs => {
+                    self.operator_method(&a, "add", b, operator)
+                }
+                (Value::String(a), b) if self.semantics.string_conversion => {
+                    let b = self.stringify(&b)?;
+                    self.concat(operator, a, b)
+                }
+                (a, Value::String(b)) if self.semantics.string_conversion => {
+                    let a = self.stringify(&a)?;
+                    self.concat(operator, a, b)
+                }
+                _ => operand_error(operator, left, right),
             },
             TokenType::Minus => match (a, b) {
                 (Value::Number(a), Value::Number(b)) => Ok(Value::Number(a - b)),
-                _ => error(operator, ErrorType::WrongType),
+                (a @ Value::Instance(_), b) if self.dialect == Dialect::LoxRs => {
+                    self.operator_method(&a, "sub", b, operator)
+                }
+                _ => operand_error(operator, left, right),
             },
             TokenType::Modulo => match (a, b) {
                 (Value::Number(a), Value::Number(b)) => Ok(Value::Number(a % b)),
-                _ => error(operator, ErrorType::WrongType),
+                _ => operand_error(operator, left, right),
             },
             TokenType::Star => match (a, b) {
                 (Value::Number(a), Value::Number(b)) => Ok(Value::Number(a * b)),
-                _ => error(operator, ErrorType::WrongType),
+                _ => operand_error(operator, left, right),
             },
             TokenType::Divide => match (a, b) {
                 (Value::Number(a), Value::Number(b)) => Ok(Value::Number(a / b)),
-                _ => error(operator, ErrorType::WrongType),
-            },
-            TokenType::BangEquals => match (a, b) {
-                (Value::Number(a), Value::Number(b)) => Ok(Value::Boolean(a != b)),
-                (Value::String(a), Value::String(b)) => Ok(Value::Boolean(a != b)),
-                (Value::Boolean(a), Value::Boolean(b)) => Ok(Value::Boolean(a != b)),
-                (Value::Null, Value::Null) => Ok(Value::Boolean(false)),
-                _ => error(operator, ErrorType::WrongType),
-            },
-            TokenType::Compare => match (a, b) {
-                (Value::Number(a), Value::Number(b)) => Ok(Value::Boolean(a == b)),
-                (Value::String(a), Value::String(b)) => Ok(Value::Boolean(a == b)),
-                (Value::Boolean(a), Value::Boolean(b)) => Ok(Value::Boolean(a == b)),
-                (Value::Null, Value::Null) => Ok(Value::Boolean(true)),
-                _ => error(operator, ErrorType::WrongType),
+                _ => operand_error(operator, left, right),
             },
+            TokenType::BangEquals => Ok(Value::Boolean(!self.equals(a, b, operator)?)),
+            TokenType::Compare => Ok(Value::Boolean(self.equals(a, b, operator)?)),
+            // jlox compares only numbers
+            TokenType::Less
+            | TokenType::LessEquals
+            | TokenType::Greater
+            | TokenType::GreaterEquals
+                if self.dialect == Dialect::Jlox
+                    && !(matches!(a, Value::Number(_)) && matches!(b, Value::Number(_))) =>
+            {
+                operand_error(operator, left, right)
+            }
+            TokenType::Less
+            | TokenType::LessEquals
+            | TokenType::Greater
+            | TokenType::GreaterEquals
+                if matches!(a, Value::Instance(_)) || matches!(b, Value::Instance(_)) =>
+            {
+                self.compare_instances(a, operator, b)
+            }
             TokenType::Less => match (a, b) {
                 (Value::Number(a), Value::Number(b)) => Ok(Value::Boolean(a < b)),
-                (Value::String(a), Value::String(b)) => Ok(Value::Boolean(a.len() < b.len())),
-                _ => error(operator, ErrorType::WrongType),
+                (Value::String(a), Value::String(b)) => Ok(Value::Boolean(a < b)),
+                _ => operand_error(operator, left, right),
             },
             TokenType::LessEquals => match (a, b) {
                 (Value::Number(a), Value::Number(b)) => Ok(Value::Boolean(a <= b)),
-                (Value::String(a), Value::String(b)) => Ok(Value::Boolean(a.len() <= b.len())),
-                _ => error(operator, ErrorType::WrongType),
+                (Value::String(a), Value::String(b)) => Ok(Value::Boolean(a <= b)),
+                _ => operand_error(operator, left, right),
             },
             TokenType::Greater => match (a, b) {
                 (Value::Number(a), Value::Number(b)) => Ok(Value::Boolean(a > b)),
-                (Value::String(a), Value::String(b)) => Ok(Value::Boolean(a.len() > b.len())),
-                _ => error(operator, ErrorType::WrongType),
+                (Value::String(a), Value::String(b)) => Ok(Value::Boolean(a > b)),
+                _ => operand_error(operator, left, right),
             },
             TokenType::GreaterEquals => match (a, b) {
                 (Value::Number(a), Value::Number(b)) => Ok(Value::Boolean(a >= b)),
-                (Value::String(a), Value::String(b)) => Ok(Value::Boolean(a.len() >= b.len())),
-                _ => error(operator, ErrorType::WrongType),
+                (Value::String(a), Value::String(b)) => Ok(Value::Boolean(a >= b)),
+                _ => operand_error(operator, left, right),
             },
-            _ => unreachable!(),
+            // only trees that weren't built by the parser can have other operators
+            _ => error(operator, ErrorType::ExpectedOperator),
         }
     }
 
-    fn visit_literal(&mut self, literal: &Literal) -> Result<Value, Error> {
+    fn visit_literal(&mut self, literal: &Literal, _span: &Span) -> Result<Value, Error> {
         Ok(Value::new(literal))
     }
 
@@ -223,7 +1299,7 @@ impl ExprVisitor<Value> for Interpreter {
                 _ => error(operator, ErrorType::WrongType),
             },
             TokenType::Bang => Ok(Value::Boolean(!val.to_bool())),
-            _ => unreachable!(),
+            _ => error(operator, ErrorType::ExpectedUnaryOperator),
         }
     }
 
@@ -231,8 +1307,8 @@ impl ExprVisitor<Value> for Interpreter {
         self.evaluate(expr)
     }
 
-    fn visit_var(&mut self, name: &String, token: &Token) -> Result<Value, Error> {
-        self.lookup_variable(VarRef::new(token, name), token)
+    fn visit_var(&mut self, name: &String, token: &Token, id: ExprId) -> Result<Value, Error> {
+        self.lookup_variable(name, token, id)
     }
 
     fn visit_assignment(
@@ -240,17 +1316,30 @@ impl ExprVisitor<Value> for Interpreter {
         name: &String,
         expr: &Expr,
         token: &Token,
+        id: ExprId,
     ) -> Result<Value, Error> {
         let value = self.evaluate(expr)?;
-        let distance = self.get_distance(&VarRef::new(token, name));
+        let slot = self.slots.borrow().get(&id).copied();
 
-        if let Some(dist) = distance {
-            match self.env.borrow_mut().assign_at(name, &value, dist) {
-                Some(val) => Ok(val),
-                None => error(token, ErrorType::UndefinedVariable),
+        if let Some(slot) = slot {
+            self.env
+                .borrow_mut()
+                .assign_at(name, &value, slot)
+                .map_err(|e| Error::new(token, e))?;
+            let env = match slot.distance {
+                0 => Rc::clone(&self.env),
+                distance => self
+                    .env
+                    .borrow()
+                    .ancestor(distance)
+                    .map_err(|e| Error::new(token, e))?,
+            };
+            if self.is_top_level(&env) {
+                self.notify_watchers(name, &value, token)?;
             }
+            Ok(value)
         } else {
-            error(token, ErrorType::UndefinedVariable)
+            self.assign_unresolved(name, value, token)
         }
     }
 
@@ -260,21 +1349,13 @@ impl ExprVisitor<Value> for Interpreter {
         operator: &Token,
         right: &Expr,
     ) -> Result<Value, Error> {
-        // TODO: this needs more testing
         let left_val = self.evaluate(left)?;
-        let right_val = self.evaluate(right)?;
-        let res = match operator.token_type {
-            TokenType::Or => {
-                if left_val.to_bool() {
-                    left_val
-                } else {
-                    right_val
-                }
-            }
-            TokenType::And => Value::Boolean(left_val.to_bool() && right_val.to_bool()),
-            _ => right_val,
-        };
-        Ok(res)
+        // the right operand is evaluated only when the left one doesn't decide the result
+        match operator.token_type {
+            TokenType::Or if left_val.to_bool() => Ok(left_val),
+            TokenType::And if !left_val.to_bool() => Ok(left_val),
+            _ => self.evaluate(right),
+        }
     }
 
     fn visit_call(
@@ -282,45 +1363,71 @@ impl ExprVisitor<Value> for Interpreter {
         callee: &Expr,
         token: &Token,
         arguments: &Vec<Expr>,
+        named: &[(String, Expr)],
     ) -> Result<Value, Error> {
+        let span = callee.span().to(token.span());
         let callee = self.evaluate(callee)?;
+        if !matches!(callee, Value::Function(_) | Value::Class(_)) {
+            return Err(Error::new(token, ErrorType::ValueNotCallable).with_span(span));
+        }
 
-        let args: Result<Vec<Value>, Error> = arguments.iter().map(|a| self.evaluate(a)).collect();
-
-        let result = match callee {
-            Value::Function(func) => {
-                self.state.enter_call();
-                func.call(self, &args?)
+        let mut args = Vec::with_capacity(arguments.len());
+        for argument in arguments {
+            match argument {
+                Expr::Spread { expr, token } => match self.evaluate(expr)? {
+                    Value::List(list) => args.extend(list.borrow().iter().cloned()),
+                    _ => return error(token, ErrorType::SpreadNotList),
+                },
+                argument => args.push(self.evaluate(argument)?),
             }
-            Value::Class(class) => class.call(self, &args?),
-            _ => error(token, ErrorType::ValueNotCallable),
-        };
+        }
+        if self.debugger.is_some() {
+            self.pause(span, Granularity::Call);
+        }
 
-        self.state.exit_call();
-        result
+        if named.is_empty() {
+            return self.call_value(callee, &args, token);
+        }
+        if !matches!(callee, Value::Class(_)) {
+            return error(token, ErrorType::NamedArgumentsOutsideClass);
+        }
+        let instance = self.call_value(callee, &args, token)?;
+        if let Value::Instance(instance) = &instance {
+            for (name, value) in named {
+                let value = self.evaluate(value)?;
+                instance.borrow_mut().set(name, token, value);
+            }
+        }
+        Ok(instance)
     }
 
     fn visit_closure(
         &mut self,
-        args: &Vec<String>,
-        body: &Vec<Stmt>,
+        args: &Rc<Vec<String>>,
+        signature: &Signature,
+        defaults: &Rc<Vec<Expr>>,
+        body: &Rc<Vec<Stmt>>,
         name: &String,
         token: &Token,
     ) -> Result<Value, Error> {
         Ok(Value::Function(Function::Standard {
-            params: args.clone(),
-            body: body.clone(),
+            params: Rc::clone(args),
+            defaults: Rc::clone(defaults),
+            variadic: signature.variadic,
+            body: Rc::clone(body),
             name: name.clone(),
             token: token.clone(),
-            this: None,
             closure: Rc::clone(&self.env),
+            slots: Rc::clone(&self.slots),
         }))
     }
 
     fn visit_get(&mut self, name: &String, token: &Token, expr: &Expr) -> Result<Value, Error> {
         let obj = self.evaluate(expr)?;
         match obj {
-            Value::Instance(instance) => instance.borrow().get(name, token),
+            Value::Instance(instance) => Instance::get(&instance, name, token),
+            value if has_prototype(&value) => get_method(&value, name)
+                .map_or_else(|| error(token, ErrorType::PropertyDoesntExist), Ok),
             _ => error(token, ErrorType::ValueNotInstance),
         }
     }
@@ -345,34 +1452,107 @@ impl ExprVisitor<Value> for Interpreter {
         Ok(instance)
     }
 
-    fn visit_this(&mut self, token: &Token) -> Result<Value, Error> {
-        self.lookup_deep("this", token)
+    fn visit_this(&mut self, token: &Token, id: ExprId) -> Result<Value, Error> {
+        self.lookup_variable("this", token, id)
     }
 
-    fn visit_super(&mut self, token: &Token, method_name: &String) -> Result<Value, Error> {
-        let superclass = self.lookup_deep("super", token)?;
+    fn visit_super(
+        &mut self,
+        token: &Token,
+        method_name: &String,
+        id: ExprId,
+    ) -> Result<Value, Error> {
+        // super lives in the class's environment and this in the one right below it,
+        // both set up when the class was defined and the method bound
+        let slot = match self.slots.borrow().get(&id).copied() {
+            Some(slot) => slot,
+            None => return error(token, ErrorType::CantUseSuper),
+        };
+        let superclass = self.env.borrow().get_at("super", slot);
+        let this = Slot {
+            distance: slot.distance - 1,
+            index: 0,
+        };
+        let instance = self.env.borrow().get_at("this", this);
 
-        match superclass.as_class().unwrap().find_method(method_name) {
-            Some(method) => {
-                let instance = self
-                    .env
-                    .borrow()
-                    .get_deep("this")
-                    .unwrap()
-                    .as_instance()
-                    .unwrap()
-                    .clone();
-                Ok(Value::Function(method.clone().bind(instance)))
+        match (superclass, instance) {
+            (Some(Value::Class(superclass)), Some(Value::Instance(instance))) => {
+                match superclass.find_method(method_name) {
+                    Some(method) => Ok(Value::Function(method.clone().bind(instance))),
+                    None => error(token, ErrorType::MethodNotFound),
+                }
             }
-            None => error(token, ErrorType::MethodNotFound),
+            _ => error(token, ErrorType::CantUseSuper),
         }
     }
+
+    /// Items of lists and characters of strings, instances are indexed by their `getIndex` method
+    fn visit_index(&mut self, object: &Expr, index: &Expr, token: &Token) -> Result<Value, Error> {
+        let object = self.evaluate(object)?;
+        let index = self.evaluate(index)?;
+        match object {
+            Value::List(ref list) => index_arg(&object, &index)
+                .map(|i| list.borrow()[i].clone())
+                .map_err(|e| Error::new(token, e)),
+            Value::String(ref s) => char_at(s, &index)
+                .map(|c| Value::String(c.to_string()))
+                .map_err(|e| Error::new(token, e)),
+            Value::Instance(_) => self.index_method(&object, "getIndex", vec![index], token),
+            _ => error(token, ErrorType::ValueNotIndexable),
+        }
+    }
+
+    /// Replaces items of lists, instances are given the value through their `setIndex` method
+    fn visit_set_index(
+        &mut self,
+        object: &Expr,
+        index: &Expr,
+        value: &Expr,
+        token: &Token,
+    ) -> Result<Value, Error> {
+        let object = self.evaluate(object)?;
+        let index = self.evaluate(index)?;
+        let value = self.evaluate(value)?;
+        match object {
+            Value::List(ref list) => {
+                let i = index_arg(&object, &index).map_err(|e| Error::new(token, e))?;
+                list.borrow_mut()[i] = value.clone();
+            }
+            Value::Instance(_) => {
+                self.index_method(&object, "setIndex", vec![index, value.clone()], token)?;
+            }
+            Value::String(_) => return error(token, ErrorType::StringNotMutable),
+            _ => return error(token, ErrorType::ValueNotIndexable),
+        }
+        Ok(value)
+    }
+
+    // the parser only puts spreads among call arguments, which `visit_call` expands itself
+    fn visit_spread(&mut self, _expr: &Expr, token: &Token) -> Result<Value, Error> {
+        error(token, ErrorType::UnparsableExpression)
+    }
+
+    // error nodes only come out of parse_partial, whose trees are never run
+    fn visit_error(&mut self, token: &Token) -> Result<Value, Error> {
+        error(token, ErrorType::UnparsableExpression)
+    }
 }
 
 impl StmtVisitor<Value> for Interpreter {
     fn visit_print_stmt(&mut self, expr: &Expr) -> Result<Value, Error> {
         let value = self.evaluate(expr)?;
-        print(&value.to_string(), PrintType::Success);
+        let text = match self.dialect {
+            Dialect::LoxRs => self.stringify(&value)?,
+            Dialect::Jlox => jlox_string(&value),
+        };
+        match &mut self.output {
+            Some(output) => output(&text),
+            None if self.capabilities.stdout => print(&text, PrintType::Success),
+            None => {
+                let denied = ErrorType::CapabilityDenied(String::from("print"));
+                return Err(Error::new(&span_token(expr.span()), denied));
+            }
+        }
         Ok(Value::Null)
     }
 
@@ -380,19 +1560,30 @@ impl StmtVisitor<Value> for Interpreter {
         Ok(self.evaluate(expr)?)
     }
 
-    fn visit_var(&mut self, name: &String, expr: &Option<Expr>) -> Result<Value, Error> {
+    fn visit_var(
+        &mut self,
+        name: &String,
+        expr: &Option<Expr>,
+        _annotation: &Option<Type>,
+        span: &Span,
+    ) -> Result<Value, Error> {
         let value = match expr {
             Some(e) => self.evaluate(e)?,
             None => Value::Null,
         };
-        self.env.borrow_mut().define_or_update(name, &value);
+        // declarations don't carry a token, their errors point to the whole statement
+        let token = span_token(*span);
+        self.declare(name, &value, &token)?;
+        if self.is_top_level(&self.env) {
+            self.notify_watchers(name, &value, &token)?;
+        }
         Ok(value)
     }
 
     fn visit_block_stmt(&mut self, statements: &Vec<Stmt>) -> Result<Value, Error> {
         // TODO: figure out if I can avoid the clones
-        let env = Rc::new(RefCell::new(Environment::from(&self.env)));
-        Ok(self.execute_block(statements, env)?)
+        let env = Environment::frame(&self.env, locals(statements));
+        Ok(self.execute_block(statements, Rc::new(RefCell::new(env)))?)
     }
 
     fn visit_if_stmt(
@@ -403,58 +1594,97 @@ impl StmtVisitor<Value> for Interpreter {
     ) -> Result<Value, Error> {
         let cond = self.evaluate(condition)?.to_bool();
         if cond {
-            Ok(then_body.accept(self)?)
+            Ok(self.execute(then_body)?)
         } else {
             let val = match else_body {
-                Some(stmt) => stmt.accept(self)?,
+                Some(stmt) => self.execute(stmt)?,
                 _ => Value::Null,
             };
             Ok(val)
         }
     }
 
-    fn visit_while_stmt(&mut self, condition: &Expr, body: &Stmt) -> Result<Value, Error> {
-        loop {
-            if self.evaluate(condition)?.to_bool() {
-                if self.state.will_break() {
-                    break;
-                }
-                body.accept(self)?;
-            } else {
+    fn visit_while_stmt(
+        &mut self,
+        condition: &Expr,
+        body: &Stmt,
+        increment: &Option<Expr>,
+    ) -> Result<Value, Error> {
+        while self.evaluate(condition)?.to_bool() {
+            if !self.loop_iteration(body)? {
                 break;
             }
+            if let Some(increment) = increment {
+                self.evaluate(increment)?;
+            }
         }
         Ok(Value::Null)
     }
 
-    fn visit_break_stmt(&mut self, _token: &Token) -> Result<Value, Error> {
-        self.state.should_break = true;
+    fn visit_do_while_stmt(&mut self, body: &Stmt, condition: &Expr) -> Result<Value, Error> {
+        while self.loop_iteration(body)? && self.evaluate(condition)?.to_bool() {}
         Ok(Value::Null)
     }
 
-    fn visit_continue_stmt(&mut self, _token: &Token) -> Result<Value, Error> {
-        self.state.should_continue = true;
+    /// Goes over the items the list has when the loop starts, or the characters of the string
+    fn visit_for_in_stmt(
+        &mut self,
+        name: &String,
+        iterable: &Expr,
+        body: &Stmt,
+        token: &Token,
+    ) -> Result<Value, Error> {
+        let items: Vec<Value> = match self.evaluate(iterable)? {
+            Value::List(list) => list.borrow().clone(),
+            Value::String(s) => s.chars().map(|c| Value::String(c.to_string())).collect(),
+            _ => {
+                return Err(
+                    Error::new(token, ErrorType::ValueNotIterable).with_span(iterable.span())
+                )
+            }
+        };
+        for item in items {
+            let mut env = Environment::frame(&self.env, 1);
+            env.bind(name, &item);
+            let prev_env = std::mem::replace(&mut self.env, Rc::new(RefCell::new(env)));
+            let result = self.loop_iteration(body);
+            self.env = prev_env;
+            if !result? {
+                break;
+            }
+        }
         Ok(Value::Null)
     }
 
+    fn visit_break_stmt(&mut self, token: &Token) -> Result<Value, Error> {
+        error(token, ErrorType::Break)
+    }
+
+    fn visit_continue_stmt(&mut self, token: &Token) -> Result<Value, Error> {
+        error(token, ErrorType::Continue)
+    }
+
     fn visit_function_stmt(
         &mut self,
         name: &String,
-        params: &Vec<String>,
-        body: &Vec<Stmt>,
+        params: &Rc<Vec<String>>,
+        signature: &Signature,
+        defaults: &Rc<Vec<Expr>>,
+        body: &Rc<Vec<Stmt>>,
         token: &Token,
     ) -> Result<Value, Error> {
-        // TODO: Is clone necessary? Probably not, it's ugly
         let function = Value::Function(Function::Standard {
             name: name.clone(),
-            body: body.clone(),
-            params: params.clone(),
+            body: Rc::clone(body),
+            params: Rc::clone(params),
+            defaults: Rc::clone(defaults),
+            variadic: signature.variadic,
             token: token.clone(),
-            this: None,
             closure: Rc::clone(&self.env),
+            slots: Rc::clone(&self.slots),
         });
 
-        self.env.borrow_mut().define_or_update(name, &function);
+        self.declare(name, &function, token)?;
 
         Ok(function)
     }
@@ -466,30 +1696,608 @@ impl StmtVisitor<Value> for Interpreter {
         members: &Vec<Stmt>,
         superclass: &Option<Expr>,
     ) -> Result<Value, Error> {
-        self.env.borrow_mut().define_or_update(name, &Value::Null);
-
         let superclass = if let Some(superclass) = superclass {
             match self.evaluate(superclass)? {
-                Value::Class(sc) => Some(Box::new(sc)),
+                Value::Class(sc) => Some(sc),
                 _ => return error(token, ErrorType::CanOnlyInheritFromClass),
             }
         } else {
             None
         };
 
-        let class = Class::new(name, members, superclass, self)?;
-        self.env
-            .borrow_mut()
-            .define_or_update(name, &Value::Class(class));
+        // methods of a subclass close over an environment holding its superclass
+        let closure = match &superclass {
+            Some(superclass) => {
+                let mut env = Environment::frame(&self.env, 1);
+                env.bind("super", &Value::Class(Rc::clone(superclass)));
+                Rc::new(RefCell::new(env))
+            }
+            None => Rc::clone(&self.env),
+        };
+        let class = Class::new(
+            name,
+            members,
+            superclass,
+            closure,
+            Rc::clone(&self.env),
+            &self.slots,
+        );
+        self.declare(name, &Value::Class(Rc::new(class)), token)?;
         Ok(Value::Null)
     }
 
-    fn visit_return_stmt(&mut self, value: &Option<Expr>, _token: &Token) -> Result<Value, Error> {
+    fn visit_return_stmt(&mut self, value: &Option<Expr>, token: &Token) -> Result<Value, Error> {
         let val = match value {
             Some(val) => self.evaluate(val)?,
             None => Value::Null,
         };
-        self.state.should_return = true;
-        Ok(val)
+        error(token, ErrorType::Return(Box::new(val)))
+    }
+
+    fn visit_try_stmt(
+        &mut self,
+        body: &[Stmt],
+        catches: &[Catch],
+        finally: &Option<Vec<Stmt>>,
+        token: &Token,
+    ) -> Result<Value, Error> {
+        self.try_depth += 1;
+        let env = Environment::frame(&self.env, locals(body));
+        let mut result = self.execute_block(body, Rc::new(RefCell::new(env)));
+        self.try_depth -= 1;
+
+        let exception = match &result {
+            Err(e) if e.error_type.is_catchable() && !self.resources.is_stopped() => {
+                Some(self.exception_value(e)?)
+            }
+            _ => None,
+        };
+        if let Some(exception) = exception {
+            // the first matching clause handles it, without one the error keeps unwinding
+            for Catch { name, class, body } in catches {
+                if self.catches(class, &exception, token)? {
+                    let mut env = Environment::frame(&self.env, 1 + locals(body));
+                    env.bind(name, &exception);
+                    result = self.execute_block(body, Rc::new(RefCell::new(env)));
+                    break;
+                }
+            }
+        }
+
+        // finally runs no matter how the blocks above were left, unless it leaves early itself,
+        // the original outcome (value, error, return, break...) carries on afterwards
+        if let Some(finally) = finally {
+            let env = Environment::frame(&self.env, locals(finally));
+            self.execute_block(finally, Rc::new(RefCell::new(env)))?;
+        }
+        result.map(|_| Value::Null)
+    }
+
+    fn visit_throw_stmt(&mut self, value: &Expr, token: &Token) -> Result<Value, Error> {
+        let value = self.evaluate(value)?;
+        error(token, ErrorType::Throw(Box::new(value)))
+    }
+
+    fn visit_match_stmt(
+        &mut self,
+        value: &Expr,
+        arms: &[Arm],
+        _token: &Token,
+    ) -> Result<Value, Error> {
+        let value = self.evaluate(value)?;
+        for Arm { pattern, body } in arms {
+            let mut bindings = Vec::new();
+            if self.matches_pattern(pattern, &value, &mut bindings)? {
+                let mut env = Environment::frame(&self.env, bindings.len());
+                for (name, value) in bindings {
+                    env.bind(name, &value);
+                }
+                let prev_env = std::mem::replace(&mut self.env, Rc::new(RefCell::new(env)));
+                let result = self.execute(body);
+                self.env = prev_env;
+                result?;
+                break;
+            }
+        }
+        Ok(Value::Null)
+    }
+
+    fn visit_error_stmt(&mut self, token: &Token, _end: &Token) -> Result<Value, Error> {
+        error(token, ErrorType::UnparsableExpression)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::Interpreter;
+    use crate::error::{Error, ErrorType};
+    use crate::function::Function;
+    use crate::limits::ResourceLimits;
+    use crate::runtime_value::Value;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn host_globals_are_visible_to_scripts() {
+        let mut interpreter = Interpreter::new();
+        interpreter.define_global("limit", Value::Number(10.0));
+        let value = interpreter.run("limit = limit + 1; limit;").unwrap();
+        assert_eq!(value.as_number(), Some(&11.0));
+    }
+
+    #[test]
+    fn host_constants_cant_be_assigned() {
+        let mut interpreter = Interpreter::new();
+        interpreter.define_host_constant("version", Value::Number(1.0));
+        let errors = interpreter.run("version = 2;").unwrap_err();
+        assert!(matches!(
+            errors[0].error_type,
+            ErrorType::AssignmentToHostConstant
+        ));
+
+        let errors = interpreter.run("fn f() { version = 3; } f();").unwrap_err();
+        assert!(matches!(
+            errors[0].error_type,
+            ErrorType::AssignmentToHostConstant
+        ));
+        let value = interpreter.run("version;").unwrap();
+        assert_eq!(value.as_number(), Some(&1.0));
+
+        // top-level declarations would replace it, locals only shadow it
+        for code in ["var version = 3;", "fn version() {}", "class version {}"] {
+            let errors = interpreter.run(code).unwrap_err();
+            assert!(
+                matches!(errors[0].error_type, ErrorType::AssignmentToHostConstant),
+                "{}",
+                code
+            );
+        }
+        // declarations have no token of their own, the error points at the whole statement
+        let errors = interpreter.run("print 1;\n  var version = 3;").unwrap_err();
+        assert_eq!(errors[0].token.line, 2);
+        assert_eq!((errors[0].span.start, errors[0].span.end), (3, 18));
+        assert_true_in(
+            &mut interpreter,
+            "fn f(version) { var v = version; { var version = 4; } return v; } f(2) == 2 and version == 1;",
+        );
+    }
+
+    #[test]
+    fn forks_share_prelude_but_not_state() {
+        let mut base = Interpreter::new();
+        base.run("var greeting = \"hello \"; fn greet(name) { return greeting + name; }")
+            .unwrap();
+
+        let mut fork = base.fork();
+        let value = fork.run("var name = \"fork\"; greet(name);").unwrap();
+        assert_eq!(value.as_string().map(String::as_str), Some("hello fork"));
+        fork.run("greeting = \"bye \";").unwrap();
+        assert_eq!(
+            fork.run("greeting;")
+                .unwrap()
+                .as_string()
+                .map(String::as_str),
+            Some("bye ")
+        );
+
+        assert_eq!(
+            base.run("greeting;")
+                .unwrap()
+                .as_string()
+                .map(String::as_str),
+            Some("hello ")
+        );
+        let errors = base.fork().run("name;").unwrap_err();
+        assert!(matches!(errors[0].error_type, ErrorType::UndefinedVariable));
+    }
+
+    #[test]
+    fn slots_go_away_with_their_programs() {
+        let slots_of =
+            |interpreter: &Interpreter, name: &str| match interpreter.env.borrow().get(name) {
+                Some(Value::Function(Function::Standard { slots, .. })) => Rc::downgrade(&slots),
+                _ => panic!("{} isn't a function", name),
+            };
+        let mut base = Interpreter::new();
+        base.run("fn twice(x) { var y = x; return y * 2; }")
+            .unwrap();
+        base.run("{ var a = 1; { var b = a; print b; } }").unwrap();
+        assert!(base.slots.borrow().is_empty());
+        let twice = slots_of(&base, "twice");
+
+        let mut fork = base.fork();
+        let value = fork
+            .run("fn thrice(x) { var y = x; return y * 3; } twice(1) + thrice(1);")
+            .unwrap();
+        assert_eq!(value.as_number(), Some(&5.0));
+        let thrice = slots_of(&fork, "thrice");
+        assert!(thrice.upgrade().is_some());
+        fork.run("thrice = null;").unwrap();
+        assert!(thrice.upgrade().is_none());
+        assert!(twice.upgrade().is_some());
+
+        base.run("twice = null;").unwrap();
+        assert!(twice.upgrade().is_none());
+    }
+
+    #[test]
+    fn host_resolves_unknown_identifiers() {
+        let asked = Rc::new(RefCell::new(Vec::new()));
+        let mut interpreter = Interpreter::new();
+        let names = Rc::clone(&asked);
+        interpreter.set_unknown_identifier_resolver(Rc::new(move |name| {
+            names.borrow_mut().push(name.to_owned());
+            match name {
+                "answer" => Some(Value::Number(42.0)),
+                _ => None,
+            }
+        }));
+
+        let value = interpreter.run("answer + answer;").unwrap();
+        assert_eq!(value.as_number(), Some(&84.0));
+        let errors = interpreter.run("question;").unwrap_err();
+        assert!(matches!(errors[0].error_type, ErrorType::UndefinedVariable));
+        assert_eq!(*asked.borrow(), vec!["answer", "question"]);
+    }
+
+    #[test]
+    fn host_natives_and_calls() {
+        let mut interpreter = Interpreter::new();
+        let prefix = String::from("> ");
+        interpreter.define_native("prompt", 1, move |args| {
+            Ok(Value::String(format!("{}{}", prefix, args[0])))
+        });
+        interpreter.define_native("fail", 0, |_| Err(ErrorType::Host(String::from("failed"))));
+        interpreter
+            .run("fn greet(name) { return prompt(\"hi \" + name); }")
+            .unwrap();
+
+        let greeting = interpreter
+            .call_global("greet", &vec![Value::String(String::from("lox"))])
+            .unwrap();
+        assert_eq!(greeting.as_string().unwrap(), "> hi lox");
+
+        let errors = interpreter.run("fail();").unwrap_err();
+        assert_eq!(errors[0].to_string(), "1.6-6 : failed");
+        assert!(interpreter.call_global("missing", &vec![]).is_err());
+    }
+
+    fn run_with_calls(code: &str) -> (Value, f64) {
+        let mut interpreter = Interpreter::new();
+        interpreter
+            .run("var calls = 0; fn effect(value) { calls = calls + 1; return value; }")
+            .unwrap();
+        let value = interpreter.run(code).unwrap();
+        let calls = interpreter.run("calls;").unwrap();
+        (value, *calls.as_number().unwrap())
+    }
+
+    #[test]
+    fn logical_operators_short_circuit() {
+        let (value, calls) = run_with_calls("false and effect(true);");
+        assert_eq!((value.as_boolean(), calls), (Some(&false), 0.0));
+        let (value, calls) = run_with_calls("true or effect(false);");
+        assert_eq!((value.as_boolean(), calls), (Some(&true), 0.0));
+        let (value, calls) = run_with_calls("true and effect(false);");
+        assert_eq!((value.as_boolean(), calls), (Some(&false), 1.0));
+        let (value, calls) = run_with_calls("false or effect(true) or effect(false);");
+        assert_eq!((value.as_boolean(), calls), (Some(&true), 1.0));
+    }
+
+    #[test]
+    fn logical_operators_return_operands() {
+        let (value, _) = run_with_calls("null or \"default\";");
+        assert_eq!(value.as_string().map(String::as_str), Some("default"));
+        let (value, _) = run_with_calls("\"first\" and 2;");
+        assert_eq!(value.as_number(), Some(&2.0));
+        // the right side isn't even looked up
+        let (value, _) = run_with_calls("true or undefinedVariable;");
+        assert_eq!(value.as_boolean(), Some(&true));
+    }
+
+    /// Runs the code in a fresh interpreter, the tests of the other modules use it and the asserts below
+    pub(crate) fn evaluate(code: &str) -> Result<Value, Vec<Error>> {
+        Interpreter::new().run(code)
+    }
+
+    pub(crate) fn assert_true(code: &str) {
+        let value = evaluate(code).unwrap();
+        assert_eq!(value.as_boolean(), Some(&true), "{}", code);
+    }
+
+    pub(crate) fn assert_error(code: &str, expected: ErrorType) {
+        let errors = evaluate(code).unwrap_err();
+        assert_eq!(
+            std::mem::discriminant(&errors[0].error_type),
+            std::mem::discriminant(&expected),
+            "{}",
+            code
+        );
+    }
+
+    #[test]
+    fn number_comparisons() {
+        assert_true("1 < 2;");
+        assert_true("2 <= 2;");
+        assert_true("-1 > -2;");
+        assert_true("0.5 >= 0.25;");
+        assert_true("!(2 < 1);");
+    }
+
+    #[test]
+    fn string_comparisons_are_lexicographic() {
+        assert_true("\"apple\" < \"banana\";");
+        // longer isn't greater
+        assert_true("\"b\" > \"abc\";");
+        assert_true("\"abc\" < \"abd\";");
+        assert_true("\"ab\" < \"abc\";");
+        assert_true("\"same\" <= \"same\";");
+        assert_true("\"same\" >= \"same\";");
+        assert_true("\"Z\" < \"a\";");
+    }
+
+    #[test]
+    fn comparing_mixed_types_is_an_error() {
+        assert_error("1 < \"1\";", ErrorType::WrongType);
+        assert_error("\"a\" >= 1;", ErrorType::WrongType);
+        assert_error("true > false;", ErrorType::WrongType);
+        assert_error("null <= null;", ErrorType::WrongType);
+    }
+
+    #[test]
+    fn only_null_and_false_are_falsey() {
+        assert_true("!null;");
+        assert_true("!false;");
+        assert_true("!!0;");
+        assert_true("!!\"\";");
+        assert_true("!!clock;");
+        assert_eq!(
+            evaluate("var x; if (\"\") x = 1; else x = 2; x;")
+                .unwrap()
+                .as_number(),
+            Some(&1.0)
+        );
+    }
+
+    #[test]
+    fn equality_across_types() {
+        assert_true("1 == 1;");
+        assert_true("\"a\" == \"a\";");
+        assert_true("null == null;");
+        assert_true("1 != \"1\";");
+        assert_true("!(null == false);");
+        assert_true("!(0 == false);");
+        assert_true("!(\"\" == null);");
+        assert_true("class A {} var a = A(); var b = A(); a == a and a != b;");
+    }
+
+    pub(crate) fn format_code(code: &str) -> String {
+        evaluate(code).unwrap().as_string().unwrap().clone()
+    }
+
+    #[test]
+    fn instances_overload_operators() {
+        let vector = "class Vector {\n\
+                 var x = 0; var y = 0;\n\
+                 add(other) { return Vector(x: this.x + other.x, y: this.y + other.y); }\n\
+                 sub(other) { return Vector(x: this.x - other.x, y: this.y - other.y); }\n\
+                 eq(other) { return other != null and this.x == other.x and this.y == other.y; }\n\
+                 lt(other) { return this.x * this.x + this.y * this.y < other.x * other.x + other.y * other.y; }\n\
+                 toString() { return \"(\" + str(this.x) + \", \" + str(this.y) + \")\"; }\n\
+             }\n\
+             var a = Vector(x: 1, y: 2); var b = Vector(x: 3, y: 4);\n";
+        for case in [
+            "a + b == Vector(x: 4, y: 6)",
+            "b - a == Vector(x: 2, y: 2) and a != b",
+            "a < b and b > a and a <= a and !(a >= b)",
+            "str(a + b) == \"(4, 6)\"",
+        ] {
+            assert_true(&format!("{}{};", vector, case));
+        }
+        let mut interpreter = Interpreter::new();
+        let printed = Rc::new(RefCell::new(Vec::new()));
+        let output = Rc::clone(&printed);
+        interpreter.set_output(move |text| output.borrow_mut().push(text.to_owned()));
+        interpreter.run(&format!("{}print a;", vector)).unwrap();
+        assert_eq!(*printed.borrow(), vec!["(1, 2)"]);
+
+        // without the methods instances keep comparing by identity and can't be added
+        assert_true("class P {} var p = P(); p == p and p != P();");
+        assert_error(
+            "class P {} P() + P();",
+            ErrorType::MissingOperatorMethod(String::new()),
+        );
+        assert_error(
+            "class P { lt(o) { return true; } } 1 < P();",
+            ErrorType::WrongType,
+        );
+    }
+
+    #[test]
+    fn indexes_lists_and_instances() {
+        assert_true("var l = list(); l.push(1); l.push(2); l[1] = l[0] + 10; l[1] == 11 and l.get(1) == 11;");
+        assert_true(
+            "var l = list(); l.push(list()); l[0].push(3); (l[0][0] = 4) == 4 and l[0][0] == 4;",
+        );
+        assert_error(
+            "var l = list(); l.push(1); l[1];",
+            ErrorType::IndexOutOfRange,
+        );
+        assert_error(
+            "var l = list(); l.push(1); l[\"a\"] = 2;",
+            ErrorType::WrongType,
+        );
+        assert_error("1[0];", ErrorType::ValueNotIndexable);
+
+        let grid = "class Grid {\n\
+                 var cells = list();\n\
+                 getIndex(i) { if (i >= this.cells.len()) return null; return this.cells[i]; }\n\
+                 setIndex(i, v) { while (this.cells.len() <= i) this.cells.push(null); this.cells[i] = v; }\n\
+             }\n\
+             var g = Grid();\n";
+        assert_true(&format!(
+            "{}g[2] = \"c\"; g[2] == \"c\" and g[5] == null and g.cells.len() == 3;",
+            grid
+        ));
+        assert_error(
+            "class P {} P()[0];",
+            ErrorType::MissingOperatorMethod(String::new()),
+        );
+        assert_error(
+            "class P { getIndex(i) { return i; } } P()[0] = 1;",
+            ErrorType::MissingOperatorMethod(String::new()),
+        );
+    }
+
+    #[test]
+    fn thrown_values_are_caught() {
+        assert_true("var e; try { throw 42; } catch (err) { e = err; } e == 42;");
+        assert_true(
+            "var e; try { 1 / null; } catch (err) { e = err; } type(e.message) == \"string\";",
+        );
+        assert_true("fn f() { throw \"boom\"; } var e; try { f(); } catch (err) { e = err; } e == \"boom\";");
+        assert_true("var e = 1; try { throw 2; } catch (e) { } e == 1;");
+        assert_error("throw 1;", ErrorType::Throw(Box::new(Value::Null)));
+    }
+
+    #[test]
+    fn finally_always_runs() {
+        assert_true("var n = 0; try { n = 1; } finally { n = n + 1; } n == 2;");
+        assert_true("var n = 0; try { try { throw 1; } finally { n = 1; } } catch (e) { n = n + e; } n == 2;");
+        assert_true(
+            "var n = 0; fn f() { try { return 1; } finally { n = 5; } } f() == 1 and n == 5;",
+        );
+        assert_true("var n = 0; while (true) { try { break; } finally { n = n + 1; } } n == 1;");
+        assert_true("fn f() { try { return 1; } finally { return 2; } } f() == 2;");
+        assert_true(
+            "var e; try { try { throw 1; } finally { throw 2; } } catch (err) { e = err; } e == 2;",
+        );
+        assert_error("try { } ", ErrorType::ExpectedCatchOrFinally);
+    }
+
+    #[test]
+    fn signals_unwind_blocks() {
+        assert_true("fn f() { { { return 1; } } return 2; } f() == 1;");
+        assert_true("var n = 0; for (var i = 0; i < 5; i = i + 1) { if (i == 2) continue; n = n + 1; } n == 4;");
+        assert_true("var i = 0; while (true) { { i = i + 1; if (i == 3) break; } } i == 3;");
+    }
+
+    #[test]
+    fn do_while_runs_at_least_once() {
+        assert_true("var n = 0; do { n = n + 1; } while (false); n == 1;");
+        assert_true("var n = 0; do n = n + 1; while (n < 5); n == 5;");
+        assert_true("var n = 0; do { n = n + 1; if (n == 3) break; } while (true); n == 3;");
+        assert_true(
+            "var n = 0; var odd = 0; do { n = n + 1; if (n == 2) continue; odd = odd + 1; } while (n < 3); odd == 2;",
+        );
+        assert_error("do { } while (true)", ErrorType::ExpectedSemicolon);
+        assert_error("do { } (true);", ErrorType::ExpectedWhile);
+    }
+
+    #[test]
+    fn loop_state_is_restored() {
+        assert_true(
+            "var n = 0; while (n < 3) { while (false) {} n = n + 1; if (n == 2) break; } n == 2;",
+        );
+        assert_error(
+            "while (true) { fn f() { break; } }",
+            ErrorType::NotAllowedOutsideLoop,
+        );
+    }
+
+    #[test]
+    fn errors_chain_their_causes() {
+        assert_true(
+            "var e; try { throw Error(\"parse failed\", cause: Error(\"bad token\")); } catch (err) { e = err; } e.message == \"parse failed\" and e.cause.message == \"bad token\";",
+        );
+        assert_true("Error(\"no cause\").cause == null;");
+        assert_error("fn f() {} f(x: 1);", ErrorType::NamedArgumentsOutsideClass);
+        assert_error(
+            "Error(cause: 1, \"late\");",
+            ErrorType::PositionalAfterNamedArgument,
+        );
+
+        let errors = evaluate(
+            "try { throw \"io\"; } catch (e) { throw Error(\"load failed\", cause: Error(\"read failed\", cause: e)); }",
+        )
+        .unwrap_err();
+        assert_eq!(
+            errors[0].error_type.to_string(),
+            "Uncaught exception: Error: load failed\n  caused by: Error: read failed\n  caused by: io"
+        );
+        let causes: Vec<String> = errors[0].causes().iter().map(|c| c.to_string()).collect();
+        assert_eq!(causes, vec!["Error instance", "io"]);
+    }
+
+    #[test]
+    fn catch_clauses_filter_by_class() {
+        let classes =
+            "class IOError : Error {} class ParseError : Error {} class EOFError : IOError {} ";
+        assert_true(&format!(
+            "{}var c; try {{ throw EOFError(\"eof\"); }} catch (e: ParseError) {{ c = 1; }} catch (e: IOError) {{ c = e.message; }} c == \"eof\";",
+            classes
+        ));
+        assert_true(&format!(
+            "{}var c; try {{ try {{ throw ParseError(\"p\"); }} catch (e: IOError) {{ c = 1; }} }} catch (e) {{ c = e; }} instanceof(c, ParseError);",
+            classes
+        ));
+        assert_true("var c; try { 1 / null; } catch (e: Error) { c = true; } c;");
+        assert_true(
+            "var n = 0; try { try { throw 1; } catch (e: Error) { } finally { n = 1; } } catch (e) { n = n + e; } n == 2;",
+        );
+        assert_error(
+            "try { } catch (e: Missing) { }",
+            ErrorType::UndefinedCatchClass,
+        );
+        assert_error(
+            "var x = 1; try { throw 1; } catch (e: x) { }",
+            ErrorType::CatchFilterNotClass,
+        );
+    }
+
+    /// Runs the code with limits, so a random infinite loop or recursion doesn't hang the test
+    fn run_untrusted(code: &str) {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_limits(ResourceLimits {
+            max_call_depth: Some(20),
+            max_statements: Some(1000),
+            ..ResourceLimits::default()
+        });
+        let _ = interpreter.run(code);
+        // tools look at broken code with the recovering parser
+        let _ = crate::fixes::diagnose(code);
+        let _ = crate::formatter::format_partial(code);
+    }
+
+    const FRAGMENTS: &[&str] = &[
+        "(", ")", "{", "}", "|", "=>", ",", ".", ";", ":", "-", "+", "*", "/", "%", "!", "=", "==",
+        "<", ">=", "and", "or", "var", "fn", "class", "if", "else", "while", "for", "do", "return",
+        "break", "continue", "try", "catch", "finally", "throw", "match", "in", "[", "]", "...",
+        "print", "this", "super", "null", "true", "1", "0x", "1.5e", "\"a", "\"b\"", "x", "f",
+        "/*", "//", "\n", "format(",
+    ];
+
+    proptest::proptest! {
+        #[test]
+        fn random_bytes_never_panic(bytes in proptest::collection::vec(proptest::num::u8::ANY, 0..200)) {
+            run_untrusted(&String::from_utf8_lossy(&bytes));
+        }
+
+        #[test]
+        fn random_tokens_never_panic(
+            fragments in proptest::collection::vec(proptest::sample::select(FRAGMENTS), 0..60)
+        ) {
+            run_untrusted(&fragments.join(" "));
+        }
+
+        #[test]
+        fn random_format_specs_never_panic(spec in "[<>]?[0-9]{0,14}(\\.[0-9]{0,14})?[fex]?") {
+            run_untrusted(&format!("format(1.5, \"{}\"); format(\"lox\", \"{}\");", spec, spec));
+        }
+    }
+
+    pub(crate) fn assert_true_in(interpreter: &mut Interpreter, code: &str) {
+        let value = interpreter.run(code).unwrap();
+        assert_eq!(value.as_boolean(), Some(&true), "{}", code);
     }
 }
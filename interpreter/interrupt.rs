@@ -0,0 +1,45 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Stops the interpreter it was taken from at the next statement, it can be used from any thread.
+/// The run fails with `ErrorType::Interrupted`, scripts can catch it to clean up before they're stopped for good.
+#[derive(Debug, Clone, Default)]
+pub struct InterruptHandle(Arc<AtomicBool>);
+
+impl InterruptHandle {
+    pub fn interrupt(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Clears the request, so that it stops a single run
+    pub(crate) fn take(&self) -> bool {
+        self.0.swap(false, Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::ErrorType;
+    use crate::interpreter::tests::assert_true_in;
+    use crate::interpreter::Interpreter;
+
+    #[test]
+    fn interrupts_from_other_threads() {
+        let mut interpreter = Interpreter::new();
+        for code in &[
+            "while (true) {}",
+            "while (true) { try { while (true) {} } catch (e) {} }",
+        ] {
+            let handle = interpreter.interrupt_handle();
+            let interrupter = std::thread::spawn(move || {
+                std::thread::sleep(std::time::Duration::from_millis(20));
+                handle.interrupt();
+            });
+            let errors = interpreter.run(code).unwrap_err();
+            assert!(matches!(errors[0].error_type, ErrorType::Interrupted));
+            interrupter.join().unwrap();
+        }
+        // the interruption stops a single run
+        assert_true_in(&mut interpreter, "true;");
+    }
+}
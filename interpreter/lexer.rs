@@ -1,51 +1,109 @@
-use super::token::{Literal, Token, TokenType, KEYWORDS};
+use super::token::{Literal, Token, TokenType, JLOX_KEYWORDS, KEYWORDS};
+use crate::compat::Dialect;
 use crate::error::{Error, ErrorType};
-use log::{ debug };
+use log::debug;
+
+/// Identifiers start with a letter (in any script) or an underscore
+fn is_identifier_start(c: char) -> bool {
+    c.is_alphabetic() || c == '_'
+}
+
+/// After the first character identifiers can also contain digits
+fn is_identifier_part(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Comment the lexer skipped over, kept so tools like the formatter can put it back
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comment {
+    /// Line and column the comment starts at
+    pub line: usize,
+    pub start: usize,
+    /// Whether code comes before the comment on that line
+    pub trailing: bool,
+    /// The comment with its `//` or `/* */`
+    pub text: String,
+}
 
 #[derive(Debug)]
 pub struct Lexer {
     source_code: Vec<char>,
     pub tokens: Vec<Token>,
+    pub comments: Vec<Comment>,
     start: usize,
     current: usize,
     line: usize,
     offset_start: usize,
     offset_current: usize,
     errors: Vec<Error>,
+    dialect: Dialect,
+    /// Tokens after which scanning stops with `TooManyTokens`
+    max_tokens: Option<usize>,
 }
 
 impl Lexer {
     pub fn new(source_code: &str) -> Self {
+        Lexer::with_dialect(source_code, Dialect::LoxRs)
+    }
+
+    pub fn with_dialect(source_code: &str, dialect: Dialect) -> Self {
         Lexer {
             source_code: source_code.chars().collect(),
             tokens: Vec::new(),
+            comments: Vec::new(),
             start: 0,
             current: 0,
             line: 1,
             offset_current: 0,
             offset_start: 0,
             errors: Vec::new(),
+            dialect,
+            max_tokens: None,
         }
     }
 
+    pub fn set_max_tokens(&mut self, max_tokens: usize) {
+        self.max_tokens = Some(max_tokens);
+    }
+
+    /// jlox has no `:`, a `<` right after the name of a class declaration introduces its superclass
+    fn is_jlox_superclass(&self) -> bool {
+        let mut previous = self.tokens.iter().rev();
+        matches!(
+            (previous.next(), previous.next()),
+            (
+                Some(Token {
+                    token_type: TokenType::Identifier(_),
+                    ..
+                }),
+                Some(Token {
+                    token_type: TokenType::Class,
+                    ..
+                })
+            )
+        )
+    }
+
+    /// Past the end of the source there's only '\0', like `peek` returns
     fn advance(&mut self) -> char {
         self.current += 1;
         self.offset_current += 1;
         self.source_code
             .get(self.current - 1)
-            .expect("Unexpected end of stream")
-            .clone()
+            .copied()
+            .unwrap_or('\0')
     }
 
     fn is_not_empty(&self) -> bool {
         self.source_code.get(self.current).is_some()
     }
 
-    fn peek(&self, offset: i16) -> char {
-        self.source_code
-            .get((self.current as i16 + offset) as usize)
-            .unwrap_or(&'\0')
-            .clone()
+    fn peek(&self, offset: isize) -> char {
+        self.current
+            .checked_add_signed(offset)
+            .and_then(|i| self.source_code.get(i))
+            .copied()
+            .unwrap_or('\0')
     }
 
     fn next_matches(&mut self, to_match: char) -> bool {
@@ -62,31 +120,66 @@ impl Lexer {
         self.offset_start = 0;
     }
 
-    // TODO: while skipping line and there is no new line at the end the program crashes
     fn skip_line(&mut self) {
-        while self.peek(0) != '\n' {
+        while self.is_not_empty() && self.peek(0) != '\n' {
             self.advance();
         }
     }
 
-    fn next_comment(&mut self) -> bool {
-        if self.peek(0) == '/' {
+    /// Skips everything up to the matching */, block comments can be nested
+    fn skip_block_comment(&mut self) -> Result<(), Error> {
+        let line = self.line;
+        let start = self.offset_start + 1;
+        let mut depth = 1;
+
+        while self.is_not_empty() {
+            match self.advance() {
+                '/' if self.next_matches('*') => depth += 1,
+                '*' if self.next_matches('/') => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(());
+                    }
+                }
+                '\n' => self.next_line(),
+                _ => (),
+            }
+        }
+
+        Err(Error::new(
+            &Token::new(TokenType::Invalid, line, start, start + 1),
+            ErrorType::CommentNotClosed,
+        ))
+    }
+
+    fn next_comment(&mut self) -> Result<bool, Error> {
+        let (line, start) = (self.line, self.offset_start + 1);
+        if self.next_matches('/') {
             self.skip_line();
-            return true;
+        } else if self.next_matches('*') {
+            self.skip_block_comment()?;
+        } else {
+            return Ok(false);
         }
-        false
+        self.comments.push(Comment {
+            line,
+            start,
+            trailing: self.tokens.last().is_some_and(|token| token.line == line),
+            text: self.get_slice(),
+        });
+        Ok(true)
     }
 
     fn raise_error(&mut self, error_type: ErrorType) -> Result<Token, Error> {
-        Err(Error {
-            token: Token {
+        Err(Error::new(
+            &Token {
                 token_type: TokenType::Invalid,
                 start: self.offset_start + 1,
                 end: self.offset_current,
                 line: self.line,
             },
             error_type,
-        })
+        ))
     }
 
     fn create_token(&self, token_type: TokenType) -> Result<Token, Error> {
@@ -101,8 +194,8 @@ impl Lexer {
     fn get_slice(&self) -> String {
         self.source_code
             .get(self.start..self.current)
-            .expect("Unexpected end of stream")
-            .into_iter()
+            .unwrap_or_default()
+            .iter()
             .collect::<String>()
     }
 
@@ -110,29 +203,68 @@ impl Lexer {
         while self.is_not_empty() {
             let c = self.advance();
             if c == '"' {
-                let slice = self.get_slice();
-                let value = slice.chars().skip(1).take(&slice.len() - 2).collect();
+                // skip the quotes, source is indexed by characters so multi-byte ones are kept whole
+                let value = self.source_code[self.start + 1..self.current - 1]
+                    .iter()
+                    .collect();
 
                 return self.create_token(TokenType::Literal(Literal::String(value)));
+            } else if c == '\n' {
+                self.next_line();
             }
         }
         self.raise_error(ErrorType::StringNotClosed)
     }
 
-    fn omit_number(&mut self) {
-        while self.peek(0).is_digit(10) {
+    /// Skips digits in the given radix, underscores are allowed between them (1_000_000)
+    fn omit_number(&mut self, radix: u32) {
+        while self.peek(0).is_digit(radix) || (self.peek(0) == '_' && self.peek(1).is_digit(radix))
+        {
             self.advance();
         }
     }
 
+    fn get_hex_number(&mut self) -> Result<Token, Error> {
+        // skip the x, the 0 has already been consumed
+        self.advance();
+        self.omit_number(16);
+
+        let value = self
+            .get_slice()
+            .chars()
+            .skip(2)
+            .filter_map(|c| c.to_digit(16))
+            .fold(0.0, |value, digit| value * 16.0 + digit as f64);
+        self.create_token(TokenType::Literal(Literal::Number(value)))
+    }
+
     fn get_number(&mut self) -> Result<Token, Error> {
-        self.omit_number();
+        if self.peek(-1) == '0'
+            && matches!(self.peek(0), 'x' | 'X')
+            && self.peek(1).is_ascii_hexdigit()
+        {
+            return self.get_hex_number();
+        }
+
+        self.omit_number(10);
+
+        if self.peek(0) == '.' && self.peek(1).is_ascii_digit() {
+            self.advance();
+            self.omit_number(10);
+        }
 
-        if self.peek(0) == '.' && self.peek(1).is_digit(10) {
-            self.omit_number();
+        let has_exponent = matches!(self.peek(0), 'e' | 'E')
+            && (self.peek(1).is_ascii_digit()
+                || (matches!(self.peek(1), '+' | '-') && self.peek(2).is_ascii_digit()));
+        if has_exponent {
+            self.advance();
+            if matches!(self.peek(0), '+' | '-') {
+                self.advance();
+            }
+            self.omit_number(10);
         }
 
-        let num = self.get_slice().parse::<f64>();
+        let num = self.get_slice().replace('_', "").parse::<f64>();
 
         match num {
             Ok(value) => self.create_token(TokenType::Literal(Literal::Number(value))),
@@ -141,22 +273,20 @@ impl Lexer {
     }
 
     fn get_identifier(&mut self) -> Result<Token, Error> {
-        let mut identifier_literal = String::new();
-        loop {
-            // check if identifier is one of the keywords
-            identifier_literal = self.get_slice();
-            let keyword = KEYWORDS.get::<str>(&identifier_literal);
-            if let Some(token_type) = keyword {
-                return self.create_token(token_type.clone());
-            } else if !self.peek(0).is_alphabetic() {
-                break;
-            } else {
-                self.advance();
-            }
+        while is_identifier_part(self.peek(0)) {
+            self.advance();
         }
 
-        // if not return it as an identifier
-        self.create_token(TokenType::Identifier(identifier_literal))
+        // check if identifier is one of the keywords
+        let identifier_literal = self.get_slice();
+        let keywords = match self.dialect {
+            Dialect::LoxRs => &*KEYWORDS,
+            Dialect::Jlox => &*JLOX_KEYWORDS,
+        };
+        match keywords.get::<str>(&identifier_literal) {
+            Some(token_type) => self.create_token(token_type.clone()),
+            None => self.create_token(TokenType::Identifier(identifier_literal)),
+        }
     }
 
     fn get_literal(&mut self) -> Result<Token, Error> {
@@ -164,9 +294,9 @@ impl Lexer {
 
         if c == '"' {
             self.get_string()
-        } else if c.is_digit(10) {
+        } else if c.is_ascii_digit() {
             self.get_number()
-        } else if c.is_alphanumeric() {
+        } else if is_identifier_start(c) {
             self.get_identifier()
         } else {
             self.raise_error(ErrorType::UnexpectedCharacter)
@@ -188,21 +318,35 @@ impl Lexer {
                     self.next_line();
                     continue;
                 }
-                '/' => {
-                    if self.next_comment() {
+                '/' => match self.next_comment() {
+                    Ok(true) => continue,
+                    Ok(false) => (),
+                    Err(e) => {
+                        self.errors.push(e);
                         continue;
                     }
-                }
+                },
                 _ => (),
             }
 
+            let jlox = self.dialect == Dialect::Jlox;
             let token_type: Option<TokenType> = match c {
+                // jlox doesn't have these, so they are unexpected characters there
+                ':' | '%' | '|' | '[' | ']' if jlox => None,
+                '<' if jlox && self.is_jlox_superclass() => Some(TokenType::Inherit),
                 ':' => Some(TokenType::Inherit),
                 '(' => Some(TokenType::OpenParenthesis),
                 ')' => Some(TokenType::CloseParenthesis),
                 '{' => Some(TokenType::OpenBrace),
                 '}' => Some(TokenType::CloseBrace),
+                '[' => Some(TokenType::OpenBracket),
+                ']' => Some(TokenType::CloseBracket),
                 ',' => Some(TokenType::Coma),
+                '.' if !jlox && self.peek(0) == '.' && self.peek(1) == '.' => {
+                    self.advance();
+                    self.advance();
+                    Some(TokenType::Ellipsis)
+                }
                 '.' => Some(TokenType::Dot),
                 '-' => Some(TokenType::Minus),
                 '+' => Some(TokenType::Plus),
@@ -237,7 +381,7 @@ impl Lexer {
                 '=' => {
                     let token_type = if self.next_matches('=') {
                         TokenType::Compare
-                    } else if self.next_matches('>') {
+                    } else if !jlox && self.next_matches('>') {
                         TokenType::Arrow
                     } else {
                         TokenType::Assign
@@ -254,6 +398,12 @@ impl Lexer {
             };
 
             match token {
+                Ok(t) if matches!(self.max_tokens, Some(max) if self.tokens.len() >= max) => {
+                    let max = self.tokens.len();
+                    self.errors
+                        .push(Error::new(&t, ErrorType::TooManyTokens(max)));
+                    break;
+                }
                 Ok(t) => {
                     self.tokens.push(t);
                 }
@@ -263,21 +413,22 @@ impl Lexer {
             }
         }
 
-        self.tokens.push(self.create_token(TokenType::EOF).unwrap());
+        self.tokens.extend(self.create_token(TokenType::EOF));
 
         if self.errors.len() > 0 {
-            debug!("LEXER: {:#?}",self.errors);
+            debug!("LEXER: {:#?}", self.errors);
             return Err(self.errors.clone());
         }
         Ok(self.tokens.clone())
     }
 }
 
+#[cfg(test)]
 mod tests {
-    use crate::lexer::Lexer;
+    use crate::interpreter::tests::evaluate;
+    use crate::lexer::{Comment, Lexer};
     use crate::token::{Literal, Token, TokenType};
-    #[cfg(test)]
-    use pretty_assertions::{assert_eq, assert_ne};
+    use pretty_assertions::assert_eq;
 
     #[test]
     fn parse_literals() {
@@ -374,4 +525,139 @@ mod tests {
             ]
         )
     }
+
+    fn token_types(code: &str) -> Vec<TokenType> {
+        Lexer::new(code)
+            .scan_tokens()
+            .unwrap()
+            .into_iter()
+            .map(|t| t.token_type)
+            .collect()
+    }
+
+    #[test]
+    fn line_comment_at_the_end_of_file() {
+        assert_eq!(
+            token_types("1 // no new line after this"),
+            vec![TokenType::Literal(Literal::Number(1.0)), TokenType::EOF]
+        );
+    }
+
+    #[test]
+    fn nested_block_comments() {
+        let code = "1 /* outer /* inner */\n still comment */ 2\n3 /**/";
+        let tokens = Lexer::new(code).scan_tokens().unwrap();
+        let lines: Vec<usize> = tokens.iter().map(|t| t.line).collect();
+        assert_eq!(lines, vec![1, 2, 3, 3]);
+        assert_eq!(
+            tokens[1].token_type,
+            TokenType::Literal(Literal::Number(2.0))
+        );
+    }
+
+    #[test]
+    fn comments_are_kept() {
+        let mut lexer = Lexer::new("// first\nvar a; /* b\n c */\n");
+        lexer.scan_tokens().unwrap();
+        assert_eq!(
+            lexer.comments,
+            vec![
+                Comment {
+                    line: 1,
+                    start: 1,
+                    trailing: false,
+                    text: String::from("// first"),
+                },
+                Comment {
+                    line: 2,
+                    start: 8,
+                    trailing: true,
+                    text: String::from("/* b\n c */"),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn unclosed_block_comment() {
+        let errors = Lexer::new("1\n/* /* */").scan_tokens().unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].token.line, 2);
+    }
+
+    #[test]
+    fn identifiers_with_digits_underscores_and_unicode() {
+        assert_eq!(
+            token_types("_private my_var2 zażółć 変数 format iffy if"),
+            vec![
+                TokenType::Identifier(String::from("_private")),
+                TokenType::Identifier(String::from("my_var2")),
+                TokenType::Identifier(String::from("zażółć")),
+                TokenType::Identifier(String::from("変数")),
+                TokenType::Identifier(String::from("format")),
+                TokenType::Identifier(String::from("iffy")),
+                TokenType::If,
+                TokenType::EOF
+            ]
+        );
+    }
+
+    #[test]
+    fn strings_keep_multi_byte_characters() {
+        let tokens = Lexer::new("\"héllo 👋\" ż").scan_tokens().unwrap();
+        assert_eq!(
+            tokens[0].token_type,
+            TokenType::Literal(Literal::String(String::from("héllo 👋")))
+        );
+        // columns are counted in characters, not bytes
+        assert_eq!((tokens[0].start, tokens[0].end), (1, 9));
+        assert_eq!((tokens[1].start, tokens[1].end), (11, 11));
+    }
+
+    #[test]
+    fn multi_line_strings_count_lines() {
+        let tokens = Lexer::new("\"first\nsecond\" x").scan_tokens().unwrap();
+        assert_eq!(
+            tokens[0].token_type,
+            TokenType::Literal(Literal::String(String::from("first\nsecond")))
+        );
+        assert_eq!(tokens[1].line, 2);
+    }
+
+    #[test]
+    fn number_literals() {
+        let number = |value| TokenType::Literal(Literal::Number(value));
+        assert_eq!(
+            token_types("1.5 0xFF 0x1_0 1e9 2.5E-3 1e+2 1_000_000"),
+            vec![
+                number(1.5),
+                number(255.0),
+                number(16.0),
+                number(1e9),
+                number(2.5e-3),
+                number(100.0),
+                number(1_000_000.0),
+                TokenType::EOF
+            ]
+        );
+        // these suffixes don't belong to the number
+        assert_eq!(
+            token_types("1. 1_ 0x"),
+            vec![
+                number(1.0),
+                TokenType::Dot,
+                number(1.0),
+                TokenType::Identifier(String::from("_")),
+                number(0.0),
+                TokenType::Identifier(String::from("x")),
+                TokenType::EOF
+            ]
+        );
+    }
+
+    #[test]
+    fn lexes_sources_longer_than_a_short_index() {
+        let code = format!("{}0x10;", "var a = 1;\n".repeat(5000));
+        assert_eq!(evaluate(&code).unwrap().as_number(), Some(&16.0));
+    }
 }
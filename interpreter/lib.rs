@@ -4,70 +4,562 @@ extern crate derive_more;
 extern crate lazy_static;
 #[macro_use]
 extern crate enum_as_inner;
+mod ast;
+pub mod audit;
+mod check;
 mod class;
+mod compat;
+mod crash_report;
+mod debugger;
+mod diagnostics;
+mod duplicates;
 mod environment;
 pub mod error;
+mod explain;
 mod expr;
+mod features;
+#[cfg(feature = "ffi")]
+mod ffi;
+mod fixes;
+mod formatter;
 mod function;
+mod grading;
+mod grammar;
+mod hot_reload;
+mod inspect;
 mod interpreter;
+mod interrupt;
 mod lexer;
+mod limits;
+mod lint;
+mod lsp;
+mod metrics;
+mod natives;
 mod parser;
+mod pattern;
+#[cfg(feature = "plugins")]
+mod plugin;
+mod profile;
+mod prototypes;
+#[cfg(feature = "python")]
+mod python;
+mod repl;
 mod resolver;
 mod runtime_value;
+mod scopes;
+mod snapshot;
 mod statement;
+mod stats;
+mod stdlib;
+mod testing;
 mod token;
+mod types;
 mod utils;
+mod validate;
+mod walk;
+mod watch;
+pub use crate::ast::{dump_ast, print_ast, print_partial_ast};
+pub use crate::check::{check_project, FileCheck, ProjectCheck};
+pub use crate::compat::{jlox_string, run_jlox, Dialect, JloxFailure};
+pub use crate::crash_report::CrashReport;
+pub use crate::debugger::{
+    Breakpoint, Breakpoints, CliDebugger, Debugger, Frame, Granularity, StackFrame,
+};
+pub use crate::diagnostics::{render_diagnostics, DiagnosticOptions};
+pub use crate::duplicates::{find_duplicates, CloneSite, Duplicate, DuplicateKind};
 use crate::error::Error;
-use crate::interpreter::Interpreter;
-use crate::lexer::Lexer;
-use crate::parser::Parser;
-use crate::resolver::Resolver;
-use crate::utils::print_errors;
-use log::{debug, Level};
-use std::fs::read_to_string;
-use std::io::{self, BufRead};
-
-pub fn run_prompt() {
+pub use crate::explain::Explainer;
+pub use crate::features::{Features, LanguageVersion, Semantics};
+pub use crate::fixes::{apply_fixes, diagnose, fix_source, Diagnostic, Fix, TextEdit};
+pub use crate::formatter::{format_partial, format_source};
+pub use crate::grading::{grade, TestCase, TestResult};
+pub use crate::grammar::{grammar, to_ebnf, to_railroad_svg, Production, Rule};
+use crate::hot_reload::Change;
+pub use crate::inspect::{InspectedChild, ValueInspection};
+pub use crate::interpreter::{Interpreter, UnknownIdentifierResolver};
+pub use crate::interrupt::InterruptHandle;
+pub use crate::limits::{
+    Capabilities, ResourceLimits, DEFAULT_MAX_CALL_DEPTH, SANDBOX_MAX_STATEMENTS,
+};
+pub use crate::lint::{lint, Finding, LintConfig, LintRule, Severity};
+pub use crate::lsp::{run_language_server, LanguageServer};
+pub use crate::metrics::{
+    metrics, ClassMetrics, FunctionMetrics, MetricThresholds, Metrics, Violation,
+};
+pub use crate::natives::NativeResult;
+#[cfg(feature = "plugins")]
+pub use crate::plugin::{load_plugin, REGISTER_SYMBOL};
+pub use crate::profile::{FunctionProfile, Profile, ProfiledFunction};
+use crate::repl::Session;
+pub use crate::runtime_value::Value;
+pub use crate::scopes::{scope_report, Reference, Resolution, Scope, ScopeKind, ScopeReport};
+pub use crate::snapshot::SnapshotError;
+pub use crate::stats::Stats;
+pub use crate::stdlib::{Namespace, StdlibConfig};
+pub use crate::testing::TestReport;
+pub use crate::token::Span;
+use crate::utils::{print, print_errors, PrintType};
+pub use crate::validate::{validate, ValidationLimits};
+pub use crate::watch::WatchCallback;
+use std::fs::{metadata, read_dir, read_to_string, write};
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::thread::sleep;
+use std::time::Duration;
+
+/// Interactive session, besides code it understands `:save path` and `:open path`
+pub fn run_prompt(diagnostics: &DiagnosticOptions) {
+    let mut session = Session::new();
+    let stdin = io::stdin();
     loop {
-        println!(">");
+        print!("> ");
+        io::stdout().flush().ok();
         let mut code = String::new();
-        let stdin = io::stdin();
-        stdin.lock().read_line(&mut code).unwrap();
+        match stdin.lock().read_line(&mut code) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => (),
+        }
+
+        let code = code.trim();
+        let result = if let Some(path) = code.strip_prefix(":save ") {
+            if let Err(e) = write(path.trim(), session.save()) {
+                print(&format!("Can't save {}: {}", path, e), PrintType::Error);
+            }
+            Ok(())
+        } else if let Some(path) = code.strip_prefix(":open ") {
+            match read_source(path.trim()) {
+                Some(source_code) => session.run(&source_code).map(|_| ()),
+                None => Ok(()),
+            }
+        } else {
+            session.run(code).map(|_| ())
+        };
+        if let Err(errors) = result {
+            print_errors(&errors, diagnostics);
+        }
     }
 }
 
+/// Entry point of the web playground
+#[cfg(all(target_arch = "wasm32", not(target_os = "wasi")))]
 #[wasm_bindgen]
 pub fn execute(source_code: &str) {
+    execute_with_features(source_code, Features::all());
+}
+
+/// Playground entry point for the chapters of the book, programs can only use what the chapter has introduced
+#[cfg(all(target_arch = "wasm32", not(target_os = "wasi")))]
+#[wasm_bindgen]
+pub fn execute_chapter(source_code: &str, chapter: u8) {
+    match LanguageVersion::chapter(chapter) {
+        Some(version) => execute_with_features(source_code, version.features()),
+        None => print(&format!("There's no chapter {}", chapter), PrintType::Error),
+    }
+}
+
+#[cfg(all(target_arch = "wasm32", not(target_os = "wasi")))]
+fn execute_with_features(source_code: &str, features: Features) {
+    use log::{debug, Level};
     console_log::init_with_level(Level::Debug);
 
-    match run_code(source_code) {
+    // the browser has a small stack and no way to stop a script other than closing the tab
+    let mut interpreter = Interpreter::new();
+    interpreter.set_features(features);
+    interpreter.set_limits(ResourceLimits {
+        max_call_depth: Some(100),
+        max_statements: Some(10_000_000),
+        ..ResourceLimits::default()
+    });
+    match interpreter.run(source_code) {
         Ok(_) => (),
         Err(errors) => {
             debug!("{:#?}", errors);
-            print_errors(&errors)
+            print_errors(&errors, &interpreter.diagnostic_options())
         }
     }
 }
 
 pub fn run_code(source_code: &str) -> Result<(), Vec<Error>> {
-    let mut lexer = Lexer::new(source_code);
-    let tokens = lexer.scan_tokens()?;
-    let mut parser = Parser::new(&tokens);
-    let stmts = parser.parse_tokens()?;
-    let mut interpreter = Interpreter::new();
-    let mut resolver = Resolver::new(&mut interpreter);
-    resolver.resolve_stmts(&stmts)?;
-    if let Err(e) = interpreter.interpret(&stmts) {
-        return Err(vec![e]);
-    }
+    Interpreter::new().run(source_code)?;
     Ok(())
 }
 
-pub fn run_file(path: &str) {
-    let source_code = read_to_string(path).expect("This file doesn't exist");
-    execute(&source_code);
+/// Runs the file and prints errors to stderr, under WASI the file has to be inside a preopened directory
+pub fn run_file(interpreter: &mut Interpreter, path: &str) {
+    if let Some(source_code) = read_source(path) {
+        if let Err(errors) = interpreter.run(&source_code) {
+            print_errors(&errors, &interpreter.diagnostic_options());
+        }
+    }
+}
+
+/// Runs the file with profiling on and prints the profile to stderr once it's done, failed or not
+pub fn profile_file(interpreter: &mut Interpreter, path: &str) {
+    interpreter.set_profiling(true);
+    run_file(interpreter, path);
+    eprintln!("{}", interpreter.profile_report());
+}
+
+/// Runs the file, then the tests it registered with `test(name, fn)`, and prints how each of them went.
+/// Returns whether all of them passed.
+pub fn test_file(interpreter: &mut Interpreter, path: &str) -> bool {
+    let source_code = match read_source(path) {
+        Some(source_code) => source_code,
+        None => return false,
+    };
+    if let Err(errors) = interpreter.run(&source_code) {
+        print_errors(&errors, &interpreter.diagnostic_options());
+        return false;
+    }
+    let report = interpreter.run_tests();
+    for name in &report.passed {
+        print(&format!("test {} ... ok", name), PrintType::Success);
+    }
+    for (name, error) in &report.failed {
+        print(&format!("test {} ... FAILED", name), PrintType::Error);
+        print(&format!("  {}", error), PrintType::Error);
+    }
+    print(&report.summary(), PrintType::Success);
+    report.is_success()
+}
+
+/// Runs the file the way jlox does, returns jlox's exit code: 65 for compile errors, 70 for runtime ones
+pub fn run_file_jlox(interpreter: &mut Interpreter, path: &str) -> i32 {
+    let source_code = match read_source(path) {
+        Some(source_code) => source_code,
+        // jlox's code for a missing input file
+        None => return 66,
+    };
+    match run_jlox(interpreter, &source_code) {
+        Ok(()) => 0,
+        Err(failure) => {
+            match &failure {
+                JloxFailure::Compile(errors) => {
+                    errors.iter().for_each(|e| print(e, PrintType::Error))
+                }
+                JloxFailure::Runtime(error) => print(error, PrintType::Error),
+            }
+            failure.exit_code()
+        }
+    }
+}
+
+/// Like `run_file`, but a panic of the interpreter is caught and written down as a `CrashReport`
+pub fn run_file_reporting_crashes(
+    interpreter: &mut Interpreter,
+    path: &str,
+    report_path: &str,
+    options: &[String],
+) {
+    if let Some(source_code) = read_source(path) {
+        match std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            interpreter.run(&source_code)
+        })) {
+            Ok(Ok(_)) => (),
+            Ok(Err(errors)) => print_errors(&errors, &interpreter.diagnostic_options()),
+            Err(panic) => {
+                let report = CrashReport::new(&source_code, options, panic.as_ref());
+                match report.write(report_path) {
+                    Ok(()) => print(
+                        &format!(
+                            "Internal error, a crash report was written to {}",
+                            report_path
+                        ),
+                        PrintType::Error,
+                    ),
+                    Err(e) => print(
+                        &format!("Internal error, can't write the crash report: {}", e),
+                        PrintType::Error,
+                    ),
+                }
+            }
+        }
+    }
+}
+
+/// Runs the file printing every step of its evaluation, see `Explainer`
+pub fn explain_file(interpreter: &mut Interpreter, path: &str) {
+    if let Some(source_code) = read_source(path) {
+        interpreter.set_debugger(Box::new(Explainer::new(&source_code, |step| {
+            println!("{}", step)
+        })));
+        if let Err(errors) = interpreter.run(&source_code) {
+            print_errors(&errors, &interpreter.diagnostic_options());
+        }
+    }
+}
+
+/// Runs the file under `CliDebugger`, which pauses before the first statement.
+/// Post-mortem it only pauses at breakpoints and at the statement an uncaught error comes from.
+pub fn debug_file(interpreter: &mut Interpreter, path: &str, post_mortem: bool) {
+    if let Some(source_code) = read_source(path) {
+        let debugger = if post_mortem {
+            CliDebugger::post_mortem(&source_code)
+        } else {
+            CliDebugger::new(&source_code)
+        };
+        interpreter.set_debugger(Box::new(debugger));
+        if let Err(errors) = interpreter.run(&source_code) {
+            print_errors(&errors, &interpreter.diagnostic_options());
+        }
+    }
+}
+
+/// Prints the file formatted by `format_source`, or its errors if it doesn't parse
+pub fn format_file(path: &str, diagnostics: &DiagnosticOptions) {
+    if let Some(source_code) = read_source(path) {
+        match format_source(&source_code) {
+            Ok(formatted) => print!("{}", formatted),
+            Err(errors) => print_errors(&errors, diagnostics),
+        }
+    }
+}
+
+/// Prints the syntax tree of the file as s-expressions, or its errors if it doesn't parse
+pub fn print_file_ast(path: &str, diagnostics: &DiagnosticOptions) {
+    if let Some(source_code) = read_source(path) {
+        match print_ast(&source_code) {
+            Ok(ast) => print!("{}", ast),
+            Err(errors) => print_errors(&errors, diagnostics),
+        }
+    }
+}
+
+/// Prints the syntax tree of the file in the indented format of `dump_ast`, or its errors if it doesn't parse
+pub fn dump_file_ast(path: &str, diagnostics: &DiagnosticOptions) {
+    if let Some(source_code) = read_source(path) {
+        match dump_ast(&source_code) {
+            Ok(ast) => print!("{}", ast),
+            Err(errors) => print_errors(&errors, diagnostics),
+        }
+    }
+}
+
+/// Prints the scopes of the file and where its variables resolve to, as text or as JSON
+pub fn print_file_scopes(path: &str, json: bool, diagnostics: &DiagnosticOptions) {
+    if let Some(source_code) = read_source(path) {
+        match scope_report(&source_code) {
+            Ok(report) if json => match serde_json::to_string_pretty(&report) {
+                Ok(json) => println!("{}", json),
+                Err(e) => print(&e.to_string(), PrintType::Error),
+            },
+            Ok(report) => print!("{}", report),
+            Err(errors) => print_errors(&errors, diagnostics),
+        }
+    }
+}
+
+/// Prints what `diagnose` finds in the file as JSON, fixes included
+pub fn print_file_diagnostics(path: &str) {
+    if let Some(source_code) = read_source(path) {
+        match serde_json::to_string_pretty(&diagnose(&source_code)) {
+            Ok(json) => println!("{}", json),
+            Err(e) => print(&e.to_string(), PrintType::Error),
+        }
+    }
+}
+
+/// Applies every quick fix to the file in place and prints the problems left, false if there are any
+pub fn fix_file(path: &str) -> bool {
+    let source_code = match read_source(path) {
+        Some(source_code) => source_code,
+        None => return false,
+    };
+    let (fixed, applied) = fix_source(&source_code);
+    if applied > 0 {
+        if let Err(e) = write(path, &fixed) {
+            print(&format!("Couldn't write {}: {}", path, e), PrintType::Error);
+            return false;
+        }
+    }
+    println!("Applied {} fixes to {}", applied, path);
+    let left = diagnose(&fixed);
+    for diagnostic in &left {
+        let Span { line, start, end } = diagnostic.span;
+        let kind = if diagnostic.warning { "warning: " } else { "" };
+        let message = format!(
+            "{}.{}-{} : {}{}",
+            line, start, end, kind, diagnostic.message
+        );
+        print(&message, PrintType::Error);
+    }
+    left.is_empty()
+}
+
+/// Prints the metrics of the file and every one over the thresholds, false if there are any
+pub fn metrics_file(
+    path: &str,
+    thresholds: &MetricThresholds,
+    diagnostics: &DiagnosticOptions,
+) -> bool {
+    let source_code = match read_source(path) {
+        Some(source_code) => source_code,
+        None => return false,
+    };
+    match metrics(&source_code) {
+        Ok(metrics) => {
+            print!("{}", metrics);
+            let violations = metrics.violations(thresholds);
+            for violation in &violations {
+                print(&violation.to_string(), PrintType::Error);
+            }
+            violations.is_empty()
+        }
+        Err(errors) => {
+            print_errors(&errors, diagnostics);
+            false
+        }
+    }
+}
+
+/// Prints what `lint` found in the file, false if it found errors or the file can't be checked
+pub fn lint_file(path: &str, config: &LintConfig, diagnostics: &DiagnosticOptions) -> bool {
+    let source_code = match read_source(path) {
+        Some(source_code) => source_code,
+        None => return false,
+    };
+    match lint(&source_code, config) {
+        Ok(findings) => {
+            for finding in &findings {
+                println!("{}", finding);
+            }
+            findings.iter().all(|f| f.severity != Severity::Error)
+        }
+        Err(errors) => {
+            print_errors(&errors, diagnostics);
+            false
+        }
+    }
+}
+
+/// Prints the code duplicated across the files, directories are searched for `.lox` files.
+/// False if there is any, or if a file can't be read or parsed.
+pub fn print_duplicates(
+    paths: &[String],
+    min_size: usize,
+    diagnostics: &DiagnosticOptions,
+) -> bool {
+    let mut files = Vec::new();
+    paths
+        .iter()
+        .for_each(|path| lox_files(Path::new(path), &mut files));
+    let mut sources = Vec::new();
+    for file in files {
+        match read_source(&file) {
+            Some(source_code) => sources.push((file, source_code)),
+            None => return false,
+        }
+    }
+    match find_duplicates(&sources, min_size) {
+        Ok(duplicates) => {
+            duplicates
+                .iter()
+                .for_each(|duplicate| println!("{}", duplicate));
+            duplicates.is_empty()
+        }
+        Err((file, errors)) => {
+            print(&format!("Can't parse {}:", file), PrintType::Error);
+            print_errors(&errors, diagnostics);
+            false
+        }
+    }
+}
+
+/// Prints the diagnostics of every file under the paths and a summary table, false if any file has errors
+pub fn print_project_check(paths: &[String]) -> bool {
+    let mut files = Vec::new();
+    paths
+        .iter()
+        .for_each(|path| lox_files(Path::new(path), &mut files));
+    let mut sources = Vec::new();
+    let mut readable = true;
+    for file in files {
+        match read_source(&file) {
+            Some(source_code) => sources.push((file, source_code)),
+            None => readable = false,
+        }
+    }
+    let check = check_project(&sources);
+    println!("{}", check);
+    readable && check.errors() == 0
+}
+
+/// The path if it's a file, the `.lox` files under it if it's a directory, sorted so the output is stable
+fn lox_files(path: &Path, files: &mut Vec<String>) {
+    match read_dir(path) {
+        Ok(entries) => {
+            let mut paths: Vec<_> = entries
+                .filter_map(|entry| Some(entry.ok()?.path()))
+                .collect();
+            paths.sort();
+            for path in paths {
+                if path.is_dir() || path.extension().is_some_and(|ext| ext == "lox") {
+                    lox_files(&path, files);
+                }
+            }
+        }
+        Err(_) => files.push(path.to_string_lossy().into_owned()),
+    }
+}
+
+fn read_source(path: &str) -> Option<String> {
+    match read_to_string(path) {
+        Ok(source_code) => Some(source_code),
+        Err(e) => {
+            print(&format!("Can't read {}: {}", path, e), PrintType::Error);
+            None
+        }
+    }
+}
+
+/// Runs the file again on every save. When only top-level functions changed, they are swapped
+/// in the live interpreter instead, so top-level variables keep their values.
+/// Programs can define `onReload()`, which is called after every swap.
+pub fn watch_file(path: &str, diagnostics: &DiagnosticOptions) {
+    let modified_at = || metadata(path).and_then(|m| m.modified()).ok();
+    let mut interpreter = Interpreter::new();
+    let mut source_code = String::new();
+    let mut last_modified = None;
+
+    loop {
+        let modified = modified_at();
+        if modified == last_modified {
+            sleep(Duration::from_millis(200));
+            continue;
+        }
+        last_modified = modified;
+
+        let new_source_code = match read_source(path) {
+            Some(source_code) => source_code,
+            None => continue,
+        };
+        let result = match hot_reload::compare(&source_code, &new_source_code) {
+            Change::Functions(names) if !source_code.is_empty() => {
+                hot_reload::reload(&mut interpreter, &mut source_code, &new_source_code, &names)
+                    .map(|()| {
+                        if !names.is_empty() {
+                            print(
+                                &format!("Reloaded {}", names.join(", ")),
+                                PrintType::Success,
+                            );
+                        }
+                    })
+            }
+            _ => {
+                print(&format!("Running {}", path), PrintType::Success);
+                interpreter = Interpreter::new();
+                // the fresh interpreter holds the new program even if it fails halfway
+                source_code = new_source_code.clone();
+                interpreter.run(&new_source_code).map(|_| ())
+            }
+        };
+        if let Err(errors) = result {
+            print_errors(&errors, diagnostics);
+        }
+    }
 }
 
+#[cfg(all(target_arch = "wasm32", not(target_os = "wasi")))]
 use wasm_bindgen::prelude::*;
 
 // When the `wee_alloc` feature is enabled, use `wee_alloc` as the global
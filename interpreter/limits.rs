@@ -0,0 +1,448 @@
+use crate::error::ErrorType;
+use crate::runtime_value::Value;
+use std::time::{Duration, Instant};
+
+/// Calls nested by default, as many as the 8 MiB stack of a main thread fits in a debug build
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 100;
+
+/// Statements a sandboxed script can execute per run, a few seconds' worth in a debug build
+pub const SANDBOX_MAX_STATEMENTS: u64 = 10_000_000;
+
+/// Statements a script can still run once it's been told to stop, so its catch and finally blocks can clean up
+pub const CLEANUP_STATEMENTS: u64 = 1000;
+
+/// Caps on the memory and time a script can take, for running untrusted code.
+/// Only the call depth is limited by default, so runaway recursion fails instead of crashing the host.
+#[derive(Debug, Clone)]
+pub struct ResourceLimits {
+    /// Longest string a script can build, in bytes
+    pub max_string_bytes: Option<usize>,
+    /// Bytes of all strings built by scripts over the interpreter's lifetime, dropped ones included
+    pub max_total_string_bytes: Option<usize>,
+    /// Most items a single list can hold
+    pub max_collection_len: Option<usize>,
+    /// Calls a script can nest, every call takes a good deal of the host's stack
+    pub max_call_depth: Option<usize>,
+    /// Statements a single `run` can execute, unlike the timeout it works in the browser too
+    pub max_statements: Option<u64>,
+    /// Time a single `run` can take. Browser wasm has no clock, so it can't be used there.
+    pub timeout: Option<Duration>,
+}
+
+impl Default for ResourceLimits {
+    fn default() -> Self {
+        ResourceLimits {
+            max_string_bytes: None,
+            max_total_string_bytes: None,
+            max_collection_len: None,
+            max_call_depth: Some(DEFAULT_MAX_CALL_DEPTH),
+            max_statements: None,
+            timeout: None,
+        }
+    }
+}
+
+impl ResourceLimits {
+    /// Limits of `Interpreter::sandboxed`, so a stuck script stops instead of hanging the host
+    pub fn sandboxed() -> Self {
+        ResourceLimits {
+            max_statements: Some(SANDBOX_MAX_STATEMENTS),
+            ..ResourceLimits::default()
+        }
+    }
+}
+
+/// What natives scripts call may do on the host, the ones that aren't allowed raise `CapabilityDenied`.
+/// Everything is allowed by default, files still need `Interpreter::allow_file_access` and C libraries `Interpreter::allow_ffi`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Capabilities {
+    /// `readFile` and `fileExists`
+    pub fs_read: bool,
+    /// `writeFile` and `appendFile`
+    pub fs_write: bool,
+    /// The process' arguments and standard input: `argCount`, `arg` and `readLine`
+    pub env: bool,
+    /// `clock`, `now` and `sleep`
+    pub time: bool,
+    /// No built-in uses it, it's there for the natives hosts define
+    pub network: bool,
+    /// Print statements, unless the host captures them with `Interpreter::set_output`
+    pub stdout: bool,
+    /// `ffiLoad` and `ffiCall`, the C functions they call can do anything the process can
+    pub ffi: bool,
+}
+
+impl Capabilities {
+    pub fn all() -> Self {
+        Capabilities {
+            fs_read: true,
+            fs_write: true,
+            env: true,
+            time: true,
+            network: true,
+            stdout: true,
+            ffi: true,
+        }
+    }
+
+    pub fn none() -> Self {
+        Capabilities {
+            fs_read: false,
+            fs_write: false,
+            env: false,
+            time: false,
+            network: false,
+            stdout: false,
+            ffi: false,
+        }
+    }
+}
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        Capabilities::all()
+    }
+}
+
+/// What scripts have allocated so far, checked against the limits
+#[derive(Debug, Default)]
+pub(crate) struct ResourceUsage {
+    pub limits: ResourceLimits,
+    string_bytes: usize,
+    call_depth: usize,
+    statements: u64,
+    deadline: Option<Instant>,
+    /// Why the run is being stopped and the cleanup statements it has left
+    stopping: Option<(ErrorType, u64)>,
+}
+
+impl ResourceUsage {
+    pub fn new(limits: ResourceLimits) -> Self {
+        ResourceUsage {
+            limits,
+            ..ResourceUsage::default()
+        }
+    }
+
+    /// Statement count and time are limited per run, the call depth is left as it is,
+    /// because runs can be started by natives in the middle of a call
+    pub fn start_run(&mut self) {
+        self.statements = 0;
+        self.deadline = self.limits.timeout.map(|timeout| Instant::now() + timeout);
+        self.stopping = None;
+    }
+
+    /// Called before every statement. Once the budget runs out the script gets `CLEANUP_STATEMENTS` more
+    /// to handle the error, after that every statement fails, so scripts can't just catch it and carry on.
+    pub fn execute_statement(&mut self) -> Result<(), ErrorType> {
+        if let Some((reason, left)) = &mut self.stopping {
+            if *left == 0 {
+                return Err(reason.clone());
+            }
+            *left -= 1;
+            return Ok(());
+        }
+        self.statements += 1;
+        if self
+            .limits
+            .max_statements
+            .is_some_and(|max| self.statements > max)
+            || self
+                .deadline
+                .is_some_and(|deadline| Instant::now() > deadline)
+        {
+            return Err(self.stop(ErrorType::ExecutionTimeout));
+        }
+        Ok(())
+    }
+
+    /// Starts the cleanup of a run that has to stop, stopping it again doesn't give it more statements
+    pub fn stop(&mut self, reason: ErrorType) -> ErrorType {
+        if self.stopping.is_none() {
+            self.stopping = Some((reason.clone(), CLEANUP_STATEMENTS));
+        }
+        reason
+    }
+
+    /// Whether the run has used up its cleanup statements, its errors can't be caught anymore
+    pub fn is_stopped(&self) -> bool {
+        matches!(self.stopping, Some((_, 0)))
+    }
+
+    pub fn enter_call(&mut self) -> Result<(), ErrorType> {
+        if self
+            .limits
+            .max_call_depth
+            .is_some_and(|max| self.call_depth >= max)
+        {
+            return Err(ErrorType::StackOverflow);
+        }
+        self.call_depth += 1;
+        Ok(())
+    }
+
+    pub fn exit_call(&mut self) {
+        self.call_depth -= 1;
+    }
+
+    /// Called before a string of this many bytes is built
+    pub fn allocate_string(&mut self, bytes: usize) -> Result<(), ErrorType> {
+        self.check_string(bytes)?;
+        self.string_bytes += bytes;
+        Ok(())
+    }
+
+    /// Whether a string of this many bytes could be built, without accounting for it
+    pub fn check_string(&self, bytes: usize) -> Result<(), ErrorType> {
+        if self.limits.max_string_bytes.is_some_and(|max| bytes > max) {
+            return Err(ErrorType::ResourceLimit(format!(
+                "string of {} bytes is longer than allowed",
+                bytes
+            )));
+        }
+        if self
+            .limits
+            .max_total_string_bytes
+            .is_some_and(|max| self.string_bytes + bytes > max)
+        {
+            return Err(ErrorType::ResourceLimit(String::from(
+                "strings built by the script take more memory than allowed",
+            )));
+        }
+        Ok(())
+    }
+
+    /// Called before a list grows to this many items
+    pub fn grow_list(&self, len: usize) -> Result<(), ErrorType> {
+        if self.limits.max_collection_len.is_some_and(|max| len > max) {
+            return Err(ErrorType::ResourceLimit(format!(
+                "list of {} items is longer than allowed",
+                len
+            )));
+        }
+        Ok(())
+    }
+
+    /// Accounts for a value built by a native function
+    pub fn allocate(&mut self, value: &Value) -> Result<(), ErrorType> {
+        match value {
+            Value::String(s) => self.allocate_string(s.len()),
+            Value::List(list) => self.grow_list(list.borrow().len()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Capabilities, ResourceLimits};
+    use crate::error::ErrorType;
+    use crate::interpreter::tests::assert_true_in;
+    use crate::interpreter::Interpreter;
+    use crate::natives::define_native;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn sandboxes_untrusted_code() {
+        let mut interpreter = Interpreter::sandboxed();
+        interpreter.allow_file_access();
+        for code in [
+            "clock();",
+            "print 1;",
+            "argCount();",
+            "readFile(\"Cargo.toml\");",
+        ] {
+            let errors = interpreter.run(code).unwrap_err();
+            assert!(
+                matches!(&errors[0].error_type, ErrorType::CapabilityDenied(_)),
+                "{}",
+                code
+            );
+        }
+        let printed = Rc::new(RefCell::new(Vec::new()));
+        let captured = Rc::clone(&printed);
+        interpreter.set_output(move |line| captured.borrow_mut().push(line.to_owned()));
+        interpreter.run("print Math.abs(-1);").unwrap();
+        assert_eq!(*printed.borrow(), vec!["1"]);
+        interpreter.set_capabilities(Capabilities {
+            time: true,
+            ..Capabilities::none()
+        });
+        assert!(interpreter.run("clock() > 0;").is_ok());
+        assert!(interpreter.run("Time.now();").is_ok());
+        assert!(interpreter.run("sleep(0);").is_ok());
+        let errors = interpreter.run("while (true) {}").unwrap_err();
+        assert!(matches!(errors[0].error_type, ErrorType::ExecutionTimeout));
+    }
+
+    #[test]
+    fn requires_granted_capabilities() {
+        let mut interpreter = Interpreter::sandboxed();
+        let errors = interpreter.run("require(\"fs\");").unwrap_err();
+        assert!(matches!(
+            &errors[0].error_type,
+            ErrorType::CapabilityDenied(name) if name == "Capability 'fs'"
+        ));
+        interpreter.grant_fs();
+        let value = interpreter
+            .run("var fs = require(\"fs\"); fs.fileExists(\"Cargo.toml\");")
+            .unwrap();
+        assert!(value.to_bool());
+        let errors = interpreter.run("readFile(\"Cargo.toml\");").unwrap_err();
+        assert!(matches!(errors[0].error_type, ErrorType::UndefinedVariable));
+        interpreter.grant("net", |env| {
+            define_native(env, "get", 1, |args| Ok(args[0].clone()))
+        });
+        let value = interpreter.run("require(\"net\").get(2);").unwrap();
+        assert_eq!(value.as_number(), Some(&2.0));
+        assert!(interpreter
+            .run("var fs = require(\"fs\"); fs.readFile(\"Cargo.toml\");")
+            .is_ok());
+        let errors = interpreter.run("require(1);").unwrap_err();
+        assert!(matches!(errors[0].error_type, ErrorType::WrongType));
+    }
+
+    #[test]
+    fn strings_are_limited() {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_limits(ResourceLimits {
+            max_string_bytes: Some(8),
+            max_total_string_bytes: Some(20),
+            ..ResourceLimits::default()
+        });
+        assert_true_in(&mut interpreter, "\"abcd\" + \"efgh\" == \"abcdefgh\";");
+        let errors = interpreter.run("\"abcde\" + \"fghij\";").unwrap_err();
+        assert!(matches!(errors[0].error_type, ErrorType::ResourceLimit(_)));
+        assert_true_in(
+            &mut interpreter,
+            "var caught; try { \"abcdefghij\".upper(); } catch (e: ResourceLimit) { caught = e; } instanceof(caught, Error);",
+        );
+        // 8 bytes were built by the first run, the rest of the budget runs out in the loop
+        assert_true_in(
+            &mut interpreter,
+            "var n = 0; try { while (true) { \"ab\" + \"cd\"; n = n + 1; } } catch (e: ResourceLimit) { } n == 3;",
+        );
+    }
+
+    #[test]
+    fn lists_are_limited() {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_limits(ResourceLimits {
+            max_collection_len: Some(3),
+            ..ResourceLimits::default()
+        });
+        assert_true_in(
+            &mut interpreter,
+            "var l = list(); l.push(1); l.push(2); l.push(3); l.len() == 3;",
+        );
+        // the item isn't added, so catching the error doesn't let the list grow past the limit
+        assert_true_in(
+            &mut interpreter,
+            "var caught = false; try { l.push(4); } catch (e: ResourceLimit) { caught = true; } caught and l.len() == 3;",
+        );
+        let errors = interpreter.run("chars(\"abcd\");").unwrap_err();
+        assert!(matches!(errors[0].error_type, ErrorType::ResourceLimit(_)));
+        assert_true_in(
+            &mut interpreter,
+            "map(l, fn (x) { return x * 2; }).get(2) == 6;",
+        );
+    }
+
+    #[test]
+    fn call_depth_is_limited() {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_limits(ResourceLimits {
+            max_call_depth: Some(50),
+            ..ResourceLimits::default()
+        });
+        interpreter
+            .run("fn down(n) { if (n == 0) return 0; return down(n - 1); }")
+            .unwrap();
+        assert_true_in(&mut interpreter, "down(49) == 0;");
+        let errors = interpreter.run("down(50);").unwrap_err();
+        assert!(matches!(errors[0].error_type, ErrorType::StackOverflow));
+        assert_true_in(
+            &mut interpreter,
+            "var caught = false; try { down(1000); } catch (e: ResourceLimit) { caught = true; } caught and down(10) == 0;",
+        );
+
+        // interpreters are limited without any setup, on the stack a main thread has
+        let overflowed = std::thread::Builder::new()
+            .stack_size(8 * 1024 * 1024)
+            .spawn(|| {
+                let errors = Interpreter::new()
+                    .run("fn f(n) { return f(n + 1); } f(0);")
+                    .unwrap_err();
+                matches!(errors[0].error_type, ErrorType::StackOverflow)
+            })
+            .unwrap();
+        assert!(overflowed.join().unwrap());
+    }
+
+    #[test]
+    fn execution_time_is_limited() {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_limits(ResourceLimits {
+            max_statements: Some(100),
+            ..ResourceLimits::default()
+        });
+        let errors = interpreter.run("while (true) {}").unwrap_err();
+        assert!(matches!(errors[0].error_type, ErrorType::ExecutionTimeout));
+        // the budget is per run, catching the error doesn't give the script more of it
+        let errors = interpreter
+            .run("var n = 0; while (true) { try { while (true) { n = n + 1; } } catch (e) {} }")
+            .unwrap_err();
+        assert!(matches!(errors[0].error_type, ErrorType::ExecutionTimeout));
+        assert_true_in(&mut interpreter, "n > 10;");
+
+        interpreter.set_limits(ResourceLimits {
+            timeout: Some(std::time::Duration::from_millis(20)),
+            ..ResourceLimits::default()
+        });
+        let errors = interpreter.run("while (true) {}").unwrap_err();
+        assert!(matches!(errors[0].error_type, ErrorType::ExecutionTimeout));
+    }
+
+    #[test]
+    fn stopped_scripts_can_clean_up() {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_limits(ResourceLimits {
+            max_statements: Some(100),
+            ..ResourceLimits::default()
+        });
+        let errors = interpreter
+            .run(
+                "var cleaned = false; var caught;
+                 try { while (true) {} } catch (e: InterruptError) { caught = e; } finally { cleaned = true; }
+                 while (true) {}",
+            )
+            .unwrap_err();
+        assert!(matches!(errors[0].error_type, ErrorType::ExecutionTimeout));
+        assert_true_in(
+            &mut interpreter,
+            "cleaned and instanceof(caught, Error) and caught.message == \"Execution took longer than allowed\";",
+        );
+        // once the cleanup statements run out, the error isn't caught anymore and finally blocks can't run
+        let errors = interpreter
+            .run(
+                "var n = 0; var last = 0;
+                 while (true) { try { while (true) { n = n + 1; } } catch (e) {} finally { last = n; } }",
+            )
+            .unwrap_err();
+        assert!(matches!(errors[0].error_type, ErrorType::ExecutionTimeout));
+        assert_true_in(&mut interpreter, "last > 0 and last < n;");
+
+        interpreter.set_limits(ResourceLimits::default());
+        let handle = interpreter.interrupt_handle();
+        let interrupter = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            handle.interrupt();
+        });
+        interpreter
+            .run("try { while (true) {} } catch (e: InterruptError) { cleaned = \"interrupted\"; }")
+            .unwrap();
+        interrupter.join().unwrap();
+        assert_true_in(&mut interpreter, "cleaned == \"interrupted\";");
+    }
+}
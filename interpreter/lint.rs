@@ -0,0 +1,436 @@
+use crate::error::Error;
+use crate::expr::Expr;
+use crate::interpreter::Interpreter;
+use crate::lexer::Lexer;
+use crate::parser::Parser;
+use crate::resolver::Resolver;
+use crate::scopes::{ScopeKind, ScopeReport};
+use crate::statement::Stmt;
+use crate::token::{Literal, Span, Token, TokenType};
+use crate::walk::{walk_expr, walk_stmt, walk_stmts, Walk};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LintRule {
+    /// Local declared in a block and never referenced
+    UnusedVariable,
+    /// Block, loop body or `try` clause without statements, function bodies aside
+    EmptyBlock,
+    /// `if` or loop condition made of literals only, `while (true)` aside
+    ConstantCondition,
+    /// `a = a` or `a.b = a.b`
+    SelfAssignment,
+    /// Declaration hiding a native or prelude function
+    ShadowedBuiltin,
+    /// `if`, loops and `try` nested deeper than `LintConfig::max_nesting` inside a function
+    DeepNesting,
+}
+
+impl LintRule {
+    pub const ALL: [LintRule; 6] = [
+        LintRule::UnusedVariable,
+        LintRule::EmptyBlock,
+        LintRule::ConstantCondition,
+        LintRule::SelfAssignment,
+        LintRule::ShadowedBuiltin,
+        LintRule::DeepNesting,
+    ];
+
+    pub fn id(self) -> &'static str {
+        match self {
+            LintRule::UnusedVariable => "unused-variable",
+            LintRule::EmptyBlock => "empty-block",
+            LintRule::ConstantCondition => "constant-condition",
+            LintRule::SelfAssignment => "self-assignment",
+            LintRule::ShadowedBuiltin => "shadowed-builtin",
+            LintRule::DeepNesting => "deep-nesting",
+        }
+    }
+
+    pub fn from_id(id: &str) -> Option<LintRule> {
+        LintRule::ALL.iter().copied().find(|rule| rule.id() == id)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// Rules `lint` checks and how severe their findings are, every rule is a warning by default
+#[derive(Debug, Clone, PartialEq)]
+pub struct LintConfig {
+    severities: HashMap<LintRule, Option<Severity>>,
+    pub max_nesting: usize,
+}
+
+impl Default for LintConfig {
+    fn default() -> Self {
+        LintConfig {
+            severities: HashMap::new(),
+            max_nesting: 4,
+        }
+    }
+}
+
+impl LintConfig {
+    /// None turns the rule off
+    pub fn set(&mut self, rule: LintRule, severity: Option<Severity>) {
+        self.severities.insert(rule, severity);
+    }
+
+    pub fn severity(&self, rule: LintRule) -> Option<Severity> {
+        self.severities
+            .get(&rule)
+            .copied()
+            .unwrap_or(Some(Severity::Warning))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Finding {
+    pub rule: LintRule,
+    pub severity: Severity,
+    pub message: String,
+    pub span: Span,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "[line {}:{}] {}: {} [{}]",
+            self.span.line,
+            self.span.start,
+            self.severity,
+            self.message,
+            self.rule.id()
+        )
+    }
+}
+
+/// Checks the program for code that runs but is likely a mistake, sorted by position.
+/// Errors if it doesn't lex, parse or resolve.
+pub fn lint(source_code: &str, config: &LintConfig) -> Result<Vec<Finding>, Vec<Error>> {
+    let tokens = Lexer::new(source_code).scan_tokens()?;
+    let stmts = Parser::new(&tokens).parse_tokens()?;
+    let mut interpreter = Interpreter::new();
+    let builtins = interpreter.global_names();
+    let mut resolver = Resolver::new(&mut interpreter);
+    resolver.record_scopes();
+    resolver.resolve(&stmts)?;
+    let report = resolver.take_report().unwrap_or_default();
+
+    let mut linter = Linter {
+        config,
+        findings: Vec::new(),
+        depth: 0,
+    };
+    declarations(&mut linter, &report, &builtins, &tokens);
+    walk_stmts(&mut linter, &stmts);
+    let mut findings = linter.findings;
+    findings.sort_by_key(|finding| (finding.span.line, finding.span.start));
+    Ok(findings)
+}
+
+/// Unused locals and shadowed built-ins, found in the scopes the resolver went through
+fn declarations(linter: &mut Linter, report: &ScopeReport, builtins: &[String], tokens: &[Token]) {
+    for scope in &report.scopes {
+        if matches!(scope.kind, ScopeKind::Class | ScopeKind::Superclass) {
+            continue;
+        }
+        for (name, site) in scope.names.iter().zip(&scope.sites) {
+            let span = name_span(tokens, name, *site);
+            if builtins.contains(name) {
+                linter.report(
+                    LintRule::ShadowedBuiltin,
+                    format!("{} hides the built-in of the same name", name),
+                    span,
+                );
+            }
+            let used = report.references.iter().any(|reference| {
+                &reference.name == name
+                    && matches!(reference.resolution, Some(resolution) if resolution.scope == scope.id)
+            });
+            if scope.kind == ScopeKind::Block && !used {
+                linter.report(
+                    LintRule::UnusedVariable,
+                    format!("{} is never used", name),
+                    span,
+                );
+            }
+        }
+    }
+}
+
+/// Span of the name in the statement declaring it, parameters are found after their function's name
+fn name_span(tokens: &[Token], name: &str, site: Span) -> Span {
+    tokens
+        .iter()
+        .find(|token| {
+            (token.line, token.start) >= (site.line, site.start)
+                && matches!(&token.token_type, TokenType::Identifier(n) if n == name)
+        })
+        .map_or(site, Token::span)
+}
+
+/// Literals, and operators applied to nothing but literals
+fn is_constant(expr: &Expr) -> bool {
+    match expr {
+        Expr::Literal { .. } => true,
+        Expr::Grouping { expr, .. } | Expr::Unary { expr, .. } => is_constant(expr),
+        Expr::Binary { left, right, .. } | Expr::Logical { left, right, .. } => {
+            is_constant(left) && is_constant(right)
+        }
+        _ => false,
+    }
+}
+
+fn ungrouped(expr: &Expr) -> &Expr {
+    match expr {
+        Expr::Grouping { expr, .. } => ungrouped(expr),
+        expr => expr,
+    }
+}
+
+/// Same variable, or the same property of the same variable or `this`
+fn same_place(a: &Expr, b: &Expr) -> bool {
+    match (ungrouped(a), ungrouped(b)) {
+        (Expr::Var { name: a, .. }, Expr::Var { name: b, .. }) => a == b,
+        (Expr::This { .. }, Expr::This { .. }) => true,
+        (
+            Expr::Get {
+                name: a,
+                expr: a_obj,
+                ..
+            },
+            Expr::Get {
+                name: b,
+                expr: b_obj,
+                ..
+            },
+        ) => a == b && same_place(a_obj, b_obj),
+        _ => false,
+    }
+}
+
+struct Linter<'a> {
+    config: &'a LintConfig,
+    findings: Vec<Finding>,
+    /// Nesting of `if`, loops and `try` in the function being walked
+    depth: usize,
+}
+
+impl Linter<'_> {
+    fn report(&mut self, rule: LintRule, message: String, span: Span) {
+        if let Some(severity) = self.config.severity(rule) {
+            self.findings.push(Finding {
+                rule,
+                severity,
+                message,
+                span,
+            });
+        }
+    }
+
+    fn condition(&mut self, condition: &Expr, in_loop: bool) {
+        let infinite_loop = in_loop
+            && matches!(
+                ungrouped(condition),
+                Expr::Literal {
+                    value: Literal::Bool(true),
+                    ..
+                }
+            );
+        if is_constant(condition) && !infinite_loop {
+            self.report(
+                LintRule::ConstantCondition,
+                String::from("Condition is always the same"),
+                condition.span(),
+            );
+        }
+    }
+
+    fn empty(&mut self, stmts: &[Stmt], span: Span) {
+        if stmts.is_empty() {
+            self.report(LintRule::EmptyBlock, String::from("Empty block"), span);
+        }
+    }
+
+    /// Functions start counting the nesting over
+    fn function(&mut self, body: &[Stmt]) {
+        let depth = std::mem::replace(&mut self.depth, 0);
+        for stmt in body {
+            match stmt {
+                // the body's own block, an empty one is a function that does nothing on purpose
+                Stmt::Block { stmts, .. } => walk_stmts(self, stmts),
+                stmt => self.stmt(stmt),
+            }
+        }
+        self.depth = depth;
+    }
+}
+
+impl<'a> Walk<'a> for Linter<'_> {
+    fn stmt(&mut self, stmt: &'a Stmt) {
+        let nests = match stmt {
+            Stmt::If { condition, .. } => {
+                self.condition(condition, false);
+                true
+            }
+            Stmt::While { condition, .. } | Stmt::DoWhile { condition, .. } => {
+                self.condition(condition, true);
+                true
+            }
+            Stmt::Try {
+                body,
+                catches,
+                finally,
+                token,
+            } => {
+                self.empty(body, token.span());
+                for catch in catches {
+                    self.empty(&catch.body, token.span());
+                }
+                if let Some(finally) = finally {
+                    self.empty(finally, token.span());
+                }
+                true
+            }
+            Stmt::Block { stmts, span } => {
+                self.empty(stmts, *span);
+                false
+            }
+            Stmt::Match { .. } | Stmt::ForIn { .. } => true,
+            Stmt::Function { body, .. } => return self.function(body),
+            _ => false,
+        };
+
+        if nests {
+            self.depth += 1;
+            if self.depth == self.config.max_nesting + 1 {
+                let message = format!("Nested deeper than {} levels", self.config.max_nesting);
+                self.report(LintRule::DeepNesting, message, stmt.span());
+            }
+        }
+        walk_stmt(self, stmt);
+        if nests {
+            self.depth -= 1;
+        }
+    }
+
+    fn expr(&mut self, expr: &'a Expr) {
+        match expr {
+            Expr::Assign {
+                name,
+                expr: value,
+                token,
+                ..
+            } if matches!(ungrouped(value), Expr::Var { name: read, .. } if read == name) => self
+                .report(
+                    LintRule::SelfAssignment,
+                    format!("{} is assigned to itself", name),
+                    token.span(),
+                ),
+            Expr::Set {
+                name,
+                token,
+                value,
+                obj,
+            } if matches!(ungrouped(value), Expr::Get { name: read, expr, .. } if read == name && same_place(expr, obj)) => {
+                self.report(
+                    LintRule::SelfAssignment,
+                    format!("{} is assigned to itself", name),
+                    token.span(),
+                )
+            }
+            Expr::Closure { body, .. } => return self.function(body),
+            _ => (),
+        }
+        walk_expr(self, expr);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{lint, LintConfig, LintRule, Severity};
+
+    fn rules(source_code: &str, config: &LintConfig) -> Vec<(&'static str, usize)> {
+        lint(source_code, config)
+            .unwrap()
+            .into_iter()
+            .map(|finding| (finding.rule.id(), finding.span.line))
+            .collect()
+    }
+
+    #[test]
+    fn finds_likely_mistakes() {
+        let source_code = "fn f(clock) {
+    var unused = 1;
+    var used = 2;
+    used = used;
+    used.x = used.x;
+    if (1 < 2) {}
+    while (true) { return used; }
+}
+fn g() {}
+try { g(); } catch (err) {}";
+        let config = LintConfig::default();
+        assert_eq!(
+            rules(source_code, &config),
+            vec![
+                ("shadowed-builtin", 1),
+                ("unused-variable", 2),
+                ("self-assignment", 4),
+                ("self-assignment", 5),
+                ("constant-condition", 6),
+                ("empty-block", 6),
+                ("empty-block", 10),
+            ]
+        );
+        let findings = lint(source_code, &config).unwrap();
+        assert_eq!(
+            findings[1].to_string(),
+            "[line 2:9] warning: unused is never used [unused-variable]"
+        );
+    }
+
+    #[test]
+    fn follows_the_config() {
+        let source_code = "fn f(a) {
+    if (a) { while (a) { if (a) { print a; } } }
+    if (a) { fn g() { if (a) { if (a) { print a; } } } }
+}";
+        let mut config = LintConfig {
+            max_nesting: 2,
+            ..LintConfig::default()
+        };
+        config.set(LintRule::DeepNesting, Some(Severity::Error));
+        config.set(LintRule::UnusedVariable, None);
+        let findings = lint(source_code, &config).unwrap();
+        // g starts counting over, so only the first if nests too deep
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].rule, LintRule::DeepNesting);
+        assert_eq!(findings[0].severity, Severity::Error);
+        assert_eq!((findings[0].span.line, findings[0].span.start), (2, 26));
+        assert_eq!(
+            LintRule::from_id("deep-nesting"),
+            Some(LintRule::DeepNesting)
+        );
+        assert!(lint("print", &config).is_err());
+    }
+}
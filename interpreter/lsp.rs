@@ -0,0 +1,988 @@
+use crate::fixes::{diagnose, Diagnostic, Fix};
+use crate::interpreter::Interpreter;
+use crate::lexer::Lexer;
+use crate::metrics::closing_line;
+use crate::parser::Parser;
+use crate::resolver::Resolver;
+use crate::runtime_value::Value as RuntimeValue;
+use crate::scopes::{Reference, ScopeReport};
+use crate::statement::Stmt;
+use crate::token::{Span, Token, TokenType};
+use crate::walk::{walk_stmt, walk_stmts, Walk};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::mem;
+
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+
+// https://microsoft.github.io/language-server-protocol/specifications/specification-current/#symbolKind
+const SYMBOL_CLASS: u8 = 5;
+const SYMBOL_METHOD: u8 = 6;
+const SYMBOL_FUNCTION: u8 = 12;
+const SYMBOL_VARIABLE: u8 = 13;
+
+/// Semantic token types in the order of `SemanticKind`
+const TOKEN_TYPES: &[&str] = &[
+    "class",
+    "function",
+    "method",
+    "property",
+    "parameter",
+    "variable",
+];
+const TOKEN_MODIFIERS: &[&str] = &["declaration", "defaultLibrary"];
+const DECLARATION: u32 = 1;
+const DEFAULT_LIBRARY: u32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SemanticKind {
+    Class,
+    Function,
+    Method,
+    Property,
+    Parameter,
+    Variable,
+}
+
+/// Language server speaking JSON-RPC, it answers from the lexer, parser and resolver
+/// without ever running the documents
+#[derive(Debug, Default)]
+pub struct LanguageServer {
+    /// Text of the open documents by their uri
+    documents: HashMap<String, String>,
+    shutting_down: bool,
+    exited: bool,
+}
+
+impl LanguageServer {
+    pub fn new() -> Self {
+        LanguageServer::default()
+    }
+
+    /// Whether the client asked the server to exit
+    pub fn exited(&self) -> bool {
+        self.exited
+    }
+
+    /// Messages to send back to the client, responses and notifications
+    pub fn handle(&mut self, message: &Value) -> Vec<Value> {
+        let method = message["method"].as_str().unwrap_or_default();
+        let params = &message["params"];
+        let id = match message.get("id") {
+            Some(id) => id.clone(),
+            None => return self.notification(method, params),
+        };
+        if self.shutting_down {
+            return vec![error_response(
+                id,
+                INVALID_REQUEST,
+                "Server is shutting down",
+            )];
+        }
+        let result = match method {
+            "initialize" => capabilities(),
+            "shutdown" => {
+                self.shutting_down = true;
+                Value::Null
+            }
+            "textDocument/definition" => self.definition(params),
+            "textDocument/hover" => self.hover(params),
+            "textDocument/documentSymbol" => self.document_symbols(params),
+            "textDocument/codeAction" => self.code_actions(params),
+            "textDocument/semanticTokens/full" => self.semantic_tokens(params),
+            "textDocument/foldingRange" => self.folding_ranges(params),
+            _ => {
+                let message = format!("Unknown method {}", method);
+                return vec![error_response(id, METHOD_NOT_FOUND, &message)];
+            }
+        };
+        vec![json!({ "jsonrpc": "2.0", "id": id, "result": result })]
+    }
+
+    fn notification(&mut self, method: &str, params: &Value) -> Vec<Value> {
+        let uri = params["textDocument"]["uri"]
+            .as_str()
+            .unwrap_or_default()
+            .to_owned();
+        match method {
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+                self.documents.insert(uri.clone(), text.to_owned());
+                vec![self.diagnostics(&uri)]
+            }
+            "textDocument/didChange" => {
+                // full sync, the last change is the whole document
+                let changes = params["contentChanges"].as_array();
+                if let Some(text) = changes
+                    .and_then(|c| c.last())
+                    .and_then(|c| c["text"].as_str())
+                {
+                    self.documents.insert(uri.clone(), text.to_owned());
+                }
+                vec![self.diagnostics(&uri)]
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                vec![publish_diagnostics(&uri, Vec::new())]
+            }
+            "exit" => {
+                self.exited = true;
+                Vec::new()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn document(&self, params: &Value) -> Option<&String> {
+        let uri = params["textDocument"]["uri"].as_str()?;
+        self.documents.get(uri)
+    }
+
+    fn diagnostics(&self, uri: &str) -> Value {
+        let text = self.documents.get(uri).map_or("", String::as_str);
+        let diagnostics = diagnose(text)
+            .iter()
+            .map(|diagnostic| lsp_diagnostic(text, diagnostic))
+            .collect();
+        publish_diagnostics(uri, diagnostics)
+    }
+
+    fn definition(&self, params: &Value) -> Value {
+        let text = match self.document(params) {
+            Some(text) => text,
+            None => return Value::Null,
+        };
+        let analysis = Analysis::new(text);
+        match analysis.declaration_at(text, &params["position"]) {
+            Some(Declaration::Site(name, site)) => json!({
+                "uri": params["textDocument"]["uri"],
+                "range": range(text, analysis.name_span(&name, site)),
+            }),
+            _ => Value::Null,
+        }
+    }
+
+    fn hover(&self, params: &Value) -> Value {
+        let text = match self.document(params) {
+            Some(text) => text,
+            None => return Value::Null,
+        };
+        let analysis = Analysis::new(text);
+        let contents = match analysis.declaration_at(text, &params["position"]) {
+            Some(Declaration::Site(name, site)) => {
+                let span = analysis.name_span(&name, site);
+                let line = text.lines().nth(span.line - 1).unwrap_or_default();
+                format!(
+                    "```lox\n{}\n```\n`{}` is declared on line {}",
+                    line.trim(),
+                    name,
+                    span.line
+                )
+            }
+            Some(Declaration::BuiltIn(name)) => format!("`{}` is built-in", name),
+            None => return Value::Null,
+        };
+        json!({ "contents": { "kind": "markdown", "value": contents } })
+    }
+
+    /// Quick fixes of the diagnostics on the lines of the range
+    fn code_actions(&self, params: &Value) -> Value {
+        let text = match self.document(params) {
+            Some(text) => text,
+            None => return Value::Null,
+        };
+        let first = params["range"]["start"]["line"]
+            .as_u64()
+            .unwrap_or_default() as usize
+            + 1;
+        let last = params["range"]["end"]["line"].as_u64().unwrap_or_default() as usize + 1;
+        let actions: Vec<Value> = diagnose(text)
+            .iter()
+            .filter(|diagnostic| (first..=last).contains(&diagnostic.span.line))
+            .filter_map(|diagnostic| {
+                let fix = diagnostic.fix.as_ref()?;
+                Some(json!({
+                    "title": fix.title,
+                    "kind": "quickfix",
+                    "diagnostics": [lsp_diagnostic(text, diagnostic)],
+                    "edit": { "changes": { params["textDocument"]["uri"].as_str()?: text_edits(text, fix) } },
+                }))
+            })
+            .collect();
+        Value::Array(actions)
+    }
+
+    /// Names classified by what declared them, encoded relative to the previous one as lsp expects
+    fn semantic_tokens(&self, params: &Value) -> Value {
+        let text = match self.document(params) {
+            Some(text) => text,
+            None => return Value::Null,
+        };
+        let mut data = Vec::new();
+        let (mut previous_line, mut previous_start) = (0, 0);
+        for (token, kind, modifiers) in Analysis::new(text).classify() {
+            let line = token.line - 1;
+            let start = utf16_column(text, token.line, token.start);
+            let length = utf16_column(text, token.line, token.end + 1) - start;
+            let delta_start = match line == previous_line {
+                true => start - previous_start,
+                false => start,
+            };
+            data.extend([
+                (line - previous_line) as u32,
+                delta_start as u32,
+                length as u32,
+                kind as u32,
+                modifiers,
+            ]);
+            previous_line = line;
+            previous_start = start;
+        }
+        json!({ "data": data })
+    }
+
+    /// Functions, classes and blocks spreading over more than a line, their closing brace stays visible
+    fn folding_ranges(&self, params: &Value) -> Value {
+        let text = match self.document(params) {
+            Some(text) => text,
+            None => return Value::Null,
+        };
+        let analysis = Analysis::new(text);
+        let mut folds = Folds {
+            tokens: &analysis.tokens,
+            lines: Vec::new(),
+        };
+        walk_stmts(&mut folds, &analysis.stmts);
+        let mut lines = folds.lines;
+        lines.sort_unstable();
+        lines.dedup_by_key(|(start, _)| *start);
+        let ranges: Vec<Value> = lines
+            .into_iter()
+            .filter(|(start, end)| end - 1 > *start)
+            .map(|(start, end)| json!({ "startLine": start - 1, "endLine": end - 2 }))
+            .collect();
+        Value::Array(ranges)
+    }
+
+    fn document_symbols(&self, params: &Value) -> Value {
+        let text = match self.document(params) {
+            Some(text) => text,
+            None => return Value::Null,
+        };
+        let analysis = Analysis::new(text);
+        let symbols: Vec<Value> = analysis
+            .stmts
+            .iter()
+            .filter_map(|stmt| analysis.symbol(text, stmt, SYMBOL_FUNCTION))
+            .collect();
+        Value::Array(symbols)
+    }
+}
+
+/// Reads framed messages until the client exits or closes the input
+pub fn run_language_server(mut input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+    let mut server = LanguageServer::new();
+    while let Some(message) = read_message(&mut input)? {
+        for reply in server.handle(&message) {
+            write_message(&mut output, &reply)?;
+        }
+        if server.exited() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+    let length = length.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, "message without Content-Length")
+    })?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+}
+
+fn write_message(output: &mut impl Write, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+fn capabilities() -> Value {
+    json!({
+        "capabilities": {
+            "textDocumentSync": 1,
+            "definitionProvider": true,
+            "hoverProvider": true,
+            "documentSymbolProvider": true,
+            "codeActionProvider": true,
+            "semanticTokensProvider": {
+                "legend": { "tokenTypes": TOKEN_TYPES, "tokenModifiers": TOKEN_MODIFIERS },
+                "full": true,
+            },
+            "foldingRangeProvider": true,
+        },
+        "serverInfo": { "name": "lox", "version": env!("CARGO_PKG_VERSION") },
+    })
+}
+
+fn error_response(id: Value, code: i64, message: &str) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": code, "message": message },
+    })
+}
+
+fn publish_diagnostics(uri: &str, diagnostics: Vec<Value>) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": { "uri": uri, "diagnostics": diagnostics },
+    })
+}
+
+/// The fix goes along in `data`, as the lox edits and the lsp edits making it
+fn lsp_diagnostic(text: &str, diagnostic: &Diagnostic) -> Value {
+    let data = diagnostic.fix.as_ref().map(|fix| {
+        json!({
+            "fix": fix,
+            "edits": text_edits(text, fix),
+        })
+    });
+    json!({
+        "range": range(text, diagnostic.span),
+        "severity": if diagnostic.warning { 2 } else { 1 },
+        "code": diagnostic.code,
+        "source": "lox",
+        "message": diagnostic.message,
+        "data": data,
+    })
+}
+
+fn text_edits(text: &str, fix: &Fix) -> Vec<Value> {
+    fix.edits
+        .iter()
+        .map(|edit| {
+            json!({
+                "range": {
+                    "start": position(text, edit.line, edit.start),
+                    "end": position(text, edit.line, edit.end),
+                },
+                "newText": edit.text,
+            })
+        })
+        .collect()
+}
+
+/// Lsp positions count lines from 0 and characters in utf-16 code units
+fn position(text: &str, line: usize, column: usize) -> Value {
+    let character = utf16_column(text, line, column);
+    json!({ "line": line.saturating_sub(1), "character": character })
+}
+
+/// Utf-16 code units before the column, counted from 0
+fn utf16_column(text: &str, line: usize, column: usize) -> usize {
+    text.lines()
+        .nth(line.saturating_sub(1))
+        .map_or(column.saturating_sub(1), |line| {
+            line.chars()
+                .take(column.saturating_sub(1))
+                .map(char::len_utf16)
+                .sum()
+        })
+}
+
+fn range(text: &str, span: Span) -> Value {
+    json!({
+        "start": position(text, span.line, span.start),
+        "end": position(text, span.line, span.end + 1),
+    })
+}
+
+/// Line and column of an lsp position, as the lexer counts them
+fn location(text: &str, position: &Value) -> Option<(usize, usize)> {
+    let line = position["line"].as_u64()? as usize;
+    let character = position["character"].as_u64()? as usize;
+    let mut units = 0;
+    let mut column = 1;
+    for c in text.lines().nth(line)?.chars() {
+        units += c.len_utf16();
+        if units > character {
+            break;
+        }
+        column += 1;
+    }
+    Some((line + 1, column))
+}
+
+enum Declaration {
+    /// Name and the statement declaring it
+    Site(String, Span),
+    BuiltIn(String),
+}
+
+/// What the lexer, parser and resolver know about a document
+struct Analysis {
+    tokens: Vec<Token>,
+    stmts: Vec<Stmt>,
+    report: ScopeReport,
+}
+
+impl Analysis {
+    /// Lexer errors leave the rest empty, errors are reported by `diagnose`
+    fn new(text: &str) -> Self {
+        let mut analysis = Analysis {
+            tokens: Vec::new(),
+            stmts: Vec::new(),
+            report: ScopeReport::default(),
+        };
+        analysis.tokens = match Lexer::new(text).scan_tokens() {
+            Ok(tokens) => tokens,
+            Err(_) => return analysis,
+        };
+        // broken statements are skipped, the rest of the file still has its symbols and scopes
+        analysis.stmts = Parser::new(&analysis.tokens).parse_partial().0;
+        let mut interpreter = Interpreter::new();
+        let mut resolver = Resolver::new(&mut interpreter);
+        resolver.record_scopes();
+        // scopes are reported up to the first error
+        let _ = resolver.resolve(&analysis.stmts);
+        analysis.report = resolver.take_report().unwrap_or_default();
+        analysis
+    }
+
+    /// Declaration of the variable referenced at the position
+    fn declaration_at(&self, text: &str, position: &Value) -> Option<Declaration> {
+        let (line, column) = location(text, position)?;
+        let reference = self.report.references.iter().find(|reference| {
+            let span = reference.span;
+            span.line == line && span.start <= column && column <= span.end
+        })?;
+        Some(self.declaration(reference))
+    }
+
+    fn declaration(&self, reference: &Reference) -> Declaration {
+        let name = reference.name.clone();
+        // globals are looked up by name in the top-level scope
+        let scope = reference
+            .resolution
+            .map_or(0, |resolution| resolution.scope);
+        let site = self.report.scopes.get(scope).and_then(|scope| {
+            let index = scope.names.iter().position(|n| *n == name)?;
+            scope.sites.get(index).copied()
+        });
+        match site {
+            Some(site) => Declaration::Site(name, site),
+            None => Declaration::BuiltIn(name),
+        }
+    }
+
+    /// Names the resolver or the syntax tell something about, with the kind and modifiers of each
+    fn classify(&self) -> Vec<(&Token, SemanticKind, u32)> {
+        let mut declarations = Declarations {
+            analysis: self,
+            in_class: false,
+            kinds: HashMap::new(),
+        };
+        walk_stmts(&mut declarations, &self.stmts);
+        let mut kinds = declarations.kinds;
+        // closure parameters sit between bars
+        let mut in_bars = false;
+        for token in &self.tokens {
+            match &token.token_type {
+                TokenType::Bar => in_bars = !in_bars,
+                TokenType::Identifier(_) if in_bars => {
+                    kinds.insert((token.line, token.start), SemanticKind::Parameter);
+                }
+                _ => (),
+            }
+        }
+
+        let natives = Interpreter::new();
+        let mut classified = Vec::new();
+        for (index, token) in self.tokens.iter().enumerate() {
+            if !matches!(token.token_type, TokenType::Identifier(_)) {
+                continue;
+            }
+            if let Some(kind) = kinds.get(&(token.line, token.start)) {
+                classified.push((token, *kind, DECLARATION));
+                continue;
+            }
+            let after_dot = index > 0 && self.tokens[index - 1].token_type == TokenType::Dot;
+            if after_dot {
+                let called = self
+                    .tokens
+                    .get(index + 1)
+                    .is_some_and(|next| next.token_type == TokenType::OpenParenthesis);
+                let kind = match called {
+                    true => SemanticKind::Method,
+                    false => SemanticKind::Property,
+                };
+                classified.push((token, kind, 0));
+                continue;
+            }
+            let reference = self.report.references.iter().find(|reference| {
+                reference.span.line == token.line && reference.span.start == token.start
+            });
+            let reference = match reference {
+                Some(reference) => reference,
+                None => continue,
+            };
+            match self.declaration(reference) {
+                Declaration::Site(name, site) => {
+                    let span = self.name_span(&name, site);
+                    let kind = kinds
+                        .get(&(span.line, span.start))
+                        .copied()
+                        .unwrap_or(SemanticKind::Variable);
+                    classified.push((token, kind, 0));
+                }
+                Declaration::BuiltIn(name) => {
+                    let kind = match natives.global_value(&name) {
+                        Some(RuntimeValue::Class(_)) => SemanticKind::Class,
+                        Some(_) => SemanticKind::Function,
+                        None => continue,
+                    };
+                    classified.push((token, kind, DEFAULT_LIBRARY));
+                }
+            }
+        }
+        classified
+    }
+
+    /// Span of the name in the statement declaring it
+    fn name_span(&self, name: &str, site: Span) -> Span {
+        self.tokens
+            .iter()
+            .find(|token| {
+                (token.line, token.start) >= (site.line, site.start)
+                    && matches!(&token.token_type, TokenType::Identifier(n) if n == name)
+            })
+            .map_or(site, |token| Span {
+                line: token.line,
+                start: token.start,
+                end: token.end,
+            })
+    }
+
+    fn symbol(&self, text: &str, stmt: &Stmt, function_kind: u8) -> Option<Value> {
+        let (name, token, kind, children) = match stmt {
+            Stmt::Function { name, token, .. } => (name, token, function_kind, Vec::new()),
+            Stmt::Class {
+                name,
+                token,
+                members,
+                ..
+            } => {
+                let methods = members
+                    .iter()
+                    .filter_map(|member| self.symbol(text, member, SYMBOL_METHOD))
+                    .collect();
+                (name, token, SYMBOL_CLASS, methods)
+            }
+            Stmt::Var { name, span, .. } => {
+                let span = self.name_span(name, *span);
+                return Some(json!({
+                    "name": name,
+                    "kind": SYMBOL_VARIABLE,
+                    "range": range(text, span),
+                    "selectionRange": range(text, span),
+                }));
+            }
+            _ => return None,
+        };
+        let name_span = Span {
+            line: token.line,
+            start: token.start,
+            end: token.end,
+        };
+        let end_line = closing_line(&self.tokens, token);
+        let end_column = text
+            .lines()
+            .nth(end_line - 1)
+            .map_or(0, |line| line.chars().count());
+        Some(json!({
+            "name": name,
+            "kind": kind,
+            "range": {
+                "start": position(text, token.line, 1),
+                "end": position(text, end_line, end_column + 1),
+            },
+            "selectionRange": range(text, name_span),
+            "children": children,
+        }))
+    }
+}
+
+/// Kinds of the names declared in the program, by where their name starts
+struct Declarations<'a> {
+    analysis: &'a Analysis,
+    /// Whether the statement being walked is a member of a class
+    in_class: bool,
+    kinds: HashMap<(usize, usize), SemanticKind>,
+}
+
+impl<'s> Walk<'s> for Declarations<'_> {
+    fn stmt(&mut self, stmt: &'s Stmt) {
+        let in_class = mem::take(&mut self.in_class);
+        match stmt {
+            Stmt::Function { token, .. } => {
+                let kind = match in_class {
+                    true => SemanticKind::Method,
+                    false => SemanticKind::Function,
+                };
+                self.kinds.insert((token.line, token.start), kind);
+                let params = self
+                    .analysis
+                    .tokens
+                    .iter()
+                    .skip_while(|t| *t != token)
+                    .skip(1)
+                    .take_while(|t| t.token_type != TokenType::CloseParenthesis);
+                for param in params.filter(|t| matches!(t.token_type, TokenType::Identifier(_))) {
+                    self.kinds
+                        .insert((param.line, param.start), SemanticKind::Parameter);
+                }
+                walk_stmt(self, stmt);
+            }
+            Stmt::Class {
+                token,
+                members,
+                superclass,
+                ..
+            } => {
+                self.kinds
+                    .insert((token.line, token.start), SemanticKind::Class);
+                if let Some(superclass) = superclass {
+                    self.expr(superclass);
+                }
+                self.in_class = true;
+                walk_stmts(self, members);
+            }
+            Stmt::Var { name, span, .. } => {
+                let span = self.analysis.name_span(name, *span);
+                let kind = match in_class {
+                    true => SemanticKind::Property,
+                    false => SemanticKind::Variable,
+                };
+                self.kinds.insert((span.line, span.start), kind);
+                walk_stmt(self, stmt);
+            }
+            Stmt::ForIn { token, .. } => {
+                self.kinds
+                    .insert((token.line, token.start), SemanticKind::Variable);
+                walk_stmt(self, stmt);
+            }
+            _ => walk_stmt(self, stmt),
+        }
+        self.in_class = in_class;
+    }
+}
+
+/// First and closing line of every function, class and block
+struct Folds<'a> {
+    tokens: &'a [Token],
+    lines: Vec<(usize, usize)>,
+}
+
+impl<'s> Walk<'s> for Folds<'_> {
+    fn stmt(&mut self, stmt: &'s Stmt) {
+        match stmt {
+            Stmt::Function { token, .. }
+            | Stmt::Class { token, .. }
+            | Stmt::Try { token, .. }
+            | Stmt::Match { token, .. } => {
+                self.lines
+                    .push((token.line, closing_line(self.tokens, token)));
+            }
+            Stmt::Block { span, .. } => {
+                let brace = self
+                    .tokens
+                    .iter()
+                    .find(|t| t.line == span.line && t.start == span.start);
+                if let Some(brace) = brace {
+                    self.lines
+                        .push((span.line, closing_line(self.tokens, brace)));
+                }
+            }
+            _ => (),
+        }
+        walk_stmt(self, stmt);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{run_language_server, LanguageServer};
+    use serde_json::{json, Value};
+    use std::io::Cursor;
+
+    const URI: &str = "file:///test.lox";
+
+    fn open(server: &mut LanguageServer, text: &str) -> Vec<Value> {
+        server.handle(&json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didOpen",
+            "params": { "textDocument": { "uri": URI, "languageId": "lox", "version": 1, "text": text } },
+        }))
+    }
+
+    fn request(server: &mut LanguageServer, method: &str, line: u64, character: u64) -> Value {
+        let replies = server.handle(&json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": method,
+            "params": {
+                "textDocument": { "uri": URI },
+                "position": { "line": line, "character": character },
+                "range": {
+                    "start": { "line": line, "character": character },
+                    "end": { "line": line, "character": character },
+                },
+            },
+        }));
+        replies[0]["result"].clone()
+    }
+
+    const SOURCE: &str =
+        "var a = 1;\nfn f(x) {\n    return x + a;\n}\nclass C {\n    m() { return f(clock()); }\n}";
+
+    #[test]
+    fn publishes_diagnostics_on_change() {
+        let mut server = LanguageServer::new();
+        let replies = open(&mut server, "var a = ;\nreturn 1;");
+        assert_eq!(replies[0]["method"], "textDocument/publishDiagnostics");
+        let diagnostics = replies[0]["params"]["diagnostics"].as_array().unwrap();
+        assert!(!diagnostics.is_empty());
+        assert_eq!(diagnostics[0]["range"]["start"]["line"], 0);
+
+        let replies = server.handle(&json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didChange",
+            "params": {
+                "textDocument": { "uri": URI, "version": 2 },
+                "contentChanges": [{ "text": SOURCE }],
+            },
+        }));
+        assert_eq!(replies[0]["params"]["diagnostics"], json!([]));
+
+        let replies = open(&mut server, "fn f() { return 1; }\nreturn 1;");
+        let diagnostics = &replies[0]["params"]["diagnostics"];
+        assert_eq!(diagnostics[0]["code"], "CantReturnOutsideFunction");
+        assert_eq!(diagnostics[0]["range"]["start"]["line"], 1);
+    }
+
+    #[test]
+    fn goes_to_definition() {
+        let mut server = LanguageServer::new();
+        open(&mut server, SOURCE);
+        // x in `return x + a`
+        let definition = request(&mut server, "textDocument/definition", 2, 11);
+        assert_eq!(definition["uri"], URI);
+        assert_eq!(
+            definition["range"],
+            json!({ "start": { "line": 1, "character": 5 }, "end": { "line": 1, "character": 6 } })
+        );
+        // a global
+        let definition = request(&mut server, "textDocument/definition", 2, 15);
+        assert_eq!(
+            definition["range"]["start"],
+            json!({ "line": 0, "character": 4 })
+        );
+        // a function called from a method
+        let definition = request(&mut server, "textDocument/definition", 5, 17);
+        assert_eq!(
+            definition["range"]["start"],
+            json!({ "line": 1, "character": 3 })
+        );
+        // natives have no declaration
+        assert_eq!(
+            request(&mut server, "textDocument/definition", 5, 19),
+            Value::Null
+        );
+        assert_eq!(
+            request(&mut server, "textDocument/definition", 0, 0),
+            Value::Null
+        );
+    }
+
+    #[test]
+    fn hovers_with_declaration_site() {
+        let mut server = LanguageServer::new();
+        open(&mut server, SOURCE);
+        let hover = request(&mut server, "textDocument/hover", 2, 15);
+        let contents = hover["contents"]["value"].as_str().unwrap();
+        assert!(contents.contains("var a = 1;"));
+        assert!(contents.contains("declared on line 1"));
+        let hover = request(&mut server, "textDocument/hover", 5, 20);
+        assert_eq!(hover["contents"]["value"], "`clock` is built-in");
+    }
+
+    #[test]
+    fn lists_document_symbols() {
+        let mut server = LanguageServer::new();
+        open(&mut server, SOURCE);
+        let replies = server.handle(&json!({
+            "jsonrpc": "2.0",
+            "id": 2,
+            "method": "textDocument/documentSymbol",
+            "params": { "textDocument": { "uri": URI } },
+        }));
+        let symbols = replies[0]["result"].as_array().unwrap();
+        let names: Vec<_> = symbols.iter().map(|s| s["name"].clone()).collect();
+        assert_eq!(names, vec!["a", "f", "C"]);
+        assert_eq!(symbols[1]["kind"], 12);
+        assert_eq!(symbols[1]["range"]["end"]["line"], 3);
+        assert_eq!(symbols[2]["children"][0]["name"], "m");
+        assert_eq!(symbols[2]["children"][0]["kind"], 6);
+    }
+
+    #[test]
+    fn offers_quick_fixes() {
+        let mut server = LanguageServer::new();
+        let replies = open(&mut server, "var total = 1;\nprint totl;");
+        let diagnostic = &replies[0]["params"]["diagnostics"][0];
+        assert_eq!(diagnostic["code"], "UndefinedVariable");
+        assert_eq!(diagnostic["data"]["fix"]["title"], "Change to total");
+        let actions = request(&mut server, "textDocument/codeAction", 1, 0);
+        assert_eq!(actions[0]["kind"], "quickfix");
+        assert_eq!(
+            actions[0]["edit"]["changes"][URI][0],
+            json!({
+                "range": { "start": { "line": 1, "character": 6 }, "end": { "line": 1, "character": 10 } },
+                "newText": "total",
+            })
+        );
+        assert_eq!(
+            request(&mut server, "textDocument/codeAction", 0, 0),
+            json!([])
+        );
+    }
+
+    fn document_request(server: &mut LanguageServer, method: &str) -> Value {
+        let replies = server.handle(&json!({
+            "jsonrpc": "2.0",
+            "id": 3,
+            "method": method,
+            "params": { "textDocument": { "uri": URI } },
+        }));
+        replies[0]["result"].clone()
+    }
+
+    #[test]
+    fn classifies_semantic_tokens() {
+        let mut server = LanguageServer::new();
+        open(
+            &mut server,
+            "class Point {\n    var x;\n    move(dx) { this.x = this.x + dx; return this.norm(); }\n}\nvar p = Point(1);\nvar f = |a| => a + clock();",
+        );
+        let result = document_request(&mut server, "textDocument/semanticTokens/full");
+        let data: Vec<u64> = result["data"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|n| n.as_u64().unwrap())
+            .collect();
+        // absolute line, column, length, type and modifiers of every token
+        let mut tokens = Vec::new();
+        let (mut line, mut column) = (0, 0);
+        for chunk in data.chunks(5) {
+            if chunk[0] > 0 {
+                column = 0;
+            }
+            line += chunk[0];
+            column += chunk[1];
+            tokens.push((line, column, chunk[2], chunk[3], chunk[4]));
+        }
+        let (class, function, method, property, parameter, variable) = (0, 1, 2, 3, 4, 5);
+        assert_eq!(
+            tokens,
+            vec![
+                (0, 6, 5, class, 1),
+                (1, 8, 1, property, 1),
+                (2, 4, 4, method, 1),
+                (2, 9, 2, parameter, 1),
+                (2, 20, 1, property, 0),
+                (2, 29, 1, property, 0),
+                (2, 33, 2, parameter, 0),
+                (2, 49, 4, method, 0),
+                (4, 4, 1, variable, 1),
+                (4, 8, 5, class, 0),
+                (5, 4, 1, variable, 1),
+                (5, 9, 1, parameter, 1),
+                (5, 15, 1, parameter, 0),
+                (5, 19, 5, function, 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn folds_functions_classes_and_blocks() {
+        let mut server = LanguageServer::new();
+        open(
+            &mut server,
+            "fn f(x) {\n    if (x) {\n        print x;\n    }\n}\nclass C { m() {} }\nwhile (true) {\n    break;\n}",
+        );
+        let ranges = document_request(&mut server, "textDocument/foldingRange");
+        assert_eq!(
+            ranges,
+            json!([
+                { "startLine": 0, "endLine": 3 },
+                { "startLine": 1, "endLine": 2 },
+                { "startLine": 6, "endLine": 7 },
+            ])
+        );
+    }
+
+    #[test]
+    fn speaks_framed_json_rpc() {
+        let messages = [
+            json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {} }),
+            json!({ "jsonrpc": "2.0", "method": "initialized", "params": {} }),
+            json!({ "jsonrpc": "2.0", "id": 2, "method": "workspace/unknown", "params": {} }),
+            json!({ "jsonrpc": "2.0", "id": 3, "method": "shutdown" }),
+            json!({ "jsonrpc": "2.0", "id": 4, "method": "initialize", "params": {} }),
+            json!({ "jsonrpc": "2.0", "method": "exit" }),
+            json!({ "jsonrpc": "2.0", "id": 5, "method": "shutdown" }),
+        ];
+        let input: String = messages
+            .iter()
+            .map(|message| {
+                let body = message.to_string();
+                format!("Content-Length: {}\r\n\r\n{}", body.len(), body)
+            })
+            .collect();
+        let mut output = Vec::new();
+        run_language_server(Cursor::new(input), &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let replies: Vec<Value> = output
+            .split("Content-Length: ")
+            .filter(|part| !part.is_empty())
+            .map(|part| serde_json::from_str(part.split_once("\r\n\r\n").unwrap().1).unwrap())
+            .collect();
+        assert_eq!(replies.len(), 4);
+        assert_eq!(replies[0]["result"]["capabilities"]["hoverProvider"], true);
+        assert_eq!(replies[1]["error"]["code"], -32601);
+        assert_eq!(replies[2]["id"], 3);
+        assert_eq!(replies[3]["error"]["code"], -32600);
+    }
+}
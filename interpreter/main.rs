@@ -1,14 +1,292 @@
-use interpreter::{run_code, run_file, run_prompt};
-use std::{env, fs::File};
+use interpreter::{
+    debug_file, dump_file_ast, explain_file, fix_file, format_file, grammar, lint_file,
+    metrics_file, print_duplicates, print_file_ast, print_file_diagnostics, print_file_scopes,
+    print_project_check, profile_file, run_file, run_file_jlox, run_file_reporting_crashes,
+    run_language_server, run_prompt, test_file, to_ebnf, to_railroad_svg, watch_file, Capabilities,
+    DiagnosticOptions, Features, Interpreter, LanguageVersion, LintConfig, LintRule,
+    MetricThresholds, ResourceLimits, Semantics, Severity,
+};
+use std::env;
+use std::process;
+use std::thread;
+
+/// Every Lox call nests many Rust calls, the main thread's stack fits only about a hundred of them
+const STACK_SIZE: usize = 256 * 1024 * 1024;
+/// WASI has no threads, so scripts run on the main thread's stack there
+const MAX_CALL_DEPTH: usize = if cfg!(target_os = "wasi") { 100 } else { 1000 };
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    let file_name = args.get(1);
+    match thread::Builder::new().stack_size(STACK_SIZE).spawn(cli) {
+        Ok(cli) => {
+            if cli.join().is_err() {
+                process::exit(101);
+            }
+        }
+        Err(_) => cli(),
+    }
+}
+
+fn cli() {
+    let options: Vec<String> = env::args().skip(1).collect();
+    let mut args = options.clone().into_iter();
+    let mut interpreter = Interpreter::new();
+    interpreter.set_limits(ResourceLimits {
+        max_call_depth: Some(MAX_CALL_DEPTH),
+        ..ResourceLimits::default()
+    });
+    let mut file_name = None;
+    let mut watch = false;
+    let mut debug = false;
+    let mut test = false;
+    let mut post_mortem = false;
+    let mut explain = false;
+    let mut crash_report = None;
+    let mut file_access = true;
+    let mut format = false;
+    let mut ast = false;
+    let mut emit = None;
+    let mut script_args = Vec::new();
+    let mut jlox = false;
+    let mut reserve_keywords = false;
+    let mut grammar_format = None;
+    let mut diagnostics = DiagnosticOptions::default();
+    let mut metrics = false;
+    let mut duplicates = false;
+    let mut min_size = 20;
+    let mut thresholds = MetricThresholds::default();
+    let mut lsp = false;
+    let mut fix = false;
+    let mut check_project = false;
+    let mut profile = false;
+    let mut lint = false;
+    let mut lint_config = LintConfig::default();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            // everything after the script belongs to it
+            _ if file_name.is_some() => script_args.push(arg),
+            "--plugin" => match args.next() {
+                Some(path) => load_plugin(&mut interpreter, &path),
+                None => exit_with("--plugin expects a path to a shared library"),
+            },
+            "--ffi" => allow_ffi(&mut interpreter),
+            "--fmt" => format = true,
+            "--ast" => ast = true,
+            arg if arg.starts_with("--emit=") => emit = Some(arg["--emit=".len()..].to_owned()),
+            arg if arg.starts_with("--max-errors=") => match arg["--max-errors=".len()..].parse() {
+                Ok(max) => diagnostics.max_errors = Some(max),
+                Err(_) => exit_with("--max-errors expects a number"),
+            },
+            "--no-dedup" => diagnostics.dedup = false,
+            "--strict" => interpreter.set_strict(true),
+            "--sandbox" => interpreter.set_capabilities(Capabilities {
+                stdout: true,
+                ..Capabilities::none()
+            }),
+            "--string-conversion" => interpreter.set_semantics(Semantics {
+                string_conversion: true,
+            }),
+            "--compat=jlox" => jlox = true,
+            "--reserve-keywords" => reserve_keywords = true,
+            arg if arg.starts_with("--compat=") => exit_with("--compat only supports jlox"),
+            arg if arg.starts_with("--chapter=") => {
+                match arg["--chapter=".len()..]
+                    .parse()
+                    .ok()
+                    .and_then(LanguageVersion::chapter)
+                {
+                    Some(version) => interpreter.set_features(version),
+                    None => exit_with("--chapter expects a chapter of the book from 8 to 13"),
+                }
+            }
+            arg if arg.starts_with("--seed=") => match arg["--seed=".len()..].parse() {
+                Ok(seed) => interpreter.set_random_seed(seed),
+                Err(_) => exit_with("--seed expects a whole number"),
+            },
+            "--debug" => post_mortem = true,
+            "--explain" => explain = true,
+            "--profile" => profile = true,
+            "--no-file-access" => file_access = false,
+            "--crash-report" => match args.next() {
+                Some(path) => crash_report = Some(path),
+                None => exit_with("--crash-report expects a path to write the report to"),
+            },
+            "watch" if file_name.is_none() && !watch && !debug && !test => watch = true,
+            "debug" if file_name.is_none() && !watch && !debug && !test => debug = true,
+            "test" if file_name.is_none() && !watch && !debug && !test => test = true,
+            "metrics" if file_name.is_none() && !watch && !debug && !test => metrics = true,
+            arg if metrics && arg.starts_with("--max-") => {
+                let (name, limit) = arg.split_at(arg.find('=').unwrap_or(arg.len()));
+                let limit = limit.get(1..).and_then(|limit| limit.parse().ok());
+                match name {
+                    "--max-function-lines" if limit.is_some() => thresholds.function_lines = limit,
+                    "--max-complexity" if limit.is_some() => thresholds.complexity = limit,
+                    "--max-nesting" if limit.is_some() => thresholds.nesting = limit,
+                    "--max-class-members" if limit.is_some() => thresholds.class_members = limit,
+                    _ => exit_with(&format!("{} expects a number, e.g. {}=10", name, name)),
+                }
+            }
+            "duplicates" if file_name.is_none() && !metrics && !watch && !debug && !test => {
+                duplicates = true
+            }
+            arg if duplicates && arg.starts_with("--min-size=") => {
+                match arg["--min-size=".len()..].parse() {
+                    Ok(size) => min_size = size,
+                    Err(_) => exit_with("--min-size expects a number of syntax tree nodes"),
+                }
+            }
+            "lint" if file_name.is_none() && !metrics && !watch && !debug && !test => lint = true,
+            arg if lint && arg.starts_with("--max-nesting=") => {
+                match arg["--max-nesting=".len()..].parse() {
+                    Ok(max) => lint_config.max_nesting = max,
+                    Err(_) => exit_with("--max-nesting expects a number"),
+                }
+            }
+            arg if lint && arg.starts_with("--") && arg.contains('=') => {
+                let (level, rule) = arg.split_at(arg.find('=').unwrap_or_default());
+                let severity = match level {
+                    "--allow" => None,
+                    "--warn" => Some(Severity::Warning),
+                    "--deny" => Some(Severity::Error),
+                    _ => exit_with(&format!("Unknown option {}", level)),
+                };
+                match LintRule::from_id(&rule[1..]) {
+                    Some(rule) => lint_config.set(rule, severity),
+                    None => exit_with(&format!("{} is not a lint rule", &rule[1..])),
+                }
+            }
+            "lsp" if file_name.is_none() => lsp = true,
+            "fix" if file_name.is_none() && !metrics && !watch && !debug && !test => fix = true,
+            "check-project" if file_name.is_none() && !metrics && !watch && !debug && !test => {
+                check_project = true
+            }
+            "grammar" if file_name.is_none() && grammar_format.is_none() => {
+                grammar_format = Some(String::from("ebnf"))
+            }
+            arg if arg.starts_with("--format=") && grammar_format.is_some() => {
+                grammar_format = Some(arg["--format=".len()..].to_owned())
+            }
+            _ => file_name = Some(arg),
+        }
+    }
+
+    interpreter.set_diagnostic_options(diagnostics);
+
+    if lsp {
+        let stdin = std::io::stdin();
+        if let Err(error) = run_language_server(stdin.lock(), std::io::stdout()) {
+            exit_with(&format!("Language server failed: {}", error));
+        }
+        return;
+    }
+
+    if let Some(grammar_format) = grammar_format {
+        match grammar_format.as_str() {
+            "ebnf" => print!("{}", to_ebnf(&grammar())),
+            "railroad-svg" => print!("{}", to_railroad_svg(&grammar())),
+            _ => exit_with("--format expects ebnf or railroad-svg"),
+        }
+        return;
+    }
+
+    if reserve_keywords {
+        interpreter.set_features(Features {
+            reserve_future_keywords: true,
+            ..interpreter.features()
+        });
+    }
+    if file_access {
+        interpreter.allow_file_access();
+    }
+    interpreter.set_args(script_args.clone());
+
     match file_name {
+        Some(file_name) if format => format_file(&file_name, &diagnostics),
+        Some(file_name) if ast => print_file_ast(&file_name, &diagnostics),
+        Some(file_name) if emit.is_some() => match emit.as_deref() {
+            Some("ast") => print_file_ast(&file_name, &diagnostics),
+            Some("ast-text") => dump_file_ast(&file_name, &diagnostics),
+            Some("scopes") => print_file_scopes(&file_name, false, &diagnostics),
+            Some("scopes-json") => print_file_scopes(&file_name, true, &diagnostics),
+            Some("diagnostics-json") => print_file_diagnostics(&file_name),
+            _ => exit_with("--emit expects ast, ast-text, scopes, scopes-json or diagnostics-json"),
+        },
+        Some(file_name) if jlox => process::exit(run_file_jlox(&mut interpreter, &file_name)),
+        Some(file_name) if duplicates => {
+            let mut paths = vec![file_name];
+            paths.extend(script_args);
+            if !print_duplicates(&paths, min_size, &diagnostics) {
+                process::exit(1);
+            }
+        }
+        Some(file_name) if check_project => {
+            let mut paths = vec![file_name];
+            paths.extend(script_args);
+            if !print_project_check(&paths) {
+                process::exit(1);
+            }
+        }
+        Some(file_name) if fix => {
+            if !fix_file(&file_name) {
+                process::exit(1);
+            }
+        }
+        Some(file_name) if lint => {
+            if !lint_file(&file_name, &lint_config, &diagnostics) {
+                process::exit(1);
+            }
+        }
+        Some(file_name) if metrics => {
+            if !metrics_file(&file_name, &thresholds, &diagnostics) {
+                process::exit(1);
+            }
+        }
+        Some(file_name) if watch => watch_file(&file_name, &diagnostics),
+        Some(file_name) if test => {
+            if !test_file(&mut interpreter, &file_name) {
+                process::exit(1);
+            }
+        }
+        Some(file_name) if debug => debug_file(&mut interpreter, &file_name, false),
+        Some(file_name) if explain => explain_file(&mut interpreter, &file_name),
+        Some(file_name) if profile => profile_file(&mut interpreter, &file_name),
+        Some(file_name) if post_mortem => debug_file(&mut interpreter, &file_name, true),
         Some(file_name) => {
             println!("Opening file...");
-            run_file(file_name);
+            match &crash_report {
+                Some(report) => {
+                    run_file_reporting_crashes(&mut interpreter, &file_name, report, &options)
+                }
+                None => run_file(&mut interpreter, &file_name),
+            }
         }
-        _ => run_prompt(),
+        _ => run_prompt(&diagnostics),
+    }
+}
+
+#[cfg(feature = "plugins")]
+fn load_plugin(interpreter: &mut Interpreter, path: &str) {
+    if let Err(e) = interpreter::load_plugin(interpreter, path) {
+        exit_with(&format!("Can't load plugin {}: {}", path, e));
     }
 }
+
+#[cfg(not(feature = "plugins"))]
+fn load_plugin(_interpreter: &mut Interpreter, _path: &str) {
+    exit_with("This build doesn't support plugins, rebuild it with --features plugins");
+}
+
+#[cfg(feature = "ffi")]
+fn allow_ffi(interpreter: &mut Interpreter) {
+    interpreter.allow_ffi();
+}
+
+#[cfg(not(feature = "ffi"))]
+fn allow_ffi(_interpreter: &mut Interpreter) {
+    exit_with("This build can't call C libraries, rebuild it with --features ffi");
+}
+
+fn exit_with(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(1);
+}
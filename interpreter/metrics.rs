@@ -0,0 +1,393 @@
+use crate::error::Error;
+use crate::expr::Expr;
+use crate::lexer::Lexer;
+use crate::parser::Parser;
+use crate::statement::Stmt;
+use crate::token::{Token, TokenType};
+use crate::walk::{walk_expr, walk_stmt, walk_stmts, Walk};
+use std::fmt;
+use std::mem;
+
+/// Size and complexity of a function, method or closure
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionMetrics {
+    /// Methods are named `Class.method`
+    pub name: String,
+    pub line: usize,
+    /// From the line of the name to the line of the closing brace
+    pub lines: usize,
+    /// One plus every branch: `if`, loops, `and`, `or` and `catch` clauses.
+    /// Branches of nested functions count only for them.
+    pub complexity: usize,
+    /// Deepest nesting of `if`, loops and `try` inside the function
+    pub nesting: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClassMetrics {
+    pub name: String,
+    pub line: usize,
+    pub lines: usize,
+    pub methods: usize,
+    pub fields: usize,
+}
+
+/// Limits `Metrics::violations` checks, there's no limit on what's None
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MetricThresholds {
+    pub function_lines: Option<usize>,
+    pub complexity: Option<usize>,
+    pub nesting: Option<usize>,
+    /// Methods and fields together
+    pub class_members: Option<usize>,
+}
+
+/// Metric of a function or class that is over its limit
+#[derive(Debug, Clone, PartialEq)]
+pub struct Violation {
+    /// e.g. `function f` or `class A`
+    pub subject: String,
+    pub line: usize,
+    pub metric: &'static str,
+    pub value: usize,
+    pub limit: usize,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "[line {}] {}: {} is {}, over the limit of {}",
+            self.line, self.subject, self.metric, self.value, self.limit
+        )
+    }
+}
+
+/// Metrics of every function and class of a program, in the order they appear in the source
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Metrics {
+    pub functions: Vec<FunctionMetrics>,
+    pub classes: Vec<ClassMetrics>,
+}
+
+impl Metrics {
+    pub fn violations(&self, thresholds: &MetricThresholds) -> Vec<Violation> {
+        let mut violations = Vec::new();
+        let mut check = |subject: String, line, metric, value, limit: Option<usize>| {
+            if let Some(limit) = limit.filter(|limit| value > *limit) {
+                violations.push(Violation {
+                    subject,
+                    line,
+                    metric,
+                    value,
+                    limit,
+                });
+            }
+        };
+        for function in &self.functions {
+            let subject = || format!("function {}", function.name);
+            check(
+                subject(),
+                function.line,
+                "lines",
+                function.lines,
+                thresholds.function_lines,
+            );
+            check(
+                subject(),
+                function.line,
+                "complexity",
+                function.complexity,
+                thresholds.complexity,
+            );
+            check(
+                subject(),
+                function.line,
+                "nesting",
+                function.nesting,
+                thresholds.nesting,
+            );
+        }
+        for class in &self.classes {
+            let members = class.methods + class.fields;
+            check(
+                format!("class {}", class.name),
+                class.line,
+                "members",
+                members,
+                thresholds.class_members,
+            );
+        }
+        violations.sort_by_key(|violation| violation.line);
+        violations
+    }
+}
+
+impl fmt::Display for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for function in &self.functions {
+            writeln!(
+                f,
+                "[line {}] function {}: {} lines, complexity {}, nesting {}",
+                function.line, function.name, function.lines, function.complexity, function.nesting
+            )?;
+        }
+        for class in &self.classes {
+            writeln!(
+                f,
+                "[line {}] class {}: {} lines, {} methods, {} fields",
+                class.line, class.name, class.lines, class.methods, class.fields
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Measures the functions and classes of the program, which has to parse
+pub fn metrics(source_code: &str) -> Result<Metrics, Vec<Error>> {
+    let tokens = Lexer::new(source_code).scan_tokens()?;
+    let stmts = Parser::new(&tokens).parse_tokens()?;
+    let mut collector = Collector {
+        tokens: &tokens,
+        metrics: Metrics::default(),
+        counter: Counter::default(),
+    };
+    // top-level code isn't a function, its branches are counted nowhere
+    walk_stmts(&mut collector, &stmts);
+    let mut metrics = collector.metrics;
+    metrics.functions.sort_by_key(|function| function.line);
+    metrics.classes.sort_by_key(|class| class.line);
+    Ok(metrics)
+}
+
+/// Line of the brace closing the first block after the token
+pub(crate) fn closing_line(tokens: &[Token], from: &Token) -> usize {
+    let start = tokens
+        .iter()
+        .position(|token| token.line == from.line && token.start == from.start)
+        .unwrap_or(tokens.len());
+    let mut depth = 0;
+    for token in &tokens[start..] {
+        match token.token_type {
+            TokenType::OpenBrace => depth += 1,
+            TokenType::CloseBrace if depth == 1 => return token.line,
+            TokenType::CloseBrace => depth -= 1,
+            _ => (),
+        }
+    }
+    from.line
+}
+
+/// Branches and nesting of the function being walked
+#[derive(Default)]
+struct Counter {
+    complexity: usize,
+    depth: usize,
+    nesting: usize,
+}
+
+struct Collector<'t> {
+    tokens: &'t [Token],
+    metrics: Metrics,
+    counter: Counter,
+}
+
+impl Collector<'_> {
+    fn end_line(&self, from: &Token) -> usize {
+        closing_line(self.tokens, from)
+    }
+
+    fn function(&mut self, name: String, token: &Token, body: &[Stmt], end_line: usize) {
+        let counter = Counter {
+            complexity: 1,
+            ..Counter::default()
+        };
+        let outer = mem::replace(&mut self.counter, counter);
+        walk_stmts(self, body);
+        let counter = mem::replace(&mut self.counter, outer);
+        self.metrics.functions.push(FunctionMetrics {
+            name,
+            line: token.line,
+            lines: end_line + 1 - token.line,
+            complexity: counter.complexity,
+            nesting: counter.nesting,
+        });
+    }
+
+    /// Walks a branch or loop, the statements inside it are one level deeper
+    fn nested(&mut self, stmt: &Stmt, branches: usize) {
+        self.counter.complexity += branches;
+        self.counter.depth += 1;
+        self.counter.nesting = self.counter.nesting.max(self.counter.depth);
+        walk_stmt(self, stmt);
+        self.counter.depth -= 1;
+    }
+
+    fn class(&mut self, name: &str, token: &Token, members: &[Stmt], superclass: &Option<Expr>) {
+        if let Some(superclass) = superclass {
+            self.expr(superclass);
+        }
+        let mut methods = 0;
+        let mut fields = 0;
+        for member in members {
+            match member {
+                Stmt::Function {
+                    name: method,
+                    body,
+                    token,
+                    ..
+                } => {
+                    methods += 1;
+                    let end_line = self.end_line(token);
+                    self.function(format!("{}.{}", name, method), token, body, end_line);
+                }
+                member => {
+                    fields += 1;
+                    // field initializers don't belong to any function
+                    let outer = mem::take(&mut self.counter);
+                    self.stmt(member);
+                    self.counter = outer;
+                }
+            }
+        }
+        self.metrics.classes.push(ClassMetrics {
+            name: name.to_owned(),
+            line: token.line,
+            lines: self.end_line(token) + 1 - token.line,
+            methods,
+            fields,
+        });
+    }
+}
+
+impl<'a> Walk<'a> for Collector<'_> {
+    fn stmt(&mut self, stmt: &'a Stmt) {
+        match stmt {
+            Stmt::If { .. } | Stmt::While { .. } | Stmt::DoWhile { .. } | Stmt::ForIn { .. } => {
+                self.nested(stmt, 1)
+            }
+            Stmt::Try { catches, .. } => self.nested(stmt, catches.len()),
+            Stmt::Match { arms, .. } => self.nested(stmt, arms.len()),
+            Stmt::Function {
+                name, body, token, ..
+            } => self.function(name.clone(), token, body, self.end_line(token)),
+            Stmt::Class {
+                name,
+                token,
+                members,
+                superclass,
+            } => self.class(name, token, members, superclass),
+            _ => walk_stmt(self, stmt),
+        }
+    }
+
+    fn expr(&mut self, expr: &'a Expr) {
+        match expr {
+            Expr::Logical { .. } => {
+                self.counter.complexity += 1;
+                walk_expr(self, expr);
+            }
+            Expr::Closure {
+                body, name, token, ..
+            } => {
+                let end_line = match body.as_slice() {
+                    [Stmt::Return {
+                        value: Some(value), ..
+                    }] => value.span().line,
+                    _ => self.end_line(token),
+                };
+                self.function(name.clone(), token, body, end_line);
+            }
+            _ => walk_expr(self, expr),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{metrics, MetricThresholds};
+
+    const PROGRAM: &str = "fn small() { return 1; }
+
+fn branches(a, b) {
+    if (a and b) {
+        while (a) {
+            if (b or a) a = false;
+        }
+    } else {
+        try {
+            throw 1;
+        } catch (e) {}
+    }
+    var f = |x| => x and a;
+    return f;
+}
+
+class A {
+    var x;
+    var y = 1;
+    get() {
+        return this.x;
+    }
+    set(x) { this.x = x; }
+}";
+
+    #[test]
+    fn measures_functions_and_classes() {
+        let metrics = metrics(PROGRAM).unwrap();
+        let measured: Vec<_> = metrics
+            .functions
+            .iter()
+            .map(|f| (f.name.as_str(), f.line, f.lines, f.complexity, f.nesting))
+            .collect();
+        assert_eq!(
+            measured,
+            vec![
+                ("small", 1, 1, 1, 0),
+                ("branches", 3, 13, 7, 3),
+                ("closure", 13, 1, 2, 0),
+                ("A.get", 20, 3, 1, 0),
+                ("A.set", 23, 1, 1, 0),
+            ]
+        );
+        let class = &metrics.classes[0];
+        assert_eq!(
+            (
+                class.name.as_str(),
+                class.line,
+                class.lines,
+                class.methods,
+                class.fields
+            ),
+            ("A", 17, 8, 2, 2)
+        );
+        assert!(metrics
+            .to_string()
+            .contains("[line 3] function branches: 13 lines, complexity 7, nesting 3\n"));
+    }
+
+    #[test]
+    fn reports_metrics_over_the_thresholds() {
+        let metrics = metrics(PROGRAM).unwrap();
+        assert!(metrics.violations(&MetricThresholds::default()).is_empty());
+        let violations: Vec<String> = metrics
+            .violations(&MetricThresholds {
+                function_lines: Some(10),
+                complexity: Some(5),
+                nesting: Some(3),
+                class_members: Some(3),
+            })
+            .iter()
+            .map(|violation| violation.to_string())
+            .collect();
+        assert_eq!(
+            violations,
+            vec![
+                "[line 3] function branches: lines is 13, over the limit of 10",
+                "[line 3] function branches: complexity is 7, over the limit of 5",
+                "[line 17] class A: members is 4, over the limit of 3",
+            ]
+        );
+        assert!(super::metrics("fn (").is_err());
+    }
+}
@@ -0,0 +1,1057 @@
+use crate::environment::Environment;
+use crate::error::ErrorType;
+use crate::function::Function;
+use crate::interpreter::Interpreter;
+use crate::limits::Capabilities;
+use crate::prototypes::char_at;
+use crate::runtime_value::{format_number, Value};
+use std::cell::{Cell, RefCell};
+use std::cmp::Ordering;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::rc::Rc;
+
+/// Natives only report what went wrong, the interpreter attaches the location of the call
+pub type NativeResult = Result<Value, ErrorType>;
+/// Bodies are closures, so hosts can register natives that capture their own state.
+/// They get the interpreter to call back into, e.g. with the functions passed to them.
+pub type NativeBody = Rc<dyn Fn(&mut Interpreter, &[Value]) -> NativeResult>;
+
+pub fn define_native(
+    env: &mut Environment,
+    name: &str,
+    arity: usize,
+    body: impl Fn(&[Value]) -> NativeResult + 'static,
+) {
+    define_higher_order(env, name, arity, move |_, args| body(args));
+}
+
+/// Like `define_native`, but the last `optional` of the `arity` parameters can be left out, they're null then
+pub fn define_native_with_optional(
+    env: &mut Environment,
+    name: &str,
+    arity: usize,
+    optional: usize,
+    body: impl Fn(&[Value]) -> NativeResult + 'static,
+) {
+    let body = move |_: &mut Interpreter, args: &[Value]| {
+        let mut padded = args.to_vec();
+        padded.resize(arity, Value::Null);
+        body(&padded)
+    };
+    define(env, name, arity, optional, Rc::new(body));
+}
+
+/// Like `define_native`, for natives that touch the host. They fail unless the interpreter's capabilities allow it.
+pub(crate) fn define_host_native(
+    env: &mut Environment,
+    name: &str,
+    arity: usize,
+    allowed: fn(&Capabilities) -> bool,
+    body: impl Fn(&[Value]) -> NativeResult + 'static,
+) {
+    let native = name.to_owned();
+    define_higher_order(env, name, arity, move |interpreter, args| {
+        if !allowed(&interpreter.capabilities()) {
+            return Err(ErrorType::CapabilityDenied(native.clone()));
+        }
+        body(args)
+    });
+}
+
+/// Defines a native that calls back into the interpreter, see `Interpreter::call`
+pub fn define_higher_order(
+    env: &mut Environment,
+    name: &str,
+    arity: usize,
+    body: impl Fn(&mut Interpreter, &[Value]) -> NativeResult + 'static,
+) {
+    define(env, name, arity, 0, Rc::new(body));
+}
+
+fn define(env: &mut Environment, name: &str, arity: usize, optional: usize, body: NativeBody) {
+    let function = Value::Function(Function::Native {
+        name: name.to_owned(),
+        arity,
+        optional,
+        variadic: false,
+        body,
+    });
+    env.bind(name, &function);
+}
+
+/// Defines all the built-in functions in the given environment
+pub fn define_natives(env: &mut Environment) {
+    define_host_native(env, "clock", 0, |allowed| allowed.time, clock);
+    #[cfg(not(all(target_arch = "wasm32", not(target_os = "wasi"))))]
+    define_host_native(env, "sleep", 1, |allowed| allowed.time, sleep);
+    define_native(env, "formatDuration", 1, format_duration);
+    #[cfg(not(all(target_arch = "wasm32", not(target_os = "wasi"))))]
+    define_line_reader(env, read_stdin_line);
+    define_args(env, Vec::new());
+    define_native(env, "len", 1, len);
+    define_higher_order(env, "format", 2, format);
+    define_native(env, "type", 1, type_of);
+    define_native(env, "instanceof", 2, instance_of);
+    define_native(env, "callable", 1, callable);
+    define_higher_order(env, "str", 1, |interpreter, args| {
+        let text = interpreter.stringify(&args[0]).map_err(|e| e.error_type)?;
+        Ok(Value::String(text))
+    });
+    define_native(env, "num", 1, num);
+    define_native(env, "bool", 1, |args| Ok(Value::Boolean(args[0].to_bool())));
+    define_higher_order(env, "require", 1, |interpreter, args| match &args[0] {
+        Value::String(name) => interpreter.require(name),
+        _ => Err(ErrorType::WrongType),
+    });
+    define_native(env, "memoize", 1, |args| match &args[0] {
+        Value::Function(function) => Ok(Value::Function(function.clone().memoize())),
+        _ => Err(ErrorType::WrongType),
+    });
+    define_math(env);
+    define_dates(env);
+    define_lists(env);
+    define_strings(env);
+    define_assertions(env);
+}
+
+/// Natives scripts can read and write files with, hosts opt into them with `Interpreter::allow_file_access`
+#[cfg(not(all(target_arch = "wasm32", not(target_os = "wasi"))))]
+pub(crate) fn define_file_natives(env: &mut Environment) {
+    define_host_native(env, "readFile", 1, |allowed| allowed.fs_read, read_file);
+    define_host_native(env, "writeFile", 2, |allowed| allowed.fs_write, write_file);
+    define_host_native(
+        env,
+        "appendFile",
+        2,
+        |allowed| allowed.fs_write,
+        append_file,
+    );
+    define_host_native(env, "fileExists", 1, |allowed| allowed.fs_read, file_exists);
+}
+
+/// The same natives for the `fs` capability object, holding the object is what allows calling them
+#[cfg(not(all(target_arch = "wasm32", not(target_os = "wasi"))))]
+pub(crate) fn define_fs_capability(env: &mut Environment) {
+    define_native(env, "readFile", 1, read_file);
+    define_native(env, "writeFile", 2, write_file);
+    define_native(env, "appendFile", 2, append_file);
+    define_native(env, "fileExists", 1, file_exists);
+}
+
+#[cfg(not(all(target_arch = "wasm32", not(target_os = "wasi"))))]
+fn read_file(args: &[Value]) -> NativeResult {
+    std::fs::read_to_string(string_arg(&args[0])?)
+        .map(Value::String)
+        .map_err(io_error)
+}
+
+#[cfg(not(all(target_arch = "wasm32", not(target_os = "wasi"))))]
+fn write_file(args: &[Value]) -> NativeResult {
+    std::fs::write(string_arg(&args[0])?, string_arg(&args[1])?)
+        .map(|_| Value::Null)
+        .map_err(io_error)
+}
+
+#[cfg(not(all(target_arch = "wasm32", not(target_os = "wasi"))))]
+fn append_file(args: &[Value]) -> NativeResult {
+    use std::io::Write;
+    let contents = string_arg(&args[1])?;
+    std::fs::OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(string_arg(&args[0])?)
+        .and_then(|mut file| file.write_all(contents.as_bytes()))
+        .map(|_| Value::Null)
+        .map_err(io_error)
+}
+
+#[cfg(not(all(target_arch = "wasm32", not(target_os = "wasi"))))]
+fn file_exists(args: &[Value]) -> NativeResult {
+    Ok(Value::Boolean(
+        std::path::Path::new(string_arg(&args[0])?).is_file(),
+    ))
+}
+
+#[cfg(not(all(target_arch = "wasm32", not(target_os = "wasi"))))]
+fn string_arg(value: &Value) -> Result<&str, ErrorType> {
+    value
+        .as_string()
+        .map(String::as_str)
+        .ok_or(ErrorType::WrongType)
+}
+
+#[cfg(not(all(target_arch = "wasm32", not(target_os = "wasi"))))]
+fn io_error(e: std::io::Error) -> ErrorType {
+    ErrorType::Io(e.to_string())
+}
+
+/// `readLine()` returns the next line without its line break, or null once there are no more
+pub(crate) fn define_line_reader(
+    env: &mut Environment,
+    reader: impl FnMut() -> Option<String> + 'static,
+) {
+    let reader = RefCell::new(reader);
+    define_host_native(
+        env,
+        "readLine",
+        0,
+        |allowed| allowed.env,
+        move |_args| Ok((reader.borrow_mut())().map_or(Value::Null, Value::String)),
+    );
+}
+
+#[cfg(not(all(target_arch = "wasm32", not(target_os = "wasi"))))]
+fn read_stdin_line() -> Option<String> {
+    let mut line = String::new();
+    match std::io::stdin().read_line(&mut line) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => {
+            let len = line.trim_end_matches(&['\r', '\n'][..]).len();
+            line.truncate(len);
+            Some(line)
+        }
+    }
+}
+
+/// `argCount()` and `arg(index)`, index out of range gives null
+pub(crate) fn define_args(env: &mut Environment, args: Vec<String>) {
+    let count = args.len();
+    define_host_native(
+        env,
+        "argCount",
+        0,
+        |allowed| allowed.env,
+        move |_args| Ok(Value::Number(count as f64)),
+    );
+    define_host_native(
+        env,
+        "arg",
+        1,
+        |allowed| allowed.env,
+        move |index| match &index[0] {
+            Value::Number(i) if i.fract() == 0.0 => Ok(args
+                .get(i.max(0.0) as usize)
+                .filter(|_| *i >= 0.0)
+                .map_or(Value::Null, |arg| Value::String(arg.clone()))),
+            _ => Err(ErrorType::WrongType),
+        },
+    );
+}
+
+/// Number functions and constants, random numbers come from a generator seeded once per environment
+/// unless the host or the script picks the seed
+fn define_math(env: &mut Environment) {
+    define_native(env, "abs", 1, |args| number_op(args, f64::abs));
+    define_native(env, "floor", 1, |args| number_op(args, f64::floor));
+    define_native(env, "ceil", 1, |args| number_op(args, f64::ceil));
+    define_native(env, "round", 1, |args| number_op(args, f64::round));
+    define_native(env, "sqrt", 1, |args| number_op(args, f64::sqrt));
+    define_native(env, "pow", 2, |args| numbers_op(args, f64::powf));
+    define_native(env, "min", 2, |args| numbers_op(args, f64::min));
+    define_native(env, "max", 2, |args| numbers_op(args, f64::max));
+    define_random(env, RandomState::new().build_hasher().finish());
+    env.define_read_only("PI", &Value::Number(std::f64::consts::PI));
+    env.define_read_only("E", &Value::Number(std::f64::consts::E));
+}
+
+fn number_op(args: &[Value], op: fn(f64) -> f64) -> NativeResult {
+    match &args[0] {
+        Value::Number(n) => Ok(Value::Number(op(*n))),
+        _ => Err(ErrorType::WrongType),
+    }
+}
+
+fn numbers_op(args: &[Value], op: fn(f64, f64) -> f64) -> NativeResult {
+    match (&args[0], &args[1]) {
+        (Value::Number(a), Value::Number(b)) => Ok(Value::Number(op(*a, *b))),
+        _ => Err(ErrorType::WrongType),
+    }
+}
+
+/// `random()` in [0, 1), `randomInt(a, b)` from a to b inclusive and `setSeed(n)` restarting both,
+/// the same seed gives the same numbers on every target
+pub(crate) fn define_random(env: &mut Environment, seed: u64) {
+    let state = Rc::new(Cell::new(seeded(seed)));
+    let random = Rc::clone(&state);
+    define_native(env, "random", 0, move |_args| {
+        Ok(Value::Number(next_random(&random)))
+    });
+    let random_int = Rc::clone(&state);
+    define_native(env, "randomInt", 2, move |args| {
+        match (integer_arg(&args[0])?, integer_arg(&args[1])?) {
+            (a, b) if a > b => Err(ErrorType::EmptyRange),
+            (a, b) => {
+                let offset = (next_random(&random_int) * (b - a + 1.0)).floor();
+                Ok(Value::Number(a + offset))
+            }
+        }
+    });
+    define_native(env, "setSeed", 1, move |args| {
+        state.set(seeded(integer_arg(&args[0])? as i64 as u64));
+        Ok(Value::Null)
+    });
+}
+
+fn integer_arg(value: &Value) -> Result<f64, ErrorType> {
+    match value {
+        Value::Number(n) if n.fract() == 0.0 => Ok(*n),
+        _ => Err(ErrorType::WrongType),
+    }
+}
+
+/// Spreads the seed's bits with SplitMix64, so close seeds give unrelated sequences. Xorshift never leaves 0.
+fn seeded(seed: u64) -> u64 {
+    let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    (z ^ (z >> 31)).max(1)
+}
+
+/// Xorshift, in [0, 1)
+fn next_random(state: &Cell<u64>) -> f64 {
+    let mut x = state.get();
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    state.set(x);
+    (x >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Seconds since the Unix epoch
+#[cfg(not(all(target_arch = "wasm32", not(target_os = "wasi"))))]
+fn clock(_args: &[Value]) -> NativeResult {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    Ok(Value::Number(now.as_secs_f64()))
+}
+
+#[cfg(all(target_arch = "wasm32", not(target_os = "wasi")))]
+#[wasm_bindgen::prelude::wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(js_namespace = performance)]
+    fn now() -> f64;
+    #[wasm_bindgen(js_namespace = Date, js_name = now)]
+    fn date_now() -> f64;
+}
+
+/// Browsers have no system clock in wasm, seconds since the page loaded are just as good for measuring
+#[cfg(all(target_arch = "wasm32", not(target_os = "wasi")))]
+fn clock(_args: &[Value]) -> NativeResult {
+    Ok(Value::Number(now() / 1000.0))
+}
+
+/// `now()`, and the calendar date and time of a timestamp from it in UTC, there's no time zone data.
+/// Timestamps are seconds since the Unix epoch, like `clock()` gives outside the browser.
+fn define_dates(env: &mut Environment) {
+    define_host_native(
+        env,
+        "now",
+        0,
+        |allowed| allowed.time,
+        |_args| Ok(Value::Number(unix_time())),
+    );
+    define_native(env, "dateFormat", 2, date_format);
+    define_native(env, "year", 1, |args| {
+        date_part(args, |date| date.year as f64)
+    });
+    define_native(env, "month", 1, |args| {
+        date_part(args, |date| date.month as f64)
+    });
+    define_native(env, "day", 1, |args| {
+        date_part(args, |date| date.day as f64)
+    });
+    define_native(env, "hour", 1, |args| {
+        date_part(args, |date| date.hour as f64)
+    });
+    define_native(env, "minute", 1, |args| {
+        date_part(args, |date| date.minute as f64)
+    });
+    define_native(env, "second", 1, |args| {
+        date_part(args, |date| date.second as f64)
+    });
+}
+
+#[cfg(not(all(target_arch = "wasm32", not(target_os = "wasi"))))]
+fn unix_time() -> f64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
+
+#[cfg(all(target_arch = "wasm32", not(target_os = "wasi")))]
+fn unix_time() -> f64 {
+    date_now() / 1000.0
+}
+
+/// UTC calendar date and time, months and days count from 1
+struct DateTime {
+    year: i64,
+    month: i64,
+    day: i64,
+    hour: i64,
+    minute: i64,
+    second: i64,
+}
+
+impl DateTime {
+    /// Fractions of a second are dropped
+    fn from_timestamp(value: &Value) -> Result<DateTime, ErrorType> {
+        let seconds = match value {
+            Value::Number(n) if n.is_finite() => n.floor() as i64,
+            _ => return Err(ErrorType::WrongType),
+        };
+        // days to the civil date, from Howard Hinnant's date algorithms
+        let days = seconds.div_euclid(86400) + 719_468;
+        let era = days.div_euclid(146_097);
+        let day_of_era = days.rem_euclid(146_097);
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let shifted_month = (5 * day_of_year + 2) / 153;
+        let month = if shifted_month < 10 {
+            shifted_month + 3
+        } else {
+            shifted_month - 9
+        };
+        let time = seconds.rem_euclid(86400);
+        Ok(DateTime {
+            year: year_of_era + era * 400 + (month <= 2) as i64,
+            month,
+            day: day_of_year - (153 * shifted_month + 2) / 5 + 1,
+            hour: time / 3600,
+            minute: time % 3600 / 60,
+            second: time % 60,
+        })
+    }
+}
+
+fn date_part(args: &[Value], part: fn(&DateTime) -> f64) -> NativeResult {
+    DateTime::from_timestamp(&args[0]).map(|date| Value::Number(part(&date)))
+}
+
+/// Replaces `%Y`, `%m`, `%d`, `%H`, `%M` and `%S` with the parts of the date, `%%` with `%`
+fn date_format(args: &[Value]) -> NativeResult {
+    let date = DateTime::from_timestamp(&args[0])?;
+    let format = args[1].as_string().ok_or(ErrorType::WrongType)?;
+    let mut formatted = String::new();
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            formatted.push(c);
+            continue;
+        }
+        let part = match chars.next() {
+            Some('Y') => format!("{:04}", date.year),
+            Some('m') => format!("{:02}", date.month),
+            Some('d') => format!("{:02}", date.day),
+            Some('H') => format!("{:02}", date.hour),
+            Some('M') => format!("{:02}", date.minute),
+            Some('S') => format!("{:02}", date.second),
+            Some('%') => String::from("%"),
+            _ => return Err(ErrorType::InvalidFormatSpec),
+        };
+        formatted.push_str(&part);
+    }
+    Ok(Value::String(formatted))
+}
+
+/// Blocks the script for the number of milliseconds, browsers can't block so it's missing there
+#[cfg(not(all(target_arch = "wasm32", not(target_os = "wasi"))))]
+fn sleep(args: &[Value]) -> NativeResult {
+    match &args[0] {
+        Value::Number(ms) if *ms >= 0.0 => {
+            std::thread::sleep(std::time::Duration::from_secs_f64(ms / 1000.0));
+            Ok(Value::Null)
+        }
+        _ => Err(ErrorType::WrongType),
+    }
+}
+
+/// Seconds as text in the largest unit that keeps them above one, e.g. `1.5 s` or `250 ms`
+fn format_duration(args: &[Value]) -> NativeResult {
+    let seconds = match &args[0] {
+        Value::Number(seconds) => *seconds,
+        _ => return Err(ErrorType::WrongType),
+    };
+    let (amount, unit) = match seconds.abs() {
+        s if s >= 60.0 => (seconds / 60.0, "min"),
+        s if s >= 1.0 || s == 0.0 => (seconds, "s"),
+        s if s >= 1e-3 => (seconds * 1e3, "ms"),
+        s if s >= 1e-6 => (seconds * 1e6, "µs"),
+        _ => (seconds * 1e9, "ns"),
+    };
+    let rounded = (amount * 100.0).round() / 100.0;
+    Ok(Value::String(format!(
+        "{} {}",
+        format_number(rounded),
+        unit
+    )))
+}
+
+/// Length of the string in characters, or of the list
+fn len(args: &[Value]) -> NativeResult {
+    match &args[0] {
+        Value::String(s) => Ok(Value::Number(s.chars().count() as f64)),
+        Value::List(list) => Ok(Value::Number(list.borrow().len() as f64)),
+        _ => Err(ErrorType::WrongType),
+    }
+}
+
+fn type_of(args: &[Value]) -> NativeResult {
+    Ok(Value::String(args[0].type_name().to_owned()))
+}
+
+/// Whether the value is an instance of the class or of any class inheriting from it
+fn instance_of(args: &[Value]) -> NativeResult {
+    match (&args[0], &args[1]) {
+        (Value::Instance(instance), Value::Class(class)) => Ok(Value::Boolean(
+            instance.borrow().class().is_subclass_of(class),
+        )),
+        (_, Value::Class(_)) => Ok(Value::Boolean(false)),
+        _ => Err(ErrorType::WrongType),
+    }
+}
+
+fn callable(args: &[Value]) -> NativeResult {
+    Ok(Value::Boolean(matches!(
+        args[0],
+        Value::Function(_) | Value::Class(_)
+    )))
+}
+
+/// Number written in the string, surrounding whitespace aside, or null when it isn't one
+fn num(args: &[Value]) -> NativeResult {
+    let number = match &args[0] {
+        Value::Number(n) => Some(*n),
+        Value::String(s) => s.trim().parse::<f64>().ok().filter(|n| n.is_finite()),
+        _ => None,
+    };
+    Ok(number.map_or(Value::Null, Value::Number))
+}
+
+/// `assert(condition, message?)` and `assertEquals(actual, expected)` raise a catchable `AssertionError`
+fn define_assertions(env: &mut Environment) {
+    define_native_with_optional(env, "assert", 2, 1, |args| {
+        if args[0].to_bool() {
+            return Ok(Value::Null);
+        }
+        Err(ErrorType::AssertionFailed(match &args[1] {
+            Value::Null => format!("expected a truthy value, got {}", args[0].repr()),
+            message => message.to_string(),
+        }))
+    });
+    define_native(env, "assertEquals", 2, |args| {
+        if args[0] == args[1] {
+            return Ok(Value::Null);
+        }
+        Err(ErrorType::AssertionFailed(format!(
+            "expected {} but got {}",
+            args[1].repr(),
+            args[0].repr()
+        )))
+    });
+}
+
+/// Strings are indexed by characters, the same ones `s[i]` and `for (var c in s)` give
+fn define_strings(env: &mut Environment) {
+    define_native(env, "chars", 1, |args| {
+        let s = args[0].as_string().ok_or(ErrorType::WrongType)?;
+        Ok(new_list(
+            s.chars().map(|c| Value::String(c.to_string())).collect(),
+        ))
+    });
+    define_native(env, "codePointAt", 2, |args| {
+        let s = args[0].as_string().ok_or(ErrorType::WrongType)?;
+        Ok(Value::Number(char_at(s, &args[1])? as u32 as f64))
+    });
+    define_native(env, "fromCodePoint", 1, |args| match args[0] {
+        Value::Number(n) if n.fract() == 0.0 && (0.0..=u32::MAX as f64).contains(&n) => {
+            char::from_u32(n as u32)
+                .map(|c| Value::String(c.to_string()))
+                .ok_or(ErrorType::InvalidCodePoint)
+        }
+        Value::Number(_) => Err(ErrorType::InvalidCodePoint),
+        _ => Err(ErrorType::WrongType),
+    });
+}
+
+/// `list()` creates an empty list, the rest take the functions they apply as their last arguments
+/// and return new lists rather than changing the ones they're given
+fn define_lists(env: &mut Environment) {
+    define_native(env, "list", 0, |_args| {
+        Ok(Value::List(Rc::new(RefCell::new(Vec::new()))))
+    });
+    define_higher_order(env, "sort", 2, sort);
+    define_higher_order(env, "map", 2, |interpreter, args| {
+        let function = function_arg(&args[1])?;
+        let mut mapped = Vec::new();
+        for item in list_arg(&args[0])? {
+            mapped.push(call(interpreter, function, &[item])?);
+        }
+        Ok(new_list(mapped))
+    });
+    define_higher_order(env, "filter", 2, |interpreter, args| {
+        let function = function_arg(&args[1])?;
+        let mut kept = Vec::new();
+        for item in list_arg(&args[0])? {
+            if call(interpreter, function, std::slice::from_ref(&item))?.to_bool() {
+                kept.push(item);
+            }
+        }
+        Ok(new_list(kept))
+    });
+    define_higher_order(env, "reduce", 3, |interpreter, args| {
+        let function = function_arg(&args[1])?;
+        let mut accumulator = args[2].clone();
+        for item in list_arg(&args[0])? {
+            accumulator = call(interpreter, function, &[accumulator, item])?;
+        }
+        Ok(accumulator)
+    });
+}
+
+/// Functions are checked before they're needed, so wrong ones are caught even for empty lists
+fn function_arg(value: &Value) -> Result<&Value, ErrorType> {
+    match value {
+        Value::Function(_) | Value::Class(_) => Ok(value),
+        _ => Err(ErrorType::WrongType),
+    }
+}
+
+/// Copy of the items, so callbacks can change the list while it's being walked
+fn list_arg(value: &Value) -> Result<Vec<Value>, ErrorType> {
+    match value {
+        Value::List(list) => Ok(list.borrow().clone()),
+        _ => Err(ErrorType::WrongType),
+    }
+}
+
+fn new_list(items: Vec<Value>) -> Value {
+    Value::List(Rc::new(RefCell::new(items)))
+}
+
+/// Errors raised inside the callback are reported at the call of the native
+fn call(interpreter: &mut Interpreter, callee: &Value, args: &[Value]) -> NativeResult {
+    interpreter.call(callee, args).map_err(|e| e.error_type)
+}
+
+/// `sort(list, comparator)`, the comparator returns a negative number when its first argument goes first,
+/// a positive one when its second does and zero when they're equal. Without one (null) numbers and strings
+/// are sorted in ascending order, lists mixing them can't be sorted.
+fn sort(interpreter: &mut Interpreter, args: &[Value]) -> NativeResult {
+    let mut items = list_arg(&args[0])?;
+    let comparator = match &args[1] {
+        Value::Null => None,
+        comparator => Some(function_arg(comparator)?),
+    };
+    // sort_by can't be stopped, the first error is kept and the remaining comparisons are skipped
+    let mut failure = None;
+    items.sort_by(|a, b| {
+        if failure.is_some() {
+            return Ordering::Equal;
+        }
+        let order = match comparator {
+            None => natural_order(a, b),
+            Some(comparator) => match call(interpreter, comparator, &[a.clone(), b.clone()]) {
+                Ok(Value::Number(n)) => Ok(n.partial_cmp(&0.0).unwrap_or(Ordering::Equal)),
+                Ok(_) => Err(ErrorType::WrongType),
+                Err(e) => Err(e),
+            },
+        };
+        order.unwrap_or_else(|e| {
+            failure = Some(e);
+            Ordering::Equal
+        })
+    });
+    match failure {
+        Some(e) => Err(e),
+        None => Ok(new_list(items)),
+    }
+}
+
+fn natural_order(a: &Value, b: &Value) -> Result<Ordering, ErrorType> {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => Ok(a.partial_cmp(b).unwrap_or(Ordering::Equal)),
+        (Value::String(a), Value::String(b)) => Ok(a.cmp(b)),
+        _ => Err(ErrorType::WrongType),
+    }
+}
+
+/// Widest width and precision `format` accepts. Rust's float formatting counts digits in a u16
+/// and panics near u16::MAX of them, this stays well below that.
+const MAX_FORMAT_SIZE: usize = 10_000;
+
+/// Longest text of a number before padding, aside from its precision: the digits of the largest
+/// finite double, its sign and point, or an exponent
+const MAX_NUMBER_DIGITS: usize = 330;
+
+/// Parsed `[<|>][width][.precision][f|e|x]` specification used by `format`
+struct FormatSpec {
+    left_align: Option<bool>,
+    width: usize,
+    precision: Option<usize>,
+    kind: Option<char>,
+}
+
+impl FormatSpec {
+    fn parse(spec: &str) -> Option<FormatSpec> {
+        let mut chars = spec.chars().peekable();
+        let left_align = match chars.peek() {
+            Some('<') => Some(true),
+            Some('>') => Some(false),
+            _ => None,
+        };
+        if left_align.is_some() {
+            chars.next();
+        }
+
+        let read_digits = |chars: &mut std::iter::Peekable<std::str::Chars>| {
+            let mut digits = String::new();
+            while let Some(c) = chars.peek().filter(|c| c.is_ascii_digit()) {
+                digits.push(*c);
+                chars.next();
+            }
+            digits
+        };
+        let size = |digits: String| {
+            digits
+                .parse::<usize>()
+                .ok()
+                .filter(|size| *size <= MAX_FORMAT_SIZE)
+        };
+        let width = match read_digits(&mut chars) {
+            digits if digits.is_empty() => 0,
+            digits => size(digits)?,
+        };
+        let precision = if chars.peek() == Some(&'.') {
+            chars.next();
+            Some(size(read_digits(&mut chars))?)
+        } else {
+            None
+        };
+        let kind = chars.next();
+        if chars.next().is_some() || kind.is_some_and(|kind| !"fex".contains(kind)) {
+            return None;
+        }
+
+        Some(FormatSpec {
+            left_align,
+            width,
+            precision,
+            kind,
+        })
+    }
+}
+
+/// Formats the value according to the spec, e.g. `format(pi, ".2")` or `format(name, "<10")`.
+/// Numbers are aligned to the right and everything else to the left by default.
+fn format(interpreter: &mut Interpreter, args: &[Value]) -> NativeResult {
+    let spec = match &args[1] {
+        Value::String(spec) => FormatSpec::parse(spec).ok_or(ErrorType::InvalidFormatSpec)?,
+        _ => return Err(ErrorType::WrongType),
+    };
+    let longest = match &args[0] {
+        Value::Number(_) => MAX_NUMBER_DIGITS + spec.precision.unwrap_or(6),
+        value => value.to_string().len(),
+    };
+    interpreter.check_string(longest.max(spec.width))?;
+
+    let text = match (&args[0], spec.kind) {
+        (Value::Number(n), _) if !n.is_finite() => format_number(*n),
+        (Value::Number(n), Some('e')) => match spec.precision {
+            Some(precision) => format!("{:.*e}", precision, n),
+            None => format!("{:e}", n),
+        },
+        (Value::Number(n), Some('x')) if n.fract() == 0.0 => {
+            let sign = if *n < 0.0 { "-" } else { "" };
+            format!("{}{:x}", sign, n.abs() as u64)
+        }
+        (Value::Number(n), Some('f')) => format!("{:.*}", spec.precision.unwrap_or(6), n),
+        (Value::Number(n), None) => match spec.precision {
+            Some(precision) => format!("{:.*}", precision, n),
+            None => format_number(*n),
+        },
+        (value, None) => match spec.precision {
+            Some(precision) => value.to_string().chars().take(precision).collect(),
+            None => value.to_string(),
+        },
+        _ => return Err(ErrorType::WrongType),
+    };
+
+    let left_align = spec
+        .left_align
+        .unwrap_or_else(|| args[0].as_number().is_none());
+    let text = if left_align {
+        format!("{:<width$}", text, width = spec.width)
+    } else {
+        format!("{:>width$}", text, width = spec.width)
+    };
+    Ok(Value::String(text))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::ErrorType;
+    use crate::interpreter::tests::{
+        assert_error, assert_true, assert_true_in, evaluate, format_code,
+    };
+    use crate::interpreter::Interpreter;
+    use crate::limits::ResourceLimits;
+
+    #[test]
+    fn len_counts_characters() {
+        assert_eq!(evaluate("len(\"héllo\");").unwrap().as_number(), Some(&5.0));
+        assert_eq!(evaluate("len(\"\");").unwrap().as_number(), Some(&0.0));
+        assert_error("len(10);", ErrorType::WrongType);
+        assert_error("len(\"a\", \"b\");", ErrorType::InvalidNumberOfArguments);
+    }
+
+    #[test]
+    fn math_natives() {
+        assert_true("abs(-2) == 2 and floor(1.5) == 1 and ceil(1.5) == 2 and round(2.5) == 3;");
+        assert_true("sqrt(16) == 4 and pow(2, 10) == 1024;");
+        assert_true("min(1, 2) == 1 and max(1, 2) == 2;");
+        assert_true("PI > 3.14 and PI < 3.15 and E > 2.71 and E < 2.72;");
+        assert_true("var r = random(); r >= 0 and r < 1 and r != random();");
+        assert_error("sqrt(\"4\");", ErrorType::WrongType);
+        assert_error("max(1);", ErrorType::InvalidNumberOfArguments);
+        assert_error("PI = 3;", ErrorType::AssignmentToHostConstant);
+    }
+
+    #[test]
+    fn lists() {
+        assert_true("var l = list(); l.push(1); l.push(\"a\"); l.len() == 2 and len(l) == 2;");
+        assert_true("var l = list(); l.push(1); l.set(0, 2); l.get(0) == 2 and l.pop() == 2 and l.pop() == null;");
+        assert_true(
+            "var l = list(); var m = l; m.push(1); l.len() == 1 and l == m and l != list();",
+        );
+        let l = evaluate("var l = list(); l.push(1); l.push(\"a\"); l;").unwrap();
+        assert_eq!(l.to_string(), "[1, \"a\"]");
+        assert_error("list().get(0);", ErrorType::IndexOutOfRange);
+        assert_error(
+            "var l = list(); l.push(1); l.get(0.5);",
+            ErrorType::WrongType,
+        );
+    }
+
+    #[test]
+    fn higher_order_natives() {
+        let numbers = "var l = list(); l.push(3); l.push(1); l.push(2);";
+        assert_true(&format!(
+            "{} var s = sort(l, null); s.get(0) == 1 and s.get(2) == 3 and l.get(0) == 3;",
+            numbers
+        ));
+        assert_true(&format!(
+            "{} var s = sort(l, |a, b| => b - a); s.get(0) == 3 and s.get(2) == 1;",
+            numbers
+        ));
+        assert_true("var l = list(); l.push(\"b\"); l.push(\"a\"); sort(l, null).get(0) == \"a\";");
+        assert_true(&format!(
+            "{} var m = map(l, |x| => x * 2); m.get(0) == 6 and m.len() == 3;",
+            numbers
+        ));
+        assert_true(&format!(
+            "{} var f = filter(l, |x| => x > 1); f.len() == 2 and f.get(1) == 2;",
+            numbers
+        ));
+        assert_true(&format!(
+            "{} reduce(l, |sum, x| => sum + x, 0) == 6;",
+            numbers
+        ));
+        assert_true(&format!(
+            "{} class Doubler {{ var x; constructor(x) {{ this.x = x * 2; }} }} map(l, Doubler).get(1).x == 2;",
+            numbers
+        ));
+        assert_error(
+            "var l = list(); l.push(1); l.push(\"a\"); sort(l, null);",
+            ErrorType::WrongType,
+        );
+        assert_error(
+            "var l = list(); l.push(1); l.push(2); sort(l, |a, b| => true);",
+            ErrorType::WrongType,
+        );
+        assert_error("map(list(), 1);", ErrorType::WrongType);
+        assert_true(
+            "var caught = false; var l = list(); l.push(1); \
+             try { map(l, |x| => x.missing); } catch (e) { caught = true; } caught;",
+        );
+    }
+
+    #[test]
+    fn assertions() {
+        assert_true("assert(true); assert(1, \"message\"); assertEquals(1 + 1, 2) == null;");
+        let errors = evaluate("assertEquals(\"a\", 1);").unwrap_err();
+        assert_eq!(
+            errors[0].error_type.to_string(),
+            "Assertion failed: expected 1 but got \"a\""
+        );
+        let errors = evaluate("assert(false, \"it's broken\");").unwrap_err();
+        assert_eq!(
+            errors[0].error_type.to_string(),
+            "Assertion failed: it's broken"
+        );
+        assert_error("assert(null);", ErrorType::AssertionFailed(String::new()));
+        assert_error("assert();", ErrorType::InvalidNumberOfArguments);
+        assert_true(
+            "var caught = false; try { assert(false); } catch (e: AssertionError) { caught = true; } caught;",
+        );
+    }
+
+    #[test]
+    fn file_natives_need_file_access() {
+        let path =
+            std::env::temp_dir().join(format!("lox-file-natives-{}.txt", std::process::id()));
+        let path = path.to_str().unwrap().replace('\\', "/");
+        let mut interpreter = Interpreter::new();
+        let errors = interpreter
+            .run(&format!("readFile(\"{}\");", path))
+            .unwrap_err();
+        assert!(matches!(errors[0].error_type, ErrorType::UndefinedVariable));
+
+        interpreter.allow_file_access();
+        assert_true_in(
+            &mut interpreter,
+            &format!(
+                "var path = \"{}\"; writeFile(path, \"a\"); appendFile(path, \"b\");
+                fileExists(path) and readFile(path) == \"ab\";",
+                path
+            ),
+        );
+        std::fs::remove_file(&path).unwrap();
+        assert_true_in(
+            &mut interpreter,
+            "var caught = false; try { readFile(path); } catch (e: IOError) { caught = true; }
+            !fileExists(path) and caught;",
+        );
+    }
+
+    #[test]
+    fn clock_measures_time() {
+        assert_true("var start = clock(); sleep(20); var elapsed = clock() - start; elapsed >= 0.02 and elapsed < 10;");
+        assert_true("clock() > 1600000000;");
+        assert_eq!(format_code("formatDuration(90);"), "1.5 min");
+        assert_eq!(format_code("formatDuration(1.234);"), "1.23 s");
+        assert_eq!(format_code("formatDuration(0.25);"), "250 ms");
+        assert_eq!(format_code("formatDuration(0.0000042);"), "4.2 µs");
+        assert_eq!(format_code("formatDuration(0);"), "0 s");
+        assert_error("sleep(-1);", ErrorType::WrongType);
+    }
+
+    #[test]
+    fn reads_lines_and_args() {
+        let mut interpreter = Interpreter::new();
+        assert_true_in(&mut interpreter, "argCount() == 0 and arg(0) == null;");
+        interpreter.set_args(vec![String::from("in.txt"), String::from("-v")]);
+        assert_true_in(
+            &mut interpreter,
+            "argCount() == 2 and arg(0) == \"in.txt\" and arg(1) == \"-v\" and arg(2) == null;",
+        );
+        let errors = interpreter.run("arg(0.5);").unwrap_err();
+        assert!(matches!(errors[0].error_type, ErrorType::WrongType));
+
+        let mut lines = vec!["second", "first"];
+        interpreter.set_line_reader(move || lines.pop().map(String::from));
+        assert_true_in(
+            &mut interpreter,
+            "readLine() == \"first\" and readLine() == \"second\" and readLine() == null;",
+        );
+    }
+
+    #[test]
+    fn format_native() {
+        assert_eq!(format_code("format(3.14159, \".2\");"), "3.14");
+        assert_eq!(format_code("format(2, \"f\");"), "2.000000");
+        assert_eq!(format_code("format(1500, \".1e\");"), "1.5e3");
+        assert_eq!(format_code("format(255, \"x\");"), "ff");
+        assert_eq!(format_code("format(42, \"5\");"), "   42");
+        assert_eq!(format_code("format(42, \"<5\");"), "42   ");
+        assert_eq!(format_code("format(\"lox\", \"5\");"), "lox  ");
+        assert_eq!(format_code("format(\"lox\", \">5.2\");"), "   lo");
+        assert_error("format(1.5, \"x\");", ErrorType::WrongType);
+        assert_error("format(\"a\", \"f\");", ErrorType::WrongType);
+        assert_error("format(1, \"5q\");", ErrorType::InvalidFormatSpec);
+        assert_error(
+            "format(1, \".999999999999\");",
+            ErrorType::InvalidFormatSpec,
+        );
+        assert_error(
+            "format(1, \"99999999999999999999999\");",
+            ErrorType::InvalidFormatSpec,
+        );
+        let mut interpreter = Interpreter::new();
+        interpreter.set_limits(ResourceLimits {
+            max_string_bytes: Some(100),
+            ..ResourceLimits::default()
+        });
+        let errors = interpreter.run("format(1, \"5000\");").unwrap_err();
+        assert!(matches!(errors[0].error_type, ErrorType::ResourceLimit(_)));
+        assert_error("format(1, 5);", ErrorType::WrongType);
+    }
+
+    #[test]
+    fn type_introspection() {
+        assert_eq!(format_code("type(1);"), "number");
+        assert_eq!(format_code("type(\"a\");"), "string");
+        assert_eq!(format_code("type(true);"), "boolean");
+        assert_eq!(format_code("type(null);"), "null");
+        assert_eq!(format_code("type(clock);"), "function");
+        assert_eq!(format_code("class A {} type(A);"), "class");
+        assert_eq!(format_code("class A {} type(A());"), "instance");
+
+        assert_true("class A {} class B : A {} class C {} var b = B(); instanceof(b, A) and instanceof(b, B) and !instanceof(b, C);");
+        assert_true("class A {} class B : A {} !instanceof(A(), B) and !instanceof(1, A);");
+        assert_true("class A {} class B {} A == A and A != B;");
+        assert_error("instanceof(1, 1);", ErrorType::WrongType);
+
+        assert_true("class A {} fn f() {} callable(A) and callable(f) and callable(clock);");
+        assert_true("class A {} !callable(A()) and !callable(1);");
+    }
+
+    #[test]
+    fn converts_between_types() {
+        assert_eq!(format_code("str(1.5);"), "1.5");
+        assert_eq!(
+            format_code("str(null) + str(true) + str(\"s\");"),
+            "nulltrues"
+        );
+        assert_eq!(
+            format_code("fn f() {} str(f);"),
+            evaluate("fn f() {} f;").unwrap().to_string()
+        );
+        assert_true("num(\" 42 \") == 42 and num(\"-1.5e2\") == -150 and num(7) == 7;");
+        assert_true("num(\"4x\") == null and num(\"inf\") == null and num(true) == null;");
+        assert_true("bool(0) and bool(\"\") and !bool(null) and !bool(false);");
+    }
+
+    #[test]
+    fn reads_dates() {
+        assert_true("now() > 1600000000 and year(now()) >= 2020;");
+        assert_eq!(
+            format_code("dateFormat(1700000000.5, \"%Y-%m-%d %H:%M:%S, 100%%\");"),
+            "2023-11-14 22:13:20, 100%"
+        );
+        assert_eq!(
+            format_code("dateFormat(-1, \"%Y-%m-%d %H:%M:%S\");"),
+            "1969-12-31 23:59:59"
+        );
+        assert_true("var t = 951782400; year(t) == 2000 and month(t) == 2 and day(t) == 29 and hour(t + 3600) == 1;");
+        assert_error("dateFormat(0, \"%q\");", ErrorType::InvalidFormatSpec);
+        assert_error("year(\"2000\");", ErrorType::WrongType);
+    }
+
+    #[test]
+    fn seeds_random_numbers() {
+        assert_true("setSeed(7); var a = random(); var b = randomInt(1, 6); setSeed(7); random() == a and randomInt(1, 6) == b;");
+        assert_true("var ok = true; for (var i = 0; i < 100; i = i + 1) { var n = randomInt(-2, 2); ok = ok and n >= -2 and n <= 2 and n == floor(n); } ok;");
+        assert_true("randomInt(3, 3) == 3;");
+        assert_error("randomInt(2, 1);", ErrorType::EmptyRange);
+        assert_error("randomInt(1.5, 2);", ErrorType::WrongType);
+        let mut interpreter = Interpreter::new();
+        interpreter.set_random_seed(7);
+        let first = interpreter.run("random();").unwrap();
+        assert_eq!(first, evaluate("setSeed(7); random();").unwrap());
+    }
+}
@@ -1,9 +1,13 @@
 use crate::error::{Error, ErrorType};
-use crate::expr::Expr;
-use crate::statement::Stmt;
-use crate::token::{Token, TokenType};
+use crate::expr::{Expr, ExprId};
+use crate::features::Features;
+use crate::pattern::Pattern;
+use crate::statement::{Arm, Catch, Stmt};
+use crate::token::{Literal, Span, Token, TokenType, FUTURE_KEYWORDS};
+use crate::types::{Signature, Type};
 use log::debug;
 use std::mem;
+use std::rc::Rc;
 
 macro_rules! matches {
     ( $sel:ident, $( $x:expr ),* ) => {
@@ -18,17 +22,75 @@ macro_rules! matches {
     };
 }
 
+/// Deepest syntax tree the parser builds. The resolver and the interpreter walk trees recursively,
+/// so deeper ones, e.g. thousands of nested parentheses, would overflow their stack.
+pub const MAX_NESTING: usize = 100;
+
+/// Peeked past the end of tokens that don't end with EOF
+static END: Token = Token {
+    token_type: TokenType::EOF,
+    line: 0,
+    start: 0,
+    end: 0,
+};
+
 pub struct Parser<'a> {
     tokens: &'a Vec<Token>,
     current: usize,
+    features: Features,
+    /// Nodes of the tree around the one being parsed
+    depth: usize,
+    /// Deepest tree allowed, at most `MAX_NESTING`
+    max_nesting: usize,
+    /// Whether failed statements and missing expressions are kept as error nodes, see `parse_partial`
+    recovering: bool,
+    /// Errors of the nodes kept while recovering
+    recovered: Vec<Error>,
 }
 
 type ExprResult = Result<Expr, Error>;
 type StmtResult = Result<Stmt, Error>;
 
+/// Parameter list of a function or closure
+struct Params {
+    names: Vec<String>,
+    signature: Signature,
+    defaults: Vec<Expr>,
+}
+
 impl<'a> Parser<'a> {
     pub fn new(tokens: &'a Vec<Token>) -> Self {
-        Parser { tokens, current: 0 }
+        Parser::with_features(tokens, Features::all())
+    }
+
+    /// Parser that rejects the constructs of the disabled features
+    pub fn with_features(tokens: &'a Vec<Token>, features: Features) -> Self {
+        Parser {
+            tokens,
+            current: 0,
+            features,
+            depth: 0,
+            max_nesting: MAX_NESTING,
+            recovering: false,
+            recovered: Vec::new(),
+        }
+    }
+
+    /// Lowers the deepest tree the parser builds, it can't go above `MAX_NESTING`
+    pub fn set_max_nesting(&mut self, max_nesting: usize) {
+        self.max_nesting = max_nesting.min(MAX_NESTING);
+    }
+
+    /// Checks the feature of the construct starting with the previous token
+    fn gate(&self, enabled: fn(&Features) -> bool, feature: &str) -> Result<(), Error> {
+        if enabled(&self.features) {
+            Ok(())
+        } else {
+            Err(Error::new(
+                self.previous(),
+                ErrorType::FeatureDisabled(feature.to_owned()),
+            ))
+        }
     }
 
     fn synchronize(&mut self) {
@@ -43,11 +105,20 @@ impl<'a> Parser<'a> {
                 | TokenType::For
                 | TokenType::If
                 | TokenType::While
+                | TokenType::Do
                 | TokenType::Print
+                | TokenType::Try
+                | TokenType::Throw
                 | TokenType::Return => {
                     debug!("Found statement. Rolling back to parsing.");
                     return;
                 }
+                // the block goes on after its broken statement
+                TokenType::CloseBrace if self.recovering => return,
+                TokenType::Semicolon if self.recovering => {
+                    self.advance();
+                    return;
+                }
                 _ => {
                     self.advance();
                 }
@@ -59,13 +130,32 @@ impl<'a> Parser<'a> {
         debug!("Peeking: {:#?}", self.tokens.get(self.current));
         self.tokens
             .get(self.current)
-            .expect("Unexpected peek into empty stream")
+            .or_else(|| self.tokens.last())
+            .unwrap_or(&END)
     }
 
     fn previous(&self) -> &Token {
-        self.tokens
-            .get(self.current - 1)
-            .expect("Unexpected failure to get previous token")
+        self.current
+            .checked_sub(1)
+            .and_then(|previous| self.tokens.get(previous))
+            .or_else(|| self.tokens.last())
+            .unwrap_or(&END)
+    }
+
+    /// Goes one node deeper into the tree, see `MAX_NESTING`
+    fn nest(&mut self) -> Result<(), Error> {
+        self.depth += 1;
+        if self.depth > self.max_nesting {
+            return Err(Error::new(self.peek(), ErrorType::NestedTooDeeply));
+        }
+        Ok(())
+    }
+
+    fn nested<T>(&mut self, parse: fn(&mut Self) -> Result<T, Error>) -> Result<T, Error> {
+        self.nest()?;
+        let node = parse(self);
+        self.depth -= 1;
+        node
     }
 
     fn check(&mut self, to_match: TokenType) -> bool {
@@ -96,18 +186,12 @@ impl<'a> Parser<'a> {
             Ok(self.advance())
         } else {
             debug!("{:?} not found. Throwing {:?}", expected, error_type);
-            Err(Error {
-                token: self.peek().clone(),
-                error_type,
-            })
+            Err(Error::new(self.peek(), error_type))
         }
     }
 
     fn error<T>(&mut self, error_type: ErrorType, token: &Token) -> Result<T, Error> {
-        Err(Error {
-            token: token.clone(),
-            error_type,
-        })
+        Err(Error::new(token, error_type))
     }
 
     pub fn parse_tokens(&mut self) -> Result<Vec<Stmt>, Vec<Error>> {
@@ -118,6 +202,8 @@ impl<'a> Parser<'a> {
                 Ok(s) => statements.push(s),
                 Err(e) => {
                     errors.push(e);
+                    // errors leave the nodes they were found in without going back up
+                    self.depth = 0;
                     self.synchronize();
                 }
             }
@@ -128,10 +214,70 @@ impl<'a> Parser<'a> {
         }
     }
 
+    /// Parses the whole program even when it has errors, for tools that look at code being written.
+    /// Statements that fail are kept as `Stmt::Error` and expressions missing before a closing token,
+    /// e.g. in `var a = ;` or `f(1, )`, as `Expr::Error`, inside otherwise complete statements.
+    pub fn parse_partial(&mut self) -> (Vec<Stmt>, Vec<Error>) {
+        self.recovering = true;
+        let mut statements = Vec::new();
+        while !self.is_at_end() {
+            statements.push(self.recovering_declaration());
+        }
+        self.recovering = false;
+        (statements, mem::take(&mut self.recovered))
+    }
+
+    /// Declaration, or an error node reaching to the start of the next statement when it fails
+    fn recovering_declaration(&mut self) -> Stmt {
+        let start = self.current;
+        let depth = self.depth;
+        match self.declaration() {
+            Ok(stmt) => stmt,
+            Err(error) => {
+                self.recovered.push(error);
+                self.depth = depth;
+                self.synchronize();
+                if self.current == start {
+                    self.advance();
+                }
+                Stmt::Error {
+                    token: self.tokens.get(start).unwrap_or(&END).clone(),
+                    end: self.previous().clone(),
+                }
+            }
+        }
+    }
+
+    /// Parses the tokens as a single expression, without a semicolon
+    pub fn parse_expression(&mut self) -> Result<Expr, Vec<Error>> {
+        let expr = self.expr().map_err(|e| vec![e])?;
+        if !self.is_at_end() {
+            return Err(vec![Error::new(
+                self.peek(),
+                ErrorType::ExpectedEndOfExpression,
+            )]);
+        }
+        Ok(expr)
+    }
+
+    /// Names reserved for upcoming features can't be used when the features say so
+    fn check_reserved(&self, token: &Token) -> Result<(), Error> {
+        match &token.token_type {
+            TokenType::Identifier(name)
+                if self.features.reserve_future_keywords
+                    && FUTURE_KEYWORDS.contains(&name.as_str()) =>
+            {
+                Err(Error::new(token, ErrorType::ReservedWord(name.clone())))
+            }
+            _ => Ok(()),
+        }
+    }
+
     fn get_identifier(&mut self) -> Result<(String, Token), Error> {
         debug!("Going to get identifier...");
         let token = self.peek().clone();
         if let TokenType::Identifier(identifier) = &token.token_type {
+            self.check_reserved(&token)?;
             self.advance();
             Ok((identifier.clone(), token.clone()))
         } else {
@@ -141,45 +287,89 @@ impl<'a> Parser<'a> {
     }
 
     fn declaration(&mut self) -> StmtResult {
+        self.nested(Self::parse_declaration)
+    }
+
+    fn parse_declaration(&mut self) -> StmtResult {
         if matches!(self, TokenType::Var) {
             self.variable()
-        } else if matches!(self, TokenType::Function) {
+        } else if self.check(TokenType::Function) && !self.at_function_expression() {
+            self.advance();
             debug!("Entering function statement");
+            self.gate(|f| f.functions, "functions")?;
             self.function_statement()
         } else if matches!(self, TokenType::Class) {
+            self.gate(|f| f.classes, "classes")?;
             self.class_statement()
         } else {
             self.statement()
         }
     }
 
+    /// Span from the given start up to the last consumed token on the same line
+    fn span_from(&self, start: Span) -> Span {
+        self.tokens[..self.current]
+            .iter()
+            .rev()
+            .find(|token| token.line == start.line)
+            .map_or(start, |token| start.to(token.span()))
+    }
+
     fn variable(&mut self) -> StmtResult {
+        let start = self.previous().span();
         let (name, _) = self.get_identifier()?;
+        let annotation = self.annotation()?;
         let expr = if matches!(self, TokenType::Assign) {
             Some(self.expr()?)
         } else {
             None
         };
         self.consume(TokenType::Semicolon, ErrorType::ExpectedSemicolon)?;
-        return Ok(Stmt::Var { name, value: expr });
+        return Ok(Stmt::Var {
+            name,
+            value: expr,
+            annotation,
+            span: self.span_from(start),
+        });
     }
 
     fn statement(&mut self) -> StmtResult {
+        self.nested(Self::parse_statement)
+    }
+
+    fn parse_statement(&mut self) -> StmtResult {
         // TODO: maybe a match would be prettier here
         if matches!(self, TokenType::Print) {
             self.print_statement()
         } else if matches!(self, TokenType::For) {
+            self.gate(|f| f.control_flow, "control flow")?;
             self.for_stmt()
         } else if matches!(self, TokenType::OpenBrace) {
             self.block()
         } else if matches!(self, TokenType::Return) {
+            self.gate(|f| f.functions || f.closures, "functions")?;
             self.return_stmt()
         } else if matches!(self, TokenType::If) {
+            self.gate(|f| f.control_flow, "control flow")?;
             self.if_statement()
         } else if matches!(self, TokenType::While) {
+            self.gate(|f| f.control_flow, "control flow")?;
             self.while_statement()
+        } else if matches!(self, TokenType::Do) {
+            self.gate(|f| f.control_flow, "control flow")?;
+            self.do_while_statement()
         } else if matches!(self, TokenType::Break, TokenType::Continue) {
+            self.gate(|f| f.break_continue, "break and continue")?;
             self.break_or_continue_statement()
+        } else if matches!(self, TokenType::Try) {
+            self.gate(|f| f.exceptions, "exceptions")?;
+            self.try_statement()
+        } else if matches!(self, TokenType::Throw) {
+            self.gate(|f| f.exceptions, "exceptions")?;
+            self.throw_statement()
+        } else if matches!(self, TokenType::Match) {
+            self.gate(|f| f.pattern_matching, "match statements")?;
+            self.match_statement()
         } else {
             self.expr_statement()
         }
@@ -187,7 +377,11 @@ impl<'a> Parser<'a> {
 
     fn return_stmt(&mut self) -> StmtResult {
         let token = self.previous().clone();
-        let value = Some(self.expr()?);
+        let value = if self.peek().token_type == TokenType::Semicolon {
+            None
+        } else {
+            Some(self.expr()?)
+        };
 
         self.consume(TokenType::Semicolon, ErrorType::ExpectedSemicolon)?;
 
@@ -195,26 +389,246 @@ impl<'a> Parser<'a> {
     }
 
     fn block(&mut self) -> StmtResult {
+        let start = self.previous().span();
+        Ok(Stmt::Block {
+            stmts: self.block_stmts()?,
+            span: self.span_from(start),
+        })
+    }
+
+    /// Statements of a block whose scope is set up by the statement owning it
+    fn block_stmts(&mut self) -> Result<Vec<Stmt>, Error> {
         let mut stmts: Vec<Stmt> = Vec::new();
 
         while &self.peek().token_type != &TokenType::CloseBrace && !self.is_at_end() {
-            let stmt = self.declaration()?;
+            let stmt = if self.recovering {
+                self.recovering_declaration()
+            } else {
+                self.declaration()?
+            };
             stmts.push(stmt);
         }
         self.consume(TokenType::CloseBrace, ErrorType::ExpectedBlockEnd)?;
-        Ok(Stmt::Block { stmts })
+        Ok(stmts)
     }
 
-    fn parse_params(&mut self, delimiter: TokenType) -> Result<Vec<String>, Error> {
+    fn try_statement(&mut self) -> StmtResult {
+        let token = self.previous().clone();
+        self.consume(TokenType::OpenBrace, ErrorType::ExpectedBlockStart)?;
+        let body = self.block_stmts()?;
+
+        let mut catches = Vec::new();
+        while matches!(self, TokenType::Catch) {
+            self.consume(
+                TokenType::OpenParenthesis,
+                ErrorType::ExpectedOpenParenthesis,
+            )?;
+            let (name, _) = self.get_identifier()?;
+            let class = if matches!(self, TokenType::Inherit) {
+                let (name, token) = self.get_identifier()?;
+                Some(Expr::Var {
+                    name,
+                    token,
+                    id: ExprId::new(),
+                })
+            } else {
+                None
+            };
+            self.consume(
+                TokenType::CloseParenthesis,
+                ErrorType::ExpectedCloseParenthesis,
+            )?;
+            self.consume(TokenType::OpenBrace, ErrorType::ExpectedBlockStart)?;
+            catches.push(Catch {
+                name,
+                class,
+                body: self.block_stmts()?,
+            });
+        }
+
+        let finally = if matches!(self, TokenType::Finally) {
+            self.consume(TokenType::OpenBrace, ErrorType::ExpectedBlockStart)?;
+            Some(self.block_stmts()?)
+        } else {
+            None
+        };
+
+        if catches.is_empty() && finally.is_none() {
+            let token = self.peek().clone();
+            return self.error(ErrorType::ExpectedCatchOrFinally, &token);
+        }
+
+        Ok(Stmt::Try {
+            body,
+            catches,
+            finally,
+            token,
+        })
+    }
+
+    fn throw_statement(&mut self) -> StmtResult {
+        let token = self.previous().clone();
+        let value = self.expr()?;
+        self.consume(TokenType::Semicolon, ErrorType::ExpectedSemicolon)?;
+        Ok(Stmt::Throw { value, token })
+    }
+
+    fn match_statement(&mut self) -> StmtResult {
+        let token = self.previous().clone();
+        self.consume(
+            TokenType::OpenParenthesis,
+            ErrorType::ExpectedOpenParenthesis,
+        )?;
+        let value = self.expr()?;
+        self.consume(
+            TokenType::CloseParenthesis,
+            ErrorType::ExpectedCloseParenthesis,
+        )?;
+        self.consume(TokenType::OpenBrace, ErrorType::ExpectedBlockStart)?;
+        let mut arms = Vec::new();
+        while !self.check(TokenType::CloseBrace) && !self.is_at_end() {
+            let pattern = self.pattern()?;
+            self.consume(TokenType::Arrow, ErrorType::ExpectedArmArrow)?;
+            arms.push(Arm {
+                pattern,
+                body: self.statement()?,
+            });
+        }
+        self.consume(TokenType::CloseBrace, ErrorType::ExpectedBlockEnd)?;
+        Ok(Stmt::Match { value, arms, token })
+    }
+
+    /// Literal, optionally negated number, name, `[items, ...rest]` or `Class { field, field: pattern }`
+    /// with the class left out to match instances of any class
+    fn pattern(&mut self) -> Result<Pattern, Error> {
+        let token = self.advance().clone();
+        match &token.token_type {
+            TokenType::Literal(value) => Ok(Pattern::Literal {
+                value: value.clone(),
+                token,
+            }),
+            TokenType::Minus => match self.peek().token_type.clone() {
+                TokenType::Literal(Literal::Number(number)) => {
+                    self.advance();
+                    Ok(Pattern::Literal {
+                        value: Literal::Number(-number),
+                        token,
+                    })
+                }
+                _ => self.error(ErrorType::ExpectedPattern, &token),
+            },
+            TokenType::Identifier(name) if self.check(TokenType::OpenBrace) => {
+                self.check_reserved(&token)?;
+                let class = Expr::Var {
+                    name: name.clone(),
+                    token: token.clone(),
+                    id: ExprId::new(),
+                };
+                self.advance();
+                self.instance_pattern(Some(class), token)
+            }
+            TokenType::Identifier(name) => {
+                self.check_reserved(&token)?;
+                Ok(Pattern::Binding {
+                    name: name.clone(),
+                    token,
+                })
+            }
+            TokenType::OpenBrace => self.instance_pattern(None, token),
+            TokenType::OpenBracket => {
+                let mut items = Vec::new();
+                let mut rest = None;
+                while !self.check(TokenType::CloseBracket) {
+                    if matches!(self, TokenType::Ellipsis) {
+                        rest = Some(self.get_identifier()?.0);
+                        break;
+                    }
+                    items.push(self.pattern()?);
+                    if !matches!(self, TokenType::Coma) {
+                        break;
+                    }
+                }
+                self.consume(TokenType::CloseBracket, ErrorType::ExpectedCloseBracket)?;
+                Ok(Pattern::List { items, rest, token })
+            }
+            _ => self.error(ErrorType::ExpectedPattern, &token),
+        }
+    }
+
+    /// Fields of an instance pattern, after its open brace
+    fn instance_pattern(&mut self, class: Option<Expr>, token: Token) -> Result<Pattern, Error> {
+        let mut fields = Vec::new();
+        while !self.check(TokenType::CloseBrace) {
+            let (name, field) = self.get_identifier()?;
+            let pattern = if matches!(self, TokenType::Inherit) {
+                self.pattern()?
+            } else {
+                Pattern::Binding {
+                    name: name.clone(),
+                    token: field,
+                }
+            };
+            fields.push((name, pattern));
+            if !matches!(self, TokenType::Coma) {
+                break;
+            }
+        }
+        self.consume(TokenType::CloseBrace, ErrorType::ExpectedBlockEnd)?;
+        Ok(Pattern::Instance {
+            class,
+            fields,
+            token,
+        })
+    }
+
+    /// `: type` if there's a colon next
+    fn annotation(&mut self) -> Result<Option<Type>, Error> {
+        if !matches!(self, TokenType::Inherit) {
+            return Ok(None);
+        }
+        self.gate(|f| f.type_annotations, "type annotations")?;
+        let token = self.advance().clone();
+        match token.token_type {
+            TokenType::Identifier(name) => Ok(Some(Type::parse(&name))),
+            TokenType::Literal(Literal::Null) => Ok(Some(Type::Null)),
+            _ => self.error(ErrorType::ExpectedType, &token),
+        }
+    }
+
+    /// Parameters up to the delimiter closing them and the return type after it.
+    /// Parameters with a default value, `name = value`, can only be followed by other such ones,
+    /// and the rest parameter, `...name`, by none.
+    fn parse_params(&mut self, delimiter: TokenType) -> Result<Params, Error> {
         let mut params: Vec<String> = Vec::new();
+        let mut types: Vec<Option<Type>> = Vec::new();
+        let mut defaults: Vec<Expr> = Vec::new();
+        let mut variadic = false;
         let next = self.peek();
 
-        if next.token_type != delimiter && next.token_type.as_identifier().is_some() {
+        if next.token_type != delimiter
+            && (next.token_type.as_identifier().is_some() || next.token_type == TokenType::Ellipsis)
+        {
             loop {
+                variadic = matches!(self, TokenType::Ellipsis);
+                if variadic {
+                    self.gate(|f| f.rest_params, "rest parameters")?;
+                }
                 let token = self.advance().clone();
-
-                if let TokenType::Identifier(param) = token.token_type {
-                    params.push(param);
+                self.check_reserved(&token)?;
+
+                if let TokenType::Identifier(param) = &token.token_type {
+                    params.push(param.clone());
+                    types.push(self.annotation()?);
+                    if variadic {
+                        if !self.check(delimiter.clone()) {
+                            return self.error(ErrorType::RestParameterNotLast, &token);
+                        }
+                    } else if matches!(self, TokenType::Assign) {
+                        self.gate(|f| f.default_params, "default parameter values")?;
+                        defaults.push(self.expr()?);
+                    } else if !defaults.is_empty() {
+                        return self.error(ErrorType::ExpectedDefault, &token);
+                    }
                 } else {
                     let token = self.previous().clone();
                     return self.error(ErrorType::UnexpectedCharacter, &token);
@@ -232,15 +646,29 @@ impl<'a> Parser<'a> {
         };
 
         self.consume(delimiter, error_type)?;
+        let signature = Signature {
+            params: types,
+            returns: self.annotation()?,
+            variadic,
+        };
 
-        Ok(params)
+        Ok(Params {
+            names: params,
+            signature,
+            defaults,
+        })
     }
 
     fn class_statement(&mut self) -> StmtResult {
         let (name, token) = self.get_identifier()?;
         let superclass = if matches!(self, TokenType::Inherit) {
+            self.gate(|f| f.inheritance, "inheritance")?;
             let (name, token) = self.get_identifier()?;
-            Some(Expr::Var { name, token })
+            Some(Expr::Var {
+                name,
+                token,
+                id: ExprId::new(),
+            })
         } else {
             None
         };
@@ -251,6 +679,7 @@ impl<'a> Parser<'a> {
 
         while !matches!(self, TokenType::CloseBrace, TokenType::EOF) {
             let val = if matches!(self, TokenType::Var) {
+                self.gate(|f| f.class_fields, "class fields")?;
                 self.variable()
             } else {
                 self.function_statement()
@@ -266,10 +695,30 @@ impl<'a> Parser<'a> {
         })
     }
 
+    /// `fn (` starts a function expression, which a statement can begin with like any other expression
+    fn at_function_expression(&self) -> bool {
+        self.tokens
+            .get(self.current + 1)
+            .is_some_and(|next| next.token_type == TokenType::OpenParenthesis)
+    }
+
     fn function_statement(&mut self) -> StmtResult {
         debug!("Entering function statement");
         let (name, token) = self.get_identifier()?;
+        let (params, body) = self.function_rest()?;
+
+        Ok(Stmt::Function {
+            params: Rc::new(params.names),
+            signature: params.signature,
+            defaults: Rc::new(params.defaults),
+            body: Rc::new(body),
+            name,
+            token,
+        })
+    }
 
+    /// Parameter list and body of a function, after its name if it has one
+    fn function_rest(&mut self) -> Result<(Params, Vec<Stmt>), Error> {
         self.consume(
             TokenType::OpenParenthesis,
             ErrorType::ExpectedOpenParenthesis,
@@ -278,21 +727,18 @@ impl<'a> Parser<'a> {
         let params = self.parse_params(TokenType::CloseParenthesis)?;
 
         self.consume(TokenType::OpenBrace, ErrorType::ExpectedBlockStart)?;
-        let body = vec![self.block()?];
-
-        Ok(Stmt::Function {
-            params,
-            body,
-            name,
-            token,
-        })
+        Ok((params, vec![self.block()?]))
     }
 
     fn for_stmt(&mut self) -> StmtResult {
+        let start = self.previous().span();
         self.consume(
             TokenType::OpenParenthesis,
             ErrorType::ExpectedOpenParenthesis,
         )?;
+        if let Some((name, token)) = self.for_in_variable() {
+            return self.for_in_stmt(start, name, token);
+        }
         let initializer = self.declaration()?;
         let condition = self.expr()?;
         self.consume(TokenType::Semicolon, ErrorType::ExpectedSemicolon)?;
@@ -304,19 +750,59 @@ impl<'a> Parser<'a> {
 
         // TODO: I feel like this allows infinite amount of for loops after for loop and some other pointless stuff
         let body = self.statement()?;
+        let span = self.span_from(start);
 
-        let body = Box::new(Stmt::Block {
-            stmts: vec![body, Stmt::Expr { expr: executor }],
-        });
-
-        let while_loop = Stmt::While { condition, body };
+        let while_loop = Stmt::While {
+            condition,
+            body: Box::new(body),
+            increment: Some(executor),
+            span,
+        };
 
         Ok(Stmt::Block {
             stmts: vec![initializer, while_loop],
+            span,
+        })
+    }
+
+    /// `var name in`, which starts a for-in loop rather than the initializer of a for loop
+    fn for_in_variable(&mut self) -> Option<(String, Token)> {
+        match (
+            &self.peek().token_type,
+            self.tokens.get(self.current + 1),
+            self.tokens.get(self.current + 2),
+        ) {
+            (TokenType::Var, Some(token), Some(next)) if next.token_type == TokenType::In => {
+                let name = token.token_type.as_identifier()?.clone();
+                let token = token.clone();
+                self.current += 3;
+                Some((name, token))
+            }
+            _ => None,
+        }
+    }
+
+    fn for_in_stmt(&mut self, start: Span, name: String, token: Token) -> StmtResult {
+        self.gate(|f| f.for_in, "for-in loops")?;
+        self.check_reserved(&token)?;
+        let iterable = self.expr()?;
+        self.consume(
+            TokenType::CloseParenthesis,
+            ErrorType::ExpectedCloseParenthesis,
+        )?;
+        let body = Box::new(self.statement()?);
+
+        Ok(Stmt::ForIn {
+            name,
+            iterable,
+            body,
+            token,
+            span: self.span_from(start),
         })
     }
 
     fn while_statement(&mut self) -> StmtResult {
+        let start = self.previous().span();
         self.consume(
             TokenType::OpenParenthesis,
             ErrorType::ExpectedCloseParenthesis,
@@ -328,10 +814,38 @@ impl<'a> Parser<'a> {
         )?;
         let body = Box::new(self.statement()?);
 
-        Ok(Stmt::While { condition, body })
+        Ok(Stmt::While {
+            condition,
+            body,
+            increment: None,
+            span: self.span_from(start),
+        })
+    }
+
+    fn do_while_statement(&mut self) -> StmtResult {
+        let start = self.previous().span();
+        let body = Box::new(self.statement()?);
+        self.consume(TokenType::While, ErrorType::ExpectedWhile)?;
+        self.consume(
+            TokenType::OpenParenthesis,
+            ErrorType::ExpectedOpenParenthesis,
+        )?;
+        let condition = self.expr()?;
+        self.consume(
+            TokenType::CloseParenthesis,
+            ErrorType::ExpectedCloseParenthesis,
+        )?;
+        self.consume(TokenType::Semicolon, ErrorType::ExpectedSemicolon)?;
+
+        Ok(Stmt::DoWhile {
+            body,
+            condition,
+            span: self.span_from(start),
+        })
     }
 
     fn if_statement(&mut self) -> StmtResult {
+        let start = self.previous().span();
         self.consume(
             TokenType::OpenParenthesis,
             ErrorType::ExpectedOpenParenthesis,
@@ -352,6 +866,7 @@ impl<'a> Parser<'a> {
             condition,
             then_body,
             else_body,
+            span: self.span_from(start),
         })
     }
 
@@ -368,23 +883,31 @@ impl<'a> Parser<'a> {
     }
 
     fn print_statement(&mut self) -> StmtResult {
+        let start = self.previous().span();
         let expr = self.expr()?;
         self.consume(TokenType::Semicolon, ErrorType::ExpectedSemicolon)?;
-        Ok(Stmt::Print { expr })
+        Ok(Stmt::Print {
+            expr,
+            span: self.span_from(start),
+        })
     }
 
     fn expr_statement(&mut self) -> StmtResult {
         let expr = self.expr()?;
         self.consume(TokenType::Semicolon, ErrorType::ExpectedSemicolon)?;
-        Ok(Stmt::Expr { expr })
+        Ok(Stmt::Expr {
+            span: self.span_from(expr.span()),
+            expr,
+        })
     }
 
     fn expr(&mut self) -> ExprResult {
-        self.closure()
+        self.nested(Self::closure)
     }
 
     fn closure(&mut self) -> ExprResult {
         if matches!(self, TokenType::Bar) {
+            self.gate(|f| f.closures, "closures")?;
             let token = self.previous().clone();
             let params = self.parse_params(TokenType::Bar)?;
             self.consume(TokenType::Arrow, ErrorType::ExpectedArrow)?;
@@ -398,8 +921,23 @@ impl<'a> Parser<'a> {
                 }
             };
             return Ok(Expr::Closure {
-                params,
-                body: vec![body],
+                params: Rc::new(params.names),
+                signature: params.signature,
+                defaults: Rc::new(params.defaults),
+                body: Rc::new(vec![body]),
+                name: String::from("closure"),
+                token,
+            });
+        }
+        if matches!(self, TokenType::Function) {
+            self.gate(|f| f.closures, "closures")?;
+            let token = self.previous().clone();
+            let (params, body) = self.function_rest()?;
+            return Ok(Expr::Closure {
+                params: Rc::new(params.names),
+                signature: params.signature,
+                defaults: Rc::new(params.defaults),
+                body: Rc::new(body),
                 name: String::from("closure"),
                 token,
             });
@@ -413,13 +951,28 @@ impl<'a> Parser<'a> {
         if matches!(self, TokenType::Assign) {
             let token = self.previous().clone();
 
-            let value = self.assignment()?;
+            let value = self.nested(Self::closure)?;
 
-            if let Expr::Var { name, token } = expr {
+            if let Expr::Var { name, token, id } = expr {
                 return Ok(Expr::Assign {
                     name,
                     expr: Box::new(value),
                     token,
+                    id,
+                });
+            }
+
+            if let Expr::Index {
+                object,
+                index,
+                token,
+            } = expr
+            {
+                return Ok(Expr::SetIndex {
+                    object,
+                    index,
+                    value: Box::new(value),
+                    token,
                 });
             }
 
@@ -440,7 +993,10 @@ impl<'a> Parser<'a> {
 
     fn or(&mut self) -> ExprResult {
         let mut expr = self.and()?;
+        let depth = self.depth;
         while matches!(self, TokenType::Or) {
+            // every operator puts the expression so far one node deeper
+            self.nest()?;
             let operator = self.previous().clone();
             let right = self.and()?;
             expr = Expr::Logical {
@@ -449,12 +1005,15 @@ impl<'a> Parser<'a> {
                 right: Box::new(right),
             };
         }
+        self.depth = depth;
         return Ok(expr);
     }
 
     fn and(&mut self) -> ExprResult {
         let mut expr = self.equality()?;
+        let depth = self.depth;
         while matches!(self, TokenType::And) {
+            self.nest()?;
             let operator = self.previous().clone();
             let right = self.equality()?;
             expr = Expr::Logical {
@@ -463,12 +1022,15 @@ impl<'a> Parser<'a> {
                 right: Box::new(right),
             };
         }
+        self.depth = depth;
         return Ok(expr);
     }
 
     fn equality(&mut self) -> ExprResult {
         let mut expr = self.comparison()?;
+        let depth = self.depth;
         while matches!(self, TokenType::Compare, TokenType::BangEquals) {
+            self.nest()?;
             let operator = self.previous().clone();
             let right = self.comparison()?;
             expr = Expr::Binary {
@@ -477,11 +1039,13 @@ impl<'a> Parser<'a> {
                 right: Box::new(right),
             };
         }
+        self.depth = depth;
         return Ok(expr);
     }
 
     fn comparison(&mut self) -> ExprResult {
         let mut expr = self.addition()?;
+        let depth = self.depth;
         while matches!(
             self,
             TokenType::Less,
@@ -489,6 +1053,7 @@ impl<'a> Parser<'a> {
             TokenType::Greater,
             TokenType::GreaterEquals
         ) {
+            self.nest()?;
             let operator = self.previous().clone();
 
             let right = self.addition()?;
@@ -498,12 +1063,15 @@ impl<'a> Parser<'a> {
                 right: Box::new(right),
             };
         }
+        self.depth = depth;
         return Ok(expr);
     }
 
     fn addition(&mut self) -> ExprResult {
         let mut expr = self.multiplication()?;
+        let depth = self.depth;
         while matches!(self, TokenType::Minus, TokenType::Plus) {
+            self.nest()?;
             let operator = self.previous().clone();
             let right = self.multiplication()?;
             expr = Expr::Binary {
@@ -512,12 +1080,15 @@ impl<'a> Parser<'a> {
                 right: Box::new(right),
             };
         }
+        self.depth = depth;
         return Ok(expr);
     }
 
     fn multiplication(&mut self) -> ExprResult {
         let mut expr = self.unary()?;
+        let depth = self.depth;
         while matches!(self, TokenType::Star, TokenType::Divide, TokenType::Modulo) {
+            self.nest()?;
             let operator = self.previous().clone();
             let right = self.unary()?;
             expr = Expr::Binary {
@@ -526,13 +1097,14 @@ impl<'a> Parser<'a> {
                 right: Box::new(right),
             };
         }
+        self.depth = depth;
         return Ok(expr);
     }
 
     fn unary(&mut self) -> ExprResult {
         if matches!(self, TokenType::Bang, TokenType::Minus) {
             let operator = self.previous().clone();
-            let right = self.unary()?;
+            let right = self.nested(Self::unary)?;
             return Ok(Expr::Unary {
                 operator,
                 expr: Box::new(right),
@@ -543,10 +1115,18 @@ impl<'a> Parser<'a> {
 
     fn finish_call(&mut self, callee: Expr) -> ExprResult {
         let mut arguments: Vec<Expr> = Vec::new();
+        let mut named: Vec<(String, Expr)> = Vec::new();
 
         if !(self.peek().token_type == TokenType::CloseParenthesis) {
             loop {
-                arguments.push(self.expr()?);
+                if let Some(name) = self.argument_name() {
+                    named.push((name, self.expr()?));
+                } else if named.is_empty() {
+                    arguments.push(self.argument()?);
+                } else {
+                    let token = self.peek().clone();
+                    return self.error(ErrorType::PositionalAfterNamedArgument, &token);
+                }
                 if !matches!(self, TokenType::Coma) {
                     break;
                 }
@@ -568,57 +1148,122 @@ impl<'a> Parser<'a> {
         Ok(Expr::Call {
             callee: Box::new(callee),
             arguments,
+            named,
             token,
         })
     }
 
+    /// Positional argument, `...list` spreads the list's items into separate ones
+    fn argument(&mut self) -> ExprResult {
+        if matches!(self, TokenType::Ellipsis) {
+            self.gate(|f| f.rest_params, "spread arguments")?;
+            let token = self.previous().clone();
+            return Ok(Expr::Spread {
+                expr: Box::new(self.expr()?),
+                token,
+            });
+        }
+        self.expr()
+    }
+
+    /// Consumes `name:` at the start of a named argument
+    fn argument_name(&mut self) -> Option<String> {
+        match (&self.peek().token_type, self.tokens.get(self.current + 1)) {
+            (TokenType::Identifier(name), Some(next)) if next.token_type == TokenType::Inherit => {
+                let name = name.clone();
+                self.current += 2;
+                Some(name)
+            }
+            _ => None,
+        }
+    }
+
     fn call(&mut self) -> ExprResult {
+        self.check_reserved(self.peek())?;
         let mut expr = self.primary()?;
+        let depth = self.depth;
         loop {
             if matches!(self, TokenType::OpenParenthesis) {
+                self.nest()?;
                 expr = self.finish_call(expr)?;
             } else if matches!(self, TokenType::Dot) {
+                self.nest()?;
                 let (name, token) = self.get_identifier()?;
                 expr = Expr::Get {
                     expr: Box::new(expr),
                     name,
                     token,
                 };
+            } else if matches!(self, TokenType::OpenBracket) {
+                self.gate(|f| f.index_expressions, "index expressions")?;
+                self.nest()?;
+                let index = self.expr()?;
+                let token = self
+                    .consume(TokenType::CloseBracket, ErrorType::ExpectedCloseBracket)?
+                    .clone();
+                expr = Expr::Index {
+                    object: Box::new(expr),
+                    index: Box::new(index),
+                    token,
+                };
             } else {
                 break;
             }
         }
 
+        self.depth = depth;
         return Ok(expr);
     }
 
     fn primary(&mut self) -> ExprResult {
+        let recovering = self.recovering;
         let token = self.advance();
         let _token = token.clone();
 
         match &token.token_type {
             TokenType::Literal(literal) => Ok(Expr::Literal {
                 value: literal.clone(),
+                span: token.span(),
             }),
             TokenType::Identifier(name) => Ok(Expr::Var {
                 name: name.clone(),
                 token: token.clone(),
+                id: ExprId::new(),
             }),
             TokenType::This => Ok(Expr::This {
                 token: token.clone(),
+                id: ExprId::new(),
             }),
             TokenType::Super => {
                 self.consume(TokenType::Dot, ErrorType::DotAfterSuper)?;
                 let (method_name, token) = self.get_identifier()?;
-                Ok(Expr::Super { method_name, token })
+                Ok(Expr::Super {
+                    method_name,
+                    token,
+                    id: ExprId::new(),
+                })
             }
             TokenType::OpenParenthesis => {
                 let body = self.expr()?;
                 self.consume(TokenType::CloseParenthesis, ErrorType::UnclosedParenthesis)?;
                 Ok(Expr::Grouping {
                     expr: Box::new(body),
+                    span: self.span_from(_token.span()),
                 })
             }
+            TokenType::Semicolon
+            | TokenType::Coma
+            | TokenType::CloseParenthesis
+            | TokenType::CloseBrace
+            | TokenType::EOF
+                if recovering =>
+            {
+                // the token closes what the expression was part of, the rest of it still parses
+                self.current -= 1;
+                self.recovered
+                    .push(Error::new(&_token, ErrorType::UnparsableExpression));
+                Ok(Expr::Error { token: _token })
+            }
             _ => {
                 // TODO: figure out better name
                 debug!("Unparsable expression");
@@ -627,3 +1272,100 @@ impl<'a> Parser<'a> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Parser;
+    use crate::error::ErrorType;
+    use crate::interpreter::tests::{assert_error, evaluate};
+    use crate::lexer::Lexer;
+    use crate::token::Span;
+
+    #[test]
+    fn errors_point_at_whole_nodes() {
+        let error = |code: &str| evaluate(code).unwrap_err()[0].to_string();
+        assert_eq!(error("1 + (true);"), "1.1-10 : Unexpected type mismatch");
+        assert_eq!(
+            error("var x = 1;\nx(2, 3);"),
+            "2.1-7 : This value is not callable"
+        );
+        assert_eq!(error("var x = 1 +;"), "1.12-12 : Unparsable expression");
+    }
+
+    #[test]
+    fn nodes_have_spans() {
+        let tokens = Lexer::new("print (1 + 2) * 3;\nif (true) {\n}")
+            .scan_tokens()
+            .unwrap();
+        let stmts = Parser::new(&tokens).parse_tokens().unwrap();
+        assert_eq!(
+            stmts[0].span(),
+            Span {
+                line: 1,
+                start: 1,
+                end: 18
+            }
+        );
+        let expr = stmts[0].as_print().unwrap().0;
+        assert_eq!(
+            expr.span(),
+            Span {
+                line: 1,
+                start: 7,
+                end: 17
+            }
+        );
+        let grouping = expr.as_binary().unwrap().0;
+        assert_eq!(
+            grouping.span(),
+            Span {
+                line: 1,
+                start: 7,
+                end: 13
+            }
+        );
+        assert_eq!(
+            stmts[1].span(),
+            Span {
+                line: 2,
+                start: 1,
+                end: 11
+            }
+        );
+    }
+
+    #[test]
+    fn deeply_nested_code_is_an_error() {
+        // debug builds take a lot more stack for every level than the 2 MiB test threads have
+        std::thread::Builder::new()
+            .stack_size(8 * 1024 * 1024)
+            .spawn(nested_code)
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    fn nested_code() {
+        let nested = |open: &str, inner: &str, close: &str, times: usize| {
+            format!("{}{}{};", open.repeat(times), inner, close.repeat(times))
+        };
+        for code in &[
+            nested("(", "1", ")", 10_000),
+            nested("-", "1", "", 10_000),
+            nested("", "1", " + 1", 10_000),
+            nested("", "f", "()", 10_000),
+            nested("{", "", "}", 10_000),
+            nested("if (true) ", "1", "", 10_000),
+        ] {
+            assert_error(code, ErrorType::NestedTooDeeply);
+        }
+        assert_eq!(
+            evaluate(&nested("(", "1", " + 1)", 90))
+                .unwrap()
+                .as_number(),
+            Some(&91.0)
+        );
+        assert_eq!(Parser::new(&Vec::new()).parse_tokens().unwrap().len(), 0);
+        assert!(Parser::new(&Vec::new()).parse_expression().is_err());
+    }
+}
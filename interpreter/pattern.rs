@@ -0,0 +1,127 @@
+use crate::expr::Expr;
+use crate::token::{Literal, Token};
+use serde::{Deserialize, Serialize};
+
+/// What a `match` arm compares the value with, the names in it are bound to the parts they match
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Pattern {
+    /// Values equal to the literal, the way `==` compares them
+    Literal { value: Literal, token: Token },
+    /// Anything, bound to the name unless it's `_`
+    Binding { name: String, token: Token },
+    /// `[first, second, ...rest]`, lists of as many items, or of at least as many with a rest
+    List {
+        items: Vec<Pattern>,
+        rest: Option<String>,
+        token: Token,
+    },
+    /// `Point { x, y: 0 }`, instances having the fields, of the class or its subclasses when there's one.
+    /// A field without a pattern, like `x` here, is bound to its own name.
+    Instance {
+        class: Option<Expr>,
+        fields: Vec<(String, Pattern)>,
+        token: Token,
+    },
+}
+
+impl Pattern {
+    /// First token of the pattern
+    pub fn token(&self) -> &Token {
+        match self {
+            Pattern::Literal { token, .. }
+            | Pattern::Binding { token, .. }
+            | Pattern::List { token, .. }
+            | Pattern::Instance { token, .. } => token,
+        }
+    }
+
+    /// Names the pattern binds, in source order
+    pub fn bindings(&self) -> Vec<&String> {
+        let mut names = Vec::new();
+        self.collect_bindings(&mut names);
+        names
+    }
+
+    fn collect_bindings<'a>(&'a self, names: &mut Vec<&'a String>) {
+        match self {
+            Pattern::Literal { .. } => (),
+            Pattern::Binding { name, .. } => {
+                if name != "_" {
+                    names.push(name);
+                }
+            }
+            Pattern::List { items, rest, .. } => {
+                for item in items {
+                    item.collect_bindings(names);
+                }
+                if let Some(rest) = rest.as_ref().filter(|rest| *rest != "_") {
+                    names.push(rest);
+                }
+            }
+            Pattern::Instance { fields, .. } => {
+                for (_, field) in fields {
+                    field.collect_bindings(names);
+                }
+            }
+        }
+    }
+
+    /// Whether it matches every value the other one does, so an arm with the other one after it is never reached
+    pub fn covers(&self, other: &Pattern) -> bool {
+        match (self, other) {
+            (Pattern::Binding { .. }, _) => true,
+            (Pattern::Literal { value: a, .. }, Pattern::Literal { value: b, .. }) => a == b,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::ErrorType;
+    use crate::interpreter::tests::{assert_error, assert_true};
+
+    #[test]
+    fn matches_values_against_patterns() {
+        let describe = "class Point { var x = 0; var y = 0; }\n\
+             fn pack(...items) { return items; }\n\
+             fn describe(value) {\n\
+                 var found = \"nothing\";\n\
+                 match (value) {\n\
+                     0 => found = \"zero\";\n\
+                     -1 => found = \"minus one\";\n\
+                     [] => found = \"empty\";\n\
+                     [first, [inner], ...rest] => found = str(first + inner) + \" and \" + str(len(rest));\n\
+                     Point { x: 0, y } => found = \"on the y axis at \" + str(y);\n\
+                     { x } => found = \"x is \" + str(x);\n\
+                     \"skip\" => {}\n\
+                     _ => found = \"other\";\n\
+                 }\n\
+                 return found;\n\
+             }\n";
+        for case in [
+            "describe(0) == \"zero\"",
+            "describe(-1) == \"minus one\"",
+            "describe(pack()) == \"empty\"",
+            "describe(pack(1, pack(2), 3, 4)) == \"3 and 2\"",
+            "describe(pack(1, 2)) == \"other\"",
+            "describe(Point(y: 2)) == \"on the y axis at 2\"",
+            "describe(Point(x: 1)) == \"x is 1\"",
+            "describe(\"skip\") == \"nothing\"",
+            "describe(true) == \"other\"",
+        ] {
+            assert_true(&format!("{}{};", describe, case));
+        }
+        // arms have their own scope and no arm has to match
+        assert_true("var x = 1; match (2) { x => {} } match (3) { 4 => x = 4; } x == 1;");
+        assert_error(
+            "match (1) { Nope { x } => {} }",
+            ErrorType::UndefinedVariable,
+        );
+        assert_error(
+            "var notClass = 1; class A {}\nmatch (A()) { notClass {} => {} }",
+            ErrorType::PatternClassNotClass,
+        );
+        assert_error("match (1) { + => {} }", ErrorType::ExpectedPattern);
+    }
+}
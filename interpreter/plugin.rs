@@ -0,0 +1,29 @@
+use crate::interpreter::Interpreter;
+use libloading::{Library, Symbol};
+
+/// Every plugin exports a function with this name, which registers its natives:
+///
+/// ```ignore
+/// #[no_mangle]
+/// pub fn lox_plugin_register(interpreter: &mut Interpreter) {
+///     interpreter.define_native("double", 1, |args| ...);
+/// }
+/// ```
+///
+/// Interpreter is passed with the Rust ABI, so plugins have to be built with the same compiler
+/// and the same version of this crate as the host.
+pub const REGISTER_SYMBOL: &str = "lox_plugin_register";
+
+type Register = fn(&mut Interpreter);
+
+/// Loads the shared library and lets it register its natives.
+/// Natives may outlive the interpreter (e.g. in forks), so the library stays loaded until the process exits.
+pub fn load_plugin(interpreter: &mut Interpreter, path: &str) -> Result<(), libloading::Error> {
+    unsafe {
+        let library = Library::new(path)?;
+        let register: Symbol<Register> = library.get(REGISTER_SYMBOL.as_bytes())?;
+        register(interpreter);
+        std::mem::forget(library);
+    }
+    Ok(())
+}
@@ -0,0 +1,179 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Lines with the most executed statements the report lists
+const HOT_LINES: usize = 10;
+
+/// Function as the profile tells it apart: by name and the line it's declared on, natives have line 0
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ProfiledFunction {
+    pub name: String,
+    pub line: usize,
+}
+
+/// Calls of a function and the time spent in them, including the functions they called.
+/// Recursive calls count once for the time, the outermost call already covers it.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FunctionProfile {
+    pub calls: u64,
+    pub time: Duration,
+}
+
+/// What a program did while profiling was on, see `Interpreter::set_profiling`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Profile {
+    /// Statements executed on each line, blocks aside
+    pub statements: BTreeMap<usize, u64>,
+    pub functions: BTreeMap<ProfiledFunction, FunctionProfile>,
+}
+
+impl Profile {
+    pub fn total_statements(&self) -> u64 {
+        self.statements.values().sum()
+    }
+}
+
+/// Collects a profile, timing the calls on the stack
+#[derive(Debug, Default)]
+pub(crate) struct Profiler {
+    profile: Profile,
+    calls: Vec<(ProfiledFunction, Instant)>,
+}
+
+impl Profiler {
+    pub fn profile(&self) -> &Profile {
+        &self.profile
+    }
+
+    pub fn count_statement(&mut self, line: usize) {
+        *self.profile.statements.entry(line).or_insert(0) += 1;
+    }
+
+    pub fn enter_call(&mut self, name: &str, line: usize) {
+        let function = ProfiledFunction {
+            name: name.to_owned(),
+            line,
+        };
+        self.profile
+            .functions
+            .entry(function.clone())
+            .or_default()
+            .calls += 1;
+        self.calls.push((function, Instant::now()));
+    }
+
+    pub fn exit_call(&mut self) {
+        if let Some((function, start)) = self.calls.pop() {
+            if self.calls.iter().all(|(active, _)| *active != function) {
+                let elapsed = start.elapsed();
+                if let Some(profile) = self.profile.functions.get_mut(&function) {
+                    profile.time += elapsed;
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for ProfiledFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            0 => write!(f, "{} (native)", self.name),
+            line => write!(f, "{} (line {})", self.name, line),
+        }
+    }
+}
+
+/// Functions from the slowest, then the lines executed the most
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut functions: Vec<_> = self.functions.iter().collect();
+        functions.sort_by(|(a_name, a), (b_name, b)| b.time.cmp(&a.time).then(a_name.cmp(b_name)));
+        let names: Vec<String> = functions.iter().map(|(name, _)| name.to_string()).collect();
+        let width = names
+            .iter()
+            .map(|name| name.chars().count())
+            .chain(Some("Function".len()))
+            .max()
+            .unwrap_or_default();
+        writeln!(
+            f,
+            "{:width$}  {:>8}  {:>10}",
+            "Function", "Calls", "Time (ms)"
+        )?;
+        for (name, (_, profile)) in names.iter().zip(&functions) {
+            writeln!(
+                f,
+                "{:width$}  {:>8}  {:>10.3}",
+                name,
+                profile.calls,
+                profile.time.as_secs_f64() * 1000.0
+            )?;
+        }
+
+        let mut lines: Vec<_> = self.statements.iter().collect();
+        lines.sort_by(|(a_line, a), (b_line, b)| b.cmp(a).then(a_line.cmp(b_line)));
+        writeln!(f)?;
+        writeln!(f, "Statements executed: {}", self.total_statements())?;
+        write!(f, "{:>6}  {:>10}", "Line", "Executions")?;
+        for (line, count) in lines.into_iter().take(HOT_LINES) {
+            write!(f, "\n{:>6}  {:>10}", line, count)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Profile, ProfiledFunction, Profiler};
+    use crate::interpreter::Interpreter;
+
+    #[test]
+    fn times_recursive_calls_once() {
+        let mut profiler = Profiler::default();
+        profiler.enter_call("f", 1);
+        profiler.enter_call("f", 1);
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        profiler.exit_call();
+        profiler.exit_call();
+        profiler.count_statement(2);
+        profiler.count_statement(2);
+        profiler.count_statement(3);
+
+        let profile = profiler.profile();
+        let f = profile.functions[&ProfiledFunction {
+            name: String::from("f"),
+            line: 1,
+        }];
+        assert_eq!(f.calls, 2);
+        // timed twice it would be at least 40 ms
+        assert!(f.time.as_millis() >= 20 && f.time.as_millis() < 40);
+        let report = profile.to_string();
+        assert!(report.starts_with("Function       Calls   Time (ms)\nf (line 1)         2"));
+        assert!(report.ends_with(
+            "Statements executed: 3\n  Line  Executions\n     2           2\n     3           1"
+        ));
+    }
+
+    #[test]
+    fn profiles_calls_and_statements() {
+        let mut interpreter = Interpreter::new();
+        interpreter.run("fn f(n) { return n; }").unwrap();
+        assert_eq!(interpreter.profile_report(), Profile::default());
+        interpreter.set_profiling(true);
+        interpreter
+            .run("for (var i = 0; i < 3; i = i + 1) f(len(\"ab\"));")
+            .unwrap();
+        let profile = interpreter.profile_report();
+        let calls = |name: &str, line| {
+            profile.functions[&ProfiledFunction {
+                name: name.to_owned(),
+                line,
+            }]
+                .calls
+        };
+        assert_eq!((calls("f", 1), calls("len", 0)), (3, 3));
+        // `var i` and the loop run once, the call and the return in f three times each
+        assert_eq!(profile.total_statements(), 8);
+    }
+}
@@ -0,0 +1,242 @@
+use crate::error::ErrorType;
+use crate::function::Function;
+use crate::interpreter::Interpreter;
+use crate::natives::NativeResult;
+use crate::runtime_value::{List, Value};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// Built-in method, called with the value it was looked up on and the arguments.
+/// The interpreter is there for the resource limits of methods growing their value.
+type Method = fn(&mut Interpreter, &Value, &[Value]) -> NativeResult;
+type Prototype = HashMap<&'static str, (usize, Method)>;
+
+lazy_static! {
+    static ref STRING: Prototype = {
+        let mut map: Prototype = HashMap::new();
+        map.insert(
+            "len",
+            (0, |_, s, _| {
+                Ok(Value::Number(string(s).chars().count() as f64))
+            }),
+        );
+        map.insert(
+            "upper",
+            (0, |_, s, _| Ok(Value::String(string(s).to_uppercase()))),
+        );
+        map.insert(
+            "lower",
+            (0, |_, s, _| Ok(Value::String(string(s).to_lowercase()))),
+        );
+        map.insert(
+            "trim",
+            (0, |_, s, _| Ok(Value::String(string(s).trim().to_owned()))),
+        );
+        map.insert(
+            "contains",
+            (1, |_, s, args| {
+                Ok(Value::Boolean(string(s).contains(string_arg(&args[0])?)))
+            }),
+        );
+        map.insert(
+            "startsWith",
+            (1, |_, s, args| {
+                Ok(Value::Boolean(string(s).starts_with(string_arg(&args[0])?)))
+            }),
+        );
+        map.insert(
+            "endsWith",
+            (1, |_, s, args| {
+                Ok(Value::Boolean(string(s).ends_with(string_arg(&args[0])?)))
+            }),
+        );
+        map.insert("indexOf", (1, index_of));
+        map
+    };
+    static ref NUMBER: Prototype = {
+        let mut map: Prototype = HashMap::new();
+        map.insert("floor", (0, |_, n, _| Ok(Value::Number(number(n).floor()))));
+        map.insert("ceil", (0, |_, n, _| Ok(Value::Number(number(n).ceil()))));
+        map.insert("round", (0, |_, n, _| Ok(Value::Number(number(n).round()))));
+        map.insert("abs", (0, |_, n, _| Ok(Value::Number(number(n).abs()))));
+        map.insert("sqrt", (0, |_, n, _| Ok(Value::Number(number(n).sqrt()))));
+        map
+    };
+    static ref LIST: Prototype = {
+        let mut map: Prototype = HashMap::new();
+        map.insert(
+            "len",
+            (0, |_, l, _| {
+                Ok(Value::Number(list(l).borrow().len() as f64))
+            }),
+        );
+        map.insert(
+            "push",
+            (1, |interpreter, l, args| {
+                interpreter.grow_list(list(l).borrow().len() + 1)?;
+                list(l).borrow_mut().push(args[0].clone());
+                Ok(Value::Null)
+            }),
+        );
+        map.insert(
+            "pop",
+            (0, |_, l, _| {
+                Ok(list(l).borrow_mut().pop().unwrap_or(Value::Null))
+            }),
+        );
+        map.insert(
+            "get",
+            (1, |_, l, args| {
+                let index = index_arg(l, &args[0])?;
+                Ok(list(l).borrow()[index].clone())
+            }),
+        );
+        map.insert(
+            "set",
+            (2, |_, l, args| {
+                let index = index_arg(l, &args[0])?;
+                list(l).borrow_mut()[index] = args[1].clone();
+                Ok(Value::Null)
+            }),
+        );
+        map
+    };
+}
+
+fn string(value: &Value) -> &str {
+    value
+        .as_string()
+        .expect("string method called on another type")
+}
+
+fn number(value: &Value) -> f64 {
+    *value
+        .as_number()
+        .expect("number method called on another type")
+}
+
+fn list(value: &Value) -> &List {
+    value.as_list().expect("list method called on another type")
+}
+
+/// Index of an existing item of the list
+pub(crate) fn index_arg(l: &Value, index: &Value) -> Result<usize, ErrorType> {
+    checked_index(index, list(l).borrow().len())
+}
+
+/// Character at the index, strings are indexed by characters rather than bytes
+pub(crate) fn char_at(s: &str, index: &Value) -> Result<char, ErrorType> {
+    let i = checked_index(index, s.chars().count())?;
+    Ok(s.chars().nth(i).expect("index checked against the length"))
+}
+
+fn checked_index(index: &Value, len: usize) -> Result<usize, ErrorType> {
+    match index {
+        Value::Number(i) if i.fract() == 0.0 => {
+            if *i >= 0.0 && (*i as usize) < len {
+                Ok(*i as usize)
+            } else {
+                Err(ErrorType::IndexOutOfRange)
+            }
+        }
+        _ => Err(ErrorType::WrongType),
+    }
+}
+
+fn string_arg(value: &Value) -> Result<&str, ErrorType> {
+    value
+        .as_string()
+        .map(|s| s.as_str())
+        .ok_or(ErrorType::WrongType)
+}
+
+/// Index in characters of the first occurrence, -1 if there is none
+fn index_of(_: &mut Interpreter, s: &Value, args: &[Value]) -> NativeResult {
+    let s = string(s);
+    let index = s
+        .find(string_arg(&args[0])?)
+        .map_or(-1.0, |byte| s[..byte].chars().count() as f64);
+    Ok(Value::Number(index))
+}
+
+/// Looks the method up in the prototype of the value's type and binds it to the value,
+/// so `"abc".len` is a function that can be called later like any other
+pub fn get_method(value: &Value, name: &str) -> Option<Value> {
+    let prototype: &Prototype = match value {
+        Value::String(_) => &STRING,
+        Value::Number(_) => &NUMBER,
+        Value::List(_) => &LIST,
+        _ => return None,
+    };
+    let (arity, method) = *prototype.get(name)?;
+    let receiver = value.clone();
+    Some(Value::Function(Function::Native {
+        name: format!("{}.{}", value.type_name(), name),
+        arity,
+        optional: 0,
+        variadic: false,
+        body: Rc::new(move |interpreter, args| method(interpreter, &receiver, args)),
+    }))
+}
+
+/// Whether values of this type have a prototype, and so properties that can be looked up
+pub fn has_prototype(value: &Value) -> bool {
+    matches!(value, Value::String(_) | Value::Number(_) | Value::List(_))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::ErrorType;
+    use crate::interpreter::tests::{assert_error, assert_true, evaluate, format_code};
+
+    #[test]
+    fn iterates_and_indexes_strings_by_character() {
+        assert_true("var s = \"h\u{e9}!\"; s[1] == \"\u{e9}\" and len(chars(s)) == 3 and chars(s)[2] == \"!\";");
+        assert_true(
+            "var out = \"\"; for (var c in \"abc\") { if (c == \"b\") continue; out = c + out; } out == \"ca\";",
+        );
+        assert_true(
+            "var sum = 0; var l = list(); l.push(1); l.push(2); l.push(3);\n\
+             for (var n in l) { if (n == 3) break; l.push(n); sum = sum + n; } sum == 3 and l.len() == 5;",
+        );
+        assert_true(
+            "var fs = list(); for (var c in \"ab\") fs.push(|| => c); fs[0]() + fs[1]() == \"ab\";",
+        );
+        assert_true("codePointAt(\"a\u{e9}\", 1) == 233 and fromCodePoint(97) + fromCodePoint(233) == \"a\u{e9}\";");
+        assert_error("\"abc\"[3];", ErrorType::IndexOutOfRange);
+        assert_error(
+            "var s = \"abc\"; s[0] = \"x\";",
+            ErrorType::StringNotMutable,
+        );
+        assert_error("for (var x in 1) {}", ErrorType::ValueNotIterable);
+        assert_error("fromCodePoint(55296);", ErrorType::InvalidCodePoint);
+        assert_error("codePointAt(\"a\", -1);", ErrorType::IndexOutOfRange);
+    }
+
+    #[test]
+    fn methods_on_primitives() {
+        assert_eq!(
+            evaluate("\"héllo\".len();").unwrap().as_number(),
+            Some(&5.0)
+        );
+        assert_eq!(format_code("\"  Lox \".trim().upper();"), "LOX");
+        assert_true("\"lox-rs\".startsWith(\"lox\") and \"lox-rs\".contains(\"-\");");
+        assert_eq!(
+            evaluate("\"héllo\".indexOf(\"l\");").unwrap().as_number(),
+            Some(&2.0)
+        );
+        assert_eq!(
+            evaluate("(12.5).floor();").unwrap().as_number(),
+            Some(&12.0)
+        );
+        assert_eq!(
+            evaluate("var n = -4; n.abs().sqrt();").unwrap().as_number(),
+            Some(&2.0)
+        );
+        assert_eq!(format_code("var upper = \"a\".upper; upper();"), "A");
+
+        assert_error("\"a\".missing();", ErrorType::PropertyDoesntExist);
+        assert_error("\"a\".contains(1);", ErrorType::WrongType);
+        assert_error("true.len();", ErrorType::ValueNotInstance);
+    }
+}
@@ -0,0 +1,111 @@
+// pyo3 0.22 macros trip this lint on every #[pymethods] function returning PyResult
+#![allow(clippy::useless_conversion)]
+
+use crate::error::{Error, ErrorType};
+use crate::interpreter::Interpreter;
+use crate::runtime_value::Value;
+use pyo3::exceptions::{PyRuntimeError, PyTypeError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBool, PyFloat, PyInt, PyList, PyString, PyTuple};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// Interpreter exposed to Python, values are converted both ways on every boundary
+#[pyclass(unsendable, name = "LoxInterpreter")]
+struct LoxInterpreter {
+    interpreter: Interpreter,
+}
+
+#[pymethods]
+impl LoxInterpreter {
+    #[new]
+    fn new() -> Self {
+        LoxInterpreter {
+            interpreter: Interpreter::new(),
+        }
+    }
+
+    /// Runs the source and returns the value of the last statement
+    fn eval(&mut self, py: Python, source: &str) -> PyResult<PyObject> {
+        match self.interpreter.run(source) {
+            Ok(value) => Ok(to_python(py, &value)),
+            Err(errors) => Err(to_exception(&errors)),
+        }
+    }
+
+    /// Calls a top level function or class
+    #[pyo3(signature = (name, *args))]
+    fn call(&mut self, py: Python, name: &str, args: &Bound<PyTuple>) -> PyResult<PyObject> {
+        let arguments = args
+            .iter()
+            .map(|arg| to_value(&arg))
+            .collect::<PyResult<Vec<Value>>>()?;
+        match self.interpreter.call_global(name, &arguments) {
+            Ok(value) => Ok(to_python(py, &value)),
+            Err(error) => Err(to_exception(&[error])),
+        }
+    }
+
+    /// Makes a Python callable available to scripts as a native function
+    fn register(&mut self, name: &str, arity: usize, function: PyObject) {
+        self.interpreter
+            .define_native(name, arity, move |args: &[Value]| {
+                Python::with_gil(|py| {
+                    let args = PyTuple::new_bound(py, args.iter().map(|arg| to_python(py, arg)));
+                    function
+                        .call1(py, args)
+                        .and_then(|result| to_value(result.bind(py)))
+                        .map_err(|e| ErrorType::Host(e.to_string()))
+                })
+            });
+    }
+}
+
+/// Values without a Python counterpart (functions, classes, instances) are passed as their string form
+fn to_python(py: Python, value: &Value) -> PyObject {
+    match value {
+        Value::Number(n) => n.into_py(py),
+        Value::String(s) => s.into_py(py),
+        Value::Boolean(b) => b.into_py(py),
+        Value::Null => py.None(),
+        // Python gets a copy, changes it makes aren't seen by the script
+        Value::List(list) => {
+            PyList::new_bound(py, list.borrow().iter().map(|item| to_python(py, item))).into_py(py)
+        }
+        Value::Function(_) | Value::Class(_) | Value::Instance(_) => value.to_string().into_py(py),
+    }
+}
+
+fn to_value(object: &Bound<PyAny>) -> PyResult<Value> {
+    if object.is_none() {
+        Ok(Value::Null)
+    // bool is a subclass of int in Python, so it has to be checked first
+    } else if object.is_instance_of::<PyBool>() {
+        Ok(Value::Boolean(object.extract()?))
+    } else if object.is_instance_of::<PyInt>() || object.is_instance_of::<PyFloat>() {
+        Ok(Value::Number(object.extract()?))
+    } else if object.is_instance_of::<PyString>() {
+        Ok(Value::String(object.extract()?))
+    } else if let Ok(list) = object.downcast::<PyList>() {
+        let items = list
+            .iter()
+            .map(|item| to_value(&item))
+            .collect::<PyResult<Vec<Value>>>()?;
+        Ok(Value::List(Rc::new(RefCell::new(items))))
+    } else {
+        Err(PyTypeError::new_err(format!(
+            "Can't convert {} to a Lox value",
+            object.get_type().name()?
+        )))
+    }
+}
+
+fn to_exception(errors: &[Error]) -> PyErr {
+    let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+    PyRuntimeError::new_err(messages.join("\n"))
+}
+
+#[pymodule]
+fn lox(module: &Bound<PyModule>) -> PyResult<()> {
+    module.add_class::<LoxInterpreter>()
+}
@@ -0,0 +1,180 @@
+use crate::error::Error;
+use crate::formatter::format_source;
+use crate::interpreter::Interpreter;
+use crate::lexer::Lexer;
+use crate::runtime_value::{format_number, Value};
+use crate::token::{Token, TokenType};
+
+/// Interpreter behind the REPL, it remembers every top-level function and class declaration
+/// as formatted source, so the session can be saved and opened later
+pub struct Session {
+    pub interpreter: Interpreter,
+    /// Name and formatted source of the latest declaration of every function and class, in the order they were declared
+    declarations: Vec<(String, String)>,
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Session {
+            interpreter: Interpreter::new(),
+            declarations: Vec::new(),
+        }
+    }
+
+    pub fn run(&mut self, source_code: &str) -> Result<Value, Vec<Error>> {
+        let value = self.interpreter.run(source_code)?;
+        for (name, declaration) in declarations(source_code) {
+            let declaration = format_source(&declaration)
+                .map_or(declaration, |formatted| formatted.trim_end().to_owned());
+            self.declarations.retain(|(declared, _)| *declared != name);
+            self.declarations.push((name, declaration));
+        }
+        Ok(value)
+    }
+
+    /// Source that recreates the current top-level bindings: variables holding numbers, strings, booleans
+    /// or null get their literal, functions and classes their latest declaration.
+    /// Anything else (instances, closures) is listed in a comment.
+    pub fn save(&self) -> String {
+        let env = self.interpreter.env.borrow();
+        let mut names: Vec<&String> = env.bindings().map(|(name, _)| name).collect();
+        names.sort();
+
+        let mut variables = String::new();
+        let mut unsaved = Vec::new();
+        for name in names {
+            let value = env.get(name).unwrap();
+            let is_declared = self.declarations.iter().any(|(n, _)| n == name);
+            match (literal(&value), &value) {
+                (Some(literal), _) => variables.push_str(&format!("var {} = {};\n", name, literal)),
+                (None, Value::Function(_)) | (None, Value::Class(_)) if is_declared => (),
+                _ => unsaved.push(format!("{} ({})", name, value)),
+            }
+        }
+
+        let mut source = variables;
+        for (name, declaration) in &self.declarations {
+            if let Some(Value::Function(_)) | Some(Value::Class(_)) = env.get(name) {
+                // declarations are set apart by a blank line, the way the formatter lays them out
+                if !source.is_empty() {
+                    source.push('\n');
+                }
+                source.push_str(declaration);
+                source.push('\n');
+            }
+        }
+        if !unsaved.is_empty() {
+            source.push_str(&format!("// not saved: {}\n", unsaved.join(", ")));
+        }
+        source
+    }
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Session::new()
+    }
+}
+
+/// Source of the value if it can be written as a literal
+fn literal(value: &Value) -> Option<String> {
+    match value {
+        Value::Number(n) if n.is_nan() => Some(String::from("0 / 0")),
+        Value::Number(n) if n.is_infinite() && *n > 0.0 => Some(String::from("1 / 0")),
+        Value::Number(n) if n.is_infinite() => Some(String::from("-1 / 0")),
+        Value::Number(n) => Some(format_number(*n)),
+        // strings have no escapes, so there is no way to write a quote inside of them
+        Value::String(s) if !s.contains('"') => Some(format!("\"{}\"", s)),
+        Value::Boolean(b) => Some(b.to_string()),
+        Value::Null => Some(String::from("null")),
+        _ => None,
+    }
+}
+
+/// Finds top-level function and class declarations and cuts their source out, from the keyword to the closing brace
+fn declarations(source_code: &str) -> Vec<(String, String)> {
+    let tokens = match Lexer::new(source_code).scan_tokens() {
+        Ok(tokens) => tokens,
+        Err(_) => return vec![],
+    };
+    let chars: Vec<char> = source_code.chars().collect();
+    let mut line_starts = vec![0];
+    line_starts.extend(
+        chars
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| **c == '\n')
+            .map(|(i, _)| i + 1),
+    );
+    let position = |token: &Token, column: usize| line_starts[token.line - 1] + column - 1;
+
+    let mut found = vec![];
+    let mut depth = 0;
+    let mut i = 0;
+    while i < tokens.len() {
+        let token = &tokens[i];
+        match (&token.token_type, tokens.get(i + 1).map(|t| &t.token_type)) {
+            (TokenType::Function, Some(TokenType::Identifier(name)))
+            | (TokenType::Class, Some(TokenType::Identifier(name)))
+                if depth == 0 =>
+            {
+                let mut body_depth = 0;
+                let end = tokens[i..].iter().position(|t| {
+                    match t.token_type {
+                        TokenType::OpenBrace => body_depth += 1,
+                        TokenType::CloseBrace => body_depth -= 1,
+                        _ => return false,
+                    }
+                    body_depth == 0
+                });
+                if let Some(end) = end {
+                    let close = &tokens[i + end];
+                    let text = &chars[position(token, token.start)..=position(close, close.end)];
+                    found.push((name.clone(), text.iter().collect()));
+                    i += end;
+                }
+            }
+            (TokenType::OpenBrace, _) => depth += 1,
+            (TokenType::CloseBrace, _) => depth -= 1,
+            _ => (),
+        }
+        i += 1;
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Session;
+
+    #[test]
+    fn saved_session_recreates_bindings() {
+        let mut session = Session::new();
+        session
+            .run("var count = 1;\nfn add(n) {\n    count = count + n;\n}\nclass Point { x() { return count; } }")
+            .unwrap();
+        session
+            .run("add(2); var name = \"lox\"; var nothing; var point = Point();")
+            .unwrap();
+        session
+            .run("fn add(n) { count = count + n * 10; }")
+            .unwrap();
+
+        let saved = session.save();
+        assert_eq!(
+            saved,
+            "var count = 3;\nvar name = \"lox\";\nvar nothing = null;\n\nclass Point {\n    x() {\n        return count;\n    }\n}\n\nfn add(n) {\n    count = count + n * 10;\n}\n// not saved: point (Point instance)\n"
+        );
+
+        let mut restored = Session::new();
+        restored.run(&saved).unwrap();
+        let count = restored.run("add(1); Point().x();").unwrap();
+        assert_eq!(count.as_number(), Some(&13.0));
+        assert_eq!(
+            restored.save(),
+            saved
+                .replace("var count = 3;", "var count = 13;")
+                .replace("// not saved: point (Point instance)\n", "")
+        );
+    }
+}
@@ -1,33 +1,15 @@
+use crate::environment::Slot;
 use crate::error::{Error, ErrorType};
-use crate::expr::{Expr, Visitor as ExprVisitor};
+use crate::expr::{Expr, ExprId, Visitor as ExprVisitor};
 use crate::interpreter::Interpreter;
-use crate::statement::{Stmt, Visitor as StmtVisitor};
-use crate::token::{Literal, Token};
+use crate::pattern::Pattern;
+use crate::scopes::{ScopeKind, ScopeReport};
+use crate::statement::{Arm, Catch, Stmt, Visitor as StmtVisitor};
+use crate::token::{Literal, Span, Token, TokenType};
+use crate::types::{Signature, Type};
 use log::debug;
 use std::collections::{HashMap, LinkedList};
-
-/// Distance to the variable from the scope it is referenced in
-#[derive(Clone, Debug)]
-pub struct VarRef {
-    token: Token,
-    pub name: String,
-}
-
-impl VarRef {
-    pub fn new(token: &Token, name: &String) -> Self {
-        VarRef {
-            token: token.clone(),
-            name: name.clone(),
-        }
-    }
-
-    pub fn to_string(&self) -> String {
-        format!(
-            "{}-{}-{}-{}",
-            self.token.line, self.token.start, self.token.end, self.name
-        )
-    }
-}
+use std::rc::Rc;
 
 #[derive(Debug, PartialEq, PartialOrd)]
 pub enum ClassType {
@@ -35,17 +17,37 @@ pub enum ClassType {
     Class,
 }
 
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum FunctionType {
+    Function,
+    Method,
+    Initializer,
+}
+
+/// Name declared in a scope, with its index among the scope's locals
+#[derive(Debug, Clone, Copy)]
+struct Local {
+    index: usize,
+    /// Whether its initializer has been resolved, before that the name can't be read
+    ready: bool,
+}
+
 #[derive(Debug)]
 pub struct ResolverState {
     pub current_class: Option<ClassType>,
+    pub current_function: Option<FunctionType>,
     pub inside_loop: bool,
+    /// Fields assigned through `this` by the constructor being resolved
+    pub initialized_fields: Vec<String>,
 }
 
 impl ResolverState {
     pub fn new() -> Self {
         ResolverState {
             current_class: None,
+            current_function: None,
             inside_loop: false,
+            initialized_fields: Vec::new(),
         }
     }
 
@@ -59,17 +61,37 @@ impl ResolverState {
 
 pub struct Resolver<'a> {
     interpreter: &'a mut Interpreter,
-    scopes: LinkedList<HashMap<String, bool>>,
+    scopes: LinkedList<HashMap<String, Local>>,
     pub state: ResolverState,
+    /// Errors found so far, the resolution goes on after every one of them
+    errors: Vec<Error>,
+    /// Code that's resolved fine, but likely isn't what was meant, see `take_warnings`
+    warnings: Vec<Error>,
+    /// Strict resolvers report shadowed variables as errors instead of warnings
+    strict: bool,
+    report: Option<ScopeReport>,
+    /// Statement being resolved, where the names declared now are reported to come from
+    statement: Span,
 }
 
 type ResolverResult = Result<(), Error>;
 
 fn error(token: &Token, error_type: ErrorType) -> ResolverResult {
-    Err(Error {
-        token: token.clone(),
-        error_type,
-    })
+    Err(Error::new(token, error_type))
+}
+
+/// How many locals the resolver numbers among the statements of a block or body, declarations are the only ones.
+/// Frames running them are created with room for that many.
+pub(crate) fn locals(stmts: &[Stmt]) -> usize {
+    stmts
+        .iter()
+        .filter(|stmt| {
+            matches!(
+                stmt,
+                Stmt::Var { .. } | Stmt::Function { .. } | Stmt::Class { .. }
+            )
+        })
+        .count()
 }
 
 impl<'a> Resolver<'a> {
@@ -77,135 +99,276 @@ impl<'a> Resolver<'a> {
         let mut scopes = LinkedList::new();
         // add the top "global" like scope
         scopes.push_back(HashMap::new());
+        let strict = interpreter.is_strict();
         Resolver {
             interpreter,
             scopes,
             state: ResolverState::new(),
+            errors: Vec::new(),
+            warnings: Vec::new(),
+            strict,
+            report: None,
+            statement: Span::default(),
         }
     }
 
-    fn resolve_stmt(&mut self, stmt: &Stmt) -> ResolverResult {
-        stmt.accept(self)
+    /// Keeps track of the scopes and references it resolves, for `take_report`
+    pub fn record_scopes(&mut self) {
+        let mut report = ScopeReport::default();
+        report.begin_scope(ScopeKind::TopLevel);
+        self.report = Some(report);
     }
 
-    pub fn resolve_stmts(&mut self, stmts: &Vec<Stmt>) -> Result<(), Vec<Error>> {
-        let mut errors: Vec<Error> = vec![];
-        for stmt in stmts {
-            match self.resolve_stmt(stmt) {
-                Err(e) => errors.push(e),
-                _ => (),
-            }
-        }
+    pub fn take_report(&mut self) -> Option<ScopeReport> {
+        self.report.take()
+    }
+
+    /// Warnings of the programs resolved so far
+    pub fn take_warnings(&mut self) -> Vec<Error> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    /// Resolves the whole program and reports every error in it, not only the first one
+    pub fn resolve(&mut self, stmts: &Vec<Stmt>) -> Result<(), Vec<Error>> {
+        self.resolve_stmts(stmts);
+        let errors = std::mem::take(&mut self.errors);
         match errors.is_empty() {
             true => Ok(()),
             false => Err(errors),
         }
     }
 
-    fn resolve_expr(&mut self, expr: &Expr) -> ResolverResult {
-        expr.accept(self)
+    /// Keeps the error of a node and goes on with its siblings
+    fn collect(&mut self, result: ResolverResult) {
+        if let Err(error) = result {
+            self.errors.push(error);
+        }
     }
 
-    fn resolve_distance(&mut self, distance: VarRef) {
-        for (depth, scope) in self.scopes.iter().rev().enumerate() {
-            if let Some(_) = scope.get(&distance.name) {
-                self.interpreter.resolve_distance(distance.clone(), depth);
-                return;
-            }
+    fn resolve_stmt(&mut self, stmt: &Stmt) {
+        let enclosing = std::mem::replace(&mut self.statement, stmt.span());
+        let result = stmt.accept(self);
+        self.collect(result);
+        self.statement = enclosing;
+    }
+
+    fn resolve_stmts(&mut self, stmts: &[Stmt]) {
+        for stmt in stmts {
+            self.resolve_stmt(stmt);
+        }
+    }
+
+    fn resolve_expr(&mut self, expr: &Expr) {
+        let result = expr.accept(self);
+        self.collect(result);
+    }
+
+    /// Tells the interpreter how many scopes away from the expression the variable lives, and where in that scope
+    fn resolve_distance(&mut self, name: &str, id: ExprId, span: Span) {
+        let found = self
+            .scopes
+            .iter()
+            .rev()
+            .enumerate()
+            .find_map(|(distance, scope)| scope.get(name).map(|local| (distance, local.index)));
+        if let Some((distance, index)) = found {
+            self.interpreter.resolve_slot(id, Slot { distance, index });
+        }
+        let depth = found.map(|(distance, _)| distance);
+        if let Some(report) = &mut self.report {
+            report.reference(name, span, depth);
         }
     }
 
     fn resolve_function(
         &mut self,
         params: &Vec<String>,
-        body: &Vec<Stmt>,
-    ) -> Result<(), Vec<Error>> {
-        self.begin_scope();
+        defaults: &[Expr],
+        body: &[Stmt],
+        function_type: FunctionType,
+    ) {
+        // default values are evaluated in the scope the function is declared in
+        for default in defaults {
+            self.resolve_expr(default);
+        }
+        let enclosing_function = self.state.current_function.replace(function_type);
+        // loops don't reach into functions declared inside of them
+        let enclosing_loop = std::mem::replace(&mut self.state.inside_loop, false);
+        self.begin_scope(ScopeKind::Function);
         for param in params {
             self.declare(param);
             self.define(param);
         }
-        self.resolve_stmts(body)?;
+        self.resolve_stmts(body);
         self.end_scope();
+        self.state.current_function = enclosing_function;
+        self.state.inside_loop = enclosing_loop;
+    }
+
+    /// Classes named by catch clauses have to be declared somewhere up the scopes or defined by the host
+    fn resolve_catch_class(&mut self, class: &Expr) -> ResolverResult {
+        if let Expr::Var { name, token, .. } = class {
+            let declared = self.scopes.iter().any(|scope| scope.contains_key(name));
+            if !declared && !self.interpreter.is_defined(name) {
+                return error(token, ErrorType::UndefinedCatchClass);
+            }
+        }
+        self.resolve_expr(class);
         Ok(())
     }
 
-    fn begin_scope(&mut self) {
+    /// Classes named by the instance patterns in the pattern
+    fn resolve_pattern(&mut self, pattern: &Pattern) {
+        match pattern {
+            Pattern::List { items, .. } => {
+                for item in items {
+                    self.resolve_pattern(item);
+                }
+            }
+            Pattern::Instance { class, fields, .. } => {
+                if let Some(class) = class {
+                    self.resolve_expr(class);
+                }
+                for (_, field) in fields {
+                    self.resolve_pattern(field);
+                }
+            }
+            Pattern::Literal { .. } | Pattern::Binding { .. } => (),
+        }
+    }
+
+    /// Under strict mode fields declared without a value have to be assigned by the constructor
+    fn check_fields_initialized(&mut self, token: &Token, members: &Vec<Stmt>) {
+        for member in members {
+            if let Stmt::Var {
+                name,
+                value: None,
+                span,
+                ..
+            } = member
+            {
+                if !self.state.initialized_fields.contains(name) {
+                    let error = Error::new(token, ErrorType::UninitializedField(name.clone()));
+                    self.errors.push(error.with_span(*span));
+                }
+            }
+        }
+    }
+
+    fn begin_scope(&mut self, kind: ScopeKind) {
         self.scopes.push_back(HashMap::new());
+        if let Some(report) = &mut self.report {
+            report.begin_scope(kind);
+        }
     }
 
     fn end_scope(&mut self) {
         self.scopes.pop_back();
+        if let Some(report) = &mut self.report {
+            report.end_scope();
+        }
     }
 
     fn declare(&mut self, name: &String) {
-        let scope = self.scopes.back_mut();
-        match scope {
-            Some(s) => {
-                if s.contains_key(name) {
-                } else {
-                    s.insert(name.clone(), false);
-                }
+        if let Some(report) = &mut self.report {
+            report.declare(name, self.statement);
+        }
+        // the top-level scope is global, where declaring a name again redefines it
+        if self.scopes.len() > 1 {
+            self.check_declaration(name);
+        }
+        if let Some(scope) = self.scopes.back_mut() {
+            let index = scope.len();
+            scope.entry(name.clone()).or_insert(Local {
+                index,
+                ready: false,
+            });
+        }
+    }
+
+    /// Local names can't be declared twice in the same scope, shadowing the ones of outer scopes
+    /// is only a warning unless the resolver is strict
+    fn check_declaration(&mut self, name: &String) {
+        let mut scopes = self.scopes.iter().rev();
+        let duplicate = matches!(scopes.next(), Some(scope) if scope.contains_key(name));
+        let shadowed = scopes.any(|scope| scope.contains_key(name));
+        let span = self.statement;
+        let token = Token::new(
+            TokenType::Identifier(name.clone()),
+            span.line,
+            span.start,
+            span.end,
+        );
+        if duplicate {
+            let error = Error::new(&token, ErrorType::AlreadyDeclared(name.clone()));
+            self.errors.push(error.with_span(span));
+        } else if shadowed {
+            let error =
+                Error::new(&token, ErrorType::ShadowedVariable(name.clone())).with_span(span);
+            match self.strict {
+                true => self.errors.push(error),
+                false => self.warnings.push(error),
             }
-            None => (),
         }
     }
 
     fn define(&mut self, name: &String) {
-        let scope = self.scopes.back_mut();
-        match scope {
-            Some(s) => {
-                s.insert(name.clone(), true);
-            }
-            None => (),
+        if let Some(local) = self.scopes.back_mut().and_then(|s| s.get_mut(name)) {
+            local.ready = true;
         }
     }
 }
 
 impl<'a> ExprVisitor<()> for Resolver<'a> {
     fn visit_binary(&mut self, left: &Expr, _operator: &Token, right: &Expr) -> ResolverResult {
-        self.resolve_expr(left)?;
-        self.resolve_expr(right)?;
+        self.resolve_expr(left);
+        self.resolve_expr(right);
         Ok(())
     }
 
-    fn visit_literal(&mut self, _literal: &Literal) -> ResolverResult {
+    fn visit_literal(&mut self, _literal: &Literal, _span: &Span) -> ResolverResult {
         Ok(())
     }
     fn visit_unary(&mut self, _operator: &Token, expr: &Expr) -> ResolverResult {
-        self.resolve_expr(expr)?;
+        self.resolve_expr(expr);
         Ok(())
     }
 
     fn visit_grouping(&mut self, expr: &Expr) -> ResolverResult {
-        self.resolve_expr(expr)?;
+        self.resolve_expr(expr);
         Ok(())
     }
 
-    fn visit_var(&mut self, name: &String, token: &Token) -> ResolverResult {
+    fn visit_var(&mut self, name: &String, token: &Token, id: ExprId) -> ResolverResult {
         if let Some(s) = self.scopes.back() {
-            if let Some(is_ready) = s.get(name) {
-                if !is_ready {
-                    return Err(Error {
-                        token: token.clone(),
-                        error_type: ErrorType::CantUseVariableInItsInitializer,
-                    });
+            if let Some(local) = s.get(name) {
+                if !local.ready {
+                    return Err(Error::new(
+                        token,
+                        ErrorType::CantUseVariableInItsInitializer,
+                    ));
                 }
             }
         }
-        self.resolve_distance(VarRef::new(token, name));
+        self.resolve_distance(name, id, token.span());
         Ok(())
     }
 
-    fn visit_assignment(&mut self, name: &String, expr: &Expr, token: &Token) -> ResolverResult {
-        self.resolve_expr(expr)?;
-        self.resolve_distance(VarRef::new(token, name));
+    fn visit_assignment(
+        &mut self,
+        name: &String,
+        expr: &Expr,
+        token: &Token,
+        id: ExprId,
+    ) -> ResolverResult {
+        self.resolve_expr(expr);
+        self.resolve_distance(name, id, token.span());
         Ok(())
     }
 
     fn visit_logical(&mut self, left: &Expr, _operator: &Token, right: &Expr) -> ResolverResult {
-        self.resolve_expr(left)?;
-        self.resolve_expr(right)?;
+        self.resolve_expr(left);
+        self.resolve_expr(right);
         Ok(())
     }
 
@@ -214,11 +377,12 @@ impl<'a> ExprVisitor<()> for Resolver<'a> {
         callee: &Expr,
         _token: &Token,
         arguments: &Vec<Expr>,
+        named: &[(String, Expr)],
     ) -> ResolverResult {
-        self.resolve_expr(callee)?;
+        self.resolve_expr(callee);
 
-        for arg in arguments {
-            self.resolve_expr(arg)?;
+        for arg in arguments.iter().chain(named.iter().map(|(_, arg)| arg)) {
+            self.resolve_expr(arg);
         }
 
         Ok(())
@@ -226,67 +390,109 @@ impl<'a> ExprVisitor<()> for Resolver<'a> {
 
     fn visit_closure(
         &mut self,
-        params: &Vec<String>,
-        body: &Vec<Stmt>,
+        params: &Rc<Vec<String>>,
+        _signature: &Signature,
+        defaults: &Rc<Vec<Expr>>,
+        body: &Rc<Vec<Stmt>>,
         _name: &String,
         _token: &Token,
     ) -> ResolverResult {
-        self.resolve_function(params, body)?;
+        self.resolve_function(params, defaults, body, FunctionType::Function);
         Ok(())
     }
 
     fn visit_get(&mut self, _name: &String, _token: &Token, expr: &Expr) -> ResolverResult {
-        self.resolve_expr(expr)?;
+        self.resolve_expr(expr);
         Ok(())
     }
 
     fn visit_set(
         &mut self,
         _token: &Token,
-        _name: &String,
+        name: &String,
         value: &Expr,
         obj: &Expr,
     ) -> ResolverResult {
-        self.resolve_expr(value)?;
-        self.resolve_expr(obj)?;
+        if self.state.current_function == Some(FunctionType::Initializer)
+            && matches!(obj, Expr::This { .. })
+        {
+            self.state.initialized_fields.push(name.clone());
+        }
+        self.resolve_expr(value);
+        self.resolve_expr(obj);
         Ok(())
     }
 
-    fn visit_this(&mut self, token: &Token) -> ResolverResult {
+    fn visit_this(&mut self, token: &Token, id: ExprId) -> ResolverResult {
         if self.state.current_class.is_none() {
             return error(token, ErrorType::CantUseThis);
         }
-        self.resolve_distance(VarRef::new(token, &String::from("this")));
+        self.resolve_distance("this", id, token.span());
         Ok(())
     }
 
-    fn visit_super(&mut self, token: &Token, _method_name: &String) -> ResolverResult {
+    fn visit_super(&mut self, token: &Token, _method_name: &String, id: ExprId) -> ResolverResult {
         if let Some(ClassType::Subclass) = self.state.current_class {
             debug!("Can use super");
-            self.resolve_distance(VarRef::new(token, &String::from("super")));
+            self.resolve_distance("super", id, token.span());
             Ok(())
         } else {
             debug!("Cant use super {:?}", self.state.current_class);
             error(token, ErrorType::CantUseSuper)
         }
     }
+
+    fn visit_index(&mut self, object: &Expr, index: &Expr, _token: &Token) -> ResolverResult {
+        self.resolve_expr(object);
+        self.resolve_expr(index);
+        Ok(())
+    }
+
+    fn visit_set_index(
+        &mut self,
+        object: &Expr,
+        index: &Expr,
+        value: &Expr,
+        _token: &Token,
+    ) -> ResolverResult {
+        self.resolve_expr(object);
+        self.resolve_expr(index);
+        self.resolve_expr(value);
+        Ok(())
+    }
+
+    fn visit_spread(&mut self, expr: &Expr, _token: &Token) -> ResolverResult {
+        self.resolve_expr(expr);
+        Ok(())
+    }
+
+    // the parser reported it already, the rest of the tree is still resolved
+    fn visit_error(&mut self, _token: &Token) -> ResolverResult {
+        Ok(())
+    }
 }
 
 impl<'a> StmtVisitor<()> for Resolver<'a> {
     fn visit_print_stmt(&mut self, expr: &Expr) -> ResolverResult {
-        self.resolve_expr(expr)?;
+        self.resolve_expr(expr);
         Ok(())
     }
 
     fn visit_expr_stmt(&mut self, expr: &Expr) -> ResolverResult {
-        self.resolve_expr(expr)?;
+        self.resolve_expr(expr);
         Ok(())
     }
 
-    fn visit_var(&mut self, name: &String, expr: &Option<Expr>) -> ResolverResult {
+    fn visit_var(
+        &mut self,
+        name: &String,
+        expr: &Option<Expr>,
+        _annotation: &Option<Type>,
+        _span: &Span,
+    ) -> ResolverResult {
         self.declare(name);
         match expr {
-            Some(e) => self.resolve_expr(e)?,
+            Some(e) => self.resolve_expr(e),
             None => (),
         };
         self.define(name);
@@ -294,8 +500,8 @@ impl<'a> StmtVisitor<()> for Resolver<'a> {
     }
 
     fn visit_block_stmt(&mut self, stmts: &Vec<Stmt>) -> ResolverResult {
-        self.begin_scope();
-        self.resolve_stmts(stmts)?;
+        self.begin_scope(ScopeKind::Block);
+        self.resolve_stmts(stmts);
         self.end_scope();
         Ok(())
     }
@@ -306,19 +512,53 @@ impl<'a> StmtVisitor<()> for Resolver<'a> {
         then_body: &Stmt,
         else_body: &Option<Box<Stmt>>,
     ) -> ResolverResult {
-        self.resolve_expr(condition)?;
-        self.resolve_stmt(then_body)?;
+        self.resolve_expr(condition);
+        self.resolve_stmt(then_body);
         if let Some(stmt) = else_body {
-            self.resolve_stmt(stmt)?;
+            self.resolve_stmt(stmt);
+        }
+        Ok(())
+    }
+
+    fn visit_while_stmt(
+        &mut self,
+        condition: &Expr,
+        body: &Stmt,
+        increment: &Option<Expr>,
+    ) -> ResolverResult {
+        let enclosing_loop = std::mem::replace(&mut self.state.inside_loop, true);
+        self.resolve_expr(condition);
+        self.resolve_stmt(body);
+        if let Some(increment) = increment {
+            self.resolve_expr(increment);
         }
+        self.state.inside_loop = enclosing_loop;
         Ok(())
     }
 
-    fn visit_while_stmt(&mut self, condition: &Expr, body: &Stmt) -> ResolverResult {
-        self.state.inside_loop = true;
-        self.resolve_expr(condition)?;
-        self.resolve_stmt(body)?;
-        self.state.inside_loop = false;
+    fn visit_do_while_stmt(&mut self, body: &Stmt, condition: &Expr) -> ResolverResult {
+        let enclosing_loop = std::mem::replace(&mut self.state.inside_loop, true);
+        self.resolve_stmt(body);
+        self.resolve_expr(condition);
+        self.state.inside_loop = enclosing_loop;
+        Ok(())
+    }
+
+    fn visit_for_in_stmt(
+        &mut self,
+        name: &String,
+        iterable: &Expr,
+        body: &Stmt,
+        _token: &Token,
+    ) -> ResolverResult {
+        self.resolve_expr(iterable);
+        let enclosing_loop = std::mem::replace(&mut self.state.inside_loop, true);
+        self.begin_scope(ScopeKind::Loop);
+        self.declare(name);
+        self.define(name);
+        self.resolve_stmt(body);
+        self.end_scope();
+        self.state.inside_loop = enclosing_loop;
         Ok(())
     }
 
@@ -334,19 +574,21 @@ impl<'a> StmtVisitor<()> for Resolver<'a> {
     fn visit_function_stmt(
         &mut self,
         name: &String,
-        params: &Vec<String>,
-        body: &Vec<Stmt>,
+        params: &Rc<Vec<String>>,
+        _signature: &Signature,
+        defaults: &Rc<Vec<Expr>>,
+        body: &Rc<Vec<Stmt>>,
         _token: &Token,
     ) -> Result<(), Error> {
         self.declare(name);
         self.define(name);
-        self.resolve_function(params, body)?;
+        self.resolve_function(params, defaults, body, FunctionType::Function);
         Ok(())
     }
     fn visit_class_stmt(
         &mut self,
         name: &String,
-        _token: &Token,
+        token: &Token,
         members: &Vec<Stmt>,
         superclass: &Option<Expr>,
     ) -> ResolverResult {
@@ -354,40 +596,332 @@ impl<'a> StmtVisitor<()> for Resolver<'a> {
         self.define(name);
 
         if let Some(sc) = superclass {
-            self.state.current_class = Some(ClassType::Subclass);
-            let (sc_name, sc_token) = sc.as_var().expect("Expected Expr::Var");
-            if sc_name == name {
-                return error(sc_token, ErrorType::CantInheritFromItself);
+            if let Some((sc_name, sc_token, _)) = sc.as_var() {
+                if sc_name == name {
+                    let error = Error::new(sc_token, ErrorType::CantInheritFromItself);
+                    self.errors.push(error);
+                }
             }
-            self.resolve_expr(sc)?;
-            self.scopes
-                .back_mut()
-                .unwrap()
-                .insert("super".to_owned(), true);
-        } else {
-            self.state.current_class = Some(ClassType::Class);
+            self.resolve_expr(sc);
         }
 
-        self.begin_scope();
+        // field values are evaluated for every new instance in the scope the class is declared in, before there's a this
+        for member in members {
+            if let Stmt::Var {
+                value: Some(value), ..
+            } = member
+            {
+                let enclosing_statement = std::mem::replace(&mut self.statement, member.span());
+                self.resolve_expr(value);
+                self.statement = enclosing_statement;
+            }
+        }
 
-        self.scopes
-            .back_mut()
-            .unwrap()
-            .insert("this".to_owned(), true);
+        let enclosing_class = self.state.current_class.replace(match superclass {
+            Some(_) => ClassType::Subclass,
+            None => ClassType::Class,
+        });
+        let enclosing_fields = std::mem::take(&mut self.state.initialized_fields);
+        if superclass.is_some() {
+            self.begin_scope(ScopeKind::Superclass);
+            self.declare(&String::from("super"));
+            self.define(&String::from("super"));
+        }
 
-        for stmt in members {
-            if let Some((params, body, ..)) = stmt.as_function() {
-                self.resolve_function(params, body)?;
+        self.begin_scope(ScopeKind::Class);
+        self.declare(&String::from("this"));
+        self.define(&String::from("this"));
+
+        for member in members {
+            if let Some((params, _, defaults, body, name, _)) = member.as_function() {
+                let function_type = if name == self.interpreter.dialect().constructor_name() {
+                    FunctionType::Initializer
+                } else {
+                    FunctionType::Method
+                };
+                let enclosing_statement = std::mem::replace(&mut self.statement, member.span());
+                self.resolve_function(params, defaults, body, function_type);
+                self.statement = enclosing_statement;
             }
         }
         self.end_scope();
-        self.state.current_class = None;
+        if superclass.is_some() {
+            self.end_scope();
+        }
+        if self.interpreter.is_strict() {
+            self.check_fields_initialized(token, members);
+        }
+        self.state.current_class = enclosing_class;
+        self.state.initialized_fields = enclosing_fields;
         Ok(())
     }
-    fn visit_return_stmt(&mut self, value: &Option<Expr>, _token: &Token) -> ResolverResult {
+    fn visit_return_stmt(&mut self, value: &Option<Expr>, token: &Token) -> ResolverResult {
         if let Some(val) = value {
-            self.resolve_expr(val)?;
+            self.resolve_expr(val);
+        }
+        match (self.state.current_function, value) {
+            (None, _) => error(token, ErrorType::CantReturnOutsideFunction),
+            (Some(FunctionType::Initializer), Some(_)) => {
+                error(token, ErrorType::CantReturnValueFromConstructor)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn visit_try_stmt(
+        &mut self,
+        body: &[Stmt],
+        catches: &[Catch],
+        finally: &Option<Vec<Stmt>>,
+        _token: &Token,
+    ) -> ResolverResult {
+        self.begin_scope(ScopeKind::Block);
+        self.resolve_stmts(body);
+        self.end_scope();
+        for Catch { name, class, body } in catches {
+            if let Some(class) = class {
+                let result = self.resolve_catch_class(class);
+                self.collect(result);
+            }
+            self.begin_scope(ScopeKind::Catch);
+            self.declare(name);
+            self.define(name);
+            self.resolve_stmts(body);
+            self.end_scope();
+        }
+        if let Some(finally) = finally {
+            self.visit_block_stmt(finally)?;
         }
         Ok(())
     }
+
+    fn visit_throw_stmt(&mut self, value: &Expr, _token: &Token) -> ResolverResult {
+        self.resolve_expr(value);
+        Ok(())
+    }
+
+    fn visit_match_stmt(&mut self, value: &Expr, arms: &[Arm], _token: &Token) -> ResolverResult {
+        self.resolve_expr(value);
+        for (i, Arm { pattern, body }) in arms.iter().enumerate() {
+            if arms[..i].iter().any(|arm| arm.pattern.covers(pattern)) {
+                self.warnings
+                    .push(Error::new(pattern.token(), ErrorType::UnreachableArm));
+            }
+            self.resolve_pattern(pattern);
+            self.begin_scope(ScopeKind::Arm);
+            for name in pattern.bindings() {
+                self.declare(name);
+                self.define(name);
+            }
+            self.resolve_stmt(body);
+            self.end_scope();
+        }
+        Ok(())
+    }
+
+    fn visit_error_stmt(&mut self, _token: &Token, _end: &Token) -> ResolverResult {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::ErrorType;
+    use crate::function::Function;
+    use crate::interpreter::tests::{
+        assert_error, assert_true, assert_true_in, evaluate, format_code,
+    };
+    use crate::interpreter::Interpreter;
+    use crate::runtime_value::Value;
+
+    #[test]
+    fn resolutions_from_separate_runs_dont_collide() {
+        let mut interpreter = Interpreter::new();
+        // both `return v` share the same position but live at different depths
+        interpreter
+            .run("fn outer() { var v = 1; { return v; } }")
+            .unwrap();
+        interpreter
+            .run("fn other() { var v = 2;   return v;   }")
+            .unwrap();
+        let value = interpreter.run("outer();").unwrap();
+        assert_eq!(value.as_number(), Some(&1.0));
+    }
+
+    #[test]
+    fn locals_are_found_by_slot() {
+        let mut interpreter = Interpreter::without_prelude();
+        interpreter
+            .run("var top = 0; fn f(a, b) { var c = a + b; { var d = c; top = d; } return c; } f(1, 2);")
+            .unwrap();
+        let slots = match interpreter.env.borrow().get("f") {
+            Some(Value::Function(Function::Standard { slots, .. })) => slots,
+            _ => panic!("f isn't a function"),
+        };
+        let mut slots: Vec<(usize, usize)> = slots
+            .borrow()
+            .values()
+            .map(|slot| (slot.distance, slot.index))
+            .collect();
+        slots.sort();
+        // d and c in the blocks, f at the top level, a, b and c a scope up, top three scopes up
+        assert_eq!(
+            slots,
+            vec![(0, 0), (0, 0), (0, 1), (1, 0), (1, 0), (1, 1), (3, 0)]
+        );
+        assert_true_in(&mut interpreter, "top == 3;");
+
+        assert_true(
+            "fn f() { var a = 1; { var b = 2; var a = 3; b = a + b; return b; } } f() == 5;",
+        );
+        assert_true("var fs = list(); for (var i = 0; i < 3; i = i + 1) { var j = i; fs.push(|| => j); } fs.get(2)() == 2;");
+        assert_true("class A { m() { return 1; } } class B : A { m() { var x = super.m(); return x + 1; } } B().m() == 2;");
+        assert_true("fn f() { var a = 1; try { throw 2; } catch (e) { var b = e; a = a + b; } return a; } f() == 3;");
+    }
+
+    #[test]
+    fn super_is_resolved_lexically() {
+        let classes = "
+            class A { method() { return \"A\"; } }
+            class B : A {
+                method() { return \"B\" + super.method(); }
+                test() { return super.method(); }
+            }
+            class C : B {}
+        ";
+        assert_eq!(format_code(&format!("{} C().test();", classes)), "A");
+        assert_eq!(format_code(&format!("{} C().method();", classes)), "BA");
+        assert_eq!(
+            format_code(&format!("{} var m = C().test; m();", classes)),
+            "A"
+        );
+        assert_error(
+            "class A { m() { return super.m(); } }",
+            ErrorType::CantUseSuper,
+        );
+    }
+
+    #[test]
+    fn this_inside_nested_functions() {
+        let code = "
+            class Box {
+                var value = 1;
+                getter() { fn get() { return this.value; } return get; }
+            }
+            var box = Box();
+            var get = box.getter();
+            box.value = 2;
+            get();
+        ";
+        assert_eq!(evaluate(code).unwrap().as_number(), Some(&2.0));
+    }
+
+    #[test]
+    fn class_members_resolve_like_other_code() {
+        assert_true(
+            "class A {\n\
+                 var n = 1;\n\
+                 m() {\n\
+                     fn helper(x) { return x + this.n; }\n\
+                     var f = |y| => { fn inner() { return this.n + y; } return inner(); };\n\
+                     return helper(1) + f(2);\n\
+                 }\n\
+             }\n\
+             A().m() == 5;",
+        );
+        // field values see the variables where the class is declared, not ones declared later
+        assert_true(
+            "var a = \"outer\"; var found;\n\
+             { class C { var show = || => a; } var c = C(); var a = \"inner\"; found = c.show(); }\n\
+             found == \"outer\";",
+        );
+        assert_true(
+            "fn make(k) { class E { var f = |x| => { fn twice() { return x * 2 + k; } return twice(); }; } return E(); }\n\
+             make(1).f(3) == 7;",
+        );
+        assert_error("class D { var f = || => this; }", ErrorType::CantUseThis);
+    }
+
+    #[test]
+    fn resolver_reports_every_error() {
+        let code = "fn f() {\n    { break; }\n    fn g() { print this; continue; }\n}\nclass A : A { m() { return super.m(); } }\nreturn this;";
+        let errors = evaluate(code).unwrap_err();
+        let found: Vec<(String, usize)> = errors
+            .iter()
+            .map(|e| (e.error_type.code(), e.span.line))
+            .collect();
+        let expected = [
+            ("NotAllowedOutsideLoop", 2),
+            ("CantUseThis", 3),
+            ("NotAllowedOutsideLoop", 3),
+            ("CantInheritFromItself", 5),
+            ("CantUseThis", 6),
+            ("CantReturnOutsideFunction", 6),
+        ];
+        let expected: Vec<(String, usize)> = expected
+            .iter()
+            .map(|(code, line)| (code.to_string(), *line))
+            .collect();
+        assert_eq!(found, expected);
+    }
+
+    #[test]
+    fn assignments_never_declare() {
+        assert_true("fn f() { var a = 1; return a = 2; } f() == 2;");
+        assert_true("var a = 1; { var a = 2; a = 3; } a == 1;");
+        assert_true("var c = 1; { fn g() { c = 5; } g(); } c == 5;");
+        assert_true("fn f() { var a = 1; fn g() { a = a + 1; } g(); g(); return a; } f() == 3;");
+        assert_error("b = 1;", ErrorType::UndefinedVariable);
+        assert_error("{ b = 1; }", ErrorType::UndefinedVariable);
+        assert_error("fn f() { b = 1; } f();", ErrorType::UndefinedVariable);
+
+        let mut interpreter = Interpreter::new();
+        assert!(interpreter.run("{ b = 1; }").is_err());
+        assert!(interpreter.run("b;").is_err());
+        interpreter.define_host_constant("LIMIT", Value::Number(1.0));
+        let errors = interpreter.run("LIMIT = 2;").unwrap_err();
+        assert!(matches!(
+            errors[0].error_type,
+            ErrorType::AssignmentToHostConstant
+        ));
+    }
+
+    #[test]
+    fn locals_are_declared_once_per_scope() {
+        assert_error(
+            "{ var a = 1; var a = 2; }",
+            ErrorType::AlreadyDeclared(String::new()),
+        );
+        assert_error("fn f(a, a) {}", ErrorType::AlreadyDeclared(String::new()));
+        assert_true("var a = 1; var a = a + 1; a == 2;");
+        assert_true("var a = 1; { var a = 2; { var a = 3; } } a == 1;");
+
+        let mut interpreter = Interpreter::new();
+        interpreter.set_strict(true);
+        let errors = interpreter
+            .run("var a = 1;\n{\n    var a = 2;\n}")
+            .unwrap_err();
+        assert_eq!(
+            errors[0].to_string(),
+            "3.5-14 : Variable a shadows a variable of an outer scope"
+        );
+    }
+
+    #[test]
+    fn return_needs_a_function() {
+        assert_error("return 1;", ErrorType::CantReturnOutsideFunction);
+        assert_error("{ return; }", ErrorType::CantReturnOutsideFunction);
+        assert_error(
+            "class A { constructor() { return 1; } }",
+            ErrorType::CantReturnValueFromConstructor,
+        );
+        assert_true(
+            "class A { constructor() { this.ok = true; return; this.ok = false; } } A().ok;",
+        );
+        assert_true("fn f() { return; } f() == null;");
+        assert_true(
+            "class A { constructor() { fn inner() { return true; } this.ok = inner(); } } A().ok;",
+        );
+        assert_true("fn f() { class A {} return true; } f();");
+    }
 }
@@ -3,17 +3,22 @@ use crate::function::Function;
 use crate::token::Literal;
 use std::cell::RefCell;
 use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::mem;
 use std::rc::Rc;
 
 type Instance = Rc<RefCell<ClassInstance>>;
+/// Lists are shared, changes made through one reference are seen through all of them
+pub type List = Rc<RefCell<Vec<Value>>>;
 #[derive(Clone, EnumAsInner)]
 pub enum Value {
     Function(Function),
     String(String),
     Number(f64),
     Boolean(bool),
-    Class(Class),
+    Class(Rc<Class>),
     Instance(Instance),
+    List(List),
     Null,
 }
 
@@ -27,26 +32,130 @@ impl Value {
         }
     }
 
+    /// Only null and false are falsey, like in the book
     pub fn to_bool(&self) -> bool {
         match self {
-            Value::String(val) => val.len() > 0,
             Value::Boolean(val) => *val,
             Value::Null => false,
-            Value::Class(_) | Value::Number(_) | Value::Function(_) | Value::Instance(_) => true,
+            Value::String(_)
+            | Value::Class(_)
+            | Value::Number(_)
+            | Value::Function(_)
+            | Value::Instance(_)
+            | Value::List(_) => true,
         }
     }
+
+    /// Like `to_string`, but strings are quoted, so they can be told apart from other values
+    pub fn repr(&self) -> String {
+        match self {
+            Value::String(s) => format!("{:?}", s),
+            value => value.to_string(),
+        }
+    }
+
+    /// Numbers are equal by value, strings by contents. Values of different types are never equal,
+    /// functions, classes, instances and lists are equal only to themselves, see `Function::is_same`.
+    pub fn is_equal(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Number(a), Value::Number(b)) => a == b,
+            (Value::String(a), Value::String(b)) => a == b,
+            (Value::Boolean(a), Value::Boolean(b)) => a == b,
+            (Value::Null, Value::Null) => true,
+            (Value::Class(a), Value::Class(b)) => a.is_same(b),
+            (Value::Instance(a), Value::Instance(b)) => Rc::ptr_eq(a, b),
+            (Value::List(a), Value::List(b)) => Rc::ptr_eq(a, b),
+            (Value::Function(a), Value::Function(b)) => a.is_same(b),
+            _ => false,
+        }
+    }
+}
+
+/// Same as `==` in Lox, see `Value::is_equal`
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        self.is_equal(other)
+    }
+}
+
+/// Value that can be a key of a hash map, keys are equal when the values are `==`.
+/// NaN isn't equal even to itself, so it can't be a key.
+#[derive(Clone, PartialEq)]
+pub struct ValueKey(Value);
+
+impl ValueKey {
+    pub fn new(value: &Value) -> Option<ValueKey> {
+        match value {
+            Value::Number(n) if n.is_nan() => None,
+            _ => Some(ValueKey(value.clone())),
+        }
+    }
+}
+
+impl Eq for ValueKey {}
+
+/// Keys hash by what makes values equal: numbers and strings by contents, the rest by identity
+impl Hash for ValueKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        mem::discriminant(&self.0).hash(state);
+        match &self.0 {
+            // zero and negative zero are equal
+            Value::Number(n) => (n + 0.0).to_bits().hash(state),
+            Value::String(s) => s.hash(state),
+            Value::Boolean(b) => b.hash(state),
+            Value::Null => (),
+            Value::Function(function) => function.identity().hash(state),
+            Value::Class(class) => Rc::as_ptr(class).hash(state),
+            Value::Instance(instance) => Rc::as_ptr(instance).hash(state),
+            Value::List(list) => Rc::as_ptr(list).hash(state),
+        }
+    }
+}
+
+/// Numbers are printed like in jlox, integral values don't have a decimal part
+pub fn format_number(num: f64) -> String {
+    if num.is_nan() {
+        String::from("NaN")
+    } else if num.is_infinite() {
+        String::from(if num > 0.0 { "Infinity" } else { "-Infinity" })
+    } else {
+        num.to_string()
+    }
+}
+
+impl Value {
+    /// Name of the value's type, as returned by the `type` native
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Function(_) => "function",
+            Value::String(_) => "string",
+            Value::Number(_) => "number",
+            Value::Boolean(_) => "boolean",
+            Value::Class(_) => "class",
+            Value::Instance(_) => "instance",
+            Value::List(_) => "list",
+            Value::Null => "null",
+        }
+    }
+}
+
+/// `[1, "a", [true]]`, strings are quoted so their elements can be told apart
+fn format_list(list: &List) -> String {
+    let items: Vec<String> = list.borrow().iter().map(Value::repr).collect();
+    format!("[{}]", items.join(", "))
 }
 
 impl fmt::Display for Value {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         let str = match self {
             Value::String(str) => str.to_string(),
-            Value::Number(num) => format!("{}", num).to_string(),
+            Value::Number(num) => format_number(*num),
             Value::Boolean(b) => b.to_string(),
             Value::Function(fun) => fun.to_string(),
             Value::Null => "null".to_string(),
             Value::Class(class) => class.to_string(),
             Value::Instance(instance) => instance.borrow().to_string(),
+            Value::List(list) => format_list(list),
         };
         fmt.write_str(&str)?;
         Ok(())
@@ -57,14 +166,47 @@ impl fmt::Debug for Value {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         let str = match self {
             Value::String(str) => str.to_string(),
-            Value::Number(num) => format!("{}", num).to_string(),
+            Value::Number(num) => format_number(*num),
             Value::Boolean(b) => b.to_string(),
             Value::Function(fun) => fun.to_string(),
             Value::Null => "null".to_string(),
             Value::Class(class) => class.to_string(),
             Value::Instance(instance) => instance.borrow().to_string(),
+            Value::List(list) => format_list(list),
         };
         fmt.write_str(&str)?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Value, ValueKey};
+    use crate::interpreter::tests::evaluate;
+
+    #[test]
+    fn values_hash_like_they_compare() {
+        use std::collections::HashSet;
+        let values = evaluate(
+            "var l = list(); fn f() {} l.push(1); l.push(-0); l.push(0); l.push(\"a\"); l.push(\"a\");
+             l.push(f); l.push(f); l.push(list()); l.push(list()); l.push(null); l;",
+        )
+        .unwrap();
+        let values = values.as_list().unwrap().borrow();
+        let unique: HashSet<ValueKey> = values.iter().filter_map(ValueKey::new).collect();
+        // -0 and 0 are equal, every list is a different one
+        assert_eq!(unique.len(), 7);
+        assert_eq!(values[1], Value::Number(0.0));
+        assert_ne!(Value::Number(f64::NAN), Value::Number(f64::NAN));
+        assert!(ValueKey::new(&Value::Number(f64::NAN)).is_none());
+    }
+
+    #[test]
+    fn numbers_print_like_jlox() {
+        let printed = |code| evaluate(code).unwrap().to_string();
+        assert_eq!(printed("3;"), "3");
+        assert_eq!(printed("7 / 2;"), "3.5");
+        assert_eq!(printed("0 / 0;"), "NaN");
+        assert_eq!(printed("-1 / 0;"), "-Infinity");
+    }
+}
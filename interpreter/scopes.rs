@@ -0,0 +1,208 @@
+use crate::error::Error;
+use crate::interpreter::Interpreter;
+use crate::lexer::Lexer;
+use crate::parser::Parser;
+use crate::resolver::Resolver;
+use crate::token::Span;
+use serde::Serialize;
+use std::fmt;
+
+/// What created a scope
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ScopeKind {
+    TopLevel,
+    Block,
+    Function,
+    /// Holds `this` for the methods
+    Class,
+    /// Holds `super` for the methods of a subclass
+    Superclass,
+    Catch,
+    /// Holds the names bound by the pattern of a match arm
+    Arm,
+    /// Holds the variable of a for-in loop, a new one for every item
+    Loop,
+}
+
+impl fmt::Display for ScopeKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match self {
+            ScopeKind::TopLevel => "top-level",
+            ScopeKind::Block => "block",
+            ScopeKind::Function => "function",
+            ScopeKind::Class => "class",
+            ScopeKind::Superclass => "superclass",
+            ScopeKind::Catch => "catch",
+            ScopeKind::Arm => "match arm",
+            ScopeKind::Loop => "for-in loop",
+        };
+        write!(f, "{}", kind)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Scope {
+    pub id: usize,
+    pub kind: ScopeKind,
+    pub parent: Option<usize>,
+    /// In the order they were declared
+    pub names: Vec<String>,
+    /// Span of the statement declaring each name, parameters point at their function
+    pub sites: Vec<Span>,
+}
+
+/// Scope a reference's variable was declared in, `depth` scopes up from the reference
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Resolution {
+    pub scope: usize,
+    pub depth: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Reference {
+    pub name: String,
+    pub span: Span,
+    /// Innermost scope the reference is in
+    pub in_scope: usize,
+    /// None for globals, they're looked up by name when the program runs
+    pub resolution: Option<Resolution>,
+}
+
+/// Every scope the resolver went through and where each variable reference points to
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ScopeReport {
+    pub scopes: Vec<Scope>,
+    pub references: Vec<Reference>,
+    /// Ids of the scopes the resolver is in, innermost last
+    #[serde(skip)]
+    open: Vec<usize>,
+}
+
+impl ScopeReport {
+    pub(crate) fn begin_scope(&mut self, kind: ScopeKind) {
+        let id = self.scopes.len();
+        self.scopes.push(Scope {
+            id,
+            kind,
+            parent: self.open.last().copied(),
+            names: Vec::new(),
+            sites: Vec::new(),
+        });
+        self.open.push(id);
+    }
+
+    pub(crate) fn end_scope(&mut self) {
+        self.open.pop();
+    }
+
+    pub(crate) fn declare(&mut self, name: &str, site: Span) {
+        let scopes = &mut self.scopes;
+        if let Some(scope) = self.open.last().and_then(|id| scopes.get_mut(*id)) {
+            if !scope.names.iter().any(|n| n == name) {
+                scope.names.push(name.to_owned());
+                scope.sites.push(site);
+            }
+        }
+    }
+
+    pub(crate) fn reference(&mut self, name: &str, span: Span, depth: Option<usize>) {
+        let resolution = depth.map(|depth| Resolution {
+            scope: self.open[self.open.len() - 1 - depth],
+            depth,
+        });
+        self.references.push(Reference {
+            name: name.to_owned(),
+            span,
+            in_scope: self.open.last().copied().unwrap_or_default(),
+            resolution,
+        });
+    }
+
+    fn write_scope(&self, f: &mut fmt::Formatter, scope: &Scope, indent: usize) -> fmt::Result {
+        write!(
+            f,
+            "{}scope {} {}",
+            "  ".repeat(indent),
+            scope.id,
+            scope.kind
+        )?;
+        if scope.names.is_empty() {
+            writeln!(f)?;
+        } else {
+            writeln!(f, ": {}", scope.names.join(", "))?;
+        }
+        for child in self.scopes.iter().filter(|s| s.parent == Some(scope.id)) {
+            self.write_scope(f, child, indent + 1)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for ScopeReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for scope in self.scopes.iter().filter(|s| s.parent.is_none()) {
+            self.write_scope(f, scope, 0)?;
+        }
+        writeln!(f, "references:")?;
+        for reference in &self.references {
+            let Span { line, start, end } = reference.span;
+            write!(f, "  {}:{}-{} {} -> ", line, start, end, reference.name)?;
+            match reference.resolution {
+                Some(Resolution { scope, depth }) => {
+                    writeln!(f, "scope {}, depth {}", scope, depth)?
+                }
+                None => writeln!(f, "global")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Resolves the program without running it and reports its scopes, see `ScopeReport`
+pub fn scope_report(source_code: &str) -> Result<ScopeReport, Vec<Error>> {
+    let mut lexer = Lexer::new(source_code);
+    let tokens = lexer.scan_tokens()?;
+    let mut parser = Parser::new(&tokens);
+    let stmts = parser.parse_tokens()?;
+    let mut interpreter = Interpreter::new();
+    let mut resolver = Resolver::new(&mut interpreter);
+    resolver.record_scopes();
+    resolver.resolve(&stmts)?;
+    Ok(resolver.take_report().unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::scope_report;
+
+    #[test]
+    fn reports_scopes_and_references() {
+        let report = scope_report(
+            "var a = 1;\nfn f(x) {\n    { var y = x + a; }\n    return clock;\n}\nclass C { m() { return this; } }",
+        )
+        .unwrap();
+        assert_eq!(
+            report.to_string(),
+            "scope 0 top-level: a, f, C
+  scope 1 function: x
+    scope 2 block
+      scope 3 block: y
+  scope 4 class: this
+    scope 5 function
+      scope 6 block
+references:
+  3:15-15 x -> scope 1, depth 2
+  3:19-19 a -> scope 0, depth 3
+  4:12-16 clock -> global
+  6:24-27 this -> scope 4, depth 2
+"
+        );
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["scopes"][3]["names"][0], "y");
+        assert_eq!(json["scopes"][3]["sites"][0]["line"], 3);
+        assert_eq!(json["scopes"][1]["sites"][0]["start"], 4);
+        assert_eq!(json["references"][2]["resolution"], serde_json::Value::Null);
+        assert_eq!(json["references"][2]["in_scope"], 2);
+    }
+}
@@ -0,0 +1,393 @@
+use crate::class::Class;
+use crate::environment::{Environment, Slot, SlotTable};
+use crate::expr::{Expr, ExprId};
+use crate::function::Function;
+use crate::runtime_value::Value;
+use crate::statement::Stmt;
+use crate::stdlib::is_namespace;
+use crate::token::Token;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+/// Why an interpreter couldn't be saved or restored
+#[derive(Debug, Clone, PartialEq)]
+pub enum SnapshotError {
+    /// The binding holds a value that can't be saved, like an instance or a function closing over a local scope
+    Unsupported(String),
+    /// The bytes weren't produced by `Interpreter::snapshot` of this version
+    Invalid(String),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SnapshotError::Unsupported(name) => write!(f, "Can't snapshot the value of {}", name),
+            SnapshotError::Invalid(reason) => write!(f, "Invalid snapshot: {}", reason),
+        }
+    }
+}
+
+type Env = Rc<RefCell<Environment>>;
+
+/// Environment a saved function closes over
+#[derive(Clone, Copy, Serialize, Deserialize)]
+enum Scope {
+    Globals,
+    TopLevel,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedFunction {
+    params: Rc<Vec<String>>,
+    defaults: Rc<Vec<Expr>>,
+    variadic: bool,
+    name: String,
+    body: Rc<Vec<Stmt>>,
+    token: Token,
+    scope: Scope,
+}
+
+#[derive(Serialize, Deserialize)]
+enum SavedValue {
+    Null,
+    Boolean(bool),
+    Number(f64),
+    String(String),
+    Function(SavedFunction),
+    /// Index into the saved classes, so classes shared by many bindings stay a single class
+    Class(usize),
+}
+
+/// Classes come after their superclasses
+#[derive(Serialize, Deserialize)]
+struct SavedClass {
+    name: String,
+    superclass: Option<usize>,
+    fields: Vec<(String, Option<Expr>)>,
+    methods: Vec<(String, SavedFunction)>,
+    /// Where the field values are evaluated
+    scope: Scope,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct SavedScope {
+    bindings: Vec<(String, SavedValue)>,
+    read_only: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Snapshot {
+    classes: Vec<SavedClass>,
+    globals: SavedScope,
+    top_level: SavedScope,
+    slots: Vec<(ExprId, Slot)>,
+}
+
+/// Native functions aren't saved, the restoring host defines them again
+pub(crate) fn save(globals: &Env, top_level: &Env) -> Result<Vec<u8>, SnapshotError> {
+    let mut saver = Saver {
+        globals,
+        top_level,
+        classes: Vec::new(),
+        saved_classes: HashMap::new(),
+        slots: HashMap::new(),
+    };
+    let globals = saver.scope(globals)?;
+    let top_level = saver.scope(top_level)?;
+    let snapshot = Snapshot {
+        classes: saver.classes,
+        globals,
+        top_level,
+        slots: saver.slots.into_iter().collect(),
+    };
+    bincode::serialize(&snapshot).map_err(|e| SnapshotError::Invalid(e.to_string()))
+}
+
+/// Defines the saved bindings in the given environments
+pub(crate) fn restore(bytes: &[u8], globals: &Env, top_level: &Env) -> Result<(), SnapshotError> {
+    let snapshot: Snapshot = ExprId::renumbering(|| bincode::deserialize(bytes))
+        .map_err(|e| SnapshotError::Invalid(e.to_string()))?;
+    let mut restorer = Restorer {
+        globals,
+        top_level,
+        classes: Vec::new(),
+        slots: Rc::new(RefCell::new(snapshot.slots.into_iter().collect())),
+    };
+    for class in snapshot.classes {
+        restorer.class(class)?;
+    }
+    restorer.scope(snapshot.globals, globals)?;
+    restorer.scope(snapshot.top_level, top_level)?;
+    Ok(())
+}
+
+struct Saver<'a> {
+    globals: &'a Env,
+    top_level: &'a Env,
+    classes: Vec<SavedClass>,
+    saved_classes: HashMap<*const Class, usize>,
+    slots: HashMap<ExprId, Slot>,
+}
+
+impl Saver<'_> {
+    fn scope(&mut self, env: &Env) -> Result<SavedScope, SnapshotError> {
+        let env = env.borrow();
+        let mut scope = SavedScope::default();
+        for (name, value) in env.bindings() {
+            match value {
+                Value::Function(Function::Native { .. }) => continue,
+                Value::Instance(instance) if is_namespace(&instance.borrow()) => continue,
+                _ => (),
+            }
+            scope
+                .bindings
+                .push((name.clone(), self.value(name, value)?));
+        }
+        scope.read_only = env.read_only().cloned().collect();
+        Ok(scope)
+    }
+
+    fn value(&mut self, name: &str, value: &Value) -> Result<SavedValue, SnapshotError> {
+        Ok(match value {
+            Value::Null => SavedValue::Null,
+            Value::Boolean(b) => SavedValue::Boolean(*b),
+            Value::Number(n) => SavedValue::Number(*n),
+            Value::String(s) => SavedValue::String(s.clone()),
+            Value::Function(function) => {
+                SavedValue::Function(self.function(name, function, false)?)
+            }
+            Value::Class(class) => SavedValue::Class(self.class(name, class)?),
+            Value::Instance(_) | Value::List(_) => {
+                return Err(SnapshotError::Unsupported(name.to_owned()))
+            }
+        })
+    }
+
+    /// Methods of subclasses close over an environment holding `super`, right inside the class' scope
+    fn function(
+        &mut self,
+        name: &str,
+        function: &Function,
+        is_subclass_method: bool,
+    ) -> Result<SavedFunction, SnapshotError> {
+        let unsupported = || SnapshotError::Unsupported(name.to_owned());
+        match function {
+            Function::Standard {
+                params,
+                defaults,
+                variadic,
+                name: function_name,
+                body,
+                token,
+                closure,
+                slots,
+            } => {
+                self.save_slots(slots);
+                let closure = if is_subclass_method {
+                    closure.borrow().enclosing().ok_or_else(unsupported)?
+                } else {
+                    Rc::clone(closure)
+                };
+                let scope = self.scope_of(&closure).ok_or_else(unsupported)?;
+                Ok(SavedFunction {
+                    params: Rc::clone(params),
+                    defaults: Rc::clone(defaults),
+                    variadic: *variadic,
+                    name: function_name.clone(),
+                    body: Rc::clone(body),
+                    token: token.clone(),
+                    scope,
+                })
+            }
+            Function::Native { .. } => Err(unsupported()),
+        }
+    }
+
+    /// Slots of the programs that declared the saved functions and classes
+    fn save_slots(&mut self, slots: &SlotTable) {
+        self.slots
+            .extend(slots.borrow().iter().map(|(id, slot)| (*id, *slot)));
+    }
+
+    fn scope_of(&self, env: &Env) -> Option<Scope> {
+        if Rc::ptr_eq(env, self.globals) {
+            Some(Scope::Globals)
+        } else if Rc::ptr_eq(env, self.top_level) {
+            Some(Scope::TopLevel)
+        } else {
+            None
+        }
+    }
+
+    fn class(&mut self, name: &str, class: &Rc<Class>) -> Result<usize, SnapshotError> {
+        if let Some(index) = self.saved_classes.get(&Rc::as_ptr(class)) {
+            return Ok(*index);
+        }
+        let superclass = match class.superclass() {
+            Some(superclass) => Some(self.class(name, superclass)?),
+            None => None,
+        };
+        let fields = class
+            .fields()
+            .map(|(field, value)| (field.clone(), value.cloned()))
+            .collect();
+        let scope = self
+            .scope_of(class.scope())
+            .ok_or_else(|| SnapshotError::Unsupported(name.to_owned()))?;
+        self.save_slots(class.slots());
+        let mut methods = Vec::new();
+        for (method, function) in class.methods() {
+            let function = self.function(name, function, superclass.is_some())?;
+            methods.push((method.clone(), function));
+        }
+
+        self.classes.push(SavedClass {
+            name: class.to_string(),
+            superclass,
+            fields,
+            methods,
+            scope,
+        });
+        let index = self.classes.len() - 1;
+        self.saved_classes.insert(Rc::as_ptr(class), index);
+        Ok(index)
+    }
+}
+
+struct Restorer<'a> {
+    globals: &'a Env,
+    top_level: &'a Env,
+    classes: Vec<Rc<Class>>,
+    /// Everything restored shares one table
+    slots: SlotTable,
+}
+
+impl Restorer<'_> {
+    fn scope(&self, scope: SavedScope, env: &Env) -> Result<(), SnapshotError> {
+        for (name, value) in scope.bindings {
+            let value = self.value(value)?;
+            if scope.read_only.contains(&name) {
+                env.borrow_mut().define_read_only(&name, &value);
+            } else {
+                env.borrow_mut().bind(&name, &value);
+            }
+        }
+        Ok(())
+    }
+
+    fn env(&self, scope: Scope) -> &Env {
+        match scope {
+            Scope::Globals => self.globals,
+            Scope::TopLevel => self.top_level,
+        }
+    }
+
+    fn value(&self, value: SavedValue) -> Result<Value, SnapshotError> {
+        Ok(match value {
+            SavedValue::Null => Value::Null,
+            SavedValue::Boolean(b) => Value::Boolean(b),
+            SavedValue::Number(n) => Value::Number(n),
+            SavedValue::String(s) => Value::String(s),
+            SavedValue::Function(function) => Value::Function(self.function(function, None)),
+            SavedValue::Class(index) => Value::Class(self.saved_class(index)?),
+        })
+    }
+
+    fn function(&self, function: SavedFunction, superclass: Option<&Rc<Class>>) -> Function {
+        let env = self.env(function.scope);
+        let closure = match superclass {
+            Some(superclass) => {
+                let mut env = Environment::frame(env, 1);
+                env.bind("super", &Value::Class(Rc::clone(superclass)));
+                Rc::new(RefCell::new(env))
+            }
+            None => Rc::clone(env),
+        };
+        Function::Standard {
+            params: function.params,
+            defaults: function.defaults,
+            variadic: function.variadic,
+            name: function.name,
+            body: function.body,
+            token: function.token,
+            closure,
+            slots: Rc::clone(&self.slots),
+        }
+    }
+
+    fn saved_class(&self, index: usize) -> Result<Rc<Class>, SnapshotError> {
+        self.classes.get(index).cloned().ok_or_else(|| {
+            SnapshotError::Invalid(format!("class {} is used before it's saved", index))
+        })
+    }
+
+    fn class(&mut self, class: SavedClass) -> Result<(), SnapshotError> {
+        let superclass = match class.superclass {
+            Some(index) => Some(self.saved_class(index)?),
+            None => None,
+        };
+        let methods = class
+            .methods
+            .into_iter()
+            .map(|(name, method)| (name, self.function(method, superclass.as_ref())))
+            .collect();
+        self.classes.push(Rc::new(Class::from_parts(
+            class.name,
+            class.fields,
+            methods,
+            superclass,
+            Rc::clone(self.env(class.scope)),
+            Rc::clone(&self.slots),
+        )));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SnapshotError;
+    use crate::error::ErrorType;
+    use crate::interpreter::tests::assert_true_in;
+    use crate::interpreter::Interpreter;
+    use crate::runtime_value::Value;
+
+    #[test]
+    fn restores_snapshots() {
+        let mut warm = Interpreter::new();
+        warm.define_host_constant("version", Value::Number(2.0));
+        warm.run(
+            "var count = 1; fn add(a) { var sum = a; { sum = sum + count; } return sum; }
+            class Shape { var sides = 0; area() { return 0; } }
+            class Square : Shape { var size = 2; area() { return this.size * this.size + super.area(); } }
+            var Alias = Square;",
+        )
+        .unwrap();
+        let bytes = warm.snapshot().unwrap();
+
+        let mut interpreter = Interpreter::restore(&bytes).unwrap();
+        let value = interpreter
+            .run("count = count + 1; add(version) + Square().area() + Square().sides;")
+            .unwrap();
+        assert_eq!(value.as_number(), Some(&8.0));
+        assert_true_in(&mut interpreter, "Alias == Square;");
+        assert_true_in(
+            &mut interpreter,
+            "var caught = false; try { throw ResourceLimit(\"x\"); } catch (e: Error) { caught = true; } caught;",
+        );
+        let errors = interpreter.run("version = 3;").unwrap_err();
+        assert!(matches!(
+            errors[0].error_type,
+            ErrorType::AssignmentToHostConstant
+        ));
+
+        warm.run("var square = Square();").unwrap();
+        assert_eq!(
+            warm.snapshot().unwrap_err(),
+            SnapshotError::Unsupported(String::from("square"))
+        );
+        assert!(Interpreter::restore(&bytes[..10]).is_err());
+    }
+}
@@ -1,11 +1,21 @@
 use crate::error::Error;
 use crate::expr::Expr;
-use crate::token::Token;
+use crate::pattern::Pattern;
+use crate::token::{Span, Token};
+use crate::types::{Signature, Type};
+use serde::{Deserialize, Serialize};
+use std::rc::Rc;
 
 pub trait Visitor<R> {
     fn visit_print_stmt(&mut self, expr: &Expr) -> Result<R, Error>;
     fn visit_expr_stmt(&mut self, expr: &Expr) -> Result<R, Error>;
-    fn visit_var(&mut self, name: &String, value: &Option<Expr>) -> Result<R, Error>;
+    fn visit_var(
+        &mut self,
+        name: &String,
+        value: &Option<Expr>,
+        annotation: &Option<Type>,
+        span: &Span,
+    ) -> Result<R, Error>;
     fn visit_block_stmt(&mut self, stms: &Vec<Stmt>) -> Result<R, Error>;
     fn visit_if_stmt(
         &mut self,
@@ -13,14 +23,29 @@ pub trait Visitor<R> {
         then_body: &Stmt,
         else_body: &Option<Box<Stmt>>,
     ) -> Result<R, Error>;
-    fn visit_while_stmt(&mut self, condition: &Expr, body: &Stmt) -> Result<R, Error>;
+    fn visit_while_stmt(
+        &mut self,
+        condition: &Expr,
+        body: &Stmt,
+        increment: &Option<Expr>,
+    ) -> Result<R, Error>;
+    fn visit_do_while_stmt(&mut self, body: &Stmt, condition: &Expr) -> Result<R, Error>;
+    fn visit_for_in_stmt(
+        &mut self,
+        name: &String,
+        iterable: &Expr,
+        body: &Stmt,
+        token: &Token,
+    ) -> Result<R, Error>;
     fn visit_break_stmt(&mut self, token: &Token) -> Result<R, Error>;
     fn visit_continue_stmt(&mut self, token: &Token) -> Result<R, Error>;
     fn visit_function_stmt(
         &mut self,
         name: &String,
-        params: &Vec<String>,
-        body: &Vec<Stmt>,
+        params: &Rc<Vec<String>>,
+        signature: &Signature,
+        defaults: &Rc<Vec<Expr>>,
+        body: &Rc<Vec<Stmt>>,
         token: &Token,
     ) -> Result<R, Error>;
     fn visit_class_stmt(
@@ -31,31 +56,80 @@ pub trait Visitor<R> {
         superclass: &Option<Expr>,
     ) -> Result<R, Error>;
     fn visit_return_stmt(&mut self, value: &Option<Expr>, token: &Token) -> Result<R, Error>;
+    fn visit_try_stmt(
+        &mut self,
+        body: &[Stmt],
+        catches: &[Catch],
+        finally: &Option<Vec<Stmt>>,
+        token: &Token,
+    ) -> Result<R, Error>;
+    fn visit_throw_stmt(&mut self, value: &Expr, token: &Token) -> Result<R, Error>;
+    fn visit_match_stmt(&mut self, value: &Expr, arms: &[Arm], token: &Token) -> Result<R, Error>;
+    fn visit_error_stmt(&mut self, token: &Token, end: &Token) -> Result<R, Error>;
 }
 
-#[derive(Debug, Clone, EnumAsInner)]
+/// `catch (name) { body }` or `catch (name: Class) { body }`, the thrown value is bound to the name.
+/// Clauses with a class only handle instances of it and of its subclasses.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Catch {
+    pub name: String,
+    pub class: Option<Expr>,
+    pub body: Vec<Stmt>,
+}
+
+/// `pattern => body`, the body runs in a scope with the names the pattern binds
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Arm {
+    pub pattern: Pattern,
+    pub body: Stmt,
+}
+
+#[derive(Debug, Clone, EnumAsInner, Serialize, Deserialize)]
 pub enum Stmt {
     Print {
         expr: Expr,
+        span: Span,
     },
     Expr {
         expr: Expr,
+        span: Span,
     },
     Var {
         name: String,
         value: Option<Expr>,
+        annotation: Option<Type>,
+        span: Span,
     },
     Block {
         stmts: Vec<Stmt>,
+        span: Span,
     },
     If {
         condition: Expr,
         then_body: Box<Stmt>,
         else_body: Option<Box<Stmt>>,
+        span: Span,
     },
     While {
         condition: Expr,
         body: Box<Stmt>,
+        /// Increment of a for loop, it runs after every iteration, even one cut short by continue
+        increment: Option<Expr>,
+        span: Span,
+    },
+    DoWhile {
+        body: Box<Stmt>,
+        condition: Expr,
+        span: Span,
+    },
+    /// `for (var name in iterable) body`, runs the body for every item of a list or character of a string.
+    /// The token is the variable's name.
+    ForIn {
+        name: String,
+        iterable: Expr,
+        body: Box<Stmt>,
+        token: Token,
+        span: Span,
     },
     Break {
         token: Token,
@@ -64,8 +138,12 @@ pub enum Stmt {
         token: Token,
     },
     Function {
-        params: Vec<String>,
-        body: Vec<Stmt>,
+        /// Shared with the functions declared from it, so declaring them copies no statements
+        params: Rc<Vec<String>>,
+        signature: Signature,
+        /// Values of the trailing parameters that can be left out
+        defaults: Rc<Vec<Expr>>,
+        body: Rc<Vec<Stmt>>,
         name: String,
         token: Token,
     },
@@ -79,29 +157,113 @@ pub enum Stmt {
         token: Token,
         value: Option<Expr>,
     },
+    Try {
+        body: Vec<Stmt>,
+        catches: Vec<Catch>,
+        finally: Option<Vec<Stmt>>,
+        token: Token,
+    },
+    Throw {
+        value: Expr,
+        token: Token,
+    },
+    /// Runs the first arm whose pattern matches the value, none when no pattern does
+    Match {
+        value: Expr,
+        arms: Vec<Arm>,
+        token: Token,
+    },
+    /// Statement that failed to parse, kept by `Parser::parse_partial`.
+    /// It spreads from its first token to the last one skipped over.
+    Error {
+        token: Token,
+        end: Token,
+    },
+}
+
+/// Expression whose value a function body gives back when it ends without returning,
+/// the last statement of a block body when that's an expression statement
+pub fn implicit_return(body: &[Stmt]) -> Option<&Expr> {
+    match body {
+        [Stmt::Block { stmts, .. }] => match stmts.last() {
+            Some(Stmt::Expr { expr, .. }) => Some(expr),
+            _ => None,
+        },
+        _ => None,
+    }
 }
 
 impl Stmt {
+    /// Source code the statement was parsed from, see `Span::to` for statements spreading over many lines
+    pub fn span(&self) -> Span {
+        match self {
+            Stmt::Print { span, .. }
+            | Stmt::Expr { span, .. }
+            | Stmt::Var { span, .. }
+            | Stmt::Block { span, .. }
+            | Stmt::If { span, .. }
+            | Stmt::While { span, .. }
+            | Stmt::DoWhile { span, .. }
+            | Stmt::ForIn { span, .. } => *span,
+            Stmt::Return {
+                token,
+                value: Some(value),
+            } => token.span().to(value.span()),
+            Stmt::Throw { token, value } => token.span().to(value.span()),
+            Stmt::Break { token }
+            | Stmt::Continue { token }
+            | Stmt::Function { token, .. }
+            | Stmt::Class { token, .. }
+            | Stmt::Return { token, .. }
+            | Stmt::Try { token, .. }
+            | Stmt::Match { token, .. }
+            | Stmt::Error { token, .. } => token.span(),
+        }
+    }
+
     pub fn accept<R>(&self, visitor: &mut dyn Visitor<R>) -> Result<R, Error> {
         match self {
-            Stmt::Print { expr } => visitor.visit_print_stmt(expr),
-            Stmt::Expr { expr } => visitor.visit_expr_stmt(expr),
-            Stmt::Var { name, value } => visitor.visit_var(name, value),
-            Stmt::Block { stmts } => visitor.visit_block_stmt(stmts),
+            Stmt::Print { expr, .. } => visitor.visit_print_stmt(expr),
+            Stmt::Expr { expr, .. } => visitor.visit_expr_stmt(expr),
+            Stmt::Var {
+                name,
+                value,
+                annotation,
+                span,
+            } => visitor.visit_var(name, value, annotation, span),
+            Stmt::Block { stmts, .. } => visitor.visit_block_stmt(stmts),
             Stmt::If {
                 condition,
                 then_body,
                 else_body,
+                ..
             } => visitor.visit_if_stmt(condition, then_body, else_body),
-            Stmt::While { condition, body } => visitor.visit_while_stmt(condition, body),
+            Stmt::While {
+                condition,
+                body,
+                increment,
+                ..
+            } => visitor.visit_while_stmt(condition, body, increment),
+            Stmt::DoWhile {
+                body, condition, ..
+            } => visitor.visit_do_while_stmt(body, condition),
+            Stmt::ForIn {
+                name,
+                iterable,
+                body,
+                token,
+                ..
+            } => visitor.visit_for_in_stmt(name, iterable, body, token),
             Stmt::Continue { token } => visitor.visit_continue_stmt(token),
             Stmt::Break { token } => visitor.visit_break_stmt(token),
             Stmt::Function {
                 name,
                 params,
+                signature,
+                defaults,
                 body,
                 token,
-            } => visitor.visit_function_stmt(name, params, body, token),
+            } => visitor.visit_function_stmt(name, params, signature, defaults, body, token),
             Stmt::Class {
                 name,
                 token,
@@ -109,6 +271,15 @@ impl Stmt {
                 superclass,
             } => visitor.visit_class_stmt(name, token, members, superclass),
             Stmt::Return { value, token } => visitor.visit_return_stmt(value, token),
+            Stmt::Try {
+                body,
+                catches,
+                finally,
+                token,
+            } => visitor.visit_try_stmt(body, catches, finally, token),
+            Stmt::Throw { value, token } => visitor.visit_throw_stmt(value, token),
+            Stmt::Match { value, arms, token } => visitor.visit_match_stmt(value, arms, token),
+            Stmt::Error { token, end } => visitor.visit_error_stmt(token, end),
         }
     }
 }
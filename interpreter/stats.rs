@@ -0,0 +1,59 @@
+use crate::error::Error;
+use std::collections::BTreeMap;
+
+/// Counters of what an interpreter did since it was created, see `Interpreter::stats`.
+/// Nothing is sent anywhere, exporting them is up to the host.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Stats {
+    /// Calls of `Interpreter::run`, failed ones included
+    pub scripts_run: u64,
+    /// Errors runs and host calls failed with, by `ErrorType::code`
+    pub errors: BTreeMap<String, u64>,
+    /// Calls scripts made to each native function
+    pub native_calls: BTreeMap<String, u64>,
+}
+
+impl Stats {
+    pub(crate) fn count_errors(&mut self, errors: &[Error]) {
+        for error in errors {
+            *self.errors.entry(error.error_type.code()).or_insert(0) += 1;
+        }
+    }
+
+    pub(crate) fn count_native_call(&mut self, native: &str) {
+        match self.native_calls.get_mut(native) {
+            Some(calls) => *calls += 1,
+            None => {
+                self.native_calls.insert(native.to_owned(), 1);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Stats;
+    use crate::interpreter::Interpreter;
+    use crate::runtime_value::Value;
+
+    #[test]
+    fn counts_usage_stats() {
+        let mut interpreter = Interpreter::new();
+        assert_eq!(interpreter.stats(), Stats::default());
+        interpreter
+            .run("len(\"a\"); len(\"ab\"); type(1);")
+            .unwrap();
+        interpreter.run("undefined;").unwrap_err();
+        interpreter.run("1 < \"a\";").unwrap_err();
+        interpreter
+            .call_global("len", &vec![Value::Number(1.0)])
+            .unwrap_err();
+
+        let stats = interpreter.stats();
+        assert_eq!(stats.scripts_run, 3);
+        assert_eq!(stats.errors["UndefinedVariable"], 1);
+        assert_eq!(stats.errors["WrongType"], 2);
+        assert_eq!(stats.native_calls["len"], 3);
+        assert_eq!(stats.native_calls["type"], 1);
+    }
+}
@@ -0,0 +1,219 @@
+use crate::class::{Class, Instance};
+use crate::environment::{Environment, SlotTable};
+use crate::runtime_value::Value;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// Group of natives scripts reach through a global object of the same name, e.g. `Math.sqrt(2)`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Namespace {
+    Math,
+    String,
+    List,
+    Io,
+    Time,
+}
+
+impl Namespace {
+    pub const ALL: [Namespace; 5] = [
+        Namespace::Math,
+        Namespace::String,
+        Namespace::List,
+        Namespace::Io,
+        Namespace::Time,
+    ];
+
+    /// Name of the global object
+    pub fn name(self) -> &'static str {
+        match self {
+            Namespace::Math => "Math",
+            Namespace::String => "String",
+            Namespace::List => "List",
+            Namespace::Io => "IO",
+            Namespace::Time => "Time",
+        }
+    }
+
+    /// Natives in the namespace, `len` is in both `String` and `List`
+    fn members(self) -> &'static [&'static str] {
+        match self {
+            Namespace::Math => &[
+                "abs",
+                "floor",
+                "ceil",
+                "round",
+                "sqrt",
+                "pow",
+                "min",
+                "max",
+                "random",
+                "randomInt",
+                "setSeed",
+                "PI",
+                "E",
+            ],
+            Namespace::String => &["len", "format", "chars", "codePointAt", "fromCodePoint"],
+            Namespace::List => &["list", "len", "sort", "map", "filter", "reduce"],
+            Namespace::Io => &[
+                "readLine",
+                "readFile",
+                "writeFile",
+                "appendFile",
+                "fileExists",
+                "argCount",
+                "arg",
+            ],
+            Namespace::Time => &[
+                "clock",
+                "now",
+                "sleep",
+                "formatDuration",
+                "dateFormat",
+                "year",
+                "month",
+                "day",
+                "hour",
+                "minute",
+                "second",
+            ],
+        }
+    }
+}
+
+/// Where `Interpreter::with_stdlib` puts the natives.
+/// The ones outside every namespace, like `type`, `str` or `assert`, are always global.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StdlibConfig {
+    pub namespaces: Vec<Namespace>,
+    /// Every native is a global under its own name too, the way scripts written before namespaces expect
+    pub globals: bool,
+}
+
+impl Default for StdlibConfig {
+    fn default() -> Self {
+        StdlibConfig {
+            namespaces: Namespace::ALL.to_vec(),
+            globals: true,
+        }
+    }
+}
+
+impl StdlibConfig {
+    /// Only the given namespaces, nothing of them in globals
+    pub fn namespaced(namespaces: &[Namespace]) -> Self {
+        StdlibConfig {
+            namespaces: namespaces.to_vec(),
+            globals: false,
+        }
+    }
+}
+
+/// Moves the natives defined in `natives` to the globals and namespaces the config asks for
+pub(crate) fn export(natives: &Environment, globals: &mut Environment, config: &StdlibConfig) {
+    for (name, value) in natives.bindings() {
+        let namespaced = Namespace::ALL
+            .iter()
+            .any(|namespace| namespace.members().contains(&name.as_str()));
+        if config.globals || !namespaced {
+            if natives.is_read_only(name) {
+                globals.define_read_only(name, value);
+            } else {
+                globals.bind(name, value);
+            }
+        }
+        for namespace in &config.namespaces {
+            if namespace.members().contains(&name.as_str()) {
+                namespace_object(globals, *namespace)
+                    .borrow_mut()
+                    .set_field(name, value.clone());
+            }
+        }
+    }
+}
+
+/// The namespace's object in these very globals, a fork gets its own copy so it doesn't change the original
+fn namespace_object(globals: &mut Environment, namespace: Namespace) -> Rc<RefCell<Instance>> {
+    if let Some(Value::Instance(instance)) = globals.get(namespace.name()) {
+        return instance;
+    }
+    let fields = match globals.get_deep(namespace.name()) {
+        Some(Value::Instance(inherited)) => inherited
+            .borrow()
+            .fields()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect(),
+        _ => HashMap::new(),
+    };
+    let class = Class::from_parts(
+        namespace.name().to_owned(),
+        Vec::new(),
+        HashMap::new(),
+        None,
+        Rc::new(RefCell::new(Environment::new())),
+        SlotTable::default(),
+    );
+    let instance = Rc::new(RefCell::new(Instance::with_fields(Rc::new(class), fields)));
+    globals.define_read_only(namespace.name(), &Value::Instance(Rc::clone(&instance)));
+    instance
+}
+
+/// Object named after a capability the host grants, holding the natives `natives` defines
+pub(crate) fn capability_object(name: &str, natives: &Environment) -> Value {
+    let fields = natives
+        .bindings()
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .collect();
+    let class = Class::from_parts(
+        name.to_owned(),
+        Vec::new(),
+        HashMap::new(),
+        None,
+        Rc::new(RefCell::new(Environment::new())),
+        SlotTable::default(),
+    );
+    Value::Instance(Rc::new(RefCell::new(Instance::with_fields(
+        Rc::new(class),
+        fields,
+    ))))
+}
+
+/// Namespace objects hold natives only, so snapshots leave them out like the natives themselves
+pub(crate) fn is_namespace(instance: &Instance) -> bool {
+    let class = instance.class();
+    class.methods().next().is_none()
+        && class.superclass().is_none()
+        && Namespace::ALL
+            .iter()
+            .any(|namespace| namespace.name() == class.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Namespace, StdlibConfig};
+    use crate::interpreter::tests::assert_true;
+    use crate::interpreter::Interpreter;
+    use crate::runtime_value::Value;
+
+    #[test]
+    fn loads_chosen_namespaces() {
+        assert_true("Math.sqrt(16) == sqrt(16) and Math.PI == PI and Time.year(0) == 1970;");
+        assert_true("List.len(list()) == String.len(\"\") and type(IO.argCount) == \"function\";");
+        let mut interpreter =
+            Interpreter::with_stdlib(StdlibConfig::namespaced(&[Namespace::Math]));
+        interpreter.set_random_seed(1);
+        assert_eq!(
+            interpreter
+                .run("Math.abs(-2) + Math.randomInt(1, 1);")
+                .unwrap(),
+            Value::Number(3.0)
+        );
+        assert_eq!(
+            interpreter.run("type(str(1));").unwrap(),
+            Value::String(String::from("string"))
+        );
+        assert!(interpreter.run("sqrt(4);").is_err());
+        assert!(interpreter.run("Time;").is_err());
+        assert!(interpreter.run("Math = null;").is_err());
+    }
+}
@@ -0,0 +1,70 @@
+use crate::environment::Environment;
+use crate::error::{Error, ErrorType};
+use crate::natives::define_native;
+use crate::runtime_value::Value;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// Tests registered by a script with `test(name, fn)`, shared between the interpreter and its `test` native
+#[derive(Clone, Default)]
+pub struct TestRegistry(Rc<RefCell<Vec<(String, Value)>>>);
+
+impl TestRegistry {
+    /// Takes the tests registered so far, so running them again needs another registration
+    pub fn take(&self) -> Vec<(String, Value)> {
+        self.0.take()
+    }
+
+    /// Defines `test(name, fn)`, which registers the function with this registry
+    pub fn define_native(&self, env: &mut Environment) {
+        let tests = self.clone();
+        define_native(env, "test", 2, move |args| match (&args[0], &args[1]) {
+            (Value::String(name), test @ Value::Function(_)) => {
+                tests.0.borrow_mut().push((name.clone(), test.clone()));
+                Ok(Value::Null)
+            }
+            _ => Err(ErrorType::WrongType),
+        });
+    }
+}
+
+/// Outcome of every registered test, in the order they were registered
+#[derive(Debug, Default)]
+pub struct TestReport {
+    pub passed: Vec<String>,
+    /// Name of the test and the error that stopped it
+    pub failed: Vec<(String, Error)>,
+}
+
+impl TestReport {
+    pub fn is_success(&self) -> bool {
+        self.failed.is_empty()
+    }
+
+    pub fn summary(&self) -> String {
+        format!("{} passed, {} failed", self.passed.len(), self.failed.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interpreter::Interpreter;
+
+    #[test]
+    fn runs_registered_tests() {
+        let mut interpreter = Interpreter::new();
+        interpreter
+            .run(
+                "test(\"adds\", || => assertEquals(1 + 2, 3));
+                 test(\"fails\", || => { assert(false, \"nope\"); });
+                 test(\"passes\", || => null);",
+            )
+            .unwrap();
+        let report = interpreter.run_tests();
+        assert_eq!(report.passed, vec!["adds", "passes"]);
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(report.failed[0].0, "fails");
+        assert_eq!(report.summary(), "2 passed, 1 failed");
+        assert!(interpreter.run_tests().passed.is_empty());
+    }
+}
@@ -1,7 +1,8 @@
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fmt;
 
-#[derive(Debug, Clone, Display, PartialEq)]
+#[derive(Debug, Clone, Display, PartialEq, Serialize, Deserialize)]
 pub enum Literal {
     String(String),
     Number(f64),
@@ -9,7 +10,7 @@ pub enum Literal {
     Null,
 }
 
-#[derive(Debug, PartialEq, Clone, Display, EnumAsInner)]
+#[derive(Debug, PartialEq, Clone, Display, EnumAsInner, Serialize, Deserialize)]
 pub enum TokenType {
     EOF,
     Bar,
@@ -18,8 +19,11 @@ pub enum TokenType {
     CloseParenthesis,
     OpenBrace,
     CloseBrace,
+    OpenBracket,
+    CloseBracket,
     Coma,
     Dot,
+    Ellipsis,
     Minus,
     Plus,
     Star,
@@ -41,6 +45,7 @@ pub enum TokenType {
     True,
     Var,
     While,
+    Do,
     For,
     And,
     Or,
@@ -54,12 +59,41 @@ pub enum TokenType {
     This,
     Null,
     Print,
+    Try,
+    Catch,
+    Finally,
+    Throw,
+    Match,
+    In,
     Arrow,
     Literal(Literal),
     Identifier(String),
 }
 
-#[derive(Debug, PartialEq, Clone)]
+/// Location of a piece of source code, columns are counted from 1 and `end` is inclusive
+#[derive(Debug, PartialEq, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct Span {
+    pub line: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    /// Span reaching from the start of this one to the end of the other one.
+    /// Spans don't cross lines, nodes spreading over many lines are pointed at by their first line.
+    pub fn to(self, other: Span) -> Span {
+        if other.line == self.line && other.end > self.end {
+            Span {
+                end: other.end,
+                ..self
+            }
+        } else {
+            self
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct Token {
     pub token_type: TokenType,
     pub line: usize,
@@ -89,8 +123,40 @@ impl Token {
             end,
         }
     }
+
+    /// How an operator is written in the source
+    pub fn operator(&self) -> &'static str {
+        match self.token_type {
+            TokenType::Plus => "+",
+            TokenType::Minus => "-",
+            TokenType::Star => "*",
+            TokenType::Divide => "/",
+            TokenType::Modulo => "%",
+            TokenType::Bang => "!",
+            TokenType::BangEquals => "!=",
+            TokenType::Compare => "==",
+            TokenType::Less => "<",
+            TokenType::LessEquals => "<=",
+            TokenType::Greater => ">",
+            TokenType::GreaterEquals => ">=",
+            TokenType::And => "and",
+            TokenType::Or => "or",
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn span(&self) -> Span {
+        Span {
+            line: self.line,
+            start: self.start,
+            end: self.end,
+        }
+    }
 }
 
+/// Identifiers kept free for upcoming features, see `Features::reserve_future_keywords`
+pub const FUTURE_KEYWORDS: &[&str] = &["async", "await", "const", "let", "import", "enum", "yield"];
+
 lazy_static! {
     pub static ref KEYWORDS: HashMap<&'static str, TokenType> = {
         let mut map: HashMap<&'static str, TokenType> = HashMap::new();
@@ -100,6 +166,7 @@ lazy_static! {
         map.insert("true", TokenType::Literal(Literal::Bool(true)));
         map.insert("var", TokenType::Var);
         map.insert("while", TokenType::While);
+        map.insert("do", TokenType::Do);
         map.insert("for", TokenType::For);
         map.insert("and", TokenType::And);
         map.insert("or", TokenType::Or);
@@ -113,6 +180,25 @@ lazy_static! {
         map.insert("null", TokenType::Literal(Literal::Null));
         map.insert("break", TokenType::Break);
         map.insert("continue", TokenType::Continue);
+        map.insert("try", TokenType::Try);
+        map.insert("catch", TokenType::Catch);
+        map.insert("finally", TokenType::Finally);
+        map.insert("throw", TokenType::Throw);
+        map.insert("match", TokenType::Match);
+        map.insert("in", TokenType::In);
+        map
+    };
+    /// Keywords of the jlox dialect, this crate's other keywords are identifiers there
+    pub static ref JLOX_KEYWORDS: HashMap<&'static str, TokenType> = {
+        let mut map: HashMap<&'static str, TokenType> = HashMap::new();
+        for keyword in &[
+            "and", "class", "else", "false", "for", "if", "or", "print", "return", "super", "this",
+            "true", "var", "while",
+        ] {
+            map.insert(*keyword, KEYWORDS[keyword].clone());
+        }
+        map.insert("fun", TokenType::Function);
+        map.insert("nil", TokenType::Literal(Literal::Null));
         map
     };
 }
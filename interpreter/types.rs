@@ -0,0 +1,609 @@
+use crate::error::{Error, ErrorType};
+use crate::expr::Expr;
+use crate::interpreter::span_token;
+use crate::statement::{implicit_return, Catch, Stmt};
+use crate::token::{Literal, Span, TokenType};
+use crate::walk::{walk_expr, walk_stmts, Walk};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// Type written in an annotation, named the way the `type` native names them.
+/// Any other name is the type of the instances of the class called so.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Type {
+    Any,
+    Number,
+    String,
+    Boolean,
+    Null,
+    Function,
+    Class,
+    List,
+    Instance(String),
+}
+
+impl Type {
+    pub fn parse(name: &str) -> Type {
+        match name {
+            "any" => Type::Any,
+            "number" => Type::Number,
+            "string" => Type::String,
+            "boolean" => Type::Boolean,
+            "null" => Type::Null,
+            "function" => Type::Function,
+            "class" => Type::Class,
+            "list" => Type::List,
+            class => Type::Instance(class.to_owned()),
+        }
+    }
+
+    fn of(literal: &Literal) -> Type {
+        match literal {
+            Literal::Number(_) => Type::Number,
+            Literal::String(_) => Type::String,
+            Literal::Bool(_) => Type::Boolean,
+            Literal::Null => Type::Null,
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Type::Any => "any",
+            Type::Number => "number",
+            Type::String => "string",
+            Type::Boolean => "boolean",
+            Type::Null => "null",
+            Type::Function => "function",
+            Type::Class => "class",
+            Type::List => "list",
+            Type::Instance(class) => class,
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Annotations of a function, `None` where there's none, and whether it takes a rest parameter
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Signature {
+    /// One for every parameter
+    pub params: Vec<Option<Type>>,
+    pub returns: Option<Type>,
+    /// The last parameter, `...name`, gets the arguments left after the others as a list
+    pub variadic: bool,
+}
+
+/// What a name is bound to, as far as the checker knows
+#[derive(Debug, Clone)]
+enum Binding {
+    Var(Option<Type>),
+    /// Function declaration whose name is never assigned, so its calls are known to reach it
+    Function {
+        signature: Signature,
+        /// Parameters without a default value
+        required: usize,
+        /// Arguments beyond it go to the rest parameter, if there's one
+        positional: usize,
+    },
+    Class(String),
+}
+
+/// Names assigned anywhere in the program, functions declared under them may be replaced at runtime
+#[derive(Default)]
+struct Assigned<'a>(HashSet<&'a str>);
+
+impl<'a> Walk<'a> for Assigned<'a> {
+    fn expr(&mut self, expr: &'a Expr) {
+        if let Expr::Assign { name, .. } = expr {
+            self.0.insert(name);
+        }
+        walk_expr(self, expr);
+    }
+}
+
+/// Gradual type checking: values known to be of a type other than the annotated one, and calls to
+/// declared functions with the wrong number of arguments. Whatever isn't annotated is of type `any`.
+pub fn check_types(stmts: &[Stmt]) -> Vec<Error> {
+    let mut assigned = Assigned::default();
+    walk_stmts(&mut assigned, stmts);
+    let mut checker = Checker {
+        assigned: assigned.0,
+        scopes: vec![HashMap::new()],
+        superclasses: HashMap::new(),
+        returns: Vec::new(),
+        errors: Vec::new(),
+    };
+    checker.stmts(stmts);
+    checker.errors
+}
+
+struct Checker<'a> {
+    assigned: HashSet<&'a str>,
+    scopes: Vec<HashMap<String, Binding>>,
+    superclasses: HashMap<String, String>,
+    /// Return type of every function the checker is in, the innermost last
+    returns: Vec<Option<Type>>,
+    errors: Vec<Error>,
+}
+
+impl<'a> Checker<'a> {
+    fn stmts(&mut self, stmts: &[Stmt]) {
+        for stmt in stmts {
+            self.stmt(stmt);
+        }
+    }
+
+    fn scoped(&mut self, check: impl FnOnce(&mut Self)) {
+        self.scopes.push(HashMap::new());
+        check(self);
+        self.scopes.pop();
+    }
+
+    fn declare(&mut self, name: &str, binding: Binding) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.to_owned(), binding);
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<&Binding> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name))
+    }
+
+    /// Instances of the class are instances of the ancestor too
+    fn inherits(&self, class: &str, ancestor: &str) -> bool {
+        let mut class = Some(class);
+        while let Some(name) = class {
+            if name == ancestor {
+                return true;
+            }
+            class = self.superclasses.get(name).map(String::as_str);
+        }
+        false
+    }
+
+    fn expect(&mut self, expected: &Option<Type>, found: &Type, span: Span) {
+        let expected = match expected {
+            Some(expected) => expected,
+            None => return,
+        };
+        let accepted = match (expected, found) {
+            (Type::Any, _) | (_, Type::Any) => true,
+            (Type::Instance(ancestor), Type::Instance(class)) => self.inherits(class, ancestor),
+            _ => expected == found,
+        };
+        if !accepted {
+            let error_type =
+                ErrorType::TypeMismatch(Box::new(expected.clone()), Box::new(found.clone()));
+            self.errors
+                .push(Error::new(&span_token(span), error_type).with_span(span));
+        }
+    }
+
+    fn function(
+        &mut self,
+        params: &[String],
+        signature: &Signature,
+        defaults: &[Expr],
+        body: &[Stmt],
+    ) {
+        let required = params.len() - defaults.len() - signature.variadic as usize;
+        for (default, expected) in defaults.iter().zip(&signature.params[required..]) {
+            let found = self.expr(default);
+            self.expect(expected, &found, default.span());
+        }
+        self.returns.push(signature.returns.clone());
+        self.scoped(|checker| {
+            for (param, annotation) in params.iter().zip(&signature.params) {
+                checker.declare(param, Binding::Var(annotation.clone()));
+            }
+            if let (true, Some(rest)) = (signature.variadic, params.last()) {
+                checker.declare(rest, Binding::Var(Some(Type::List)));
+            }
+            match (implicit_return(body), body) {
+                // the block is checked here so the value it ends with is checked like a return
+                (Some(value), [Stmt::Block { stmts, .. }]) => checker.scoped(|checker| {
+                    checker.stmts(&stmts[..stmts.len() - 1]);
+                    let found = checker.expr(value);
+                    checker.expect(&signature.returns, &found, value.span());
+                }),
+                _ => checker.stmts(body),
+            }
+        });
+        self.returns.pop();
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Print { expr, .. }
+            | Stmt::Expr { expr, .. }
+            | Stmt::Throw { value: expr, .. } => {
+                self.expr(expr);
+            }
+            Stmt::Var {
+                name,
+                value,
+                annotation,
+                ..
+            } => {
+                if let Some(value) = value {
+                    let found = self.expr(value);
+                    self.expect(annotation, &found, value.span());
+                }
+                self.declare(name, Binding::Var(annotation.clone()));
+            }
+            Stmt::Block { stmts, .. } => self.scoped(|checker| checker.stmts(stmts)),
+            Stmt::If {
+                condition,
+                then_body,
+                else_body,
+                ..
+            } => {
+                self.expr(condition);
+                self.stmt(then_body);
+                if let Some(else_body) = else_body {
+                    self.stmt(else_body);
+                }
+            }
+            Stmt::While {
+                condition,
+                body,
+                increment,
+                ..
+            } => {
+                self.expr(condition);
+                self.stmt(body);
+                if let Some(increment) = increment {
+                    self.expr(increment);
+                }
+            }
+            Stmt::DoWhile {
+                body, condition, ..
+            } => {
+                self.stmt(body);
+                self.expr(condition);
+            }
+            Stmt::ForIn {
+                name,
+                iterable,
+                body,
+                ..
+            } => {
+                self.expr(iterable);
+                self.scoped(|checker| {
+                    checker.declare(name, Binding::Var(None));
+                    checker.stmt(body);
+                });
+            }
+            Stmt::Function {
+                name,
+                params,
+                signature,
+                defaults,
+                body,
+                ..
+            } => {
+                let binding = if self.assigned.contains(name.as_str()) {
+                    Binding::Var(None)
+                } else {
+                    let positional = params.len() - signature.variadic as usize;
+                    Binding::Function {
+                        signature: signature.clone(),
+                        required: positional - defaults.len(),
+                        positional,
+                    }
+                };
+                self.declare(name, binding);
+                self.function(params, signature, defaults, body);
+            }
+            Stmt::Class {
+                name,
+                members,
+                superclass,
+                ..
+            } => {
+                if let Some(Expr::Var {
+                    name: superclass, ..
+                }) = superclass
+                {
+                    self.superclasses.insert(name.clone(), superclass.clone());
+                }
+                self.declare(name, Binding::Class(name.clone()));
+                self.scoped(|checker| {
+                    for member in members {
+                        match member {
+                            Stmt::Function {
+                                params,
+                                signature,
+                                defaults,
+                                body,
+                                ..
+                            } => checker.function(params, signature, defaults, body),
+                            field => checker.stmt(field),
+                        }
+                    }
+                });
+            }
+            Stmt::Return { value, token } => {
+                let found = match value {
+                    Some(value) => self.expr(value),
+                    None => Type::Null,
+                };
+                let span = value.as_ref().map_or(token.span(), Expr::span);
+                if let Some(expected) = self.returns.last().cloned() {
+                    self.expect(&expected, &found, span);
+                }
+            }
+            Stmt::Try {
+                body,
+                catches,
+                finally,
+                ..
+            } => {
+                self.scoped(|checker| checker.stmts(body));
+                for Catch { name, class, body } in catches {
+                    if let Some(class) = class {
+                        self.expr(class);
+                    }
+                    self.scoped(|checker| {
+                        checker.declare(name, Binding::Var(None));
+                        checker.stmts(body);
+                    });
+                }
+                if let Some(finally) = finally {
+                    self.scoped(|checker| checker.stmts(finally));
+                }
+            }
+            Stmt::Match { value, arms, .. } => {
+                self.expr(value);
+                for arm in arms {
+                    self.scoped(|checker| {
+                        for name in arm.pattern.bindings() {
+                            checker.declare(name, Binding::Var(None));
+                        }
+                        checker.stmt(&arm.body);
+                    });
+                }
+            }
+            Stmt::Break { .. } | Stmt::Continue { .. } | Stmt::Error { .. } => (),
+        }
+    }
+
+    /// Type the expression is known to evaluate to
+    fn expr(&mut self, expr: &Expr) -> Type {
+        match expr {
+            Expr::Literal { value, .. } => Type::of(value),
+            Expr::Grouping { expr, .. } => self.expr(expr),
+            Expr::Var { name, .. } => match self.lookup(name) {
+                Some(Binding::Var(Some(annotation))) => annotation.clone(),
+                Some(Binding::Function { .. }) => Type::Function,
+                Some(Binding::Class(_)) => Type::Class,
+                _ => Type::Any,
+            },
+            Expr::Assign { name, expr, .. } => {
+                let found = self.expr(expr);
+                if let Some(Binding::Var(annotation)) = self.lookup(name).cloned() {
+                    self.expect(&annotation, &found, expr.span());
+                }
+                found
+            }
+            Expr::Unary { operator, expr } => {
+                self.expr(expr);
+                match operator.token_type {
+                    TokenType::Minus => Type::Number,
+                    _ => Type::Boolean,
+                }
+            }
+            Expr::Binary {
+                left,
+                operator,
+                right,
+            } => {
+                let left = self.expr(left);
+                let right = self.expr(right);
+                match operator.token_type {
+                    TokenType::Plus => match (left, right) {
+                        (Type::Number, Type::Number) => Type::Number,
+                        (Type::String, _) | (_, Type::String) => Type::String,
+                        _ => Type::Any,
+                    },
+                    // instances can subtract to anything with a `sub` method
+                    TokenType::Minus => match (left, right) {
+                        (Type::Instance(_), _) | (_, Type::Instance(_)) => Type::Any,
+                        _ => Type::Number,
+                    },
+                    TokenType::Star | TokenType::Divide | TokenType::Modulo => Type::Number,
+                    _ => Type::Boolean,
+                }
+            }
+            Expr::Logical { left, right, .. } => {
+                let left = self.expr(left);
+                let right = self.expr(right);
+                if left == right {
+                    left
+                } else {
+                    Type::Any
+                }
+            }
+            Expr::Call {
+                callee,
+                token,
+                arguments,
+                named,
+            } => {
+                let binding = match callee.as_ref() {
+                    Expr::Var { name, .. } => self.lookup(name).cloned(),
+                    callee => {
+                        self.expr(callee);
+                        None
+                    }
+                };
+                let found: Vec<Type> = arguments.iter().map(|arg| self.expr(arg)).collect();
+                for (_, value) in named {
+                    self.expr(value);
+                }
+                // spread lists can have any number of items
+                let spread = arguments
+                    .iter()
+                    .any(|argument| matches!(argument, Expr::Spread { .. }));
+                match binding {
+                    Some(Binding::Function {
+                        signature,
+                        required,
+                        positional,
+                    }) if !spread => {
+                        if found.len() < required
+                            || (found.len() > positional && !signature.variadic)
+                        {
+                            let error = Error::new(token, ErrorType::InvalidNumberOfArguments);
+                            self.errors.push(error.with_span(expr.span()));
+                        }
+                        for ((argument, found), expected) in arguments
+                            .iter()
+                            .zip(&found)
+                            .zip(&signature.params[..positional])
+                        {
+                            self.expect(expected, found, argument.span());
+                        }
+                        signature.returns.unwrap_or(Type::Any)
+                    }
+                    Some(Binding::Class(class)) => Type::Instance(class),
+                    _ => Type::Any,
+                }
+            }
+            Expr::Closure {
+                params,
+                signature,
+                defaults,
+                body,
+                ..
+            } => {
+                self.function(params, signature, defaults, body);
+                Type::Function
+            }
+            Expr::Get { expr, .. } | Expr::Spread { expr, .. } => {
+                self.expr(expr);
+                Type::Any
+            }
+            Expr::Set { value, obj, .. } => {
+                self.expr(obj);
+                self.expr(value)
+            }
+            Expr::Index { object, index, .. } => {
+                self.expr(object);
+                self.expr(index);
+                Type::Any
+            }
+            Expr::SetIndex {
+                object,
+                index,
+                value,
+                ..
+            } => {
+                self.expr(object);
+                self.expr(index);
+                self.expr(value)
+            }
+            Expr::This { .. } | Expr::Super { .. } | Expr::Error { .. } => Type::Any,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::check_types;
+    use crate::error::ErrorType;
+    use crate::features::Features;
+    use crate::interpreter::tests::{assert_error, assert_true, assert_true_in};
+    use crate::interpreter::Interpreter;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn check(source_code: &str) -> Vec<String> {
+        let tokens = Lexer::new(source_code).scan_tokens().unwrap();
+        let stmts = Parser::new(&tokens).parse_tokens().unwrap();
+        check_types(&stmts)
+            .iter()
+            .map(|error| {
+                format!(
+                    "{}:{} {}",
+                    error.span.line, error.span.start, error.error_type
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reports_mismatches() {
+        let errors = check(
+            "fn add(a: number, b: number): number { return a + b; }\n\
+             var x: string = add(1, 2);\n\
+             add(\"1\", 2);\n\
+             fn name(): string { return 1; }\n\
+             var y: number; y = true;\n\
+             fn label(): string { 1; }",
+        );
+        assert_eq!(
+            errors,
+            vec![
+                "2:17 Expected a value of type string but got number",
+                "3:5 Expected a value of type number but got string",
+                "4:28 Expected a value of type string but got number",
+                "5:20 Expected a value of type number but got boolean",
+                "6:22 Expected a value of type string but got number",
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_wrong_arities() {
+        let errors = check("fn f(a, b) {}\nf(1);\nvar g = f;\ng(1);");
+        assert_eq!(errors, vec!["2:1 Invalid number of arguments"]);
+        // the function may be replaced by then
+        assert!(check("fn f(a) {}\nfn g() { f(1, 2); }\nf = max;").is_empty());
+    }
+
+    #[test]
+    fn accepts_untyped_code() {
+        assert!(check(
+            "class A {} class B : A {}\nvar a: A = B();\nvar n = \"1\";\nvar m: number = n;\n\
+             fn f(x) { return x; }\nvar s: string = f(1);"
+        )
+        .is_empty());
+        assert_eq!(
+            check("class A {} class B {}\nvar a: A = B();"),
+            vec!["2:12 Expected a value of type A but got B"]
+        );
+    }
+
+    #[test]
+    fn checks_types_before_running() {
+        assert_true(
+            "fn add(a: number, b: number): number { return a + b; }\n\
+             var sum: number = add(1, 2); var untyped = add; untyped(1, 2) == sum;",
+        );
+        let mut interpreter = Interpreter::new();
+        let printed = Rc::new(RefCell::new(Vec::new()));
+        let captured = Rc::clone(&printed);
+        interpreter.set_output(move |line| captured.borrow_mut().push(line.to_owned()));
+        let errors = interpreter.run("print 1;\nvar x: string = 2;").unwrap_err();
+        assert!(printed.borrow().is_empty());
+        assert!(matches!(&errors[0].error_type, ErrorType::TypeMismatch(..)));
+        assert_error(
+            "fn f(a) {}\nif (false) f();",
+            ErrorType::InvalidNumberOfArguments,
+        );
+        interpreter.set_features(Features {
+            type_annotations: false,
+            ..Features::all()
+        });
+        assert_true_in(&mut interpreter, "fn f(a) {} if (false) f(); true;");
+        let errors = interpreter.run("var x: number = 1;").unwrap_err();
+        assert!(
+            matches!(&errors[0].error_type, ErrorType::FeatureDisabled(f) if f == "type annotations")
+        );
+    }
+}
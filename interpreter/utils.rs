@@ -1,5 +1,5 @@
+use crate::diagnostics::{render_diagnostics, DiagnosticOptions};
 use crate::error::Error;
-use crate::token::Token;
 
 pub fn set_panic_hook() {
     // When the `console_error_panic_hook` feature is enabled, we can call the
@@ -17,23 +17,15 @@ pub enum PrintType {
     Error,
 }
 
-pub fn print_errors(errors: &Vec<Error>) {
-    for err in errors {
-        let Error { token, error_type } = err;
-        let Token {
-            token_type,
-            line,
-            start,
-            end,
-        } = token;
-
-        print(
-            &format!("{}.{}-{} : {}", line, start, end, error_type),
-            PrintType::Error,
-        );
+/// Prints the errors the way the options say
+pub fn print_errors(errors: &Vec<Error>, options: &DiagnosticOptions) {
+    for line in render_diagnostics(errors, options) {
+        print(&line, PrintType::Error);
     }
 }
 
+/// In the browser the output goes to the playground's console element
+#[cfg(all(target_arch = "wasm32", not(target_os = "wasi")))]
 pub fn print(s: &str, print_type: PrintType) {
     let window = web_sys::window().expect("global window does not exist");
     let document = window.document().expect("document does not exist");
@@ -56,3 +48,12 @@ pub fn print(s: &str, print_type: PrintType) {
         .expect("Failed to append element");
     console.set_scroll_top(console.client_height());
 }
+
+/// Everywhere else (native and WASI) the output goes to the standard streams
+#[cfg(not(all(target_arch = "wasm32", not(target_os = "wasi"))))]
+pub fn print(s: &str, print_type: PrintType) {
+    match print_type {
+        PrintType::Success => println!("{}", s),
+        PrintType::Error => eprintln!("{}", s),
+    }
+}
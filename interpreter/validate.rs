@@ -0,0 +1,95 @@
+use crate::error::{Error, ErrorType};
+use crate::fixes::Diagnostic;
+use crate::lexer::Lexer;
+use crate::parser::{Parser, MAX_NESTING};
+use crate::token::{Token, TokenType};
+
+/// Caps on the code `validate` looks at, the time and memory it takes grow with them linearly
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ValidationLimits {
+    /// Longest source code, in bytes. Longer one isn't lexed at all.
+    pub max_bytes: usize,
+    /// Tokens after which lexing stops
+    pub max_tokens: usize,
+    /// Deepest syntax tree, at most `MAX_NESTING`
+    pub max_nesting: usize,
+}
+
+impl Default for ValidationLimits {
+    fn default() -> Self {
+        ValidationLimits {
+            max_bytes: 64 * 1024,
+            max_tokens: 16 * 1024,
+            max_nesting: MAX_NESTING,
+        }
+    }
+}
+
+/// Syntax errors of untrusted code, e.g. snippets sent to a server, found without running or resolving it.
+/// Code over the limits gets a single error for the limit it went over.
+pub fn validate(source_code: &str, limits: &ValidationLimits) -> Vec<Diagnostic> {
+    if source_code.len() > limits.max_bytes {
+        let start = Token::new(TokenType::Invalid, 1, 1, 1);
+        let error = Error::new(&start, ErrorType::SourceTooLarge(limits.max_bytes));
+        return vec![Diagnostic::new(&error, false, None)];
+    }
+
+    let mut lexer = Lexer::new(source_code);
+    lexer.set_max_tokens(limits.max_tokens);
+    let tokens = match lexer.scan_tokens() {
+        Ok(tokens) => tokens,
+        Err(errors) => return diagnostics(&errors),
+    };
+
+    let mut parser = Parser::new(&tokens);
+    parser.set_max_nesting(limits.max_nesting);
+    match parser.parse_tokens() {
+        Ok(_) => Vec::new(),
+        Err(errors) => diagnostics(&errors),
+    }
+}
+
+fn diagnostics(errors: &[Error]) -> Vec<Diagnostic> {
+    errors
+        .iter()
+        .map(|e| Diagnostic::new(e, false, None))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{validate, ValidationLimits};
+
+    fn codes(source_code: &str, limits: &ValidationLimits) -> Vec<String> {
+        validate(source_code, limits)
+            .into_iter()
+            .map(|d| d.code)
+            .collect()
+    }
+
+    #[test]
+    fn validates_within_limits() {
+        let limits = ValidationLimits::default();
+        assert!(validate("var a = 1; print a + 2;", &limits).is_empty());
+        // undefined variables are left to the resolver
+        assert!(validate("print b;", &limits).is_empty());
+        assert_eq!(codes("print 1", &limits), vec!["ExpectedSemicolon"]);
+        assert_eq!(codes("print \"a;", &limits), vec!["StringNotClosed"]);
+    }
+
+    #[test]
+    fn stops_at_the_limits() {
+        let limits = ValidationLimits {
+            max_bytes: 40,
+            max_tokens: 12,
+            max_nesting: 6,
+        };
+        assert_eq!(codes(&"1;".repeat(21), &limits), vec!["SourceTooLarge"]);
+        let errors = validate("print 1 + 2 + 3 + 4 + 5 + 6;", &limits);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].code, "TooManyTokens");
+        assert_eq!(errors[0].span.start, 28);
+        assert!(validate("print ((1));", &limits).is_empty());
+        assert_eq!(codes("print ((((1))));", &limits), vec!["NestedTooDeeply"]);
+    }
+}
@@ -0,0 +1,226 @@
+use crate::expr::Expr;
+use crate::pattern::Pattern;
+use crate::statement::{Arm, Catch, Stmt};
+
+/// Pass that looks at the syntax tree without changing it. Both methods go on to the children
+/// by default, so a pass only overrides the one for the nodes it cares about,
+/// calling `walk_stmt` or `walk_expr` from there when it wants to go deeper.
+pub trait Walk<'a> {
+    fn stmt(&mut self, stmt: &'a Stmt) {
+        walk_stmt(self, stmt);
+    }
+
+    fn expr(&mut self, expr: &'a Expr) {
+        walk_expr(self, expr);
+    }
+}
+
+pub fn walk_stmts<'a, W: Walk<'a> + ?Sized>(walker: &mut W, stmts: &'a [Stmt]) {
+    for stmt in stmts {
+        walker.stmt(stmt);
+    }
+}
+
+/// Hands every statement and expression right inside the statement to the walker, in source order
+pub fn walk_stmt<'a, W: Walk<'a> + ?Sized>(walker: &mut W, stmt: &'a Stmt) {
+    match stmt {
+        Stmt::Print { expr, .. } | Stmt::Expr { expr, .. } | Stmt::Throw { value: expr, .. } => {
+            walker.expr(expr)
+        }
+        Stmt::Var { value, .. } | Stmt::Return { value, .. } => {
+            if let Some(value) = value {
+                walker.expr(value);
+            }
+        }
+        Stmt::Block { stmts, .. } => walk_stmts(walker, stmts),
+        Stmt::If {
+            condition,
+            then_body,
+            else_body,
+            ..
+        } => {
+            walker.expr(condition);
+            walker.stmt(then_body);
+            if let Some(else_body) = else_body {
+                walker.stmt(else_body);
+            }
+        }
+        Stmt::While {
+            condition,
+            body,
+            increment,
+            ..
+        } => {
+            walker.expr(condition);
+            walker.stmt(body);
+            if let Some(increment) = increment {
+                walker.expr(increment);
+            }
+        }
+        Stmt::DoWhile {
+            body, condition, ..
+        } => {
+            walker.stmt(body);
+            walker.expr(condition);
+        }
+        Stmt::ForIn { iterable, body, .. } => {
+            walker.expr(iterable);
+            walker.stmt(body);
+        }
+        Stmt::Function { defaults, body, .. } => {
+            for default in defaults.iter() {
+                walker.expr(default);
+            }
+            walk_stmts(walker, body);
+        }
+        Stmt::Class {
+            members,
+            superclass,
+            ..
+        } => {
+            if let Some(superclass) = superclass {
+                walker.expr(superclass);
+            }
+            walk_stmts(walker, members);
+        }
+        Stmt::Try {
+            body,
+            catches,
+            finally,
+            ..
+        } => {
+            walk_stmts(walker, body);
+            for Catch { class, body, .. } in catches {
+                if let Some(class) = class {
+                    walker.expr(class);
+                }
+                walk_stmts(walker, body);
+            }
+            if let Some(finally) = finally {
+                walk_stmts(walker, finally);
+            }
+        }
+        Stmt::Match { value, arms, .. } => {
+            walker.expr(value);
+            for Arm { pattern, body } in arms {
+                walk_pattern(walker, pattern);
+                walker.stmt(body);
+            }
+        }
+        Stmt::Break { .. } | Stmt::Continue { .. } | Stmt::Error { .. } => (),
+    }
+}
+
+/// Classes named by instance patterns are the only expressions in patterns
+fn walk_pattern<'a, W: Walk<'a> + ?Sized>(walker: &mut W, pattern: &'a Pattern) {
+    match pattern {
+        Pattern::List { items, .. } => {
+            for item in items {
+                walk_pattern(walker, item);
+            }
+        }
+        Pattern::Instance { class, fields, .. } => {
+            if let Some(class) = class {
+                walker.expr(class);
+            }
+            for (_, field) in fields {
+                walk_pattern(walker, field);
+            }
+        }
+        Pattern::Literal { .. } | Pattern::Binding { .. } => (),
+    }
+}
+
+/// Hands every expression and statement right inside the expression to the walker, in source order
+pub fn walk_expr<'a, W: Walk<'a> + ?Sized>(walker: &mut W, expr: &'a Expr) {
+    match expr {
+        Expr::Binary { left, right, .. } | Expr::Logical { left, right, .. } => {
+            walker.expr(left);
+            walker.expr(right);
+        }
+        Expr::Unary { expr, .. }
+        | Expr::Grouping { expr, .. }
+        | Expr::Assign { expr, .. }
+        | Expr::Get { expr, .. }
+        | Expr::Spread { expr, .. } => walker.expr(expr),
+        Expr::Call {
+            callee,
+            arguments,
+            named,
+            ..
+        } => {
+            walker.expr(callee);
+            for argument in arguments {
+                walker.expr(argument);
+            }
+            for (_, value) in named {
+                walker.expr(value);
+            }
+        }
+        Expr::Closure { defaults, body, .. } => {
+            for default in defaults.iter() {
+                walker.expr(default);
+            }
+            walk_stmts(walker, body);
+        }
+        Expr::Set { value, obj, .. } => {
+            walker.expr(obj);
+            walker.expr(value);
+        }
+        Expr::Index { object, index, .. } => {
+            walker.expr(object);
+            walker.expr(index);
+        }
+        Expr::SetIndex {
+            object,
+            index,
+            value,
+            ..
+        } => {
+            walker.expr(object);
+            walker.expr(index);
+            walker.expr(value);
+        }
+        Expr::Literal { .. }
+        | Expr::Var { .. }
+        | Expr::This { .. }
+        | Expr::Super { .. }
+        | Expr::Error { .. } => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{walk_expr, walk_stmts, Walk};
+    use crate::expr::Expr;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::statement::Stmt;
+
+    fn parse(source_code: &str) -> Vec<Stmt> {
+        let tokens = Lexer::new(source_code).scan_tokens().unwrap();
+        Parser::new(&tokens).parse_tokens().unwrap()
+    }
+
+    /// Names of the variables read anywhere, closures and methods included
+    struct Reads<'a>(Vec<&'a str>);
+
+    impl<'a> Walk<'a> for Reads<'a> {
+        fn expr(&mut self, expr: &'a Expr) {
+            if let Expr::Var { name, .. } = expr {
+                self.0.push(name);
+            }
+            walk_expr(self, expr);
+        }
+    }
+
+    #[test]
+    fn walks_every_node() {
+        let stmts = parse(
+            "var a = b;\nclass C : D { m() { return |x| => x + e; } }\ntry { f(g: h); } catch (err: I) { print -j; }",
+        );
+        let mut reads = Reads(Vec::new());
+        walk_stmts(&mut reads, &stmts);
+        assert_eq!(reads.0, vec!["b", "D", "x", "e", "f", "h", "I", "j"]);
+    }
+}
@@ -0,0 +1,90 @@
+use crate::environment::Environment;
+use crate::error::ErrorType;
+use crate::natives::define_native;
+use crate::runtime_value::Value;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// Called with the name and the new value of a watched top-level variable
+pub type WatchCallback = Rc<dyn Fn(&str, &Value)>;
+
+#[derive(Clone)]
+pub enum Watcher {
+    Host(WatchCallback),
+    /// Function or class registered by a script with the `watch` native
+    Script(Value),
+}
+
+/// Watchers of top-level variables, shared between the interpreter and its `watch` native
+#[derive(Clone, Default)]
+pub struct Watchers(Rc<RefCell<HashMap<String, Vec<Watcher>>>>);
+
+impl Watchers {
+    pub fn add(&self, name: &str, watcher: Watcher) {
+        self.0
+            .borrow_mut()
+            .entry(name.to_owned())
+            .or_default()
+            .push(watcher);
+    }
+
+    pub fn get(&self, name: &str) -> Vec<Watcher> {
+        self.0.borrow().get(name).cloned().unwrap_or_default()
+    }
+
+    /// Defines `watch(name, callback)`, which registers the callback with these watchers
+    pub fn define_native(&self, env: &mut Environment) {
+        let watchers = self.clone();
+        define_native(env, "watch", 2, move |args| match (&args[0], &args[1]) {
+            (Value::String(name), callback @ (Value::Function(_) | Value::Class(_))) => {
+                watchers.add(name, Watcher::Script(callback.clone()));
+                Ok(Value::Null)
+            }
+            _ => Err(ErrorType::WrongType),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::ErrorType;
+    use crate::interpreter::tests::{assert_error, assert_true, evaluate};
+    use crate::interpreter::Interpreter;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn watchers_see_top_level_changes() {
+        let mut interpreter = Interpreter::new();
+        let changes = Rc::new(RefCell::new(Vec::new()));
+        let seen = Rc::clone(&changes);
+        interpreter.watch(
+            "score",
+            Rc::new(move |name, value| seen.borrow_mut().push(format!("{}={}", name, value))),
+        );
+
+        interpreter
+            .run("var score = 1; score = 2; fn bump() { score = score + 1; } bump(); { var score = 10; score = 11; }")
+            .unwrap();
+        interpreter.fork().run("score = 5;").unwrap();
+        assert_eq!(*changes.borrow(), vec!["score=1", "score=2", "score=3"]);
+    }
+
+    #[test]
+    fn scripts_watch_variables() {
+        let code = "
+            var log = \"\";
+            fn onChange(name, value) { log = log + name + \":\" + format(value, \"\") + \" \"; }
+            watch(\"x\", onChange);
+            var x = 1;
+            x = 2;
+            fn setX() { x = 3; }
+            setX();
+            log;
+        ";
+        assert_eq!(evaluate(code).unwrap().as_string().unwrap(), "x:1 x:2 x:3 ");
+        assert_true("var n = 0; fn again(name, value) { n = value + 1; } watch(\"n\", again); n = 1; n == 2;");
+        assert_error("watch(\"x\", 1);", ErrorType::WrongType);
+    }
+}
@@ -0,0 +1,18 @@
+"""Smoke test of the Python bindings, run it after building them: maturin develop && python python/smoke_test.py"""
+import lox
+
+interpreter = lox.LoxInterpreter()
+interpreter.register("shout", 1, lambda s: s.upper())
+interpreter.eval('fn greet(name) { return shout("hello ") + name; }')
+assert interpreter.call("greet", "world") == "HELLO world"
+assert interpreter.eval("1 + 2;") == 3
+assert interpreter.eval("fn f(...items) { return items; } f(1, true, null);") == [1, True, None]
+
+try:
+    interpreter.eval("undefinedVariable;")
+except RuntimeError as error:
+    assert "undefined" in str(error).lower(), error
+else:
+    raise AssertionError("errors should raise RuntimeError")
+
+print("Python bindings work")